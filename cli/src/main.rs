@@ -1,8 +1,53 @@
+use clap::{Parser, Subcommand};
 use colored::*;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 use std::time::Duration;
 use indicatif::ProgressBar;
 use indicatif::ProgressStyle;
+use sebure_core::blockchain::{minimum_replacement_fee, MempoolConfig, Transaction};
+use sebure_core::crypto::signature::KeyPair;
+
+/// SEBURE blockchain command-line interface
+#[derive(Parser)]
+#[command(name = "sebure", version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Run blockchain tests
+    Test {
+        /// Test suite to run ('dpos' or 'all')
+        #[arg(default_value = "all")]
+        test_type: String,
+        
+        /// Enable debug logging
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    
+    /// Re-sign a stuck transaction with a higher fee so it replaces the pending one
+    SpeedUp {
+        /// JSON file holding the pending transaction
+        #[arg(long)]
+        tx: PathBuf,
+        
+        /// Hex-encoded private key of the sender
+        #[arg(long)]
+        private_key: String,
+        
+        /// New fee; defaults to the minimum replacement fee
+        #[arg(long)]
+        fee: Option<u32>,
+        
+        /// Minimum fee increase (in percent) required by the mempool
+        #[arg(long, default_value_t = MempoolConfig::default().replacement_fee_bump)]
+        bump_percent: u64,
+    },
+}
 
 /// Run blockchain tests
 fn run_tests(test_type: String, verbose: bool) -> Result<(), Box<dyn std::error::Error>> {
@@ -88,7 +133,37 @@ fn run_tests(test_type: String, verbose: bool) -> Result<(), Box<dyn std::error:
     Ok(())
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Your main function implementation here
+/// Build a replacement for a pending transaction that pays a higher fee
+///
+/// The replacement keeps the sender and nonce, so broadcasting it replaces
+/// the pending transaction in mempools that accept the fee increase.
+fn speed_up(tx_path: &Path, private_key: &str, fee: Option<u32>, bump_percent: u64) -> Result<(), Box<dyn std::error::Error>> {
+    let pending: Transaction = serde_json::from_str(&std::fs::read_to_string(tx_path)?)?;
+    
+    let minimum_fee = minimum_replacement_fee(pending.fee, bump_percent);
+    let fee = fee.unwrap_or(minimum_fee);
+    if fee < minimum_fee {
+        return Err(format!("Fee {} is below the minimum replacement fee {}", fee, minimum_fee).into());
+    }
+    
+    let keypair = KeyPair::from_seed(&hex::decode(private_key)?)?;
+    let mut replacement = pending.clone();
+    replacement.fee = fee;
+    replacement.priority_fee = pending.priority_fee.saturating_add(fee - pending.fee);
+    let chain_id = replacement.chain_id.clone();
+    replacement.sign(&keypair, &chain_id);
+    
+    println!("{} Replacing {} (fee {} -> {})",
+             "SEBURE".green(), hex::encode(&pending.id), pending.fee, fee);
+    println!("{}", serde_json::to_string_pretty(&replacement)?);
     Ok(())
 }
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    
+    match cli.command {
+        Commands::Test { test_type, verbose } => run_tests(test_type, verbose),
+        Commands::SpeedUp { tx, private_key, fee, bump_percent } => speed_up(&tx, &private_key, fee, bump_percent),
+    }
+}
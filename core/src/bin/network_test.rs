@@ -16,7 +16,7 @@ fn create_test_message() -> Message {
         None,
         Priority::Normal,
         vec![10, 11, 12], // Sample sender ID
-    )
+    ).expect("Failed to create test message")
 }
 
 fn main() {
@@ -3,7 +3,23 @@
 //! This module implements the block structure as defined in the PRD.
 
 use serde::{Serialize, Deserialize};
-use crate::types::{ShardId, Timestamp, Result};
+use crate::types::{ShardId, Timestamp, Result, Error, DEFAULT_CHAIN_ID};
+use crate::crypto::hash::{self, Hash, MerkleTree};
+use crate::crypto::signature::{self, KeyPair, Signature};
+use super::transaction::{Transaction, Receipt};
+use super::sharding::{CrossShardSettlement, ShardLayout};
+use super::parameters::BlockParameters;
+
+/// Root used for a Merkle tree without any leaves
+pub const EMPTY_ROOT: [u8; 32] = [0; 32];
+
+/// Compute the Merkle root of a list of leaves, or `EMPTY_ROOT` if there are none
+pub fn merkle_root(leaves: &[Hash]) -> Vec<u8> {
+    MerkleTree::new(leaves)
+        .root()
+        .unwrap_or(EMPTY_ROOT)
+        .to_vec()
+}
 
 /// Block header containing metadata and cryptographic links
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -11,6 +27,9 @@ pub struct BlockHeader {
     /// Block index/height in the chain
     pub index: u64,
     
+    /// Identifier of the chain the block belongs to
+    pub chain_id: String,
+    
     /// Block creation timestamp in microseconds
     pub timestamp: Timestamp,
     
@@ -32,10 +51,40 @@ pub struct BlockHeader {
     /// List of shard identifiers included in this block
     pub shard_identifiers: Vec<ShardId>,
     
+    /// Base fee charged per transaction on each shard, aligned with
+    /// `shard_identifiers`
+    pub base_fees: Vec<u64>,
+    
+    /// Block parameters for the epoch, set only in an epoch's first block
+    pub parameters: Option<BlockParameters>,
+    
+    /// Shard layout for the resharding epoch, set only in its first block
+    pub shard_layout: Option<ShardLayout>,
+    
     /// Aggregated BLS signature from validators
     pub aggregated_signature: Vec<u8>,
 }
 
+impl BlockHeader {
+    /// Compute the canonical hash of this header
+    pub fn hash(&self) -> Hash {
+        hash::hash_block_header(
+            self.index,
+            &self.chain_id,
+            self.timestamp,
+            &self.previous_hash,
+            &self.state_root,
+            &self.transaction_root,
+            &self.receipt_root,
+            &self.validator_merkle,
+            &self.shard_identifiers,
+            &self.base_fees,
+            &self.parameters.map(|parameters| parameters.encode()).unwrap_or_default(),
+            &self.shard_layout.as_ref().map(ShardLayout::encode).unwrap_or_default(),
+        )
+    }
+}
+
 /// ShardData represents transactions and validation proof for a specific shard
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ShardData {
@@ -52,8 +101,27 @@ pub struct ShardData {
     pub validator_signatures: Vec<Vec<u8>>,
 }
 
+impl ShardData {
+    /// Create shard data without any transactions
+    pub fn new(shard_id: ShardId) -> Self {
+        ShardData {
+            shard_id,
+            transactions: Vec::new(),
+            execution_proof: Vec::new(),
+            validator_signatures: Vec::new(),
+        }
+    }
+    
+    /// Size of the shard data in bytes when encoded
+    pub fn encoded_size(&self) -> Result<usize> {
+        bincode::serialized_size(self)
+            .map(|size| size as usize)
+            .map_err(|e| Error::Serialization(e.to_string()))
+    }
+}
+
 /// Receipt for cross-shard transactions
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct CrossShardReceipt {
     /// Transaction identifier
     pub transaction_id: Vec<u8>,
@@ -86,8 +154,20 @@ pub struct Block {
     /// Receipts for cross-shard transactions
     pub cross_shard_receipts: Vec<CrossShardReceipt>,
     
+    /// Cross-shard transfers from earlier blocks that this block credits to
+    /// their recipients or refunds to their senders
+    pub cross_shard_settlements: Vec<CrossShardSettlement>,
+    
     /// Set of validators that participated in this block
     pub validator_set: Vec<ValidatorRef>,
+    
+    /// Signatures of the block hash by the validators in `validator_set`,
+    /// in the same order; empty where a validator has not signed
+    pub validator_signatures: Vec<Vec<u8>>,
+    
+    /// Full bodies of the referenced transactions in block order;
+    /// empty for a compact block that only carries the IDs
+    pub transactions: Vec<Transaction>,
 }
 
 impl Block {
@@ -98,12 +178,14 @@ impl Block {
         previous_hash: Vec<u8>,
         shard_ids: Vec<ShardId>,
     ) -> Self {
-        // Create empty roots for now
-        let empty_root = vec![0; 32];
+        // Start with empty roots; they are filled in as content is added
+        let empty_root = EMPTY_ROOT.to_vec();
+        let base_fees = vec![0; shard_ids.len()];
         
         Block {
             header: BlockHeader {
                 index,
+                chain_id: DEFAULT_CHAIN_ID.to_string(),
                 timestamp,
                 previous_hash,
                 state_root: empty_root.clone(),
@@ -111,12 +193,41 @@ impl Block {
                 receipt_root: empty_root.clone(),
                 validator_merkle: empty_root.clone(),
                 shard_identifiers: shard_ids,
+                base_fees,
+                parameters: None,
+                shard_layout: None,
                 aggregated_signature: Vec::new(),
             },
             shard_data: Vec::new(),
             cross_shard_receipts: Vec::new(),
+            cross_shard_settlements: Vec::new(),
             validator_set: Vec::new(),
+            validator_signatures: Vec::new(),
+            transactions: Vec::new(),
+        }
+    }
+    
+    /// Get the block hash (the canonical hash of its header)
+    pub fn hash(&self) -> Hash {
+        self.header.hash()
+    }
+    
+    /// Size of the block in bytes when encoded
+    pub fn encoded_size(&self) -> Result<usize> {
+        bincode::serialized_size(self)
+            .map(|size| size as usize)
+            .map_err(|e| Error::Serialization(e.to_string()))
+    }
+    
+    /// Size of the block once it carries the bodies of the given transactions
+    pub fn size_with_transactions(&self, transactions: &[Transaction]) -> Result<usize> {
+        let mut size = self.encoded_size()?;
+        if !self.has_bodies() {
+            for tx in transactions {
+                size += tx.encoded_size()?;
+            }
         }
+        Ok(size)
     }
     
     /// Validate basic block properties
@@ -135,7 +246,7 @@ impl Block {
     pub fn add_shard_data(&mut self, shard_data: ShardData) -> Result<()> {
         // Check if shard ID is in the block's shard identifiers
         if !self.header.shard_identifiers.contains(&shard_data.shard_id) {
-            return Err(crate::types::Error::BlockValidation(
+            return Err(Error::BlockValidation(
                 format!("Shard ID {} not declared in block header", shard_data.shard_id)
             ));
         }
@@ -144,10 +255,209 @@ impl Block {
         Ok(())
     }
     
+    /// Add full transactions for a shard, recording both the IDs and the bodies
+    pub fn add_transactions(&mut self, shard_id: ShardId, transactions: Vec<Transaction>) -> Result<()> {
+        let mut shard_data = ShardData::new(shard_id);
+        shard_data.transactions = transactions.iter().map(|tx| tx.id.clone()).collect();
+        
+        self.add_shard_data(shard_data)?;
+        self.transactions.extend(transactions);
+        Ok(())
+    }
+    
+    /// Check if the block carries its transaction bodies
+    pub fn has_bodies(&self) -> bool {
+        !self.transactions.is_empty()
+    }
+    
+    /// Copy of the block without transaction bodies
+    pub fn to_compact(&self) -> Block {
+        let mut compact = self.clone();
+        compact.transactions.clear();
+        compact
+    }
+    
+    /// Check that the carried bodies match the transaction IDs, in order
+    pub fn verify_bodies(&self) -> Result<()> {
+        if !self.has_bodies() {
+            return Ok(());
+        }
+        
+        let ids = self.transaction_ids();
+        if ids.len() != self.transactions.len() {
+            return Err(Error::BlockValidation(format!(
+                "Block carries {} transaction bodies for {} transaction IDs",
+                self.transactions.len(), ids.len()
+            )));
+        }
+        
+        for (id, tx) in ids.into_iter().zip(&self.transactions) {
+            if *id != tx.id {
+                return Err(Error::BlockValidation(format!(
+                    "Transaction body {} does not match ID {}", hex::encode(&tx.id), hex::encode(id)
+                )));
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// Base fee charged per transaction on a shard, or 0 if the block does
+    /// not include the shard
+    pub fn base_fee(&self, shard_id: ShardId) -> u64 {
+        self.header.shard_identifiers.iter()
+            .position(|&id| id == shard_id)
+            .and_then(|index| self.header.base_fees.get(index).copied())
+            .unwrap_or(0)
+    }
+    
+    /// Number of transactions the block includes on a shard
+    pub fn shard_transaction_count(&self, shard_id: ShardId) -> usize {
+        self.shard_data.iter()
+            .filter(|shard| shard.shard_id == shard_id)
+            .map(|shard| shard.transactions.len())
+            .sum()
+    }
+    
     /// Add a validator to the block's validator set
     pub fn add_validator(&mut self, validator: ValidatorRef) {
         self.validator_set.push(validator);
     }
+    
+    /// Sign the block hash as one of the validators in its validator set
+    ///
+    /// The header must be complete, since the signature covers its hash.
+    pub fn sign_as_validator(&mut self, keypair: &KeyPair) -> Result<()> {
+        let public_key = keypair.public_key();
+        let index = self.validator_set.iter()
+            .position(|validator| *validator == public_key)
+            .ok_or_else(|| Error::BlockValidation(format!(
+                "Validator {} is not in the block's validator set", hex::encode(&public_key)
+            )))?;
+        
+        let signature = keypair.sign(&self.hash());
+        if self.validator_signatures.len() < self.validator_set.len() {
+            self.validator_signatures.resize(self.validator_set.len(), Vec::new());
+        }
+        self.validator_signatures[index] = signature.0;
+        Ok(())
+    }
+    
+    /// Validators of the validator set whose signature of the block hash
+    /// verifies, each counted once
+    pub fn signed_validators(&self) -> Vec<&ValidatorRef> {
+        let block_hash = self.hash();
+        let mut signed: Vec<&ValidatorRef> = Vec::new();
+        for (validator, sig) in self.validator_set.iter().zip(self.validator_signatures.iter()) {
+            if signed.contains(&validator) {
+                continue;
+            }
+            if signature::verify(validator, &block_hash, &Signature::new(sig.clone())).is_ok() {
+                signed.push(validator);
+            }
+        }
+        signed
+    }
+    
+    /// Transaction IDs in block order (shard by shard)
+    pub fn transaction_ids(&self) -> Vec<&Vec<u8>> {
+        self.shard_data.iter()
+            .flat_map(|shard| shard.transactions.iter())
+            .collect()
+    }
+    
+    /// Merkle leaves for the block's transactions
+    fn transaction_leaves(&self) -> Vec<Hash> {
+        self.transaction_ids().into_iter()
+            .map(|tx_id| hash::sha256(tx_id))
+            .collect()
+    }
+    
+    /// Compute the Merkle root of the block's transactions
+    pub fn compute_transaction_root(&self) -> Vec<u8> {
+        merkle_root(&self.transaction_leaves())
+    }
+    
+    /// Compute the Merkle root of the block's validator set
+    pub fn compute_validator_merkle(&self) -> Vec<u8> {
+        let leaves: Vec<Hash> = self.validator_set.iter()
+            .map(|validator| hash::sha256(validator))
+            .collect();
+        merkle_root(&leaves)
+    }
+    
+    /// Merkle leaf of a transaction receipt
+    pub fn receipt_leaf(receipt: &Receipt) -> Result<Hash> {
+        let bytes = bincode::serialize(receipt)
+            .map_err(|e| Error::Serialization(e.to_string()))?;
+        Ok(hash::sha256(&bytes))
+    }
+    
+    /// Compute the Merkle root of a list of transaction receipts
+    pub fn compute_receipt_root(receipts: &[Receipt]) -> Result<Vec<u8>> {
+        let leaves = receipts.iter().map(Self::receipt_leaf).collect::<Result<Vec<Hash>>>()?;
+        Ok(merkle_root(&leaves))
+    }
+    
+    /// Generate a Merkle inclusion proof for the receipt at `index`
+    ///
+    /// The proof can be checked against `header.receipt_root` with
+    /// `MerkleTree::verify_proof` using `receipt_leaf` as the leaf.
+    pub fn receipt_proof(receipts: &[Receipt], index: usize) -> Result<Vec<Hash>> {
+        let leaves = receipts.iter().map(Self::receipt_leaf).collect::<Result<Vec<Hash>>>()?;
+        Ok(MerkleTree::new(&leaves).generate_proof(index))
+    }
+    
+    /// Fill the transaction and validator roots from the block contents
+    pub fn update_merkle_roots(&mut self) {
+        self.header.transaction_root = self.compute_transaction_root();
+        self.header.validator_merkle = self.compute_validator_merkle();
+    }
+    
+    /// Set the receipt root from the receipts produced by executing this block
+    pub fn set_receipts(&mut self, receipts: &[Receipt]) -> Result<()> {
+        self.header.receipt_root = Self::compute_receipt_root(receipts)?;
+        Ok(())
+    }
+    
+    /// Check the transaction and validator roots against the block contents
+    pub fn verify_merkle_roots(&self) -> Result<()> {
+        if self.header.transaction_root != self.compute_transaction_root() {
+            return Err(Error::BlockValidation(
+                "Transaction root does not match block transactions".to_string()
+            ));
+        }
+        
+        if self.header.validator_merkle != self.compute_validator_merkle() {
+            return Err(Error::BlockValidation(
+                "Validator merkle root does not match block validator set".to_string()
+            ));
+        }
+        
+        Ok(())
+    }
+    
+    /// Check the receipt root against the receipts produced by executing this block
+    pub fn verify_receipt_root(&self, receipts: &[Receipt]) -> Result<()> {
+        if self.header.receipt_root != Self::compute_receipt_root(receipts)? {
+            return Err(Error::BlockValidation(
+                "Receipt root does not match execution receipts".to_string()
+            ));
+        }
+        
+        Ok(())
+    }
+    
+    /// Generate a Merkle inclusion proof for a transaction in this block
+    ///
+    /// Returns the leaf index and the sibling hashes, which can be checked
+    /// against `header.transaction_root` with `MerkleTree::verify_proof`
+    /// using `sha256(tx_id)` as the leaf.
+    pub fn transaction_proof(&self, tx_id: &[u8]) -> Option<(usize, Vec<Hash>)> {
+        let index = self.transaction_ids().iter().position(|id| id.as_slice() == tx_id)?;
+        let tree = MerkleTree::new(&self.transaction_leaves());
+        Some((index, tree.generate_proof(index)))
+    }
 }
 
 #[cfg(test)]
@@ -204,4 +514,201 @@ mod tests {
         
         assert!(block.add_shard_data(invalid_shard_data).is_err());
     }
+    
+    #[test]
+    fn test_block_hash_deterministic() {
+        let block = Block::new(1, 1_000_000, vec![7; 32], vec![0, 1]);
+        
+        assert_eq!(block.hash(), block.clone().hash());
+        assert_eq!(block.hash(), block.header.hash());
+        
+        // The aggregated signature is not part of the hash
+        let mut signed = block.clone();
+        signed.header.aggregated_signature = vec![1; 64];
+        assert_eq!(signed.hash(), block.hash());
+    }
+    
+    #[test]
+    fn test_validator_signatures() {
+        let validator = KeyPair::from_seed(&[1; 32]).unwrap();
+        let outsider = KeyPair::from_seed(&[2; 32]).unwrap();
+        let mut block = Block::new(1, 1_000_000, vec![7; 32], vec![0]);
+        block.add_validator(validator.public_key());
+        block.add_validator(outsider.public_key());
+        block.add_validator(validator.public_key());
+        block.update_merkle_roots();
+        
+        // Only validators in the set can sign, and only signers count, once
+        assert!(block.signed_validators().is_empty());
+        assert!(block.sign_as_validator(&KeyPair::from_seed(&[3; 32]).unwrap()).is_err());
+        block.sign_as_validator(&validator).unwrap();
+        block.validator_signatures[2] = block.validator_signatures[0].clone();
+        assert_eq!(block.signed_validators(), vec![&validator.public_key()]);
+        
+        // A signature of another block does not count
+        block.validator_signatures[1] = outsider.sign(&[0; 32]).0;
+        assert_eq!(block.signed_validators().len(), 1);
+        
+        // Changing the header invalidates the signatures
+        block.header.timestamp += 1;
+        assert!(block.signed_validators().is_empty());
+    }
+    
+    #[test]
+    fn test_block_hash_covers_header_fields() {
+        let mut block = Block::new(1, 1_000_000, vec![7; 32], vec![0, 1]);
+        block.header.parameters = Some(BlockParameters { max_block_size: 1024, block_interval_ms: 2000 });
+        block.header.shard_layout = Some(ShardLayout::uniform(2));
+        let original = block.hash();
+        
+        let tampered: Vec<Box<dyn Fn(&mut BlockHeader)>> = vec![
+            Box::new(|h| h.index += 1),
+            Box::new(|h| h.chain_id = "sebure-devnet".to_string()),
+            Box::new(|h| h.timestamp += 1),
+            Box::new(|h| h.previous_hash[0] ^= 1),
+            Box::new(|h| h.state_root[0] ^= 1),
+            Box::new(|h| h.transaction_root[0] ^= 1),
+            Box::new(|h| h.receipt_root[0] ^= 1),
+            Box::new(|h| h.validator_merkle[0] ^= 1),
+            Box::new(|h| h.shard_identifiers.push(2)),
+            Box::new(|h| h.shard_identifiers = vec![1, 0]),
+            Box::new(|h| h.base_fees[1] += 1),
+            Box::new(|h| h.parameters = None),
+            Box::new(|h| h.parameters.as_mut().unwrap().max_block_size += 1),
+            Box::new(|h| h.parameters.as_mut().unwrap().block_interval_ms += 1),
+            Box::new(|h| h.shard_layout = None),
+            Box::new(|h| h.shard_layout.as_mut().unwrap().epoch += 1),
+            Box::new(|h| h.shard_layout = Some(ShardLayout::uniform(3))),
+        ];
+        
+        for tamper in tampered {
+            let mut modified = block.clone();
+            tamper(&mut modified.header);
+            assert_ne!(modified.hash(), original);
+        }
+    }
+    
+    fn block_with_transactions(tx_count: u8) -> Block {
+        let mut block = Block::new(1, current_time_micros(), vec![0; 32], vec![0, 1]);
+        block.add_shard_data(ShardData {
+            shard_id: 0,
+            transactions: (0..tx_count).map(|i| vec![i; 32]).collect(),
+            execution_proof: Vec::new(),
+            validator_signatures: Vec::new(),
+        }).unwrap();
+        block.add_validator(vec![9; 32]);
+        block.add_validator(vec![8; 32]);
+        block.update_merkle_roots();
+        block
+    }
+    
+    #[test]
+    fn test_empty_block_roots() {
+        let block = Block::new(1, current_time_micros(), vec![0; 32], vec![0]);
+        
+        assert_eq!(block.compute_transaction_root(), EMPTY_ROOT.to_vec());
+        assert_eq!(block.compute_validator_merkle(), EMPTY_ROOT.to_vec());
+        assert!(block.verify_merkle_roots().is_ok());
+    }
+    
+    #[test]
+    fn test_merkle_roots_verification() {
+        let block = block_with_transactions(3);
+        assert_ne!(block.header.transaction_root, EMPTY_ROOT.to_vec());
+        assert_ne!(block.header.validator_merkle, EMPTY_ROOT.to_vec());
+        assert!(block.verify_merkle_roots().is_ok());
+        
+        // Swapping transaction order changes the root
+        let mut reordered = block.clone();
+        reordered.shard_data[0].transactions.swap(0, 1);
+        assert!(reordered.verify_merkle_roots().is_err());
+        
+        // Adding a validator without updating the root is rejected
+        let mut extra_validator = block.clone();
+        extra_validator.add_validator(vec![7; 32]);
+        assert!(extra_validator.verify_merkle_roots().is_err());
+        
+        // Tampered header roots are rejected
+        let mut tampered = block.clone();
+        tampered.header.transaction_root = EMPTY_ROOT.to_vec();
+        assert!(tampered.verify_merkle_roots().is_err());
+    }
+    
+    #[test]
+    fn test_receipt_root() {
+        let mut block = block_with_transactions(2);
+        let receipts: Vec<Receipt> = block.transaction_ids().into_iter()
+            .map(|tx_id| Receipt {
+                transaction_id: tx_id.clone(),
+                status: 0,
+                gas_used: 21000,
+                state_root: vec![0; 32],
+                logs: Vec::new(),
+            })
+            .collect();
+        
+        block.set_receipts(&receipts).unwrap();
+        assert!(block.verify_receipt_root(&receipts).is_ok());
+        assert!(block.verify_receipt_root(&receipts[..1]).is_err());
+    }
+    
+    #[test]
+    fn test_transaction_proofs() {
+        for tx_count in 1..=5 {
+            let block = block_with_transactions(tx_count);
+            let mut root = [0u8; 32];
+            root.copy_from_slice(&block.header.transaction_root);
+            
+            for i in 0..tx_count {
+                let tx_id = vec![i; 32];
+                let (index, proof) = block.transaction_proof(&tx_id).unwrap();
+                let leaf = hash::sha256(&tx_id);
+                assert!(MerkleTree::verify_proof(&root, &leaf, &proof, index));
+                
+                // The proof does not verify a different transaction
+                let other = hash::sha256(&[99; 32]);
+                assert!(!MerkleTree::verify_proof(&root, &other, &proof, index));
+            }
+        }
+        
+        assert!(block_with_transactions(2).transaction_proof(&[99; 32]).is_none());
+    }
+    
+    #[test]
+    fn test_block_bodies() {
+        let mut block = Block::new(1, current_time_micros(), vec![0; 32], vec![0, 1]);
+        let txs: Vec<Transaction> = (1..=3u8)
+            .map(|i| {
+                let mut tx = Transaction::new_transfer(vec![i; 32], 0, vec![2; 20], 0, 100, 10, 0);
+                tx.id = vec![i; 32];
+                tx
+            })
+            .collect();
+        
+        block.add_transactions(0, txs[..2].to_vec()).unwrap();
+        block.add_transactions(1, txs[2..].to_vec()).unwrap();
+        assert!(block.add_transactions(2, Vec::new()).is_err());
+        assert_eq!(block.transaction_ids(), vec![&txs[0].id, &txs[1].id, &txs[2].id]);
+        assert!(block.verify_bodies().is_ok());
+        
+        // The compact form keeps the IDs and the hash but drops the bodies
+        let compact = block.to_compact();
+        assert!(!compact.has_bodies());
+        assert_eq!(compact.hash(), block.hash());
+        assert!(compact.verify_bodies().is_ok());
+        assert!(compact.encoded_size().unwrap() < block.encoded_size().unwrap());
+        assert_eq!(
+            compact.size_with_transactions(&txs).unwrap(),
+            block.size_with_transactions(&txs).unwrap()
+        );
+        
+        // Bodies must match the IDs one for one
+        let mut swapped = block.clone();
+        swapped.transactions.swap(0, 1);
+        assert!(swapped.verify_bodies().is_err());
+        
+        let mut missing = block.clone();
+        missing.transactions.pop();
+        assert!(missing.verify_bodies().is_err());
+    }
 }
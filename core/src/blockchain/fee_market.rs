@@ -0,0 +1,260 @@
+//! # Fee Market
+//!
+//! This module prices block space with a base fee per shard. Each block
+//! commits to the base fee of every shard it includes, derived from how
+//! many transactions the parent block included on that shard: above the
+//! target the base fee rises, below it the base fee falls, by at most
+//! `1 / change_denominator` per block.
+//!
+//! A shard that a block leaves out keeps the base fee and usage of the last
+//! block that included it, which the next block including it adjusts.
+//!
+//! A transaction names the most it will pay in total and the most it will
+//! tip the block producer. It pays the base fee plus as much of the tip as
+//! fits under its maximum. Part of the base fee is burned and the rest goes
+//! to the producer along with the tip.
+
+use std::collections::BTreeMap;
+
+use crate::blockchain::{Block, Transaction};
+use crate::types::{Result, Error, ShardId};
+
+/// Percentage of the base fee that is burned rather than paid to the
+/// block producer
+pub const BASE_FEE_BURN_PERCENT: u64 = 50;
+
+/// Parameters of the base fee adjustment, set by governance
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeeMarketConfig {
+    /// Base fee of a shard without a parent base fee to adjust
+    pub initial_base_fee: u64,
+    
+    /// Lowest base fee
+    pub min_base_fee: u64,
+    
+    /// Transactions per shard and block at which the base fee holds steady
+    pub target_transactions: u64,
+    
+    /// Each block changes the base fee by at most its value divided by this
+    pub change_denominator: u64,
+}
+
+impl Default for FeeMarketConfig {
+    fn default() -> Self {
+        FeeMarketConfig {
+            initial_base_fee: 0,
+            min_base_fee: 0,
+            target_transactions: 500,
+            change_denominator: 8,
+        }
+    }
+}
+
+impl FeeMarketConfig {
+    /// Base fee following a block that charged `parent_base_fee` and
+    /// included `parent_transactions` on the shard
+    ///
+    /// The change is proportional to the distance from the target. A
+    /// congested shard raises the base fee by at least 1, so that it can
+    /// leave 0.
+    pub fn next_base_fee(&self, parent_base_fee: u64, parent_transactions: u64) -> u64 {
+        let target = self.target_transactions.max(1);
+        let step = |distance: u64| {
+            let step = parent_base_fee as u128 * distance as u128
+                / target as u128
+                / self.change_denominator.max(1) as u128;
+            u64::try_from(step).unwrap_or(u64::MAX)
+        };
+        
+        let next = if parent_transactions > target {
+            parent_base_fee.saturating_add(step(parent_transactions - target).max(1))
+        } else {
+            parent_base_fee - step(target - parent_transactions)
+        };
+        next.max(self.min_base_fee)
+    }
+    
+    /// Base fee a block charges on a shard, given the usage as of its
+    /// parent
+    ///
+    /// A shard that no block since genesis included starts at the initial
+    /// base fee.
+    pub fn base_fee_after(&self, usage: &ShardUsage, shard_id: ShardId) -> u64 {
+        match usage.last(shard_id) {
+            Some((base_fee, transactions)) => self.next_base_fee(base_fee, transactions),
+            None => self.initial_base_fee.max(self.min_base_fee),
+        }
+    }
+    
+    /// Base fees a block commits to, given the usage as of its parent,
+    /// aligned with `shard_ids`
+    pub fn base_fees_after(&self, usage: &ShardUsage, shard_ids: &[ShardId]) -> Vec<u64> {
+        shard_ids.iter()
+            .map(|&shard_id| self.base_fee_after(usage, shard_id))
+            .collect()
+    }
+}
+
+/// Base fee and transaction count of each shard in the last block that
+/// included it, as of a block
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShardUsage {
+    /// Base fee and transaction count by shard
+    shards: BTreeMap<ShardId, (u64, u64)>,
+}
+
+impl ShardUsage {
+    /// Usage as of `block`, given the usage as of its parent
+    ///
+    /// Nothing carries over from the genesis block.
+    pub fn after(&self, block: &Block) -> Self {
+        let mut usage = self.clone();
+        if block.header.index > 0 {
+            for &shard_id in &block.header.shard_identifiers {
+                let transactions = block.shard_transaction_count(shard_id) as u64;
+                usage.shards.insert(shard_id, (block.base_fee(shard_id), transactions));
+            }
+        }
+        usage
+    }
+    
+    /// Base fee and transaction count of the shard in the last block that
+    /// included it
+    pub fn last(&self, shard_id: ShardId) -> Option<(u64, u64)> {
+        self.shards.get(&shard_id).copied()
+    }
+}
+
+/// Fee a transaction pays in a block, split by where it goes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FeeCharge {
+    /// Base fee of the transaction's shard
+    pub base_fee: u64,
+    
+    /// Tip paid to the block producer
+    pub tip: u64,
+    
+    /// Part of the base fee that is burned
+    pub burned: u64,
+}
+
+impl FeeCharge {
+    /// Charge for a transaction in a block with the given base fee on its
+    /// shard
+    ///
+    /// Fails if the transaction's maximum fee does not cover the base fee.
+    pub fn new(tx: &Transaction, base_fee: u64) -> Result<Self> {
+        let max_fee = tx.fee as u64;
+        if max_fee < base_fee {
+            return Err(Error::TransactionValidation(format!(
+                "Maximum fee {} is below the base fee {}", max_fee, base_fee
+            )));
+        }
+        
+        Ok(FeeCharge {
+            base_fee,
+            tip: (tx.priority_fee as u64).min(max_fee - base_fee),
+            burned: base_fee * BASE_FEE_BURN_PERCENT / 100,
+        })
+    }
+    
+    /// Total paid by the sender
+    pub fn total(&self) -> u64 {
+        self.base_fee + self.tip
+    }
+    
+    /// Part paid to the block producer
+    pub fn producer_share(&self) -> u64 {
+        self.total() - self.burned
+    }
+}
+
+/// Maximum fee to sign for a transaction tipping `priority_fee`, given the
+/// current base fee of its shard
+///
+/// Twice the base fee leaves room for about six consecutive blocks at twice
+/// the target under the default adjustment rate.
+pub fn suggested_max_fee(base_fee: u64, priority_fee: u32) -> u32 {
+    let max_fee = base_fee.saturating_mul(2).saturating_add(priority_fee as u64);
+    u32::try_from(max_fee).unwrap_or(u32::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::ShardData;
+    
+    fn config() -> FeeMarketConfig {
+        FeeMarketConfig {
+            initial_base_fee: 100,
+            min_base_fee: 10,
+            target_transactions: 4,
+            change_denominator: 8,
+        }
+    }
+    
+    fn transaction(fee: u32, priority_fee: u32) -> Transaction {
+        let mut tx = Transaction::new_transfer(vec![1; 32], 0, vec![2; 20], 0, 1000, fee, 0);
+        tx.priority_fee = priority_fee;
+        tx
+    }
+    
+    #[test]
+    fn test_base_fee_follows_usage() {
+        let config = config();
+        
+        // On target the base fee holds, at twice the target it rises by an
+        // eighth, and an empty block lowers it by an eighth
+        assert_eq!(config.next_base_fee(800, 4), 800);
+        assert_eq!(config.next_base_fee(800, 8), 900);
+        assert_eq!(config.next_base_fee(800, 6), 850);
+        assert_eq!(config.next_base_fee(800, 0), 700);
+        
+        // The minimum holds, and congestion lifts even a zero base fee
+        assert_eq!(config.next_base_fee(10, 0), 10);
+        let free = FeeMarketConfig { min_base_fee: 0, ..config };
+        assert_eq!(free.next_base_fee(0, 5), 1);
+        assert_eq!(free.next_base_fee(0, 4), 0);
+    }
+    
+    #[test]
+    fn test_base_fee_after_parent() {
+        let config = config();
+        let mut parent = Block::new(5, 1_000_000, vec![0; 32], vec![0, 1]);
+        parent.header.base_fees = vec![800, 800];
+        parent.add_shard_data(ShardData {
+            shard_id: 0,
+            transactions: (0..8).map(|i| vec![i; 32]).collect(),
+            execution_proof: Vec::new(),
+            validator_signatures: Vec::new(),
+        }).unwrap();
+        
+        let usage = ShardUsage::default().after(&parent);
+        assert_eq!(config.base_fees_after(&usage, &[0, 1, 2]), vec![900, 700, 100]);
+        
+        // A block leaving out shard 0 does not reset it
+        let mut child = Block::new(6, 1_000_001, parent.hash().to_vec(), vec![1]);
+        child.header.base_fees = vec![700];
+        let usage = usage.after(&child);
+        assert_eq!(config.base_fees_after(&usage, &[0, 1]), vec![900, 613]);
+        
+        // Nothing carries over from genesis
+        parent.header.index = 0;
+        assert_eq!(config.base_fees_after(&ShardUsage::default().after(&parent), &[0, 1]), vec![100, 100]);
+    }
+    
+    #[test]
+    fn test_fee_charge() {
+        // The tip is capped by what the maximum fee leaves over the base fee
+        let charge = FeeCharge::new(&transaction(150, 30), 100).unwrap();
+        assert_eq!((charge.tip, charge.total(), charge.burned, charge.producer_share()), (30, 130, 50, 80));
+        
+        let charge = FeeCharge::new(&transaction(110, 30), 100).unwrap();
+        assert_eq!((charge.tip, charge.total()), (10, 110));
+        
+        assert!(FeeCharge::new(&transaction(99, 30), 100).is_err());
+        
+        assert_eq!(suggested_max_fee(100, 30), 230);
+        assert_eq!(suggested_max_fee(u64::MAX, 30), u32::MAX);
+    }
+}
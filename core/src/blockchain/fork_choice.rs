@@ -0,0 +1,438 @@
+//! # Fork Choice
+//!
+//! This module keeps every known block in a tree so that competing branches
+//! are not discarded, and selects the canonical head among them.
+//!
+//! The fork-choice rule is DPoS-aware: a branch that filled more production
+//! slots is preferred, then the branch with more validator participation,
+//! and finally the lowest tip hash breaks ties deterministically. Only
+//! verified signatures from the active validator set count as participation.
+//! Branches that do not descend from the finalized block are never selected,
+//! and the tree is re-rooted at the finalized block as finality advances.
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+use crate::blockchain::{Block, ValidatorRef};
+use crate::types::{Result, Error, BlockHeight};
+
+/// Weight of a branch under the fork-choice rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct ChainWeight {
+    /// Number of produced slots (block height)
+    pub height: BlockHeight,
+    
+    /// Cumulative number of active validators that signed the branch's blocks
+    pub participation: u64,
+}
+
+/// A block held in the block tree
+#[derive(Debug, Clone)]
+pub struct TreeEntry {
+    /// The block itself
+    pub block: Block,
+    
+    /// Hash of the block
+    pub hash: Vec<u8>,
+    
+    /// Weight of the branch ending at this block
+    pub weight: ChainWeight,
+}
+
+/// Notification sent to subscribers when the canonical head switches branch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReorgEvent {
+    /// Head before the reorganization
+    pub old_head: Vec<u8>,
+    
+    /// Head after the reorganization
+    pub new_head: Vec<u8>,
+    
+    /// Last block shared by the old and new branches
+    pub common_ancestor: Vec<u8>,
+    
+    /// Blocks removed from the canonical chain, newest first
+    pub reverted: Vec<Vec<u8>>,
+    
+    /// Blocks added to the canonical chain, oldest first
+    pub applied: Vec<Vec<u8>>,
+    
+    /// Transactions from reverted blocks that the new branch does not include
+    pub orphaned_transactions: Vec<Vec<u8>>,
+}
+
+/// Tree of known blocks rooted at the finalized block
+pub struct BlockTree {
+    /// Blocks indexed by hash
+    entries: HashMap<Vec<u8>, TreeEntry>,
+    
+    /// Child hashes indexed by parent hash
+    children: HashMap<Vec<u8>, Vec<Vec<u8>>>,
+    
+    /// Hash of the root block
+    root: Vec<u8>,
+    
+    /// Validators whose signatures count towards participation
+    validators: HashSet<ValidatorRef>,
+}
+
+impl BlockTree {
+    /// Create a tree rooted at the given genesis block
+    pub fn new(genesis: Block) -> Self {
+        let hash = genesis.hash().to_vec();
+        let weight = ChainWeight {
+            height: genesis.header.index,
+            participation: 0,
+        };
+        
+        let mut entries = HashMap::new();
+        entries.insert(hash.clone(), TreeEntry {
+            block: genesis,
+            hash: hash.clone(),
+            weight,
+        });
+        
+        BlockTree {
+            entries,
+            children: HashMap::new(),
+            root: hash,
+            validators: HashSet::new(),
+        }
+    }
+    
+    /// Set the active validators whose signatures count towards the weight
+    /// of blocks inserted from now on
+    pub fn set_validators<I>(&mut self, validators: I)
+    where
+        I: IntoIterator<Item = ValidatorRef>,
+    {
+        self.validators = validators.into_iter().collect();
+    }
+    
+    /// Number of active validators that signed a block
+    fn participation(&self, block: &Block) -> u64 {
+        block.signed_validators().into_iter()
+            .filter(|validator| self.validators.contains(*validator))
+            .count() as u64
+    }
+    
+    /// Hash of the root block
+    pub fn root(&self) -> &Vec<u8> {
+        &self.root
+    }
+    
+    /// Number of blocks in the tree
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    
+    /// Check if the tree contains a block
+    pub fn contains(&self, hash: &[u8]) -> bool {
+        self.entries.contains_key(hash)
+    }
+    
+    /// Get a block entry by hash
+    pub fn get(&self, hash: &[u8]) -> Option<&TreeEntry> {
+        self.entries.get(hash)
+    }
+    
+    /// Insert a block whose parent is already in the tree
+    ///
+    /// The block should carry its transaction bodies so it can be replayed.
+    pub fn insert(&mut self, block: Block) -> Result<Vec<u8>> {
+        let hash = block.hash().to_vec();
+        if self.entries.contains_key(&hash) {
+            return Err(Error::BlockValidation("Block already known".to_string()));
+        }
+        
+        let parent = self.entries.get(&block.header.previous_hash)
+            .ok_or_else(|| Error::BlockValidation(format!(
+                "Unknown parent block {}", hex::encode(&block.header.previous_hash)
+            )))?;
+        
+        let weight = ChainWeight {
+            height: block.header.index,
+            participation: parent.weight.participation + self.participation(&block),
+        };
+        
+        self.children.entry(block.header.previous_hash.clone())
+            .or_insert_with(Vec::new)
+            .push(hash.clone());
+        self.entries.insert(hash.clone(), TreeEntry {
+            block,
+            hash: hash.clone(),
+            weight,
+        });
+        
+        Ok(hash)
+    }
+    
+    /// Remove a block and all of its descendants, returning the removed hashes
+    pub fn remove_subtree(&mut self, hash: &[u8]) -> Vec<Vec<u8>> {
+        let mut removed = Vec::new();
+        let mut stack = vec![hash.to_vec()];
+        
+        while let Some(current) = stack.pop() {
+            if let Some(entry) = self.entries.remove(&current) {
+                if let Some(siblings) = self.children.get_mut(&entry.block.header.previous_hash) {
+                    siblings.retain(|child| child != &current);
+                }
+                if let Some(children) = self.children.remove(&current) {
+                    stack.extend(children);
+                }
+                removed.push(current);
+            }
+        }
+        
+        removed
+    }
+    
+    /// Hashes from the root to the given block, inclusive
+    pub fn path_to(&self, hash: &[u8]) -> Vec<Vec<u8>> {
+        let mut path = Vec::new();
+        let mut current = self.entries.get(hash);
+        
+        while let Some(entry) = current {
+            path.push(entry.hash.clone());
+            if entry.hash == self.root {
+                break;
+            }
+            current = self.entries.get(&entry.block.header.previous_hash);
+        }
+        
+        path.reverse();
+        path
+    }
+    
+    /// Check if `descendant` is `ancestor` or one of its descendants
+    pub fn is_descendant(&self, descendant: &[u8], ancestor: &[u8]) -> bool {
+        let ancestor_height = match self.entries.get(ancestor) {
+            Some(entry) => entry.block.header.index,
+            None => return false,
+        };
+        
+        let mut current = self.entries.get(descendant);
+        while let Some(entry) = current {
+            if entry.hash == ancestor {
+                return true;
+            }
+            if entry.block.header.index <= ancestor_height {
+                return false;
+            }
+            current = self.entries.get(&entry.block.header.previous_hash);
+        }
+        
+        false
+    }
+    
+    /// Find the most recent block shared by the branches ending at `a` and `b`
+    pub fn common_ancestor(&self, a: &[u8], b: &[u8]) -> Option<Vec<u8>> {
+        let path_a = self.path_to(a);
+        let path_b = self.path_to(b);
+        
+        path_a.iter()
+            .zip(path_b.iter())
+            .take_while(|(x, y)| x == y)
+            .last()
+            .map(|(x, _)| x.clone())
+    }
+    
+    /// Blocks without children
+    pub fn tips(&self) -> Vec<&TreeEntry> {
+        self.entries.values()
+            .filter(|entry| self.children.get(&entry.hash).map_or(true, |c| c.is_empty()))
+            .collect()
+    }
+    
+    /// Compare two entries under the fork-choice rule
+    fn compare(a: &TreeEntry, b: &TreeEntry) -> Ordering {
+        a.weight.cmp(&b.weight)
+            // Lower hash wins ties, so reverse the hash ordering
+            .then_with(|| b.hash.cmp(&a.hash))
+    }
+    
+    /// Select the best tip among the branches that descend from `finalized`
+    pub fn best_tip(&self, finalized: &[u8]) -> Option<Vec<u8>> {
+        self.tips()
+            .into_iter()
+            .filter(|entry| self.is_descendant(&entry.hash, finalized))
+            .max_by(|a, b| Self::compare(a, b))
+            .map(|entry| entry.hash.clone())
+    }
+    
+    /// Drop every branch that does not contain the finalized block and
+    /// re-root the tree at it
+    ///
+    /// Returns the hashes of the dropped side branches. The blocks from the
+    /// old root up to the finalized block leave the tree as well; they stay
+    /// available from the canonical chain.
+    pub fn prune(&mut self, finalized: &[u8]) -> Vec<Vec<u8>> {
+        let keep_path = self.path_to(finalized);
+        if keep_path.first() != Some(&self.root) {
+            return Vec::new();
+        }
+        let mut removed = Vec::new();
+        
+        // Siblings of each block on the path to the finalized block are dead branches
+        for window in keep_path.windows(2) {
+            let (parent, kept) = (&window[0], &window[1]);
+            let dead: Vec<Vec<u8>> = self.children.get(parent)
+                .map(|children| children.iter().filter(|c| *c != kept).cloned().collect())
+                .unwrap_or_default();
+            for hash in dead {
+                removed.extend(self.remove_subtree(&hash));
+            }
+        }
+        
+        // Drop the finalized prefix so walks stop at the finalized block
+        for hash in &keep_path[..keep_path.len() - 1] {
+            self.entries.remove(hash);
+            self.children.remove(hash);
+        }
+        self.root = finalized.to_vec();
+        
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::signature::KeyPair;
+    
+    fn validator(i: usize) -> KeyPair {
+        KeyPair::from_seed(&[i as u8 + 1; 32]).unwrap()
+    }
+    
+    fn child_of(parent: &Block, timestamp: u64, validators: usize) -> Block {
+        let mut block = Block::new(
+            parent.header.index + 1,
+            timestamp,
+            parent.hash().to_vec(),
+            vec![0],
+        );
+        for i in 0..validators {
+            block.add_validator(validator(i).public_key());
+        }
+        block.update_merkle_roots();
+        for i in 0..validators {
+            block.sign_as_validator(&validator(i)).unwrap();
+        }
+        block
+    }
+    
+    fn genesis() -> Block {
+        Block::new(0, 1_000, vec![0; 32], vec![0])
+    }
+    
+    fn tree_with_validators(genesis: Block, validators: usize) -> BlockTree {
+        let mut tree = BlockTree::new(genesis);
+        tree.set_validators((0..validators).map(|i| validator(i).public_key()));
+        tree
+    }
+    
+    #[test]
+    fn test_insert_requires_known_parent() {
+        let genesis = genesis();
+        let mut tree = BlockTree::new(genesis.clone());
+        
+        let block1 = child_of(&genesis, 2_000, 1);
+        let orphan = child_of(&block1, 3_000, 1);
+        
+        assert!(tree.insert(orphan).is_err());
+        assert!(tree.insert(block1.clone()).is_ok());
+        assert!(tree.insert(block1).is_err());
+        assert_eq!(tree.len(), 2);
+    }
+    
+    #[test]
+    fn test_longest_branch_wins() {
+        let genesis = genesis();
+        let mut tree = BlockTree::new(genesis.clone());
+        
+        let a1 = child_of(&genesis, 2_000, 1);
+        let b1 = child_of(&genesis, 2_001, 1);
+        let b2 = child_of(&b1, 3_000, 1);
+        tree.insert(a1.clone()).unwrap();
+        tree.insert(b1.clone()).unwrap();
+        tree.insert(b2.clone()).unwrap();
+        
+        let root = tree.root().clone();
+        assert_eq!(tree.best_tip(&root), Some(b2.hash().to_vec()));
+        assert_eq!(tree.common_ancestor(&a1.hash(), &b2.hash()), Some(root));
+        assert_eq!(tree.path_to(&b2.hash()).len(), 3);
+    }
+    
+    #[test]
+    fn test_participation_and_hash_break_ties() {
+        let genesis = genesis();
+        let mut tree = tree_with_validators(genesis.clone(), 3);
+        
+        // Same height, more validators wins
+        let a1 = child_of(&genesis, 2_000, 1);
+        let b1 = child_of(&genesis, 2_001, 3);
+        tree.insert(a1.clone()).unwrap();
+        tree.insert(b1.clone()).unwrap();
+        let root = tree.root().clone();
+        assert_eq!(tree.best_tip(&root), Some(b1.hash().to_vec()));
+        
+        // Same weight, lowest hash wins
+        let c1 = child_of(&genesis, 2_002, 3);
+        tree.insert(c1.clone()).unwrap();
+        let expected = std::cmp::min(b1.hash().to_vec(), c1.hash().to_vec());
+        assert_eq!(tree.best_tip(&root), Some(expected));
+    }
+    
+    #[test]
+    fn test_only_signed_active_validators_count() {
+        let genesis = genesis();
+        let mut tree = tree_with_validators(genesis.clone(), 1);
+        
+        // Listing validators without their signatures adds nothing
+        let mut unsigned = child_of(&genesis, 2_000, 0);
+        for i in 0..3 {
+            unsigned.add_validator(validator(i).public_key());
+        }
+        unsigned.update_merkle_roots();
+        tree.insert(unsigned.clone()).unwrap();
+        assert_eq!(tree.get(&unsigned.hash()).unwrap().weight.participation, 0);
+        
+        // Signatures from validators outside the active set add nothing
+        let signed = child_of(&genesis, 2_001, 3);
+        tree.insert(signed.clone()).unwrap();
+        assert_eq!(tree.get(&signed.hash()).unwrap().weight.participation, 1);
+    }
+    
+    #[test]
+    fn test_finalized_branch_is_kept() {
+        let genesis = genesis();
+        let mut tree = BlockTree::new(genesis.clone());
+        
+        let a1 = child_of(&genesis, 2_000, 1);
+        let b1 = child_of(&genesis, 2_001, 1);
+        let b2 = child_of(&b1, 3_000, 1);
+        tree.insert(a1.clone()).unwrap();
+        tree.insert(b1.clone()).unwrap();
+        tree.insert(b2.clone()).unwrap();
+        
+        // With a1 finalized, the longer b branch is not eligible
+        assert_eq!(tree.best_tip(&a1.hash()), Some(a1.hash().to_vec()));
+        
+        // Pruning removes the conflicting branch entirely
+        let removed = tree.prune(&a1.hash());
+        assert_eq!(removed.len(), 2);
+        assert!(!tree.contains(&b1.hash()));
+        assert!(!tree.contains(&b2.hash()));
+        assert!(tree.contains(&a1.hash()));
+        
+        // The tree is re-rooted at the finalized block
+        assert_eq!(tree.root(), &a1.hash().to_vec());
+        assert!(!tree.contains(&genesis.hash()));
+        assert_eq!(tree.len(), 1);
+        
+        let a2 = child_of(&a1, 3_001, 1);
+        tree.insert(a2.clone()).unwrap();
+        assert_eq!(tree.path_to(&a2.hash()), vec![a1.hash().to_vec(), a2.hash().to_vec()]);
+        assert!(tree.insert(child_of(&genesis, 2_002, 1)).is_err());
+    }
+}
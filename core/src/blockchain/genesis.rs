@@ -0,0 +1,391 @@
+//! # Genesis Specification
+//!
+//! This module loads the genesis specification that every node of a network
+//! starts from: chain id, genesis timestamp, shard count, initial account
+//! balances, the initial validator set and consensus parameters.
+//!
+//! A spec always produces the same genesis block, state and validator pool,
+//! so nodes can check that they agree by comparing the genesis hash.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use serde::{Serialize, Deserialize};
+
+use crate::blockchain::Block;
+use crate::blockchain::state_transition::{address_from_public_key, stake_address, compute_state_root};
+use crate::consensus::{ConsensusConfig, Validator, ValidatorPool};
+use crate::storage::{StateDB, AccountInfo};
+use crate::types::{Result, Error, ShardId, Timestamp};
+
+/// Account funded at genesis
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GenesisAccount {
+    /// Hex-encoded account address
+    pub address: String,
+    
+    /// Initial balance
+    pub balance: u64,
+}
+
+/// Validator active from genesis
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GenesisValidator {
+    /// Hex-encoded validator public key
+    pub public_key: String,
+    
+    /// Stake locked for the validator
+    pub stake: u64,
+    
+    /// Commission rate (0.0 - 1.0)
+    #[serde(default = "default_commission_rate")]
+    pub commission_rate: f32,
+}
+
+fn default_commission_rate() -> f32 {
+    0.05
+}
+
+/// Genesis specification for a network
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GenesisSpec {
+    /// Identifier of the chain
+    pub chain_id: String,
+    
+    /// Genesis block timestamp in microseconds
+    pub timestamp: Timestamp,
+    
+    /// Number of shards at genesis
+    pub shard_count: u16,
+    
+    /// Initial account balances
+    #[serde(default)]
+    pub accounts: Vec<GenesisAccount>,
+    
+    /// Initial validator set
+    #[serde(default)]
+    pub validators: Vec<GenesisValidator>,
+    
+    /// Consensus parameters; `shard_count` is taken from the spec
+    #[serde(default)]
+    pub consensus: ConsensusConfig,
+    
+    /// Expected hex-encoded genesis block hash, checked when present
+    #[serde(default)]
+    pub genesis_hash: Option<String>,
+}
+
+/// Decode a hex field of the spec
+fn decode_hex(field: &str, value: &str) -> Result<Vec<u8>> {
+    hex::decode(value.trim_start_matches("0x"))
+        .map_err(|e| Error::Initialization(format!("Invalid {} '{}': {}", field, value, e)))
+}
+
+impl GenesisSpec {
+    /// Parse a spec from JSON
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json)
+            .map_err(|e| Error::Deserialization(format!("Invalid genesis JSON: {}", e)))
+    }
+    
+    /// Parse a spec from TOML
+    pub fn from_toml(toml: &str) -> Result<Self> {
+        toml::from_str(toml)
+            .map_err(|e| Error::Deserialization(format!("Invalid genesis TOML: {}", e)))
+    }
+    
+    /// Load a spec from a `.json` or `.toml` file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)?;
+        
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::from_toml(&contents),
+            Some("json") => Self::from_json(&contents),
+            _ => Err(Error::Initialization(
+                format!("Unsupported genesis file format: {}", path.display())
+            )),
+        }
+    }
+    
+    /// Consensus parameters for the network
+    pub fn consensus_config(&self) -> ConsensusConfig {
+        let mut config = self.consensus.clone();
+        config.shard_count = self.shard_count;
+        config
+    }
+    
+    /// Check the spec for inconsistencies
+    pub fn validate(&self) -> Result<()> {
+        if self.chain_id.is_empty() {
+            return Err(Error::Initialization("Genesis chain id is empty".to_string()));
+        }
+        
+        if self.shard_count == 0 {
+            return Err(Error::Initialization("Genesis shard count must be positive".to_string()));
+        }
+        
+        // Building the state catches bad addresses and duplicates
+        self.initial_accounts()?;
+        
+        for validator in &self.validators {
+            if validator.stake < self.consensus.min_stake {
+                return Err(Error::Initialization(format!(
+                    "Validator {} stake {} is below minimum {}",
+                    validator.public_key, validator.stake, self.consensus.min_stake
+                )));
+            }
+            if !(0.0..=1.0).contains(&validator.commission_rate) {
+                return Err(Error::Initialization(format!(
+                    "Validator {} commission rate {} is out of range",
+                    validator.public_key, validator.commission_rate
+                )));
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// Accounts that exist at genesis, including locked validator stake
+    pub fn initial_accounts(&self) -> Result<BTreeMap<Vec<u8>, AccountInfo>> {
+        let mut accounts = BTreeMap::new();
+        
+        for account in &self.accounts {
+            let address = decode_hex("account address", &account.address)?;
+            let info = AccountInfo { balance: account.balance, ..AccountInfo::default() };
+            if accounts.insert(address, info).is_some() {
+                return Err(Error::Initialization(
+                    format!("Duplicate genesis account {}", account.address)
+                ));
+            }
+        }
+        
+        let mut seen = HashSet::new();
+        for validator in &self.validators {
+            let public_key = decode_hex("validator public key", &validator.public_key)?;
+            if !seen.insert(public_key.clone()) {
+                return Err(Error::Initialization(
+                    format!("Duplicate genesis validator {}", validator.public_key)
+                ));
+            }
+            
+            let stake_account = accounts.entry(stake_address(&address_from_public_key(&public_key)))
+                .or_insert_with(AccountInfo::default);
+            stake_account.balance = stake_account.balance.checked_add(validator.stake)
+                .ok_or_else(|| Error::Initialization("Genesis stake overflows".to_string()))?;
+        }
+        
+        Ok(accounts)
+    }
+    
+    /// Build the genesis block
+    pub fn build_block(&self) -> Result<Block> {
+        self.validate()?;
+        
+        let mut block = Block::new(
+            0,
+            self.timestamp,
+            vec![0; 32],
+            (0..self.shard_count).collect::<Vec<ShardId>>(),
+        );
+        block.header.chain_id = self.chain_id.clone();
+        
+        for validator in &self.validators {
+            block.add_validator(decode_hex("validator public key", &validator.public_key)?);
+        }
+        
+        block.update_merkle_roots();
+        block.header.state_root = compute_state_root(&self.initial_accounts()?);
+        block.set_receipts(&[])?;
+        
+        if let Some(ref expected) = self.genesis_hash {
+            let actual = hex::encode(block.hash());
+            if expected.trim_start_matches("0x") != actual {
+                return Err(Error::Initialization(format!(
+                    "Genesis hash mismatch: spec expects {}, built {}", expected, actual
+                )));
+            }
+        }
+        
+        Ok(block)
+    }
+    
+    /// Write the genesis accounts to an empty state database
+    pub fn apply_state(&self, state_db: &StateDB) -> Result<()> {
+        let accounts = self.initial_accounts()?;
+        
+        if !state_db.get_all_accounts()?.is_empty() {
+            return Err(Error::Initialization("State database is not empty".to_string()));
+        }
+        
+        for (address, account) in &accounts {
+            state_db.set_account_balance(address, account.balance)?;
+            state_db.set_account_nonce(address, account.nonce)?;
+        }
+        
+        Ok(())
+    }
+    
+    /// Build the validator pool active at genesis
+    pub fn validator_pool(&self) -> Result<ValidatorPool> {
+        let mut pool = ValidatorPool::new();
+        
+        for spec in &self.validators {
+            let public_key = decode_hex("validator public key", &spec.public_key)?;
+            let address = address_from_public_key(&public_key);
+            
+            let mut validator = Validator::new(
+                address.clone(),
+                public_key,
+                stake_address(&address),
+                spec.stake,
+            );
+            validator.commission_rate = spec.commission_rate;
+            validator.last_active_timestamp = self.timestamp;
+            
+            pool.add_validator(validator)?;
+        }
+        
+        pool.assign_validators_to_shards(self.shard_count)?;
+        
+        Ok(pool)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::StorageConfig;
+    
+    fn test_spec() -> GenesisSpec {
+        GenesisSpec {
+            chain_id: "sebure-devnet".to_string(),
+            timestamp: 1_700_000_000_000_000,
+            shard_count: 2,
+            accounts: vec![
+                GenesisAccount { address: hex::encode(vec![1; 32]), balance: 1_000_000 },
+                GenesisAccount { address: hex::encode(vec![2; 32]), balance: 500 },
+            ],
+            validators: vec![
+                GenesisValidator { public_key: hex::encode(vec![7; 32]), stake: 5_000, commission_rate: 0.1 },
+                GenesisValidator { public_key: hex::encode(vec![8; 32]), stake: 3_000, commission_rate: 0.05 },
+            ],
+            consensus: ConsensusConfig::default(),
+            genesis_hash: None,
+        }
+    }
+    
+    #[test]
+    fn test_genesis_is_deterministic() {
+        let spec = test_spec();
+        let block1 = spec.build_block().unwrap();
+        let block2 = spec.build_block().unwrap();
+        
+        assert_eq!(block1.hash(), block2.hash());
+        assert_eq!(block1.header.index, 0);
+        assert_eq!(block1.header.chain_id, "sebure-devnet");
+        assert_eq!(block1.header.shard_identifiers, vec![0, 1]);
+        assert_eq!(block1.validator_set.len(), 2);
+        
+        // Any change to the initial state changes the genesis hash
+        let mut changed = spec.clone();
+        changed.accounts[1].balance += 1;
+        assert_ne!(changed.build_block().unwrap().hash(), block1.hash());
+        
+        // So does building it for another chain
+        let mut other_chain = spec.clone();
+        other_chain.chain_id = "sebure-testnet".to_string();
+        assert_ne!(other_chain.build_block().unwrap().hash(), block1.hash());
+    }
+    
+    #[test]
+    fn test_json_and_toml_specs_agree() {
+        let json = r#"{
+            "chain_id": "sebure-devnet",
+            "timestamp": 1000,
+            "shard_count": 1,
+            "accounts": [{ "address": "0101", "balance": 10 }],
+            "validators": [{ "public_key": "0707", "stake": 2000 }],
+            "consensus": { "blocks_per_epoch": 50 }
+        }"#;
+        let toml = r#"
+            chain_id = "sebure-devnet"
+            timestamp = 1000
+            shard_count = 1
+            
+            [[accounts]]
+            address = "0101"
+            balance = 10
+            
+            [[validators]]
+            public_key = "0707"
+            stake = 2000
+            
+            [consensus]
+            blocks_per_epoch = 50
+        "#;
+        
+        let from_json = GenesisSpec::from_json(json).unwrap();
+        let from_toml = GenesisSpec::from_toml(toml).unwrap();
+        assert_eq!(from_json, from_toml);
+        assert_eq!(from_json.consensus_config().blocks_per_epoch, 50);
+        assert_eq!(from_json.consensus_config().shard_count, 1);
+        assert_eq!(from_json.consensus.min_stake, ConsensusConfig::default().min_stake);
+        assert_eq!(from_json.build_block().unwrap().hash(), from_toml.build_block().unwrap().hash());
+    }
+    
+    #[test]
+    fn test_genesis_hash_is_checked() {
+        let mut spec = test_spec();
+        let hash = hex::encode(spec.build_block().unwrap().hash());
+        
+        spec.genesis_hash = Some(hash);
+        assert!(spec.build_block().is_ok());
+        
+        spec.genesis_hash = Some(hex::encode(vec![0; 32]));
+        assert!(spec.build_block().is_err());
+    }
+    
+    #[test]
+    fn test_invalid_specs_are_rejected() {
+        let mut duplicate = test_spec();
+        duplicate.accounts.push(duplicate.accounts[0].clone());
+        assert!(duplicate.validate().is_err());
+        
+        let mut low_stake = test_spec();
+        low_stake.validators[0].stake = 1;
+        assert!(low_stake.validate().is_err());
+        
+        let mut bad_address = test_spec();
+        bad_address.accounts[0].address = "not hex".to_string();
+        assert!(bad_address.validate().is_err());
+        
+        let mut no_shards = test_spec();
+        no_shards.shard_count = 0;
+        assert!(no_shards.validate().is_err());
+    }
+    
+    #[test]
+    fn test_state_and_validators_match_block() {
+        let spec = test_spec();
+        let block = spec.build_block().unwrap();
+        
+        let state_db = StateDB::new("", &StorageConfig::default()).unwrap();
+        spec.apply_state(&state_db).unwrap();
+        assert_eq!(state_db.get_account_balance(&vec![1; 32]).unwrap(), 1_000_000);
+        
+        let stored: BTreeMap<Vec<u8>, AccountInfo> = state_db.get_all_accounts().unwrap().into_iter().collect();
+        assert_eq!(compute_state_root(&stored), block.header.state_root);
+        
+        // Applying twice is refused
+        assert!(spec.apply_state(&state_db).is_err());
+        
+        let pool = spec.validator_pool().unwrap();
+        assert_eq!(pool.validator_count(), 2);
+        assert_eq!(pool.get_total_stake(), 8_000);
+        let validator = pool.get_validator_by_pubkey(&vec![7; 32]).unwrap();
+        assert_eq!(validator.last_active_timestamp, spec.timestamp);
+        assert_eq!(validator.shard_assignments, vec![0]);
+    }
+}
@@ -3,12 +3,14 @@
 //! This module implements the transaction mempool, which stores pending
 //! transactions before they are included in blocks.
 
-use std::collections::{HashMap, BTreeSet};
+use std::collections::{HashMap, HashSet, BTreeMap, BTreeSet, BinaryHeap, VecDeque};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
-use crate::blockchain::Transaction;
-use crate::types::{Result, Error, ShardId, Priority};
+use crate::blockchain::{Transaction, DependencyType, ShardLayout, FeeCharge};
+use crate::blockchain::state_transition::address_from_public_key;
+use crate::storage::{StateDB, MempoolJournal, JournalRecord};
+use crate::types::{Result, Error, BlockHeight, ShardId, Timestamp, Priority, DEFAULT_CHAIN_ID};
 
 /// Configuration for the transaction mempool
 #[derive(Debug, Clone)]
@@ -24,6 +26,21 @@ pub struct MempoolConfig {
     
     /// Maximum size (in bytes) of a single transaction
     pub max_tx_size: usize,
+    
+    /// Chain id that admitted transactions must be signed for
+    pub chain_id: String,
+    
+    /// Minimum fee increase (in percent) for a transaction to replace a
+    /// pending one with the same sender and nonce
+    pub replacement_fee_bump: u64,
+    
+    /// Time (in seconds) for the rolling minimum fee to fall halfway back
+    /// to `min_fee_per_byte` after it was last raised
+    pub min_fee_half_life: u64,
+    
+    /// Number of journal records that may accumulate beyond the pending
+    /// transactions before the journal is compacted
+    pub journal_compact_interval: usize,
 }
 
 impl Default for MempoolConfig {
@@ -33,50 +50,110 @@ impl Default for MempoolConfig {
             max_age: 3600, // 1 hour
             min_fee_per_byte: 1,
             max_tx_size: 1024 * 1024, // 1 MB
+            chain_id: DEFAULT_CHAIN_ID.to_string(),
+            replacement_fee_bump: 10,
+            min_fee_half_life: 300, // 5 minutes
+            journal_compact_interval: 1000,
         }
     }
 }
 
+/// Result of offering a transaction to the mempool
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdmissionOutcome {
+    /// The transaction was added
+    Added,
+    
+    /// The transaction replaced a pending one with the same sender and nonce
+    Replaced {
+        /// ID of the replaced transaction
+        replaced: Vec<u8>,
+        
+        /// IDs of the replaced transaction's dependents, which were evicted too
+        evicted: Vec<Vec<u8>>,
+    },
+    
+    /// A pending transaction has the same sender and nonce and the fee or
+    /// priority fee was not raised enough to replace it
+    Underpriced {
+        /// Lowest fee that would replace the pending transaction
+        minimum_fee: u32,
+        
+        /// Lowest priority fee that would replace the pending transaction
+        minimum_priority_fee: u32,
+    },
+    
+    /// The transaction is already in the mempool
+    Duplicate,
+}
+
+/// Estimated size of a transfer without data: 32-byte ID and public key,
+/// 20-byte recipient and the fixed fields
+const BASE_TX_SIZE: usize = 32 + 32 + 20 + 100;
+
+/// Lowest fee that replaces a pending transaction paying `fee`
+pub fn minimum_replacement_fee(fee: u32, bump_percent: u64) -> u32 {
+    let bump = (fee as u64 * bump_percent + 99) / 100;
+    (fee as u64 + bump.max(1)).min(u32::MAX as u64) as u32
+}
+
 /// Transaction with additional mempool metadata
 #[derive(Debug, Clone)]
 struct MempoolTx {
     /// Reference to the actual transaction
     pub transaction: Arc<Transaction>,
     
+    /// Address of the sender
+    pub sender: Vec<u8>,
+    
     /// When the transaction was added to the mempool
     pub received_at: Instant,
     
+    /// Position in the order transactions were admitted in
+    pub sequence: u64,
+    
     /// Estimated size in bytes
     pub size: usize,
     
-    /// Fee per byte ratio (used for prioritization)
+    /// Tip per byte at the base fee of its shard (used for prioritization)
     pub fee_per_byte: u64,
     
-    /// Whether this transaction is ready to be included in a block
+    /// Whether the transaction's hard and state dependencies are met
     pub ready: bool,
 }
 
 impl MempoolTx {
-    /// Create a new mempool transaction
-    fn new(tx: Arc<Transaction>) -> Self {
+    /// Create a new mempool transaction admitted `sequence`-th, ranked at
+    /// the given base fee of its shard
+    fn new(tx: Arc<Transaction>, base_fee: u64, sequence: u64) -> Self {
         // Estimate size (in a real implementation, this would be more accurate)
         let size = tx.id.len() + tx.sender_public_key.len() + tx.recipient_address.len() + 
                   tx.data.content.len() + 100; // Add 100 bytes for fixed fields
         
-        // Calculate fee per byte
-        let fee_per_byte = if size > 0 {
-            tx.fee as u64 / size as u64
-        } else {
-            0
-        };
-        
-        MempoolTx {
+        let mut mempool_tx = MempoolTx {
             transaction: tx.clone(),
+            sender: address_from_public_key(&tx.sender_public_key),
             received_at: Instant::now(),
+            sequence,
             size,
-            fee_per_byte,
-            ready: true, // By default, transactions are ready (unless they have dependencies)
-        }
+            fee_per_byte: 0,
+            ready: true, // Updated once dependencies are tracked
+        };
+        mempool_tx.rank(base_fee);
+        mempool_tx
+    }
+    
+    /// Recompute the tip per byte at a base fee
+    ///
+    /// The tip is what the producer earns, `min(priority_fee, fee - base_fee)`;
+    /// a transaction whose fee does not cover the base fee tips nothing.
+    fn rank(&mut self, base_fee: u64) {
+        let tip = FeeCharge::new(&self.transaction, base_fee).map_or(0, |charge| charge.tip);
+        self.fee_per_byte = if self.size > 0 {
+            tip / self.size as u64
+        } else {
+            0
+        };
     }
     
     /// Check if the transaction has expired
@@ -87,8 +164,8 @@ impl MempoolTx {
 
 /// A comparator for transaction priority that sorts by:
 /// 1. Priority level (High > Normal > Low)
-/// 2. Fee per byte (higher is better)
-/// 3. Received time (earlier is better)
+/// 2. Tip per byte (higher is better)
+/// 3. Admission order (earlier is better)
 #[derive(PartialEq, Eq)]
 struct TxPriorityOrder {
     /// Transaction ID
@@ -97,11 +174,11 @@ struct TxPriorityOrder {
     /// Transaction priority
     priority: Priority,
     
-    /// Fee per byte
+    /// Tip per byte
     fee_per_byte: u64,
     
-    /// Received timestamp (as duration since UNIX_EPOCH)
-    received_at: Duration,
+    /// Admission sequence number
+    sequence: u64,
 }
 
 impl TxPriorityOrder {
@@ -110,7 +187,7 @@ impl TxPriorityOrder {
             tx_id: tx.transaction.id.clone(),
             priority: tx.transaction.execution_priority,
             fee_per_byte: tx.fee_per_byte,
-            received_at: tx.received_at.elapsed(),
+            sequence: tx.sequence,
         }
     }
 }
@@ -135,8 +212,151 @@ impl Ord for TxPriorityOrder {
             return fee_cmp;
         }
         
-        // Then by received time (earlier is better, so reverse ordering)
-        other.received_at.cmp(&self.received_at)
+        // Then by admission order (earlier is better, so reverse ordering)
+        other.sequence.cmp(&self.sequence)
+    }
+}
+
+/// Pending transactions of a single sender, keyed by nonce
+///
+/// `ready` holds the transactions contiguous from the account nonce, which
+/// can be executed in order; `future` holds those behind a nonce gap.
+#[derive(Debug, Clone, Default)]
+struct SenderQueue {
+    /// Next nonce expected by the chain for this sender
+    nonce: u64,
+    
+    /// Executable transactions (nonce -> tx_id)
+    ready: BTreeMap<u64, Vec<u8>>,
+    
+    /// Transactions waiting for a gap to fill (nonce -> tx_id)
+    future: BTreeMap<u64, Vec<u8>>,
+}
+
+impl SenderQueue {
+    /// Create an empty queue starting at the given account nonce
+    fn new(nonce: u64) -> Self {
+        SenderQueue {
+            nonce,
+            ..SenderQueue::default()
+        }
+    }
+    
+    /// Check if a transaction with the given nonce is queued
+    fn contains(&self, nonce: u64) -> bool {
+        self.ready.contains_key(&nonce) || self.future.contains_key(&nonce)
+    }
+    
+    /// Get the ID of the transaction queued at a nonce
+    fn get(&self, nonce: u64) -> Option<&Vec<u8>> {
+        self.ready.get(&nonce).or_else(|| self.future.get(&nonce))
+    }
+    
+    /// Check if the queue holds no transactions
+    fn is_empty(&self) -> bool {
+        self.ready.is_empty() && self.future.is_empty()
+    }
+    
+    /// Nonce following the last ready transaction
+    fn next_nonce(&self) -> u64 {
+        self.nonce + self.ready.len() as u64
+    }
+    
+    /// Queue a transaction and promote any future transactions it unblocks
+    fn insert(&mut self, nonce: u64, tx_id: Vec<u8>) {
+        self.future.insert(nonce, tx_id);
+        self.promote();
+    }
+    
+    /// Move future transactions that are now contiguous into the ready queue
+    fn promote(&mut self) {
+        let mut next = self.next_nonce();
+        while let Some(tx_id) = self.future.remove(&next) {
+            self.ready.insert(next, tx_id);
+            next += 1;
+        }
+    }
+    
+    /// Remove a transaction, demoting the ready transactions after it
+    fn remove(&mut self, nonce: u64, tx_id: &[u8]) {
+        if self.future.get(&nonce).map_or(false, |id| id.as_slice() == tx_id) {
+            self.future.remove(&nonce);
+        } else if self.ready.get(&nonce).map_or(false, |id| id.as_slice() == tx_id) {
+            let mut demoted = self.ready.split_off(&nonce);
+            demoted.remove(&nonce);
+            self.future.append(&mut demoted);
+        }
+    }
+    
+    /// Move the queue to a new account nonce, returning the ids of the
+    /// transactions whose nonces are now below it
+    fn rebase(&mut self, nonce: u64) -> Vec<Vec<u8>> {
+        let mut pending = std::mem::take(&mut self.ready);
+        pending.append(&mut self.future);
+        
+        self.future = pending.split_off(&nonce);
+        self.nonce = nonce;
+        self.promote();
+        
+        pending.into_iter().map(|(_, tx_id)| tx_id).collect()
+    }
+}
+
+/// Recently included transactions and the state roots they produced
+///
+/// Bounded so that it only remembers parents that pending transactions may
+/// still refer to; the oldest entries are forgotten first.
+#[derive(Debug, Default)]
+struct IncludedCache {
+    /// State root after each included transaction, when known
+    state_roots: HashMap<Vec<u8>, Option<Vec<u8>>>,
+    
+    /// Included transaction IDs, oldest first
+    order: VecDeque<Vec<u8>>,
+    
+    /// Maximum number of transactions remembered
+    capacity: usize,
+}
+
+impl IncludedCache {
+    /// Create an empty cache remembering up to `capacity` transactions
+    fn new(capacity: usize) -> Self {
+        IncludedCache {
+            capacity,
+            ..IncludedCache::default()
+        }
+    }
+    
+    /// Check if a transaction was included
+    fn contains(&self, tx_id: &[u8]) -> bool {
+        self.state_roots.contains_key(tx_id)
+    }
+    
+    /// State root after an included transaction, if it is known
+    fn state_root(&self, tx_id: &[u8]) -> Option<&Vec<u8>> {
+        self.state_roots.get(tx_id).and_then(|root| root.as_ref())
+    }
+    
+    /// Remember an included transaction
+    fn insert(&mut self, tx_id: Vec<u8>, state_root: Option<Vec<u8>>) {
+        if self.state_roots.insert(tx_id.clone(), state_root).is_none() {
+            self.order.push_back(tx_id);
+        }
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.state_roots.remove(&oldest);
+            }
+        }
+    }
+    
+    /// Forget a transaction whose block left the canonical chain
+    fn remove(&mut self, tx_id: &[u8]) -> bool {
+        if self.state_roots.remove(tx_id).is_some() {
+            self.order.retain(|id| id.as_slice() != tx_id);
+            true
+        } else {
+            false
+        }
     }
 }
 
@@ -151,41 +371,346 @@ pub struct Mempool {
     /// Transaction priority queue
     priority_index: Arc<Mutex<BTreeSet<TxPriorityOrder>>>,
     
+    /// Transactions ordered by tip per byte, cheapest first
+    fee_index: Arc<Mutex<BTreeSet<(u64, Vec<u8>)>>>,
+    
+    /// Rolling minimum fee per byte and when it was last raised
+    rolling_min_fee: Arc<Mutex<(u64, Instant)>>,
+    
+    /// Sequence number of the last admitted transaction
+    last_sequence: Arc<Mutex<u64>>,
+    
     /// Transactions organized by shard
     shard_index: Arc<Mutex<HashMap<ShardId, Vec<Vec<u8>>>>>,
     
-    /// Dependency tracking (tx_id -> dependent tx_ids)
+    /// Dependency tracking (tx_id -> required tx_ids), soft dependencies excluded
     dependencies: Arc<Mutex<HashMap<Vec<u8>, Vec<Vec<u8>>>>>,
     
-    /// Reverse dependency tracking (tx_id -> required tx_ids)
+    /// Reverse dependency tracking (tx_id -> dependent tx_ids)
     reverse_dependencies: Arc<Mutex<HashMap<Vec<u8>, Vec<Vec<u8>>>>>,
+    
+    /// Transactions included in the canonical chain that dependencies may refer to
+    included: Arc<Mutex<IncludedCache>>,
+    
+    /// Nonce-ordered queues indexed by sender address
+    sender_queues: Arc<Mutex<HashMap<Vec<u8>, SenderQueue>>>,
+    
+    /// Height and timestamp of the canonical head, which time bounds are
+    /// checked against
+    chain_tip: Arc<Mutex<(BlockHeight, Timestamp)>>,
+    
+    /// Shard layout of the next block, which transactions must follow once
+    /// the chain has set it
+    shard_layout: Arc<Mutex<Option<ShardLayout>>>,
+    
+    /// Base fee of the next block on each shard, which transactions are
+    /// ranked by the tip they pay above
+    base_fees: Arc<Mutex<HashMap<ShardId, u64>>>,
+    
+    /// State database used to look up account nonces
+    state_db: Arc<Mutex<Option<Arc<StateDB>>>>,
+    
+    /// Journal that admissions and removals are recorded in
+    journal: Arc<Mutex<Option<Arc<MempoolJournal>>>>,
 }
 
 impl Mempool {
     /// Create a new transaction mempool
     pub fn new(config: MempoolConfig) -> Self {
+        let rolling_min_fee = (config.min_fee_per_byte, Instant::now());
+        let included = IncludedCache::new(config.max_size);
+        
         Mempool {
             config,
             transactions: Arc::new(Mutex::new(HashMap::new())),
             priority_index: Arc::new(Mutex::new(BTreeSet::new())),
+            fee_index: Arc::new(Mutex::new(BTreeSet::new())),
+            rolling_min_fee: Arc::new(Mutex::new(rolling_min_fee)),
+            last_sequence: Arc::new(Mutex::new(0)),
             shard_index: Arc::new(Mutex::new(HashMap::new())),
             dependencies: Arc::new(Mutex::new(HashMap::new())),
             reverse_dependencies: Arc::new(Mutex::new(HashMap::new())),
+            included: Arc::new(Mutex::new(included)),
+            sender_queues: Arc::new(Mutex::new(HashMap::new())),
+            chain_tip: Arc::new(Mutex::new((0, 0))),
+            shard_layout: Arc::new(Mutex::new(None)),
+            base_fees: Arc::new(Mutex::new(HashMap::new())),
+            state_db: Arc::new(Mutex::new(None)),
+            journal: Arc::new(Mutex::new(None)),
+        }
+    }
+    
+    /// Set the state database that account nonces are read from
+    pub fn set_state_db(&self, state_db: Arc<StateDB>) {
+        *self.state_db.lock().unwrap() = Some(state_db);
+    }
+    
+    /// Set the journal that admissions and removals are recorded in
+    ///
+    /// Call [`Mempool::restore_from_journal`] to re-admit the transactions
+    /// it already holds.
+    pub fn set_journal(&self, journal: Arc<MempoolJournal>) {
+        *self.journal.lock().unwrap() = Some(journal);
+    }
+    
+    /// Re-admit the pending transactions recorded in the journal
+    ///
+    /// Transactions are revalidated as they are admitted again: their nonces
+    /// against the account nonces and, when a state database is set, their
+    /// cost against the sender's balance less that of the sender's other
+    /// restored transactions. Once they are all in, the journal is rewritten
+    /// to hold only the restored transactions, so a crash while restoring
+    /// loses nothing. Returns the number restored.
+    pub fn restore_from_journal(&self) -> Result<usize> {
+        // Detach the journal so that re-admissions are not recorded twice
+        let journal = match self.journal.lock().unwrap().take() {
+            Some(journal) => journal,
+            None => return Ok(0),
+        };
+        
+        let restored = journal.load().map(|pending| self.readmit(pending));
+        *self.journal.lock().unwrap() = Some(journal.clone());
+        let restored = restored?;
+        journal.compact(|| self.pending_transactions())?;
+        
+        log::info!("Restored {} pending transactions from the mempool journal", restored);
+        Ok(restored)
+    }
+    
+    /// Admit journaled transactions that are still valid and affordable,
+    /// returning how many were admitted
+    fn readmit(&self, pending: Vec<Transaction>) -> usize {
+        let state_db = self.state_db.lock().unwrap().clone();
+        let mut committed: HashMap<Vec<u8>, u64> = HashMap::new();
+        let mut restored = 0;
+        for tx in pending {
+            let sender = address_from_public_key(&tx.sender_public_key);
+            let cost = committed.get(&sender).copied().unwrap_or(0)
+                .saturating_add(tx.amount)
+                .saturating_add(tx.fee as u64);
+            if let Some(ref state_db) = state_db {
+                let balance = state_db.get_account_balance(&sender).unwrap_or(0);
+                if balance < cost {
+                    log::debug!("Dropping journaled transaction {}: insufficient balance", hex::encode(&tx.id));
+                    continue;
+                }
+            }
+            
+            match self.admit(&tx) {
+                Ok(AdmissionOutcome::Added) | Ok(AdmissionOutcome::Replaced { .. }) => {
+                    committed.insert(sender, cost);
+                    restored += 1;
+                },
+                Ok(outcome) => log::debug!("Dropping journaled transaction {}: {:?}", hex::encode(&tx.id), outcome),
+                Err(e) => log::debug!("Dropping journaled transaction {}: {}", hex::encode(&tx.id), e),
+            }
+        }
+        restored
+    }
+    
+    /// Record a change in the journal, if there is one
+    ///
+    /// The journal is compacted once `journal_compact_interval` records have
+    /// accumulated beyond the pending transactions.
+    fn record(&self, record: JournalRecord) {
+        let journal = match self.journal.lock().unwrap().clone() {
+            Some(journal) => journal,
+            None => return,
+        };
+        
+        if let Err(e) = journal.append(&record) {
+            log::warn!("Failed to write to the mempool journal: {}", e);
+            return;
+        }
+        
+        if journal.len() >= self.size() + self.config.journal_compact_interval {
+            if let Err(e) = journal.compact(|| self.pending_transactions()) {
+                log::warn!("Failed to compact the mempool journal: {}", e);
+            }
+        }
+    }
+    
+    /// Pending transactions in the order they were received
+    fn pending_transactions(&self) -> Vec<Transaction> {
+        let transactions = self.transactions.lock().unwrap();
+        let mut pending: Vec<&MempoolTx> = transactions.values().collect();
+        pending.sort_by_key(|mempool_tx| mempool_tx.received_at);
+        pending.into_iter().map(|mempool_tx| (*mempool_tx.transaction).clone()).collect()
+    }
+    
+    /// Look up the on-chain nonce of an account
+    fn account_nonce(&self, address: &[u8]) -> u64 {
+        self.state_db.lock().unwrap().as_ref()
+            .and_then(|state_db| state_db.get_account_nonce(address).ok())
+            .unwrap_or(0)
+    }
+    
+    /// Update the on-chain nonce of an account
+    ///
+    /// Called when blocks are added to or removed from the canonical chain.
+    /// Pending transactions of the account with lower nonces are dropped and
+    /// future transactions that became contiguous are promoted.
+    pub fn set_account_nonce(&self, address: &[u8], nonce: u64) {
+        let has_state = self.state_db.lock().unwrap().is_some();
+        let stale = {
+            let mut queues = self.sender_queues.lock().unwrap();
+            match queues.get_mut(address) {
+                Some(queue) => queue.rebase(nonce),
+                // Without a state database the queue remembers the nonce
+                None if !has_state => {
+                    queues.insert(address.to_vec(), SenderQueue::new(nonce));
+                    Vec::new()
+                },
+                None => Vec::new(),
+            }
+        };
+        
+        for tx_id in stale {
+            let _ = self.remove_transaction(&tx_id);
         }
+        
+        if has_state {
+            let mut queues = self.sender_queues.lock().unwrap();
+            if queues.get(address).map_or(false, |queue| queue.is_empty()) {
+                queues.remove(address);
+            }
+        }
+    }
+    
+    /// Update the canonical head that time bounds are checked against and
+    /// drop the transactions that can no longer be included
+    ///
+    /// Called when the canonical head changes. Expiry follows the chain
+    /// rather than the local clock, so every node drops the same transactions.
+    pub fn set_chain_tip(&self, height: BlockHeight, timestamp: Timestamp) -> usize {
+        *self.chain_tip.lock().unwrap() = (height, timestamp);
+        
+        let expired: Vec<Vec<u8>> = self.transactions.lock().unwrap().iter()
+            .filter(|(_, mempool_tx)| mempool_tx.transaction.is_expired_after(height, timestamp))
+            .map(|(tx_id, _)| tx_id.clone())
+            .collect();
+        for tx_id in &expired {
+            let _ = self.remove_transaction(tx_id);
+        }
+        
+        expired.len()
+    }
+    
+    /// Update the shard layout that transactions must follow and drop the
+    /// transactions it puts on other shards
+    ///
+    /// Called when the layout of the next block changes.
+    pub fn set_shard_layout(&self, layout: ShardLayout) -> usize {
+        if self.shard_layout.lock().unwrap().as_ref() == Some(&layout) {
+            return 0;
+        }
+        
+        let misplaced: Vec<Vec<u8>> = self.transactions.lock().unwrap().iter()
+            .filter(|(_, mempool_tx)| layout.check_transaction(&mempool_tx.transaction).is_err())
+            .map(|(tx_id, _)| tx_id.clone())
+            .collect();
+        *self.shard_layout.lock().unwrap() = Some(layout);
+        for tx_id in &misplaced {
+            let _ = self.remove_transaction(tx_id);
+        }
+        
+        misplaced.len()
+    }
+    
+    /// Update the base fees of the next block and re-rank the pending
+    /// transactions by the tip they pay above them
+    ///
+    /// Called when the canonical head changes. Shards without a base fee
+    /// are treated as having none.
+    pub fn set_base_fees(&self, base_fees: HashMap<ShardId, u64>) {
+        if *self.base_fees.lock().unwrap() == base_fees {
+            return;
+        }
+        
+        let mut transactions = self.transactions.lock().unwrap();
+        let mut priority_index = self.priority_index.lock().unwrap();
+        let mut fee_index = self.fee_index.lock().unwrap();
+        priority_index.clear();
+        fee_index.clear();
+        for (tx_id, mempool_tx) in transactions.iter_mut() {
+            mempool_tx.rank(base_fees.get(&mempool_tx.transaction.sender_shard).copied().unwrap_or(0));
+            priority_index.insert(TxPriorityOrder::new(mempool_tx));
+            fee_index.insert((mempool_tx.fee_per_byte, tx_id.clone()));
+        }
+        *self.base_fees.lock().unwrap() = base_fees;
+    }
+    
+    /// Base fee of the next block on a shard
+    pub fn base_fee(&self, shard_id: ShardId) -> u64 {
+        self.base_fees.lock().unwrap().get(&shard_id).copied().unwrap_or(0)
+    }
+    
+    /// Height and timestamp of the canonical head
+    pub fn chain_tip(&self) -> (BlockHeight, Timestamp) {
+        *self.chain_tip.lock().unwrap()
+    }
+    
+    /// Next nonce the sender can use after its ready transactions
+    pub fn next_nonce(&self, address: &[u8]) -> Option<u64> {
+        let queues = self.sender_queues.lock().unwrap();
+        queues.get(address).map(|queue| queue.next_nonce())
+    }
+    
+    /// Number of (ready, future) transactions pending for a sender
+    pub fn sender_pending(&self, address: &[u8]) -> (usize, usize) {
+        let queues = self.sender_queues.lock().unwrap();
+        queues.get(address).map_or((0, 0), |queue| (queue.ready.len(), queue.future.len()))
     }
     
     /// Add a transaction to the mempool
+    ///
+    /// Underpriced replacements and duplicates are reported as errors; use
+    /// [`Mempool::admit`] to tell the outcomes apart.
     pub fn add_transaction(&self, tx: &Transaction) -> Result<()> {
+        match self.admit(tx)? {
+            AdmissionOutcome::Added | AdmissionOutcome::Replaced { .. } => Ok(()),
+            AdmissionOutcome::Underpriced { minimum_fee, minimum_priority_fee } => Err(Error::BlockValidation(format!(
+                "Replacement transaction underpriced: fee {} and priority fee {} must reach {} and {}",
+                tx.fee, tx.priority_fee, minimum_fee, minimum_priority_fee
+            ))),
+            AdmissionOutcome::Duplicate => Err(Error::BlockValidation("Transaction already in mempool".to_string())),
+        }
+    }
+    
+    /// Offer a transaction to the mempool and report what happened to it
+    ///
+    /// A transaction with the same sender and nonce as a pending one replaces
+    /// it if its fee is at least `replacement_fee_bump` percent higher; the
+    /// replaced transaction is evicted together with everything depending on it.
+    pub fn admit(&self, tx: &Transaction) -> Result<AdmissionOutcome> {
         // Basic validation
         if tx.id.is_empty() {
             return Err(Error::BlockValidation("Transaction ID is empty".to_string()));
         }
         
         // Verify the transaction
-        tx.is_valid()?;
+        tx.is_valid(&self.config.chain_id)?;
+        
+        // Its accounts must be on the shards it names
+        if let Some(ref layout) = *self.shard_layout.lock().unwrap() {
+            layout.check_transaction(tx)?;
+        }
+        
+        // Transactions that are not valid yet are parked, expired ones are refused
+        let (height, timestamp) = self.chain_tip();
+        if tx.is_expired_after(height, timestamp) {
+            return Err(Error::BlockValidation(
+                format!("Transaction expired at {:?}", tx.valid_until)
+            ));
+        }
         
         let tx_id = tx.id.clone();
-        let mempool_tx = MempoolTx::new(Arc::new(tx.clone()));
+        let sequence = {
+            let mut last_sequence = self.last_sequence.lock().unwrap();
+            *last_sequence += 1;
+            *last_sequence
+        };
+        let mempool_tx = MempoolTx::new(Arc::new(tx.clone()), self.base_fee(tx.sender_shard), sequence);
         
         // Check size
         if mempool_tx.size > self.config.max_tx_size {
@@ -194,13 +719,64 @@ impl Mempool {
             ));
         }
         
-        // Check fee per byte
-        if mempool_tx.fee_per_byte < self.config.min_fee_per_byte {
+        // Check fee per byte against the rolling minimum
+        let min_fee_per_byte = self.min_fee_per_byte();
+        if mempool_tx.fee_per_byte < min_fee_per_byte {
             return Err(Error::BlockValidation(
-                format!("Fee per byte {} is below minimum {}", mempool_tx.fee_per_byte, self.config.min_fee_per_byte)
+                format!("Tip per byte {} is below minimum {}", mempool_tx.fee_per_byte, min_fee_per_byte)
             ));
         }
         
+        // Refuse stale nonces and look for a pending transaction with the
+        // same sender and nonce, before anything is evicted
+        let (conflicting, next_nonce) = {
+            let transactions = self.transactions.lock().unwrap();
+            if transactions.contains_key(&tx_id) {
+                return Ok(AdmissionOutcome::Duplicate);
+            }
+            
+            let queues = self.sender_queues.lock().unwrap();
+            let queue = queues.get(&mempool_tx.sender);
+            let account_nonce = match queue {
+                Some(queue) => queue.nonce,
+                None => self.account_nonce(&mempool_tx.sender),
+            };
+            if tx.nonce < account_nonce {
+                return Err(Error::BlockValidation(
+                    format!("Nonce {} is below the account nonce {}", tx.nonce, account_nonce)
+                ));
+            }
+            
+            let conflicting = queue
+                .and_then(|queue| queue.get(tx.nonce))
+                .and_then(|pending_id| transactions.get(pending_id))
+                .map(|pending| (pending.transaction.id.clone(), pending.transaction.fee, pending.transaction.priority_fee));
+            (conflicting, queue.map_or(account_nonce, |queue| queue.next_nonce()))
+        };
+        
+        // A transaction behind a nonce gap only displaces other such transactions
+        let ready = tx.nonce <= next_nonce;
+        
+        // Replace it if both the fee and the tip it pays were raised enough,
+        // so a higher cap alone cannot displace a better-tipping transaction
+        let replaced = match conflicting {
+            Some((pending_id, pending_fee, pending_priority_fee)) => {
+                let minimum_fee = minimum_replacement_fee(pending_fee, self.config.replacement_fee_bump);
+                let minimum_priority_fee = minimum_replacement_fee(pending_priority_fee, self.config.replacement_fee_bump);
+                if tx.fee < minimum_fee || tx.priority_fee < minimum_priority_fee {
+                    return Ok(AdmissionOutcome::Underpriced { minimum_fee, minimum_priority_fee });
+                }
+                
+                let mut evicted = self.remove_with_dependents(&pending_id);
+                evicted.retain(|id| id != &pending_id);
+                Some((pending_id, evicted))
+            },
+            None => None,
+        };
+        
+        // Make room by evicting cheaper transactions if the pool is full
+        self.make_room(&mempool_tx, ready)?;
+        
         // Update mempool data structures
         {
             let mut transactions = self.transactions.lock().unwrap();
@@ -215,6 +791,26 @@ impl Mempool {
                 return Err(Error::BlockValidation("Transaction already in mempool".to_string()));
             }
             
+            // Queue by sender nonce
+            let mut queues = self.sender_queues.lock().unwrap();
+            let account_nonce = match queues.get(&mempool_tx.sender) {
+                Some(queue) => queue.nonce,
+                None => self.account_nonce(&mempool_tx.sender),
+            };
+            if tx.nonce < account_nonce {
+                return Err(Error::BlockValidation(
+                    format!("Nonce {} is below the account nonce {}", tx.nonce, account_nonce)
+                ));
+            }
+            let queue = queues.entry(mempool_tx.sender.clone())
+                .or_insert_with(|| SenderQueue::new(account_nonce));
+            if queue.contains(tx.nonce) {
+                return Err(Error::BlockValidation(
+                    format!("A transaction with nonce {} from this sender is already pending", tx.nonce)
+                ));
+            }
+            queue.insert(tx.nonce, tx_id.clone());
+            
             // Add to main index
             transactions.insert(tx_id.clone(), mempool_tx.clone());
         }
@@ -225,6 +821,12 @@ impl Mempool {
             priority_index.insert(TxPriorityOrder::new(&mempool_tx));
         }
         
+        // Add to fee index
+        {
+            let mut fee_index = self.fee_index.lock().unwrap();
+            fee_index.insert((mempool_tx.fee_per_byte, tx_id.clone()));
+        }
+        
         // Add to shard index
         {
             let mut shard_index = self.shard_index.lock().unwrap();
@@ -232,49 +834,161 @@ impl Mempool {
             shard_txs.push(tx_id.clone());
         }
         
-        // Add dependency tracking if needed
-        if !tx.dependencies.is_empty() {
-            let mut mempool_tx = mempool_tx;
-            mempool_tx.ready = false; // Not ready until all dependencies are met
-            
-            // Update in transactions map
-            {
-                let mut transactions = self.transactions.lock().unwrap();
-                transactions.insert(tx_id.clone(), mempool_tx);
-            }
-            
-            // Add reverse dependencies
+        // Track hard and state dependencies
+        let parents: Vec<Vec<u8>> = tx.dependencies.iter()
+            .filter(|dep| !matches!(dep.dependency_type, DependencyType::Soft))
+            .map(|dep| dep.transaction_id.clone())
+            .collect();
+        if !parents.is_empty() {
             let mut reverse_deps = self.reverse_dependencies.lock().unwrap();
-            for dep_id in &tx.dependencies {
-                let deps = reverse_deps.entry(dep_id.clone()).or_insert_with(|| Vec::new());
-                deps.push(tx_id.clone());
+            for parent in &parents {
+                let dependents = reverse_deps.entry(parent.clone()).or_insert_with(Vec::new);
+                if !dependents.contains(&tx_id) {
+                    dependents.push(tx_id.clone());
+                }
             }
-            
-            // Check which dependencies are not met
-            let mut deps = self.dependencies.lock().unwrap();
-            let mut unmet_deps = Vec::new();
-            
-            {
+            self.dependencies.lock().unwrap().insert(tx_id.clone(), parents);
+        }
+        
+        // Work out readiness, including that of transactions waiting for this one
+        self.refresh_readiness(&tx_id);
+        self.refresh_dependents(&tx_id);
+        
+        self.record(JournalRecord::Admitted(tx.clone()));
+        
+        Ok(match replaced {
+            Some((replaced, evicted)) => AdmissionOutcome::Replaced { replaced, evicted },
+            None => AdmissionOutcome::Added,
+        })
+    }
+    
+    /// Current minimum tip per byte above the base fee for admission
+    ///
+    /// Rises while the pool is full and decays back towards the configured
+    /// `min_fee_per_byte` with a half-life of `min_fee_half_life` seconds.
+    pub fn min_fee_per_byte(&self) -> u64 {
+        let (raised, since) = *self.rolling_min_fee.lock().unwrap();
+        let floor = self.config.min_fee_per_byte;
+        if raised <= floor || self.config.min_fee_half_life == 0 {
+            return floor;
+        }
+        
+        let half_lives = since.elapsed().as_secs_f64() / self.config.min_fee_half_life as f64;
+        floor + ((raised - floor) as f64 * 0.5f64.powf(half_lives)).round() as u64
+    }
+    
+    /// Lowest tip currently admitted for a transaction carrying `data_size`
+    /// bytes of data, on top of the base fee of its shard
+    pub fn minimum_fee(&self, data_size: usize) -> u64 {
+        self.min_fee_per_byte() * (BASE_TX_SIZE + data_size) as u64
+    }
+    
+    /// Raise the rolling minimum fee per byte
+    fn raise_min_fee(&self, fee_per_byte: u64) {
+        let current = self.min_fee_per_byte();
+        *self.rolling_min_fee.lock().unwrap() = (current.max(fee_per_byte), Instant::now());
+    }
+    
+    /// Evict the cheapest transactions until there is room for `incoming`
+    ///
+    /// Only transactions tipping less per byte than `incoming` are evicted,
+    /// and never those of its own sender. An incoming transaction that is
+    /// not `ready` by nonce only evicts others that are not either. Each
+    /// eviction raises the rolling minimum fee above the evicted
+    /// transaction's tip.
+    fn make_room(&self, incoming: &MempoolTx, ready: bool) -> Result<()> {
+        while self.size() >= self.config.max_size {
+            let cheapest = {
                 let transactions = self.transactions.lock().unwrap();
-                for dep_id in &tx.dependencies {
-                    if !transactions.contains_key(dep_id) {
-                        unmet_deps.push(dep_id.clone());
+                let queues = self.sender_queues.lock().unwrap();
+                let fee_index = self.fee_index.lock().unwrap();
+                let evictable = |tx: &MempoolTx| {
+                    tx.sender != incoming.sender && (ready || queues.get(&tx.sender)
+                        .map_or(false, |queue| queue.future.contains_key(&tx.transaction.nonce)))
+                };
+                fee_index.iter()
+                    .find(|(_, tx_id)| transactions.get(tx_id).map_or(false, &evictable))
+                    .cloned()
+            };
+            
+            let (fee_per_byte, tx_id) = match cheapest {
+                Some(cheapest) if cheapest.0 < incoming.fee_per_byte => cheapest,
+                cheapest => {
+                    if let Some((fee_per_byte, _)) = cheapest {
+                        self.raise_min_fee(fee_per_byte + 1);
                     }
+                    return Err(Error::BlockValidation("Mempool is full".to_string()));
+                },
+            };
+            
+            self.raise_min_fee(fee_per_byte + 1);
+            let evicted = self.evict(&tx_id);
+            log::debug!("Evicted {} transactions tipping {} per byte to make room", evicted.len(), fee_per_byte);
+        }
+        
+        Ok(())
+    }
+    
+    /// Evict a transaction together with its sender's later nonces, which
+    /// could no longer execute, and everything depending on them
+    fn evict(&self, tx_id: &[u8]) -> Vec<Vec<u8>> {
+        let later_nonces: Vec<Vec<u8>> = {
+            let transactions = self.transactions.lock().unwrap();
+            let queues = self.sender_queues.lock().unwrap();
+            transactions.get(tx_id)
+                .and_then(|mempool_tx| queues.get(&mempool_tx.sender).map(|queue| (mempool_tx.transaction.nonce, queue)))
+                .map(|(nonce, queue)| queue.ready.range(nonce + 1..)
+                    .chain(queue.future.range(nonce + 1..))
+                    .map(|(_, id)| id.clone())
+                    .collect())
+                .unwrap_or_default()
+        };
+        
+        let mut evicted = self.remove_with_dependents(tx_id);
+        for id in later_nonces {
+            if evicted.contains(&id) {
+                continue;
+            }
+            for removed in self.remove_with_dependents(&id) {
+                if !evicted.contains(&removed) {
+                    evicted.push(removed);
                 }
             }
-            
-            if !unmet_deps.is_empty() {
-                deps.insert(tx_id.clone(), unmet_deps);
-            } else {
-                // All dependencies are met, mark as ready
-                let mut transactions = self.transactions.lock().unwrap();
-                if let Some(tx) = transactions.get_mut(&tx_id) {
-                    tx.ready = true;
+        }
+        
+        evicted
+    }
+    
+    /// Lowest fee that replaces the pending transaction with the given ID
+    pub fn replacement_fee(&self, tx_id: &[u8]) -> Option<u32> {
+        self.get_transaction(tx_id)
+            .map(|tx| minimum_replacement_fee(tx.fee, self.config.replacement_fee_bump))
+    }
+    
+    /// Remove a transaction and every pending transaction that depends on it,
+    /// directly or transitively, returning the removed IDs
+    fn remove_with_dependents(&self, tx_id: &[u8]) -> Vec<Vec<u8>> {
+        let mut removed = vec![tx_id.to_vec()];
+        {
+            let reverse_deps = self.reverse_dependencies.lock().unwrap();
+            let mut index = 0;
+            while index < removed.len() {
+                if let Some(dependents) = reverse_deps.get(&removed[index]) {
+                    for dependent in dependents {
+                        if !removed.contains(dependent) {
+                            removed.push(dependent.clone());
+                        }
+                    }
                 }
+                index += 1;
             }
         }
         
-        Ok(())
+        for id in &removed {
+            let _ = self.remove_transaction(id);
+        }
+        
+        removed
     }
     
     /// Get a transaction by its ID
@@ -295,12 +1009,30 @@ impl Mempool {
                 transactions.remove(tx_id);
             }
             
+            // Remove from the sender queue
+            {
+                let has_state = self.state_db.lock().unwrap().is_some();
+                let mut queues = self.sender_queues.lock().unwrap();
+                if let Some(queue) = queues.get_mut(&mempool_tx.sender) {
+                    queue.remove(mempool_tx.transaction.nonce, tx_id);
+                    if has_state && queue.is_empty() {
+                        queues.remove(&mempool_tx.sender);
+                    }
+                }
+            }
+            
             // Remove from priority index
             {
                 let mut priority_index = self.priority_index.lock().unwrap();
                 priority_index.remove(&TxPriorityOrder::new(&mempool_tx));
             }
             
+            // Remove from fee index
+            {
+                let mut fee_index = self.fee_index.lock().unwrap();
+                fee_index.remove(&(mempool_tx.fee_per_byte, tx_id.to_vec()));
+            }
+            
             // Remove from shard index
             {
                 let mut shard_index = self.shard_index.lock().unwrap();
@@ -309,44 +1041,91 @@ impl Mempool {
                 }
             }
             
-            // Update dependency tracking
-            {
-                let mut deps = self.dependencies.lock().unwrap();
-                deps.remove(tx_id);
-            }
-            
-            // Update reverse dependencies
-            {
+            // Stop tracking this transaction's own dependencies
+            let parents = self.dependencies.lock().unwrap().remove(tx_id);
+            if let Some(parents) = parents {
                 let mut reverse_deps = self.reverse_dependencies.lock().unwrap();
-                if let Some(dependent_txs) = reverse_deps.remove(tx_id) {
-                    let mut deps = self.dependencies.lock().unwrap();
-                    
-                    // For each transaction that depends on this one
-                    for dep_tx_id in dependent_txs {
-                        if let Some(unmet_deps) = deps.get_mut(&dep_tx_id) {
-                            // Remove this transaction from the unmet dependencies
-                            unmet_deps.retain(|id| id != tx_id);
-                            
-                            // If all dependencies are now met, mark as ready
-                            if unmet_deps.is_empty() {
-                                deps.remove(&dep_tx_id);
-                                
-                                let mut transactions = self.transactions.lock().unwrap();
-                                if let Some(tx) = transactions.get_mut(&dep_tx_id) {
-                                    tx.ready = true;
-                                }
-                            }
+                for parent in parents {
+                    if let Some(dependents) = reverse_deps.get_mut(&parent) {
+                        dependents.retain(|id| id != tx_id);
+                        if dependents.is_empty() {
+                            reverse_deps.remove(&parent);
                         }
                     }
                 }
             }
             
+            // Transactions depending on this one lose a pending parent
+            self.refresh_dependents(tx_id);
+            
+            self.record(JournalRecord::Removed(tx_id.to_vec()));
+            
             Ok(())
         } else {
             Err(Error::State(format!("Transaction {:?} not found in mempool", tx_id)))
         }
     }
     
+    /// Record that a transaction was included in a canonical block
+    ///
+    /// `state_root` is the state root of the block that included it, which
+    /// state dependencies on it are checked against. The transaction leaves
+    /// the mempool and its dependents are re-evaluated.
+    pub fn mark_included(&self, tx_id: &[u8], state_root: Option<Vec<u8>>) {
+        self.included.lock().unwrap().insert(tx_id.to_vec(), state_root);
+        if self.remove_transaction(tx_id).is_err() {
+            self.refresh_dependents(tx_id);
+        }
+    }
+    
+    /// Record that a transaction's block left the canonical chain
+    pub fn mark_reverted(&self, tx_id: &[u8]) {
+        if self.included.lock().unwrap().remove(tx_id) {
+            self.refresh_dependents(tx_id);
+        }
+    }
+    
+    /// Check if a transaction is known to be included in the canonical chain
+    pub fn is_included(&self, tx_id: &[u8]) -> bool {
+        self.included.lock().unwrap().contains(tx_id)
+    }
+    
+    /// Recompute whether a pending transaction's dependencies are met
+    ///
+    /// A hard dependency is met while its parent is pending, since the parent
+    /// can be placed ahead of it in the same block, or once the parent is
+    /// included. A state dependency is met once its parent is included and,
+    /// if it names a `required_state`, the parent produced that state root.
+    fn refresh_readiness(&self, tx_id: &[u8]) {
+        let mut transactions = self.transactions.lock().unwrap();
+        let included = self.included.lock().unwrap();
+        
+        let ready = match transactions.get(tx_id) {
+            Some(mempool_tx) => mempool_tx.transaction.dependencies.iter().all(|dep| match dep.dependency_type {
+                DependencyType::Hard => included.contains(&dep.transaction_id)
+                    || transactions.contains_key(&dep.transaction_id),
+                DependencyType::State => included.contains(&dep.transaction_id)
+                    && dep.required_state.as_ref().map_or(true, |required| {
+                        included.state_root(&dep.transaction_id) == Some(required)
+                    }),
+                DependencyType::Soft => true,
+            }),
+            None => return,
+        };
+        
+        if let Some(mempool_tx) = transactions.get_mut(tx_id) {
+            mempool_tx.ready = ready;
+        }
+    }
+    
+    /// Recompute the readiness of the transactions depending on `tx_id`
+    fn refresh_dependents(&self, tx_id: &[u8]) {
+        let dependents = self.reverse_dependencies.lock().unwrap().get(tx_id).cloned();
+        for dependent in dependents.unwrap_or_default() {
+            self.refresh_readiness(&dependent);
+        }
+    }
+    
     /// Get the number of transactions in the mempool
     pub fn size(&self) -> usize {
         let transactions = self.transactions.lock().unwrap();
@@ -355,41 +1134,106 @@ impl Mempool {
     
     /// Get a batch of transactions for inclusion in a block, filtered by shard
     pub fn get_transactions_for_block(&self, shard_id: ShardId, max_count: usize) -> Vec<Arc<Transaction>> {
-        let mut result = Vec::with_capacity(max_count);
-        let mut included_tx_ids = Vec::new();
+        self.select_ready(|tx| tx.sender_shard == shard_id, max_count)
+    }
+    
+    /// Get all ready transactions for the given shards, highest priority first
+    pub fn get_block_candidates(&self, shard_ids: &[ShardId]) -> Vec<Arc<Transaction>> {
+        self.select_ready(|tx| shard_ids.contains(&tx.sender_shard), usize::MAX)
+    }
+    
+    /// Get up to `max_count` ready transactions from any shard, highest
+    /// priority first
+    pub fn ready_transactions(&self, max_count: usize) -> Vec<Arc<Transaction>> {
+        self.select_ready(|_| true, max_count)
+    }
+    
+    /// Select ready transactions by priority without breaking the nonce
+    /// order of any sender or placing a transaction ahead of a pending hard
+    /// dependency
+    ///
+    /// Only the lowest pending nonce of each sender competes at a time; once
+    /// it is selected, the sender's next ready transaction becomes eligible.
+    /// A transaction that is not eligible blocks the rest of its sender's queue,
+    /// and one whose pending parent has not been selected yet waits for it.
+    /// Transactions are parked until they are valid in the block after the
+    /// head; the head's timestamp stands in for that block's, which is later.
+    fn select_ready<F>(&self, eligible: F, max_count: usize) -> Vec<Arc<Transaction>>
+    where
+        F: Fn(&Transaction) -> bool,
+    {
+        let (height, timestamp) = self.chain_tip();
+        let transactions = self.transactions.lock().unwrap();
+        let queues = self.sender_queues.lock().unwrap();
         
-        // Get transactions from priority index, filtered by readiness and shard
-        {
-            let transactions = self.transactions.lock().unwrap();
-            let priority_index = self.priority_index.lock().unwrap();
-            
-            for priority_tx in priority_index.iter().rev() {
-                if result.len() >= max_count {
-                    break;
-                }
-                
-                if let Some(mempool_tx) = transactions.get(&priority_tx.tx_id) {
-                    if mempool_tx.ready && mempool_tx.transaction.sender_shard == shard_id {
-                        result.push(mempool_tx.transaction.clone());
-                        included_tx_ids.push(mempool_tx.transaction.id.clone());
-                    }
+        // Heads of every sender's ready queue, parked while a pending hard
+        // parent is still unselected
+        let mut cursors = HashMap::new();
+        let mut heap = BinaryHeap::new();
+        let mut waiting: HashMap<Vec<u8>, Vec<&Vec<u8>>> = HashMap::new();
+        let mut selected: HashSet<Vec<u8>> = HashSet::new();
+        let mut advance: Vec<&Vec<u8>> = queues.keys().collect();
+        for (sender, queue) in queues.iter() {
+            cursors.insert(sender, queue.ready.values().peekable());
+        }
+        
+        let mut result = Vec::new();
+        loop {
+            // Bring the next transaction of each advanced sender into contention
+            while let Some(sender) = advance.pop() {
+                let ids = cursors.get_mut(sender).unwrap();
+                let head = match ids.peek().and_then(|tx_id| transactions.get(*tx_id)) {
+                    Some(head) if head.ready
+                        && eligible(&head.transaction)
+                        && head.transaction.check_time_bounds(height + 1, timestamp).is_ok() => head,
+                    _ => continue,
+                };
+                let unselected_parent = head.transaction.dependencies.iter()
+                    .filter(|dep| matches!(dep.dependency_type, DependencyType::Hard))
+                    .map(|dep| &dep.transaction_id)
+                    .find(|parent| transactions.contains_key(*parent) && !selected.contains(*parent));
+                match unselected_parent {
+                    Some(parent) => waiting.entry(parent.clone()).or_insert_with(Vec::new).push(sender),
+                    None => heap.push((TxPriorityOrder::new(head), sender)),
                 }
             }
+            
+            if result.len() >= max_count {
+                break;
+            }
+            let (head, sender) = match heap.pop() {
+                Some(entry) => entry,
+                None => break,
+            };
+            if let Some(mempool_tx) = transactions.get(&head.tx_id) {
+                result.push(mempool_tx.transaction.clone());
+            }
+            
+            cursors.get_mut(sender).unwrap().next();
+            advance.push(sender);
+            if let Some(senders) = waiting.remove(&head.tx_id) {
+                advance.extend(senders);
+            }
+            selected.insert(head.tx_id);
         }
         
         result
     }
     
     /// Remove expired transactions from the mempool
+    ///
+    /// A transaction expires when it has waited longer than `max_age` or its
+    /// `valid_until` bound has passed at the canonical head.
     pub fn remove_expired(&self) -> usize {
         let max_age = Duration::from_secs(self.config.max_age);
+        let (height, timestamp) = self.chain_tip();
         let mut expired_tx_ids = Vec::new();
         
         // Find expired transactions
         {
             let transactions = self.transactions.lock().unwrap();
             for (tx_id, mempool_tx) in transactions.iter() {
-                if mempool_tx.is_expired(max_age) {
+                if mempool_tx.is_expired(max_age) || mempool_tx.transaction.is_expired_after(height, timestamp) {
                     expired_tx_ids.push(tx_id.clone());
                 }
             }
@@ -405,39 +1249,56 @@ impl Mempool {
     
     /// Clear all transactions from the mempool
     pub fn clear(&self) {
-        let mut transactions = self.transactions.lock().unwrap();
-        let mut priority_index = self.priority_index.lock().unwrap();
-        let mut shard_index = self.shard_index.lock().unwrap();
-        let mut dependencies = self.dependencies.lock().unwrap();
-        let mut reverse_dependencies = self.reverse_dependencies.lock().unwrap();
+        {
+            let mut transactions = self.transactions.lock().unwrap();
+            let mut priority_index = self.priority_index.lock().unwrap();
+            let mut fee_index = self.fee_index.lock().unwrap();
+            let mut shard_index = self.shard_index.lock().unwrap();
+            let mut dependencies = self.dependencies.lock().unwrap();
+            let mut reverse_dependencies = self.reverse_dependencies.lock().unwrap();
+            let mut included = self.included.lock().unwrap();
+            let mut sender_queues = self.sender_queues.lock().unwrap();
+            
+            transactions.clear();
+            priority_index.clear();
+            fee_index.clear();
+            shard_index.clear();
+            dependencies.clear();
+            reverse_dependencies.clear();
+            *included = IncludedCache::new(self.config.max_size);
+            sender_queues.clear();
+        }
         
-        transactions.clear();
-        priority_index.clear();
-        shard_index.clear();
-        dependencies.clear();
-        reverse_dependencies.clear();
+        let journal = self.journal.lock().unwrap().clone();
+        if let Some(journal) = journal {
+            if let Err(e) = journal.compact(Vec::new) {
+                log::warn!("Failed to clear the mempool journal: {}", e);
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::crypto::signature::Signature;
+    use crate::blockchain::Dependency;
+    use crate::crypto::signature::{KeyPair, Signature};
     use crate::types::{DataType, TransactionType};
     
     fn create_test_transaction(
-        id: Vec<u8>,
+        seed: Vec<u8>,
         sender_shard: ShardId,
         amount: u64,
         fee: u32,
         priority: Priority,
         dependencies: Vec<Vec<u8>>,
     ) -> Transaction {
-        let sender_key = vec![1; 32];
+        // Each test transaction gets its own sender so nonces do not collide
+        let sender = KeyPair::from_seed(&[seed[0]; 32]).unwrap();
         let recipient = vec![2; 20];
         
         let mut tx = Transaction::new_transfer(
-            sender_key,
+            sender.public_key(),
             sender_shard,
             recipient,
             sender_shard, // same shard for simplicity
@@ -446,10 +1307,11 @@ mod tests {
             0, // nonce
         );
         
-        // Override the auto-generated ID
-        tx.id = id;
         tx.execution_priority = priority;
-        tx.dependencies = dependencies;
+        for dependency in dependencies {
+            tx.add_dependency(dependency, DependencyType::Hard);
+        }
+        tx.sign(&sender, DEFAULT_CHAIN_ID);
         
         tx
     }
@@ -467,7 +1329,7 @@ mod tests {
             vec![1, 2, 3, 4],
             0, // shard 0
             1000,
-            10,
+            1000,
             Priority::Normal,
             Vec::new(),
         ));
@@ -495,7 +1357,7 @@ mod tests {
             vec![1, 1, 1, 1],
             0,
             1000,
-            10,
+            1000,
             Priority::Low,
             Vec::new(),
         );
@@ -504,7 +1366,7 @@ mod tests {
             vec![2, 2, 2, 2],
             0,
             1000,
-            20, // higher fee
+            2000, // higher fee
             Priority::Normal,
             Vec::new(),
         );
@@ -513,7 +1375,7 @@ mod tests {
             vec![3, 3, 3, 3],
             0,
             1000,
-            5, // lower fee
+            500, // lower fee
             Priority::High,
             Vec::new(),
         );
@@ -532,23 +1394,49 @@ mod tests {
         assert_eq!(transactions[2].id, tx1.id); // Low priority
     }
     
+    #[test]
+    fn test_equal_fees_selected_in_admission_order() {
+        let mempool = Mempool::new(MempoolConfig::default());
+        let txs: Vec<Transaction> = (1..=3u8)
+            .map(|seed| signed_transfer(&KeyPair::from_seed(&[seed; 32]).unwrap(), 0, 1000))
+            .collect();
+        for tx in &txs {
+            mempool.add_transaction(tx).unwrap();
+        }
+        
+        let ids: Vec<Vec<u8>> = mempool.get_transactions_for_block(0, 10).iter().map(|tx| tx.id.clone()).collect();
+        assert_eq!(ids, txs.iter().map(|tx| tx.id.clone()).collect::<Vec<_>>());
+        
+        // Removal finds the indexed entry again
+        assert_eq!(mempool.priority_index.lock().unwrap().len(), 3);
+        mempool.remove_transaction(&txs[1].id).unwrap();
+        assert_eq!(mempool.priority_index.lock().unwrap().len(), 2);
+    }
+    
     #[test]
     fn test_mempool_dependencies() {
         let config = MempoolConfig::default();
         let mempool = Mempool::new(config);
         
         // Create transaction with a dependency
-        let tx1_id = vec![1, 1, 1, 1];
-        let tx2_id = vec![2, 2, 2, 2];
+        let tx1 = create_test_transaction(
+            vec![1, 1, 1, 1],
+            0,
+            500,
+            500,
+            Priority::Normal,
+            Vec::new(),
+        );
         
         let tx2 = create_test_transaction(
-            tx2_id.clone(),
+            vec![2, 2, 2, 2],
             0,
             1000,
-            10,
+            1000,
             Priority::Normal,
-            vec![tx1_id.clone()], // depends on tx1
+            vec![tx1.id.clone()], // depends on tx1
         );
+        let tx2_id = tx2.id.clone();
         
         // Add tx2 first (which depends on tx1)
         mempool.add_transaction(&tx2).unwrap();
@@ -561,15 +1449,6 @@ mod tests {
         }
         
         // Now add tx1
-        let tx1 = create_test_transaction(
-            tx1_id.clone(),
-            0,
-            500,
-            5,
-            Priority::Normal,
-            Vec::new(),
-        );
-        
         mempool.add_transaction(&tx1).unwrap();
         
         // Check that tx2 is now ready
@@ -586,7 +1465,7 @@ mod tests {
         assert_eq!(block_txs.len(), 2);
         
         // Remove tx1
-        mempool.remove_transaction(&tx1_id).unwrap();
+        mempool.remove_transaction(&tx1.id).unwrap();
         
         // Check that tx2 is not ready again
         {
@@ -610,7 +1489,7 @@ mod tests {
             vec![1, 1, 1, 1],
             0, // shard 0
             1000,
-            10,
+            1000,
             Priority::Normal,
             Vec::new(),
         );
@@ -619,7 +1498,7 @@ mod tests {
             vec![2, 2, 2, 2],
             1, // shard 1
             1000,
-            10,
+            1000,
             Priority::Normal,
             Vec::new(),
         );
@@ -628,7 +1507,7 @@ mod tests {
             vec![3, 3, 3, 3],
             0, // shard 0
             1000,
-            10,
+            1000,
             Priority::Normal,
             Vec::new(),
         );
@@ -662,7 +1541,7 @@ mod tests {
             vec![1, 2, 3, 4],
             0,
             1000,
-            10,
+            1000,
             Priority::Normal,
             Vec::new(),
         );
@@ -694,7 +1573,7 @@ mod tests {
             vec![1, 1, 1, 1],
             0,
             1000,
-            10,
+            1000,
             Priority::Normal,
             Vec::new(),
         );
@@ -703,7 +1582,7 @@ mod tests {
             vec![2, 2, 2, 2],
             0,
             1000,
-            10,
+            1000,
             Priority::Normal,
             Vec::new(),
         );
@@ -717,13 +1596,552 @@ mod tests {
             vec![3, 3, 3, 3],
             0,
             1000,
-            10,
+            1000,
             Priority::Normal,
             Vec::new(),
         );
         
         // Should fail because mempool is full
-        let result = mempool.add_transaction(tx3);
+        let result = mempool.add_transaction(&tx3);
         assert!(result.is_err());
     }
+    
+    #[test]
+    fn test_mempool_rejects_invalid_signatures() {
+        let mempool = Mempool::new(MempoolConfig::default());
+        
+        // Signed for another chain
+        let sender = KeyPair::from_seed(&[1; 32]).unwrap();
+        let mut tx = Transaction::new_transfer(sender.public_key(), 0, vec![2; 20], 0, 1000, 1000, 0);
+        tx.sign(&sender, "sebure-devnet");
+        assert!(mempool.add_transaction(&tx).is_err());
+        
+        // Modified after signing
+        tx.sign(&sender, DEFAULT_CHAIN_ID);
+        tx.amount += 1;
+        assert!(mempool.add_transaction(&tx).is_err());
+        
+        tx.amount -= 1;
+        
+        // Signed, but under an ID that does not match its contents
+        let mut renamed = tx.clone();
+        renamed.id = vec![1; 32];
+        assert!(mempool.add_transaction(&renamed).is_err());
+        
+        assert!(mempool.add_transaction(&tx).is_ok());
+    }
+    
+    fn signed_transfer(sender: &KeyPair, nonce: u64, fee: u32) -> Transaction {
+        let mut tx = Transaction::new_transfer(sender.public_key(), 0, vec![2; 20], 0, 1000, fee, nonce);
+        tx.sign(sender, DEFAULT_CHAIN_ID);
+        tx
+    }
+    
+    #[test]
+    fn test_sender_queues_promote_when_gaps_fill() {
+        let mempool = Mempool::new(MempoolConfig::default());
+        let sender = KeyPair::from_seed(&[7; 32]).unwrap();
+        let address = address_from_public_key(&sender.public_key());
+        
+        let tx0 = signed_transfer(&sender, 0, 1000);
+        let tx1 = signed_transfer(&sender, 1, 1000);
+        let tx2 = signed_transfer(&sender, 2, 5000);
+        
+        mempool.add_transaction(&tx0).unwrap();
+        mempool.add_transaction(&tx2).unwrap();
+        assert_eq!(mempool.sender_pending(&address), (1, 1));
+        assert_eq!(mempool.next_nonce(&address), Some(1));
+        
+        // The gapped transaction is not a block candidate
+        let candidates = mempool.get_block_candidates(&[0]);
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].id, tx0.id);
+        
+        // Filling the gap promotes it, and the higher fee does not jump the queue
+        mempool.add_transaction(&tx1).unwrap();
+        assert_eq!(mempool.sender_pending(&address), (3, 0));
+        let ids: Vec<Vec<u8>> = mempool.get_block_candidates(&[0]).iter().map(|tx| tx.id.clone()).collect();
+        assert_eq!(ids, vec![tx0.id.clone(), tx1.id.clone(), tx2.id.clone()]);
+        
+        // Same nonces are rejected unless the fee is raised enough
+        let underpriced = signed_transfer(&sender, 1, 1050);
+        assert!(mempool.add_transaction(&underpriced).is_err());
+        
+        // Removing a ready transaction demotes the ones after it
+        mempool.remove_transaction(&tx1.id).unwrap();
+        assert_eq!(mempool.sender_pending(&address), (1, 1));
+    }
+    
+    #[test]
+    fn test_block_selection_keeps_sender_nonce_order() {
+        let mempool = Mempool::new(MempoolConfig::default());
+        let alice = KeyPair::from_seed(&[7; 32]).unwrap();
+        let bob = KeyPair::from_seed(&[8; 32]).unwrap();
+        
+        let a0 = signed_transfer(&alice, 0, 1000);
+        let a1 = signed_transfer(&alice, 1, 9000);
+        let b0 = signed_transfer(&bob, 0, 5000);
+        for tx in [&a1, &a0, &b0] {
+            mempool.add_transaction(tx).unwrap();
+        }
+        
+        // b0 outbids a0; a1 pays the most but has to wait for a0
+        let ids: Vec<Vec<u8>> = mempool.get_transactions_for_block(0, 10).iter().map(|tx| tx.id.clone()).collect();
+        assert_eq!(ids, vec![b0.id.clone(), a0.id.clone(), a1.id.clone()]);
+        
+        let ids: Vec<Vec<u8>> = mempool.get_transactions_for_block(0, 2).iter().map(|tx| tx.id.clone()).collect();
+        assert_eq!(ids, vec![b0.id.clone(), a0.id.clone()]);
+    }
+    
+    #[test]
+    fn test_account_nonce_updates_drop_stale_transactions() {
+        let mempool = Mempool::new(MempoolConfig::default());
+        let sender = KeyPair::from_seed(&[7; 32]).unwrap();
+        let address = address_from_public_key(&sender.public_key());
+        
+        let tx1 = signed_transfer(&sender, 1, 1000);
+        let tx2 = signed_transfer(&sender, 2, 1000);
+        mempool.add_transaction(&tx1).unwrap();
+        mempool.add_transaction(&tx2).unwrap();
+        assert_eq!(mempool.sender_pending(&address), (0, 2));
+        
+        // Nonce 0 was included in a block elsewhere
+        mempool.set_account_nonce(&address, 1);
+        assert_eq!(mempool.sender_pending(&address), (2, 0));
+        
+        // Nonce 1 was replaced on chain by another transaction
+        mempool.set_account_nonce(&address, 2);
+        assert_eq!(mempool.sender_pending(&address), (1, 0));
+        assert!(mempool.get_transaction(&tx1.id).is_none());
+        
+        // Nonces below the account nonce are refused
+        let stale = signed_transfer(&sender, 1, 1000);
+        assert!(mempool.add_transaction(&stale).is_err());
+    }
+    
+    #[test]
+    fn test_account_nonce_read_from_state() {
+        let mempool = Mempool::new(MempoolConfig::default());
+        let state_db = Arc::new(StateDB::new("", &crate::storage::StorageConfig::default()).unwrap());
+        mempool.set_state_db(state_db.clone());
+        
+        let sender = KeyPair::from_seed(&[7; 32]).unwrap();
+        let address = address_from_public_key(&sender.public_key());
+        state_db.set_account_nonce(&address, 3).unwrap();
+        
+        assert!(mempool.add_transaction(&signed_transfer(&sender, 2, 1000)).is_err());
+        mempool.add_transaction(&signed_transfer(&sender, 3, 1000)).unwrap();
+        assert_eq!(mempool.sender_pending(&address), (1, 0));
+    }
+    
+    #[test]
+    fn test_replace_by_fee() {
+        let mempool = Mempool::new(MempoolConfig::default());
+        let sender = KeyPair::from_seed(&[7; 32]).unwrap();
+        let address = address_from_public_key(&sender.public_key());
+        
+        let original = signed_transfer(&sender, 0, 1000);
+        assert_eq!(mempool.admit(&original).unwrap(), AdmissionOutcome::Added);
+        assert_eq!(mempool.admit(&original).unwrap(), AdmissionOutcome::Duplicate);
+        assert_eq!(mempool.replacement_fee(&original.id), Some(1100));
+        
+        // A 5% bump is not enough
+        let cheap = signed_transfer(&sender, 0, 1050);
+        assert_eq!(mempool.admit(&cheap).unwrap(), AdmissionOutcome::Underpriced { minimum_fee: 1100, minimum_priority_fee: 1100 });
+        assert!(mempool.get_transaction(&original.id).is_some());
+        
+        // Raising only the cap leaves the tip, and the pending transaction, in place
+        let mut capped = signed_transfer(&sender, 0, 2000);
+        capped.priority_fee = 1000;
+        capped.sign(&sender, DEFAULT_CHAIN_ID);
+        assert_eq!(mempool.admit(&capped).unwrap(), AdmissionOutcome::Underpriced { minimum_fee: 1100, minimum_priority_fee: 1100 });
+        assert!(mempool.get_transaction(&original.id).is_some());
+        
+        // A 10% bump replaces the pending transaction
+        let bumped = signed_transfer(&sender, 0, 1100);
+        assert_eq!(mempool.admit(&bumped).unwrap(), AdmissionOutcome::Replaced {
+            replaced: original.id.clone(),
+            evicted: Vec::new(),
+        });
+        assert!(mempool.get_transaction(&original.id).is_none());
+        assert!(mempool.get_transaction(&bumped.id).is_some());
+        assert_eq!(mempool.sender_pending(&address), (1, 0));
+        assert_eq!(mempool.size(), 1);
+    }
+    
+    #[test]
+    fn test_minimum_replacement_fee() {
+        assert_eq!(minimum_replacement_fee(1000, 10), 1100);
+        assert_eq!(minimum_replacement_fee(1001, 10), 1102);
+        assert_eq!(minimum_replacement_fee(5, 0), 6);
+        assert_eq!(minimum_replacement_fee(u32::MAX, 10), u32::MAX);
+    }
+    
+    #[test]
+    fn test_full_pool_evicts_cheapest_chains() {
+        let mempool = Mempool::new(MempoolConfig {
+            max_size: 3,
+            ..MempoolConfig::default()
+        });
+        let alice = KeyPair::from_seed(&[7; 32]).unwrap();
+        let bob = KeyPair::from_seed(&[8; 32]).unwrap();
+        let carol = KeyPair::from_seed(&[9; 32]).unwrap();
+        
+        let a0 = signed_transfer(&alice, 0, 1000);
+        let a1 = signed_transfer(&alice, 1, 3000);
+        let b0 = signed_transfer(&bob, 0, 2000);
+        for tx in [&a0, &a1, &b0] {
+            mempool.add_transaction(tx).unwrap();
+        }
+        assert_eq!(mempool.min_fee_per_byte(), 1);
+        
+        // Paying no more than the cheapest pending transaction is refused
+        let cheap = signed_transfer(&carol, 0, 1000);
+        assert!(mempool.add_transaction(&cheap).is_err());
+        assert_eq!(mempool.size(), 3);
+        
+        // A better-paying transaction evicts a0 along with alice's later nonce
+        let rich = signed_transfer(&carol, 0, 5000);
+        mempool.add_transaction(&rich).unwrap();
+        assert_eq!(mempool.size(), 2);
+        assert!(mempool.get_transaction(&a0.id).is_none());
+        assert!(mempool.get_transaction(&a1.id).is_none());
+        assert!(mempool.get_transaction(&b0.id).is_some());
+        
+        // The evicted fee level is no longer admitted, even with room to spare
+        let fee_per_byte = 1000 / BASE_TX_SIZE as u64;
+        assert_eq!(mempool.min_fee_per_byte(), fee_per_byte + 1);
+        assert_eq!(mempool.minimum_fee(0), (fee_per_byte + 1) * BASE_TX_SIZE as u64);
+        assert!(mempool.add_transaction(&signed_transfer(&alice, 0, 1000)).is_err());
+    }
+    
+    #[test]
+    fn test_full_pool_keeps_ready_transactions_for_future_ones() {
+        let mempool = Mempool::new(MempoolConfig {
+            max_size: 2,
+            ..MempoolConfig::default()
+        });
+        let alice = KeyPair::from_seed(&[7; 32]).unwrap();
+        let bob = KeyPair::from_seed(&[8; 32]).unwrap();
+        let carol = KeyPair::from_seed(&[9; 32]).unwrap();
+        
+        let a0 = signed_transfer(&alice, 0, 1000);
+        let b5 = signed_transfer(&bob, 5, 2000);
+        mempool.add_transaction(&a0).unwrap();
+        mempool.add_transaction(&b5).unwrap();
+        mempool.set_account_nonce(&address_from_public_key(&carol.public_key()), 1);
+        
+        // A stale nonce is refused without evicting anything
+        assert!(mempool.add_transaction(&signed_transfer(&carol, 0, 9000)).is_err());
+        assert_eq!(mempool.size(), 2);
+        assert_eq!(mempool.min_fee_per_byte(), 1);
+        
+        // A gapped transaction only displaces another gapped one, however
+        // much it pays
+        let c3 = signed_transfer(&carol, 3, 3000);
+        mempool.add_transaction(&c3).unwrap();
+        assert!(mempool.get_transaction(&a0.id).is_some());
+        assert!(mempool.get_transaction(&b5.id).is_none());
+        
+        let c4 = signed_transfer(&carol, 4, 9000);
+        assert!(mempool.add_transaction(&c4).is_err());
+        assert!(mempool.get_transaction(&a0.id).is_some());
+    }
+    
+    #[test]
+    fn test_transactions_ranked_by_tip_above_base_fee() {
+        let mempool = Mempool::new(MempoolConfig {
+            max_size: 2,
+            ..MempoolConfig::default()
+        });
+        let alice = KeyPair::from_seed(&[7; 32]).unwrap();
+        let bob = KeyPair::from_seed(&[8; 32]).unwrap();
+        let carol = KeyPair::from_seed(&[9; 32]).unwrap();
+        let tipping = |sender: &KeyPair, fee: u32, priority_fee: u32| {
+            let mut tx = Transaction::new_transfer(sender.public_key(), 0, vec![2; 20], 0, 1000, fee, 0);
+            tx.priority_fee = priority_fee;
+            tx.sign(sender, DEFAULT_CHAIN_ID);
+            tx
+        };
+        mempool.set_base_fees(HashMap::from([(0, 40_000)]));
+        
+        // a pays the higher fee but b leaves the producer a higher tip
+        let a = tipping(&alice, 90_000, 5_000);
+        let b = tipping(&bob, 50_000, 50_000);
+        mempool.add_transaction(&a).unwrap();
+        mempool.add_transaction(&b).unwrap();
+        let ids: Vec<Vec<u8>> = mempool.get_transactions_for_block(0, 10).iter().map(|tx| tx.id.clone()).collect();
+        assert_eq!(ids, vec![b.id.clone(), a.id.clone()]);
+        
+        // Eviction and the rolling minimum follow the tip as well
+        let c = tipping(&carol, 90_000, 9_000);
+        mempool.add_transaction(&c).unwrap();
+        assert!(mempool.get_transaction(&a.id).is_none());
+        assert_eq!(mempool.min_fee_per_byte(), 5_000 / BASE_TX_SIZE as u64 + 1);
+        
+        // A higher base fee eats into b's tip but not c's
+        mempool.set_base_fees(HashMap::from([(0, 45_000)]));
+        let ids: Vec<Vec<u8>> = mempool.get_transactions_for_block(0, 10).iter().map(|tx| tx.id.clone()).collect();
+        assert_eq!(ids, vec![c.id.clone(), b.id.clone()]);
+    }
+    
+    #[test]
+    fn test_transactions_follow_the_shard_layout() {
+        let mempool = Mempool::new(MempoolConfig::default());
+        let layout = ShardLayout::uniform(2);
+        let sender = KeyPair::from_seed(&[1; 32]).unwrap();
+        let recipient = vec![2; 20];
+        let sender_shard = layout.shard_for_address(&address_from_public_key(&sender.public_key()));
+        let recipient_shard = layout.shard_for_address(&recipient);
+        let transfer = |sender_shard: ShardId, recipient_shard: ShardId| {
+            let mut tx = Transaction::new_transfer(sender.public_key(), sender_shard, recipient.clone(), recipient_shard, 1000, 1000, 0);
+            tx.sign(&sender, DEFAULT_CHAIN_ID);
+            tx
+        };
+        
+        // Any shards are taken until the chain sets a layout
+        let misplaced = transfer(1 - sender_shard, recipient_shard);
+        mempool.add_transaction(&misplaced).unwrap();
+        
+        // The layout drops it and refuses transactions naming the wrong shards
+        assert_eq!(mempool.set_shard_layout(layout), 1);
+        assert!(mempool.get_transaction(&misplaced.id).is_none());
+        assert!(mempool.add_transaction(&transfer(sender_shard, 1 - recipient_shard)).is_err());
+        mempool.add_transaction(&transfer(sender_shard, recipient_shard)).unwrap();
+    }
+    
+    #[test]
+    fn test_min_fee_decays_after_congestion() {
+        let mempool = Mempool::new(MempoolConfig::default());
+        mempool.raise_min_fee(41);
+        assert_eq!(mempool.min_fee_per_byte(), 41);
+        
+        // One half-life later the premium over the floor has halved
+        let half_life = Duration::from_secs(mempool.config.min_fee_half_life);
+        *mempool.rolling_min_fee.lock().unwrap() = (41, Instant::now().checked_sub(half_life).unwrap());
+        assert_eq!(mempool.min_fee_per_byte(), 21);
+        
+        // Long after, it is back at the configured minimum
+        *mempool.rolling_min_fee.lock().unwrap() = (41, Instant::now().checked_sub(half_life * 20).unwrap());
+        assert_eq!(mempool.min_fee_per_byte(), 1);
+    }
+    
+    #[test]
+    fn test_journal_restores_pending_transactions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mempool.journal");
+        let alice = KeyPair::from_seed(&[7; 32]).unwrap();
+        let bob = KeyPair::from_seed(&[8; 32]).unwrap();
+        let alice_address = address_from_public_key(&alice.public_key());
+        
+        let a0 = signed_transfer(&alice, 0, 1000);
+        let a1 = signed_transfer(&alice, 1, 1000);
+        let a2 = signed_transfer(&alice, 2, 1000);
+        let b0 = signed_transfer(&bob, 0, 1000);
+        {
+            let mempool = Mempool::new(MempoolConfig::default());
+            mempool.set_journal(Arc::new(MempoolJournal::open(&path).unwrap()));
+            for tx in [&a0, &a1, &a2, &b0] {
+                mempool.add_transaction(tx).unwrap();
+            }
+            mempool.remove_transaction(&b0.id).unwrap();
+        }
+        
+        // After a restart, a0 has been mined and alice can only afford one more transfer
+        let state_db = Arc::new(StateDB::new("", &crate::storage::StorageConfig::default()).unwrap());
+        state_db.set_account_nonce(&alice_address, 1).unwrap();
+        state_db.set_account_balance(&alice_address, 3000).unwrap();
+        
+        let mempool = Mempool::new(MempoolConfig::default());
+        mempool.set_state_db(state_db);
+        mempool.set_journal(Arc::new(MempoolJournal::open(&path).unwrap()));
+        assert_eq!(mempool.restore_from_journal().unwrap(), 1);
+        assert_eq!(mempool.size(), 1);
+        assert!(mempool.get_transaction(&a1.id).is_some());
+        
+        // The journal now only holds what was restored
+        let journal = MempoolJournal::open(&path).unwrap();
+        let ids: Vec<Vec<u8>> = journal.load().unwrap().into_iter().map(|tx| tx.id).collect();
+        assert_eq!(ids, vec![a1.id.clone()]);
+        assert_eq!(journal.len(), 1);
+        
+        // Later admissions are still recorded
+        let b1 = signed_transfer(&bob, 0, 2000);
+        mempool.add_transaction(&b1).unwrap();
+        let ids: Vec<Vec<u8>> = MempoolJournal::open(&path).unwrap().load().unwrap().into_iter().map(|tx| tx.id).collect();
+        assert_eq!(ids, vec![a1.id.clone(), b1.id.clone()]);
+    }
+    
+    #[test]
+    fn test_journal_is_compacted() {
+        let dir = tempfile::tempdir().unwrap();
+        let mempool = Mempool::new(MempoolConfig {
+            journal_compact_interval: 4,
+            ..MempoolConfig::default()
+        });
+        let journal = Arc::new(MempoolJournal::open(dir.path().join("mempool.journal")).unwrap());
+        mempool.set_journal(journal.clone());
+        
+        let sender = KeyPair::from_seed(&[7; 32]).unwrap();
+        let mut last = Vec::new();
+        for bump in 1..=10 {
+            let tx = signed_transfer(&sender, 0, 1000 * bump);
+            mempool.add_transaction(&tx).unwrap();
+            assert!(journal.len() < mempool.size() + 4);
+            last = tx.id;
+        }
+        
+        let pending = journal.load().unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].id, last);
+    }
+    
+    fn dependent_transfer(sender: &KeyPair, fee: u32, dependency: Dependency) -> Transaction {
+        let mut tx = Transaction::new_transfer(sender.public_key(), 0, vec![2; 20], 0, 1000, fee, 0);
+        tx.dependencies.push(dependency);
+        tx.sign(sender, DEFAULT_CHAIN_ID);
+        tx
+    }
+    
+    fn depends_on(parent: &Transaction, dependency_type: DependencyType, required_state: Option<Vec<u8>>) -> Dependency {
+        Dependency {
+            transaction_id: parent.id.clone(),
+            dependency_type,
+            required_state,
+        }
+    }
+    
+    #[test]
+    fn test_hard_dependency_selected_after_parent() {
+        let mempool = Mempool::new(MempoolConfig::default());
+        let alice = KeyPair::from_seed(&[7; 32]).unwrap();
+        let bob = KeyPair::from_seed(&[8; 32]).unwrap();
+        
+        // The child pays more but must follow its parent
+        let parent = signed_transfer(&alice, 0, 1000);
+        let child = dependent_transfer(&bob, 9000, depends_on(&parent, DependencyType::Hard, None));
+        mempool.add_transaction(&child).unwrap();
+        assert!(mempool.get_block_candidates(&[0]).is_empty());
+        
+        mempool.add_transaction(&parent).unwrap();
+        let ids: Vec<Vec<u8>> = mempool.get_block_candidates(&[0]).iter().map(|tx| tx.id.clone()).collect();
+        assert_eq!(ids, vec![parent.id.clone(), child.id.clone()]);
+        
+        // The parent wins the only slot despite paying less
+        assert_eq!(mempool.get_transactions_for_block(0, 1)[0].id, parent.id);
+        
+        // Once the parent is included, the child stands on its own
+        mempool.mark_included(&parent.id, None);
+        assert!(mempool.get_transaction(&parent.id).is_none());
+        let ids: Vec<Vec<u8>> = mempool.get_block_candidates(&[0]).iter().map(|tx| tx.id.clone()).collect();
+        assert_eq!(ids, vec![child.id.clone()]);
+        
+        // Reverting the parent's block leaves the child waiting again
+        mempool.mark_reverted(&parent.id);
+        assert!(mempool.get_block_candidates(&[0]).is_empty());
+    }
+    
+    #[test]
+    fn test_parent_expiry_blocks_dependents() {
+        let mempool = Mempool::new(MempoolConfig {
+            max_age: 1,
+            ..MempoolConfig::default()
+        });
+        let alice = KeyPair::from_seed(&[7; 32]).unwrap();
+        let bob = KeyPair::from_seed(&[8; 32]).unwrap();
+        
+        let parent = signed_transfer(&alice, 0, 1000);
+        mempool.add_transaction(&parent).unwrap();
+        std::thread::sleep(Duration::from_millis(700));
+        let child = dependent_transfer(&bob, 1000, depends_on(&parent, DependencyType::Hard, None));
+        mempool.add_transaction(&child).unwrap();
+        assert_eq!(mempool.get_block_candidates(&[0]).len(), 2);
+        
+        // Only the parent has expired
+        std::thread::sleep(Duration::from_millis(700));
+        assert_eq!(mempool.remove_expired(), 1);
+        assert!(mempool.get_transaction(&child.id).is_some());
+        assert!(mempool.get_block_candidates(&[0]).is_empty());
+    }
+    
+    #[test]
+    fn test_state_dependency_checks_required_state() {
+        let mempool = Mempool::new(MempoolConfig::default());
+        let alice = KeyPair::from_seed(&[7; 32]).unwrap();
+        let bob = KeyPair::from_seed(&[8; 32]).unwrap();
+        let carol = KeyPair::from_seed(&[9; 32]).unwrap();
+        
+        let parent = signed_transfer(&alice, 0, 1000);
+        let expecting = dependent_transfer(&bob, 1000, depends_on(&parent, DependencyType::State, Some(vec![9; 32])));
+        let any_state = dependent_transfer(&carol, 1000, depends_on(&parent, DependencyType::State, None));
+        for tx in [&parent, &expecting, &any_state] {
+            mempool.add_transaction(tx).unwrap();
+        }
+        
+        // State dependencies wait for the parent to be included
+        let ids: Vec<Vec<u8>> = mempool.get_block_candidates(&[0]).iter().map(|tx| tx.id.clone()).collect();
+        assert_eq!(ids, vec![parent.id.clone()]);
+        
+        // The parent produced a different state than required
+        mempool.mark_included(&parent.id, Some(vec![1; 32]));
+        let ids: Vec<Vec<u8>> = mempool.get_block_candidates(&[0]).iter().map(|tx| tx.id.clone()).collect();
+        assert_eq!(ids, vec![any_state.id.clone()]);
+        
+        // Included again on another fork with the required state
+        mempool.mark_reverted(&parent.id);
+        mempool.mark_included(&parent.id, Some(vec![9; 32]));
+        assert_eq!(mempool.get_block_candidates(&[0]).len(), 2);
+    }
+    
+    #[test]
+    fn test_soft_dependency_does_not_block() {
+        let mempool = Mempool::new(MempoolConfig::default());
+        let bob = KeyPair::from_seed(&[8; 32]).unwrap();
+        
+        let parent = signed_transfer(&KeyPair::from_seed(&[7; 32]).unwrap(), 0, 1000);
+        let child = dependent_transfer(&bob, 1000, depends_on(&parent, DependencyType::Soft, None));
+        mempool.add_transaction(&child).unwrap();
+        assert_eq!(mempool.get_block_candidates(&[0]).len(), 1);
+    }
+    
+    #[test]
+    fn test_time_bounds_park_and_expire_transactions() {
+        use crate::blockchain::TimeBound;
+        
+        let mempool = Mempool::new(MempoolConfig::default());
+        mempool.set_chain_tip(10, 1_000);
+        let alice = KeyPair::from_seed(&[7; 32]).unwrap();
+        let bob = KeyPair::from_seed(&[8; 32]).unwrap();
+        let carol = KeyPair::from_seed(&[9; 32]).unwrap();
+        
+        let bounded = |sender: &KeyPair, valid_after: Option<TimeBound>, valid_until: Option<TimeBound>| {
+            let mut tx = signed_transfer(sender, 0, 1000);
+            tx.valid_after = valid_after;
+            tx.valid_until = valid_until;
+            tx.sign(sender, DEFAULT_CHAIN_ID);
+            tx
+        };
+        
+        // Already expired transactions are refused
+        let stale = bounded(&alice, None, Some(TimeBound::Height(10)));
+        assert!(mempool.add_transaction(&stale).is_err());
+        
+        // A scheduled payment is parked until its height comes up
+        let scheduled = bounded(&alice, Some(TimeBound::Height(12)), None);
+        let offer = bounded(&bob, None, Some(TimeBound::Timestamp(2_000)));
+        let timed = bounded(&carol, Some(TimeBound::Timestamp(1_500)), None);
+        for tx in [&scheduled, &offer, &timed] {
+            mempool.add_transaction(tx).unwrap();
+        }
+        let ids: Vec<Vec<u8>> = mempool.get_block_candidates(&[0]).iter().map(|tx| tx.id.clone()).collect();
+        assert_eq!(ids, vec![offer.id.clone()]);
+        
+        assert_eq!(mempool.set_chain_tip(11, 1_500), 0);
+        assert_eq!(mempool.get_block_candidates(&[0]).len(), 3);
+        
+        // The offer lapses once the head reaches its time
+        assert_eq!(mempool.set_chain_tip(12, 2_000), 1);
+        assert!(mempool.get_transaction(&offer.id).is_none());
+        assert_eq!(mempool.size(), 2);
+    }
 }
@@ -7,21 +7,47 @@ mod block;
 mod transaction;
 mod state;
 mod mempool;
+pub mod state_transition;
+pub mod fork_choice;
+pub mod parallel;
+pub mod optimistic;
+pub mod sharding;
+pub mod parameters;
+pub mod fee_market;
+mod genesis;
 
 // Re-export main types
 pub use block::Block;
 pub use block::BlockHeader;
 pub use block::ShardData;
+pub use block::CrossShardReceipt;
+pub use block::ValidatorRef;
+pub use block::{merkle_root, EMPTY_ROOT};
 pub use transaction::Transaction;
+pub use transaction::TransactionData;
 pub use transaction::Receipt;
+pub use transaction::Log;
+pub use transaction::{Dependency, DependencyType, OptimisticStatus, TimeBound};
+pub use transaction::{BatchOperation, MAX_BATCH_OPERATIONS};
 pub use state::{Account, AccountType, ShardState, GlobalState};
-pub use mempool::{Mempool, MempoolConfig};
+pub use mempool::{Mempool, MempoolConfig, AdmissionOutcome, minimum_replacement_fee};
+pub use state_transition::{StateTransition, StateOverlay, StateUndo, ExecutionResult};
+pub use fork_choice::{BlockTree, ChainWeight, ReorgEvent};
+pub use parallel::{AccessSet, ConflictGraph, ExecutionSchedule, StateKey};
+pub use optimistic::{OptimisticExecutor, OptimisticStats};
+pub use sharding::{ShardManager, Shard, CrossShardTransfer, CrossShardProof, CrossShardSettlement, CrossShardMessage, StateProof};
+pub use sharding::{AccountProof, StateTree};
+pub use sharding::{ShardLayout, ReshardAction, ReshardingConfig, PartitioningRule};
+pub use parameters::{BlockParameters, AdaptiveConfig, EpochLoad, EpochProgress};
+pub use fee_market::{FeeMarketConfig, FeeCharge, ShardUsage, BASE_FEE_BURN_PERCENT};
+pub use genesis::{GenesisSpec, GenesisAccount, GenesisValidator};
 
-use crate::types::{Result, Error, ShardId};
-use crate::crypto::hash;
-use crate::storage::ChainStore;
-use std::collections::HashMap;
+use crate::types::{Result, Error, ShardId, DEFAULT_CHAIN_ID};
+use crate::storage::{ChainStore, StateDB, AccountInfo, LogEntry, LogFilter, MempoolJournal};
+use crate::consensus::ValidatorPool;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, Sender};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Configuration for the blockchain
@@ -41,6 +67,23 @@ pub struct BlockchainConfig {
     
     /// Mempool configuration
     pub mempool_config: mempool::MempoolConfig,
+    
+    /// Chain id that transactions must be signed for, also applied to the mempool
+    pub chain_id: String,
+    
+    /// Blocks after its source block within which a cross-shard transfer
+    /// must be credited before it is refunded
+    pub cross_shard_timeout: u64,
+    
+    /// Epoch length and thresholds for splitting and merging shards
+    pub resharding: ReshardingConfig,
+    
+    /// Bounds within which the block size limit and block interval adapt
+    /// to load, starting from `max_block_size` and `target_block_time`
+    pub adaptive_parameters: AdaptiveConfig,
+    
+    /// Adjustment of the per-shard base fee
+    pub fee_market: FeeMarketConfig,
 }
 
 impl Default for BlockchainConfig {
@@ -51,6 +94,11 @@ impl Default for BlockchainConfig {
             max_block_size: 1024 * 1024, // 1 MB
             finality_confirmations: 3, // 3 blocks
             mempool_config: mempool::MempoolConfig::default(),
+            chain_id: DEFAULT_CHAIN_ID.to_string(),
+            cross_shard_timeout: sharding::DEFAULT_CROSS_SHARD_TIMEOUT,
+            resharding: ReshardingConfig::default(),
+            adaptive_parameters: AdaptiveConfig::default(),
+            fee_market: FeeMarketConfig::default(),
         }
     }
 }
@@ -66,6 +114,12 @@ pub struct Blockchain {
     /// Chain store for persistence
     chain_store: Option<Arc<ChainStore>>,
     
+    /// State database that blocks are applied to
+    state_db: Option<Arc<StateDB>>,
+    
+    /// Journal that keeps pending transactions across restarts
+    mempool_journal: Option<Arc<MempoolJournal>>,
+    
     /// Current height of the blockchain
     height: Arc<Mutex<u64>>,
     
@@ -77,6 +131,37 @@ pub struct Blockchain {
     
     /// Cached blocks by height
     blocks: Arc<Mutex<HashMap<u64, Block>>>,
+    
+    /// All known blocks, including side branches
+    block_tree: Arc<Mutex<Option<BlockTree>>>,
+    
+    /// Height and hash of the latest finalized block
+    finalized: Arc<Mutex<(u64, Vec<u8>)>>,
+    
+    /// State undo data for non-finalized canonical blocks, by block hash
+    ///
+    /// Locked after `block_tree` when both are held.
+    undo_log: Arc<Mutex<HashMap<Vec<u8>, StateUndo>>>,
+    
+    /// Subscribers notified of chain reorganizations
+    reorg_subscribers: Arc<Mutex<Vec<Sender<ReorgEvent>>>>,
+    
+    /// Speculative executor of pending transactions, if enabled
+    optimistic: Option<Arc<OptimisticExecutor>>,
+    
+    /// Shards and the cross-shard transfers awaiting settlement
+    shard_manager: Arc<Mutex<ShardManager>>,
+    
+    /// Shard layout each block of the block tree was built under, by hash
+    shard_layouts: Arc<Mutex<HashMap<Vec<u8>, ShardLayout>>>,
+    
+    /// Block parameters and epoch load as of each block of the block tree,
+    /// by hash
+    epoch_progress: Arc<Mutex<HashMap<Vec<u8>, EpochProgress>>>,
+    
+    /// Base fee and usage of each shard as of each block of the block
+    /// tree, by hash
+    shard_usage: Arc<Mutex<HashMap<Vec<u8>, ShardUsage>>>,
 }
 
 impl Blockchain {
@@ -87,25 +172,106 @@ impl Blockchain {
     
     /// Create a new blockchain with given configuration
     pub fn with_config(config: BlockchainConfig) -> Result<Self> {
-        let mempool = Arc::new(Mempool::new(config.mempool_config.clone()));
+        let mempool = Self::new_mempool(&config);
+        let mut shard_manager = ShardManager::with_timeout(config.cross_shard_timeout);
+        shard_manager.set_resharding_config(config.resharding.clone());
         let empty_hash = vec![0; 32]; // Placeholder until genesis block is created
         
         Ok(Blockchain {
             config,
             mempool,
             chain_store: None,
+            state_db: None,
+            mempool_journal: None,
             height: Arc::new(Mutex::new(0)),
             genesis_hash: Arc::new(Mutex::new(empty_hash.clone())),
-            latest_hash: Arc::new(Mutex::new(empty_hash)),
+            latest_hash: Arc::new(Mutex::new(empty_hash.clone())),
             blocks: Arc::new(Mutex::new(HashMap::new())),
+            block_tree: Arc::new(Mutex::new(None)),
+            finalized: Arc::new(Mutex::new((0, empty_hash))),
+            undo_log: Arc::new(Mutex::new(HashMap::new())),
+            reorg_subscribers: Arc::new(Mutex::new(Vec::new())),
+            optimistic: None,
+            shard_manager: Arc::new(Mutex::new(shard_manager)),
+            shard_layouts: Arc::new(Mutex::new(HashMap::new())),
+            epoch_progress: Arc::new(Mutex::new(HashMap::new())),
+            shard_usage: Arc::new(Mutex::new(HashMap::new())),
         })
     }
     
+    /// Create a mempool that admits transactions for the configured chain
+    fn new_mempool(config: &BlockchainConfig) -> Arc<Mempool> {
+        Arc::new(Mempool::new(MempoolConfig {
+            chain_id: config.chain_id.clone(),
+            ..config.mempool_config.clone()
+        }))
+    }
+    
     /// Set the chain store for persistence
     pub fn set_chain_store(&mut self, chain_store: Arc<ChainStore>) {
         self.chain_store = Some(chain_store);
     }
     
+    /// Set the state database that blocks are executed against
+    pub fn set_state_db(&mut self, state_db: Arc<StateDB>) {
+        self.mempool.set_state_db(state_db.clone());
+        self.state_db = Some(state_db);
+    }
+    
+    /// Set the journal that keeps pending transactions across restarts
+    ///
+    /// The transactions already in the journal are revalidated and restored
+    /// to the mempool, so the state database should be set first. Returns
+    /// the number of transactions restored.
+    pub fn set_mempool_journal(&mut self, journal: Arc<MempoolJournal>) -> Result<usize> {
+        self.mempool.set_journal(journal.clone());
+        self.mempool_journal = Some(journal);
+        self.mempool.restore_from_journal()
+    }
+    
+    /// Enable or disable optimistic execution
+    ///
+    /// When enabled, pending transactions are pre-executed after each new
+    /// head and blocks extending the head reuse the results that still hold.
+    pub fn set_optimistic_execution(&mut self, enabled: bool) {
+        self.optimistic = if enabled {
+            Some(Arc::new(OptimisticExecutor::new()))
+        } else {
+            None
+        };
+    }
+    
+    /// Statistics of optimistic execution, if it is enabled
+    pub fn optimistic_stats(&self) -> Option<OptimisticStats> {
+        self.optimistic.as_ref().map(|optimistic| optimistic.stats())
+    }
+    
+    /// Pre-execute the transactions most likely to be in the next block
+    ///
+    /// Does nothing unless optimistic execution is enabled and a state
+    /// database is set. Returns the number of transactions speculated.
+    pub fn speculate_pending(&self) -> Result<usize> {
+        match (self.optimistic.as_ref(), self.state_db.as_ref()) {
+            (Some(optimistic), Some(state_db)) => {
+                let head = self.tree_block(&self.get_latest_hash())?;
+                let usage = self.shard_usage_of(&head)?;
+                let fee_market = &self.config.fee_market;
+                let pending = self.mempool.ready_transactions(self.config.max_transactions_per_block);
+                optimistic.speculate(&StateTransition::new(state_db), &pending, |shard_id| {
+                    fee_market.base_fee_after(&usage, shard_id)
+                })
+            },
+            _ => Ok(0),
+        }
+    }
+    
+    /// Subscribe to chain reorganization events
+    pub fn subscribe_reorgs(&self) -> Receiver<ReorgEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.reorg_subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+    
     /// Generate a genesis block
     pub fn generate_genesis_block(&self, 
                                  timestamp: Option<u64>, 
@@ -126,16 +292,70 @@ impl Blockchain {
             vec![0; 32], // No previous block, use zeros
             vec![0 as ShardId], // Start with just one shard
         );
+        genesis_block.header.chain_id = self.config.chain_id.clone();
         
-        // Add initial balances if provided
-        if let Some(_balances) = initial_balances {
-            // In a real implementation, we would add transactions to set initial balances
-            // For now, we just acknowledge that this would happen
+        // Commit to the initial balances; use `initialize_with_spec` to
+        // also write them to the state database
+        if let Some(balances) = initial_balances {
+            let accounts: BTreeMap<Vec<u8>, AccountInfo> = balances.into_iter()
+                .map(|(address, balance)| (address, AccountInfo { balance, ..AccountInfo::default() }))
+                .collect();
+            genesis_block.header.state_root = state_transition::compute_state_root(&accounts);
         }
         
         Ok(genesis_block)
     }
     
+    /// Initialize the blockchain from a genesis specification
+    ///
+    /// Adopts the chain id of the specification, builds the genesis block,
+    /// seeds the state database if one is set and returns the validator pool
+    /// active at genesis.
+    pub fn initialize_with_spec(&mut self, spec: &GenesisSpec) -> Result<ValidatorPool> {
+        let genesis_block = spec.build_block()?;
+        let validators = spec.validator_pool()?;
+        
+        if spec.chain_id != self.config.chain_id {
+            if self.mempool.size() > 0 {
+                return Err(Error::Initialization(
+                    "Cannot change chain id while transactions are pending".to_string()
+                ));
+            }
+            self.config.chain_id = spec.chain_id.clone();
+            self.mempool = Self::new_mempool(&self.config);
+            if let Some(ref state_db) = self.state_db {
+                self.mempool.set_state_db(state_db.clone());
+            }
+            if let Some(ref journal) = self.mempool_journal {
+                self.mempool.set_journal(journal.clone());
+            }
+        }
+        
+        if let Some(ref state_db) = self.state_db {
+            spec.apply_state(state_db)?;
+        }
+        
+        self.initialize_with_genesis(genesis_block)?;
+        self.set_active_validators(validators.get_all_validators().into_iter()
+            .map(|validator| validator.public_key.clone()))?;
+        
+        Ok(validators)
+    }
+    
+    /// Set the validators whose block signatures count towards the weight
+    /// of a branch under the fork-choice rule
+    ///
+    /// Applies to blocks added from now on.
+    pub fn set_active_validators<I>(&self, validators: I) -> Result<()>
+    where
+        I: IntoIterator<Item = ValidatorRef>,
+    {
+        self.block_tree.lock().unwrap().as_mut()
+            .ok_or_else(|| Error::BlockValidation("Blockchain not initialized".to_string()))?
+            .set_validators(validators);
+        Ok(())
+    }
+    
     /// Initialize the blockchain with a genesis block
     pub fn initialize_with_genesis(&mut self, genesis_block: Block) -> Result<()> {
         // Ensure we don't already have a genesis block
@@ -146,13 +366,26 @@ impl Blockchain {
             }
         }
         
+        if genesis_block.header.chain_id != self.config.chain_id {
+            return Err(Error::Initialization(format!(
+                "Genesis block is for chain {}, expected {}",
+                genesis_block.header.chain_id, self.config.chain_id
+            )));
+        }
+        
         // Compute the hash of the genesis block
-        let block_hash = hash::sha256(&[0; 32]); // Use a placeholder hash for now
+        let block_hash = genesis_block.hash();
         
         // Store the genesis block
         {
             let mut blocks = self.blocks.lock().unwrap();
             blocks.insert(0, genesis_block.clone());
+            
+            let mut block_tree = self.block_tree.lock().unwrap();
+            *block_tree = Some(BlockTree::new(genesis_block.clone()));
+            
+            let mut finalized = self.finalized.lock().unwrap();
+            *finalized = (genesis_block.header.index, block_hash.to_vec());
         }
         
         // Update genesis and latest hash
@@ -163,12 +396,85 @@ impl Blockchain {
             let mut latest_hash = self.latest_hash.lock().unwrap();
             *latest_hash = block_hash.to_vec();
         }
+        self.mempool.set_chain_tip(genesis_block.header.index, genesis_block.header.timestamp);
+        
+        // Divide the accounts between the genesis shards
+        let layout = ShardLayout::new(&genesis_block.header.shard_identifiers);
+        self.shard_manager.lock().unwrap().set_layout(layout.clone(), self.state_db.as_deref())?;
+        self.shard_layouts.lock().unwrap().insert(block_hash.to_vec(), layout.clone());
+        self.mempool.set_shard_layout(layout.clone());
+        let progress = EpochProgress::measure(std::slice::from_ref(&genesis_block), self.initial_parameters())?;
+        self.epoch_progress.lock().unwrap().insert(block_hash.to_vec(), progress);
+        self.shard_usage.lock().unwrap().insert(block_hash.to_vec(), ShardUsage::default());
+        self.set_mempool_base_fees(&genesis_block, &layout)?;
         
-        // If we have a chain store, persist the genesis block
+        // If we have a chain store, persist the genesis block and pick up
+        // where a previous run on the same chain left off
         if let Some(ref chain_store) = self.chain_store {
+            let resumes = chain_store.get_latest_height().map_or(false, |height| height > 0);
+            if resumes && chain_store.get_genesis_hash().as_deref() != Some(&block_hash[..]) {
+                return Err(Error::Initialization("Chain store holds another chain".to_string()));
+            }
             chain_store.put_block(genesis_block)?;
+            if resumes {
+                self.resume_from_chain_store(chain_store)?;
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// Make the head of the stored canonical chain the head, as after a
+    /// restart
+    ///
+    /// The state database must hold the state after the stored head. The
+    /// head is taken as final and becomes the root of the block tree, and
+    /// the shard manager replays the stored chain to recover the transfers
+    /// awaiting settlement and the current epoch under the layout the chain
+    /// committed to.
+    fn resume_from_chain_store(&self, chain_store: &ChainStore) -> Result<()> {
+        let head_height = chain_store.get_latest_height().unwrap_or(0);
+        let blocks = (1..=head_height)
+            .map(|height| chain_store.get_block_by_height(height))
+            .collect::<Result<Vec<Block>>>()?;
+        let head = match blocks.last() {
+            Some(head) => head.clone(),
+            None => return Ok(()),
+        };
+        
+        let epoch_start = self.config.resharding.epoch_start(head.header.index);
+        let epoch = &blocks[(epoch_start - 1) as usize..];
+        let layout = self.layout_for(&epoch[0])?;
+        {
+            let mut shard_manager = self.shard_manager.lock().unwrap();
+            shard_manager.restore_pending(&blocks, |block| chain_store.get_block_receipts(block))?;
+            shard_manager.restore_epoch(layout.clone(), epoch, self.state_db.as_deref())?;
+            self.mempool.set_shard_layout(shard_manager.layout().clone());
         }
         
+        let parameters_start = self.config.adaptive_parameters.epoch_start(head.header.index);
+        let progress = if parameters_start == 0 {
+            EpochProgress::measure(&blocks, self.initial_parameters())?
+        } else {
+            let epoch = &blocks[(parameters_start - 1) as usize..];
+            EpochProgress::measure(epoch, Self::committed_parameters(&epoch[0])?)?
+        };
+        
+        let head_hash = head.hash().to_vec();
+        self.shard_layouts.lock().unwrap().insert(head_hash.clone(), layout);
+        self.epoch_progress.lock().unwrap().insert(head_hash.clone(), progress);
+        let usage = blocks.iter().fold(ShardUsage::default(), |usage, block| usage.after(block));
+        self.shard_usage.lock().unwrap().insert(head_hash.clone(), usage);
+        *self.height.lock().unwrap() = head.header.index;
+        *self.latest_hash.lock().unwrap() = head_hash.clone();
+        *self.finalized.lock().unwrap() = (head.header.index, head_hash);
+        *self.block_tree.lock().unwrap() = Some(BlockTree::new(head.clone()));
+        self.blocks.lock().unwrap().insert(head.header.index, head.clone());
+        self.mempool.set_chain_tip(head.header.index, head.header.timestamp);
+        let layout = self.shard_manager.lock().unwrap().layout().clone();
+        self.set_mempool_base_fees(&head, &layout)?;
+        
+        log::info!("Resumed the chain at height {}", head.header.index);
         Ok(())
     }
     
@@ -203,96 +509,659 @@ impl Blockchain {
             prev_hash,
             shard_ids.clone(),
         );
+        block.header.chain_id = self.config.chain_id.clone();
         
-        // Add transactions from mempool for each shard
-        for &shard_id in &shard_ids {
-            let transactions = self.mempool.get_transactions_for_block(
-                shard_id,
-                self.config.max_transactions_per_block,
-            );
+        // The first block of an epoch commits to the epoch's parameters
+        let prev = self.tree_block(&block.header.previous_hash)?;
+        let parameters = self.parameters_at(&prev, height)?;
+        if self.config.adaptive_parameters.is_epoch_start(height) {
+            block.header.parameters = Some(parameters);
+        }
+        let max_block_size = parameters.max_block_size as usize;
+        
+        // The first block of a resharding epoch commits to the layout the
+        // last epoch boundary produced
+        if self.config.resharding.is_epoch_start(height) {
+            block.header.shard_layout = Some(self.shard_manager.lock().unwrap().layout().clone());
+        }
+        let layout = self.layout_for(&block)?;
+        
+        // Each shard charges the base fee its last use on the branch implies
+        block.header.base_fees = self.config.fee_market.base_fees_after(&self.shard_usage_of(&prev)?, &shard_ids);
+        
+        // Execute candidate transactions if we track state, so that
+        // transactions that would fail are left out of the block
+        let engine = self.state_db.as_ref().map(|state_db| StateTransition::new(state_db));
+        let mut overlay = engine.as_ref().map(|engine| engine.overlay());
+        
+        // Settle the cross-shard transfers due on these shards first
+        let mut settlements = self.shard_manager.lock().unwrap().settlements_for(&shard_ids, height);
+        if let (Some(engine), Some(overlay)) = (engine.as_ref(), overlay.as_mut()) {
+            settlements.retain(|settlement| match engine.apply_settlement(overlay, settlement) {
+                Ok(()) => true,
+                Err(e) => {
+                    log::debug!("Leaving settlement of {} out of block: {}", hex::encode(settlement.transaction_id()), e);
+                    false
+                },
+            });
+        }
+        block.cross_shard_settlements = settlements;
+        
+        // Fill the block greedily by fee priority up to both limits
+        let mut size = block.encoded_size()?;
+        let mut selected: Vec<Transaction> = Vec::new();
+        let mut used_shards = HashSet::new();
+        
+        for tx in self.mempool.get_block_candidates(&shard_ids) {
+            if selected.len() >= self.config.max_transactions_per_block {
+                break;
+            }
+            
+            let base_fee = block.base_fee(tx.sender_shard);
+            let includable = tx.check_time_bounds(height, timestamp)
+                .and_then(|_| layout.check_transaction(&tx))
+                .and_then(|_| FeeCharge::new(&tx, base_fee).map(|_| ()));
+            if let Err(e) = includable {
+                log::debug!("Leaving transaction {} out of block: {}", hex::encode(&tx.id), e);
+                continue;
+            }
+            
+            // Each transaction adds its body and its ID reference to the block
+            let mut extra = tx.encoded_size()? + tx.id.len() + 8;
+            if !used_shards.contains(&tx.sender_shard) {
+                extra += ShardData::new(tx.sender_shard).encoded_size()?;
+            }
+            if size + extra > max_block_size {
+                // A smaller transaction may still fit
+                continue;
+            }
             
-            if !transactions.is_empty() {
-                let tx_hashes = transactions.iter()
-                    .map(|tx| tx.id().clone())
-                    .collect::<Vec<_>>();
-                
-                let shard_data = ShardData {
-                    shard_id,
-                    transactions: tx_hashes,
-                    execution_proof: Vec::new(),
-                    validator_signatures: Vec::new(),
+            size += extra;
+            used_shards.insert(tx.sender_shard);
+            selected.push((*tx).clone());
+        }
+        
+        // Lay the selected transactions out by shard; this is the execution order
+        let mut ordered: Vec<Transaction> = Vec::with_capacity(selected.len());
+        for &shard_id in &shard_ids {
+            ordered.extend(selected.iter().filter(|tx| tx.sender_shard == shard_id).cloned());
+        }
+        self.retain_dependency_order(&mut ordered);
+        
+        // Execute in block order, leaving out transactions that fail and
+        // those that hard-depend on them; a failed transaction leaves the
+        // overlay unchanged
+        let mut execution = None;
+        if let (Some(engine), Some(mut overlay)) = (engine.as_ref(), overlay) {
+            let mut receipts = Vec::with_capacity(ordered.len());
+            let mut dropped: HashSet<Vec<u8>> = HashSet::new();
+            ordered.retain(|tx| {
+                let executed = match tx.dependencies.iter()
+                    .find(|dep| matches!(dep.dependency_type, DependencyType::Hard) && dropped.contains(&dep.transaction_id)) {
+                    Some(dep) => Err(Error::TransactionValidation(
+                        format!("dependency {} left out of block", hex::encode(&dep.transaction_id))
+                    )),
+                    None => engine.execute_transaction(&mut overlay, tx, block.base_fee(tx.sender_shard), None),
                 };
-                
-                // Add the shard data to the block
-                let mut shard_data_vec = block.shard_data.clone();
-                shard_data_vec.push(shard_data);
-                block.shard_data = shard_data_vec;
+                match executed {
+                    Ok(receipt) => {
+                        receipts.push(receipt);
+                        true
+                    },
+                    Err(e) => {
+                        log::debug!("Leaving transaction {} out of block: {}", hex::encode(&tx.id), e);
+                        dropped.insert(tx.id.clone());
+                        false
+                    },
+                }
+            });
+            execution = Some((receipts, overlay.state_root()?));
+        }
+        
+        // The block carries the full transaction bodies
+        for &shard_id in &shard_ids {
+            let shard_txs: Vec<Transaction> = ordered.iter()
+                .filter(|tx| tx.sender_shard == shard_id)
+                .cloned()
+                .collect();
+            
+            if !shard_txs.is_empty() {
+                block.add_transactions(shard_id, shard_txs)?;
             }
         }
         
+        // Commit to the block contents in the header
+        block.update_merkle_roots();
+        
+        // Commit to the execution results
+        if let Some((receipts, state_root)) = execution {
+            block.header.state_root = state_root;
+            block.set_receipts(&receipts)?;
+            block.cross_shard_receipts = sharding::outbound_receipts(&receipts)?;
+        }
+        
         Ok(block)
     }
     
-    /// Add a block to the chain after validation
-    pub fn add_block(&mut self, block: Block) -> Result<()> {
-        // Validate the block
-        self.validate_block(&block)?;
+    /// Get the transactions of a block in block order
+    ///
+    /// Bodies carried by the block are used directly; a compact block is
+    /// resolved from the mempool and the chain store.
+    fn resolve_block_transactions(&self, block: &Block) -> Result<Vec<Transaction>> {
+        if block.has_bodies() {
+            block.verify_bodies()?;
+            return Ok(block.transactions.clone());
+        }
+        
+        block.transaction_ids().into_iter()
+            .map(|tx_id| self.get_transaction(tx_id).map_err(|_| Error::BlockValidation(
+                format!("Unknown transaction {} in block", hex::encode(tx_id))
+            )))
+            .collect()
+    }
+    
+    /// IDs of the transactions in a compact block that this node cannot resolve
+    ///
+    /// These have to be fetched from peers before the block can be imported.
+    pub fn missing_transactions(&self, block: &Block) -> Vec<Vec<u8>> {
+        if block.has_bodies() {
+            return Vec::new();
+        }
+        
+        block.transaction_ids().into_iter()
+            .filter(|tx_id| self.get_transaction(tx_id).is_err())
+            .cloned()
+            .collect()
+    }
+    
+    /// Fill in the transaction bodies of a compact block
+    pub fn complete_block(&self, block: &mut Block) -> Result<()> {
+        if !block.has_bodies() {
+            block.transactions = self.resolve_block_transactions(block)?;
+        }
+        Ok(())
+    }
+    
+    /// Add a block to the block tree after validation
+    ///
+    /// A block on top of the current head extends the canonical chain. Any
+    /// other block is kept as a side branch, and the canonical chain is
+    /// reorganized if that branch becomes the best one.
+    pub fn add_block(&mut self, mut block: Block) -> Result<()> {
+        // Validate the block against its parent and resolve its transactions
+        let transactions = self.validate_block_contents(&block)?;
+        let layout = self.layout_for(&block)?;
+        let progress = self.epoch_progress_of(&block)?;
+        let usage = self.shard_usage_of(&block)?;
+        let block_hash = block.hash().to_vec();
+        
+        // Keep the full bodies so the block can be replayed and served
+        if !block.has_bodies() {
+            block.transactions = transactions;
+        }
+        
+        if block.header.previous_hash == self.get_latest_hash() {
+            self.extend_head(block)?;
+            self.shard_layouts.lock().unwrap().insert(block_hash.clone(), layout);
+            self.epoch_progress.lock().unwrap().insert(block_hash.clone(), progress);
+            self.shard_usage.lock().unwrap().insert(block_hash, usage);
+        } else {
+            {
+                let mut block_tree = self.block_tree.lock().unwrap();
+                block_tree.as_mut()
+                    .ok_or_else(|| Error::BlockValidation("Blockchain not initialized".to_string()))?
+                    .insert(block)?;
+            }
+            self.shard_layouts.lock().unwrap().insert(block_hash.clone(), layout);
+            self.epoch_progress.lock().unwrap().insert(block_hash.clone(), progress);
+            self.shard_usage.lock().unwrap().insert(block_hash, usage);
+            self.select_head()?;
+        }
+        
+        self.update_finality();
+        
+        // Get a head start on the next block
+        if let Err(e) = self.speculate_pending() {
+            log::debug!("Failed to pre-execute pending transactions: {}", e);
+        }
+        
+        Ok(())
+    }
+    
+    /// Execute a block on top of the current head and make it the new head
+    fn extend_head(&self, block: Block) -> Result<()> {
+        // Execute the block and commit its state changes, all or nothing
+        let (receipts, undo) = match self.state_db {
+            Some(ref state_db) => {
+                let engine = StateTransition::new(state_db);
+                let (result, undo) = match self.optimistic {
+                    Some(ref optimistic) => {
+                        let (result, overlay) = optimistic.execute_block(&engine, &block, &block.transactions)?;
+                        engine.commit_execution(&block, result, overlay)?
+                    },
+                    None => engine.apply_block(&block, &block.transactions)?,
+                };
+                (Some(result.receipts), Some(undo))
+            },
+            None => (None, None),
+        };
+        
+        // A block whose transfers cannot be tracked is not added
+        if let Err(e) = self.track_shards(&block, receipts.as_deref()) {
+            if let (Some(state_db), Some(undo)) = (self.state_db.as_deref(), undo.as_ref()) {
+                undo.revert(state_db)?;
+            }
+            return Err(e);
+        }
+        
+        let block_hash = {
+            let mut block_tree = self.block_tree.lock().unwrap();
+            block_tree.as_mut()
+                .ok_or_else(|| Error::BlockValidation("Blockchain not initialized".to_string()))?
+                .insert(block.clone())?
+        };
+        
+        if let Some(undo) = undo {
+            self.undo_log.lock().unwrap().insert(block_hash, undo);
+        }
+        
+        self.make_canonical(block, receipts)
+    }
+    
+    /// Record a block joining the canonical chain with the shard manager,
+    /// resharding if it ends an epoch
+    ///
+    /// Must run while the state database holds the state after the block.
+    /// `receipts` are the execution receipts of the block, if it was executed.
+    fn track_shards(&self, block: &Block, receipts: Option<&[Receipt]>) -> Result<()> {
+        let mut shard_manager = self.shard_manager.lock().unwrap();
+        
+        // The first block of an epoch must commit to the layout the last
+        // boundary on its branch produced
+        if self.config.resharding.is_epoch_start(block.header.index)
+            && block.header.shard_layout.as_ref() != Some(shard_manager.layout()) {
+            return Err(Error::BlockValidation(format!(
+                "Block {} commits to shard layout {:?}, expected {:?}",
+                block.header.index, block.header.shard_layout, shard_manager.layout()
+            )));
+        }
+        
+        // Track the cross-shard transfers the block sent and settled
+        shard_manager.record_block(block, receipts.unwrap_or(&[]))?;
         
-        // Compute the block hash
-        let block_hash = hash::sha256(&[0; 32]); // Use a placeholder hash for now
+        if shard_manager.is_epoch_boundary(block.header.index) {
+            let actions = shard_manager.end_epoch(block.header.index, self.state_db.as_deref())?;
+            if !actions.is_empty() {
+                log::info!(
+                    "Resharded after block {}: {} shards in epoch {}",
+                    block.header.index, shard_manager.layout().ranges.len(), shard_manager.layout().epoch
+                );
+            }
+        }
         
+        Ok(())
+    }
+    
+    /// Undo `track_shards` for blocks leaving the canonical chain, newest first
+    fn untrack_shards<'b, I>(&self, blocks: I) -> Result<()>
+    where
+        I: IntoIterator<Item = &'b Block>,
+    {
+        let mut shard_manager = self.shard_manager.lock().unwrap();
+        for block in blocks {
+            shard_manager.revert_block(block, self.state_db.as_deref(), || self.epoch_before(block))?;
+        }
+        Ok(())
+    }
+    
+    /// Get the shard layout of the epoch that `block` belongs to and the
+    /// epoch's blocks before it on its branch, oldest first
+    fn epoch_before(&self, block: &Block) -> Result<(ShardLayout, Vec<Block>)> {
+        let start = self.config.resharding.epoch_start(block.header.index);
+        let blocks = if start < block.header.index {
+            let parent = self.ancestor_at(block, block.header.index - 1)?;
+            self.branch_from(&parent, start)?
+        } else {
+            Vec::new()
+        };
+        Ok((self.layout_for(block)?, blocks))
+    }
+    
+    /// Record a block as the canonical block at its height
+    ///
+    /// `receipts` are the execution receipts of the block, if it was executed.
+    fn make_canonical(&self, block: Block, receipts: Option<Vec<Receipt>>) -> Result<()> {
         // Update chain state
         {
             let mut height = self.height.lock().unwrap();
             *height = block.header.index;
             
             let mut latest_hash = self.latest_hash.lock().unwrap();
-            *latest_hash = block_hash.to_vec();
+            *latest_hash = block.hash().to_vec();
             
             let mut blocks = self.blocks.lock().unwrap();
             blocks.insert(block.header.index, block.clone());
         }
         
-        // Remove included transactions from mempool
+        // Remove included transactions from mempool, remembering the state
+        // of their block for transactions that depend on them
         for shard_data in &block.shard_data {
             for tx_hash in &shard_data.transactions {
-                let _ = self.mempool.remove_transaction(tx_hash);
+                self.mempool.mark_included(tx_hash, Some(block.header.state_root.clone()));
             }
         }
+        self.sync_mempool_nonces(&block.transactions, true);
+        self.mempool.set_chain_tip(block.header.index, block.header.timestamp);
+        let layout = self.shard_manager.lock().unwrap().layout().clone();
+        self.mempool.set_shard_layout(layout.clone());
+        self.set_mempool_base_fees(&block, &layout)?;
         
-        // If we have a chain store, persist the block
+        // If we have a chain store, persist the block, its transactions and receipts
         if let Some(ref chain_store) = self.chain_store {
+            if let Some(ref receipts) = receipts {
+                chain_store.put_receipts(&block, receipts)?;
+            }
             chain_store.put_block(block)?;
         }
         
         Ok(())
     }
     
-    /// Validate a block
+    /// Drop transactions that would come before a hard dependency which is
+    /// still pending in the mempool, and everything depending on them
+    fn retain_dependency_order(&self, ordered: &mut Vec<Transaction>) {
+        let mut placed: HashSet<Vec<u8>> = HashSet::new();
+        ordered.retain(|tx| {
+            let parents_placed = tx.dependencies.iter()
+                .filter(|dep| matches!(dep.dependency_type, DependencyType::Hard))
+                .all(|dep| placed.contains(&dep.transaction_id)
+                    || self.mempool.get_transaction(&dep.transaction_id).is_none());
+            if parents_placed {
+                placed.insert(tx.id.clone());
+            } else {
+                log::debug!("Leaving transaction {} out of block: pending dependency not placed ahead of it", hex::encode(&tx.id));
+            }
+            parents_placed
+        });
+    }
+    
+    /// Update the mempool's account nonces for the senders of `transactions`
+    ///
+    /// Nonces are read from the state database when there is one; otherwise
+    /// they are derived from the transactions, which were either `included`
+    /// in or removed from the canonical chain.
+    fn sync_mempool_nonces(&self, transactions: &[Transaction], included: bool) {
+        let mut nonces: HashMap<Vec<u8>, u64> = HashMap::new();
+        for tx in transactions {
+            let sender = state_transition::address_from_public_key(&tx.sender_public_key);
+            let nonce = match self.state_db {
+                Some(ref state_db) => state_db.get_account_nonce(&sender).unwrap_or(0),
+                None if included => tx.nonce + 1,
+                None => tx.nonce,
+            };
+            let entry = nonces.entry(sender).or_insert(nonce);
+            *entry = if included { (*entry).max(nonce) } else { (*entry).min(nonce) };
+        }
+        
+        for (sender, nonce) in nonces {
+            self.mempool.set_account_nonce(&sender, nonce);
+        }
+    }
+    
+    /// Apply the fork-choice rule and switch to the best branch if it changed
+    fn select_head(&self) -> Result<()> {
+        let finalized_hash = self.finalized.lock().unwrap().1.clone();
+        let best_tip = {
+            let block_tree = self.block_tree.lock().unwrap();
+            block_tree.as_ref().and_then(|tree| tree.best_tip(&finalized_hash))
+        };
+        
+        match best_tip {
+            Some(best_tip) if best_tip != self.get_latest_hash() => self.reorganize(best_tip),
+            _ => Ok(()),
+        }
+    }
+    
+    /// Switch the canonical chain to the branch ending at `new_head`
+    ///
+    /// State is rolled back to the common ancestor and the new branch is
+    /// replayed. If a block on the new branch fails to execute, the old
+    /// branch is restored and the failing block and its descendants are
+    /// dropped from the tree.
+    fn reorganize(&self, new_head: Vec<u8>) -> Result<()> {
+        let old_head = self.get_latest_hash();
+        
+        // Collect both branches below their common ancestor
+        let (common_ancestor, reverted, applied) = {
+            let block_tree = self.block_tree.lock().unwrap();
+            let tree = block_tree.as_ref()
+                .ok_or_else(|| Error::BlockValidation("Blockchain not initialized".to_string()))?;
+            let common_ancestor = tree.common_ancestor(&old_head, &new_head)
+                .ok_or_else(|| Error::BlockValidation("Branches share no common ancestor".to_string()))?;
+            let branch = |tip: &[u8]| -> Vec<fork_choice::TreeEntry> {
+                tree.path_to(tip).into_iter()
+                    .skip_while(|hash| *hash != common_ancestor)
+                    .skip(1)
+                    .filter_map(|hash| tree.get(&hash).cloned())
+                    .collect()
+            };
+            let (reverted, applied) = (branch(&old_head), branch(&new_head));
+            (common_ancestor, reverted, applied)
+        };
+        
+        let mut new_receipts = HashMap::new();
+        if let Some(ref state_db) = self.state_db {
+            let engine = StateTransition::new(state_db);
+            
+            // Make sure the whole old branch can be undone before touching state
+            let undos = {
+                let undo_log = self.undo_log.lock().unwrap();
+                reverted.iter()
+                    .map(|entry| undo_log.get(&entry.hash).cloned().ok_or_else(|| Error::State(
+                        format!("Missing undo data for block {}", hex::encode(&entry.hash))
+                    )))
+                    .collect::<Result<Vec<_>>>()?
+            };
+            
+            // Roll state back to the common ancestor
+            for undo in undos.iter().rev() {
+                undo.revert(state_db)?;
+            }
+            self.untrack_shards(reverted.iter().rev().map(|entry| &entry.block))?;
+            
+            // Replay the new branch
+            let mut new_undos = Vec::new();
+            for entry in &applied {
+                let replayed = engine.apply_block(&entry.block, &entry.block.transactions)
+                    .and_then(|(result, undo)| match self.track_shards(&entry.block, Some(&result.receipts)) {
+                        Ok(()) => Ok((result, undo)),
+                        Err(e) => undo.revert(state_db).and(Err(e)),
+                    });
+                match replayed {
+                    Ok((result, undo)) => {
+                        new_receipts.insert(entry.hash.clone(), result.receipts);
+                        new_undos.push((entry.hash.clone(), undo));
+                    },
+                    Err(e) => {
+                        log::warn!("Abandoning reorg: block {} could not be applied: {}", hex::encode(&entry.hash), e);
+                        
+                        // Restore the old branch
+                        self.untrack_shards(applied[..new_undos.len()].iter().rev().map(|entry| &entry.block))?;
+                        for (_, undo) in new_undos.iter().rev() {
+                            undo.revert(state_db)?;
+                        }
+                        {
+                            let mut undo_log = self.undo_log.lock().unwrap();
+                            for old in &reverted {
+                                let (result, undo) = engine.apply_block(&old.block, &old.block.transactions)?;
+                                self.track_shards(&old.block, Some(&result.receipts))?;
+                                undo_log.insert(old.hash.clone(), undo);
+                            }
+                        }
+                        
+                        // Forget the invalid block and everything built on it
+                        if let Some(tree) = self.block_tree.lock().unwrap().as_mut() {
+                            let mut shard_layouts = self.shard_layouts.lock().unwrap();
+                            let mut epoch_progress = self.epoch_progress.lock().unwrap();
+                            let mut shard_usage = self.shard_usage.lock().unwrap();
+                            for removed in tree.remove_subtree(&entry.hash) {
+                                shard_layouts.remove(&removed);
+                                epoch_progress.remove(&removed);
+                                shard_usage.remove(&removed);
+                            }
+                        }
+                        return Err(e);
+                    },
+                }
+            }
+            
+            let mut undo_log = self.undo_log.lock().unwrap();
+            for entry in &reverted {
+                undo_log.remove(&entry.hash);
+            }
+            undo_log.extend(new_undos);
+        } else {
+            self.untrack_shards(reverted.iter().rev().map(|entry| &entry.block))?;
+            for entry in &applied {
+                self.track_shards(&entry.block, None)?;
+            }
+        }
+        
+        // Replace the canonical blocks above the common ancestor
+        {
+            let mut blocks = self.blocks.lock().unwrap();
+            for entry in &reverted {
+                blocks.remove(&entry.block.header.index);
+            }
+        }
+        let reverted_transactions: Vec<Transaction> = reverted.iter()
+            .flat_map(|entry| entry.block.transactions.iter().cloned())
+            .collect();
+        self.sync_mempool_nonces(&reverted_transactions, false);
+        for tx in &reverted_transactions {
+            self.mempool.mark_reverted(&tx.id);
+        }
+        for entry in &applied {
+            self.make_canonical(entry.block.clone(), new_receipts.remove(&entry.hash))?;
+        }
+        
+        // Return transactions that only the old branch included to the mempool
+        let included: HashSet<&Vec<u8>> = applied.iter()
+            .flat_map(|entry| entry.block.transaction_ids())
+            .collect();
+        let mut orphaned_transactions = Vec::new();
+        for tx in reverted.iter().flat_map(|entry| entry.block.transactions.iter()) {
+            if included.contains(&tx.id) {
+                continue;
+            }
+            if let Err(e) = self.mempool.add_transaction(tx) {
+                log::debug!("Dropping orphaned transaction {}: {}", hex::encode(&tx.id), e);
+            }
+            orphaned_transactions.push(tx.id.clone());
+        }
+        
+        log::info!(
+            "Chain reorganized: {} blocks reverted, {} applied, new head {}",
+            reverted.len(), applied.len(), hex::encode(&new_head)
+        );
+        
+        // Notify subscribers, dropping those that have gone away
+        let event = ReorgEvent {
+            old_head,
+            new_head,
+            common_ancestor,
+            reverted: reverted.iter().rev().map(|entry| entry.hash.clone()).collect(),
+            applied: applied.iter().map(|entry| entry.hash.clone()).collect(),
+            orphaned_transactions,
+        };
+        self.reorg_subscribers.lock().unwrap()
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+        
+        Ok(())
+    }
+    
+    /// Advance the finalized block, prune branches that conflict with it and
+    /// re-root the block tree there
+    fn update_finality(&self) {
+        let target = self.get_height().saturating_sub(self.config.finality_confirmations);
+        
+        let mut finalized = self.finalized.lock().unwrap();
+        if target <= finalized.0 {
+            return;
+        }
+        
+        let hash = match self.blocks.lock().unwrap().get(&target) {
+            Some(block) => block.hash().to_vec(),
+            None => return,
+        };
+        *finalized = (target, hash.clone());
+        
+        if let Some(tree) = self.block_tree.lock().unwrap().as_mut() {
+            // Finalized blocks are never reverted, so their undo data can go
+            let finalized_path = tree.path_to(&hash);
+            tree.prune(&hash);
+            
+            let mut undo_log = self.undo_log.lock().unwrap();
+            for finalized_hash in finalized_path {
+                undo_log.remove(&finalized_hash);
+            }
+            self.shard_layouts.lock().unwrap().retain(|hash, _| tree.contains(hash));
+            self.epoch_progress.lock().unwrap().retain(|hash, _| tree.contains(hash));
+            self.shard_usage.lock().unwrap().retain(|hash, _| tree.contains(hash));
+        }
+    }
+    
+    /// Validate a block against its parent in the block tree
     pub fn validate_block(&self, block: &Block) -> Result<()> {
+        self.validate_block_contents(block).map(|_| ())
+    }
+    
+    /// Validate a block and return its transactions in block order
+    fn validate_block_contents(&self, block: &Block) -> Result<Vec<Transaction>> {
         // Check basic block properties
         block.validate_basic()?;
         
-        // Get current height
-        let current_height = *self.height.lock().unwrap();
+        // Find the parent block
+        let prev = {
+            let block_tree = self.block_tree.lock().unwrap();
+            let tree = block_tree.as_ref()
+                .ok_or_else(|| Error::BlockValidation("Blockchain not initialized".to_string()))?;
+            
+            if tree.contains(&block.hash()) {
+                return Err(Error::BlockValidation("Block already known".to_string()));
+            }
+            
+            tree.get(&block.header.previous_hash)
+                .map(|entry| entry.block.clone())
+                .ok_or_else(|| Error::BlockValidation(
+                    format!("Invalid previous hash: unknown parent block {}", 
+                            hex::encode(&block.header.previous_hash))
+                ))?
+        };
+        
+        // Blocks from another network are never valid here
+        if block.header.chain_id != self.config.chain_id {
+            return Err(Error::BlockValidation(
+                format!("Block is for chain {}, expected {}", 
+                        block.header.chain_id, self.config.chain_id)
+            ));
+        }
         
         // Check block height
-        if block.header.index != current_height + 1 {
+        if block.header.index != prev.header.index + 1 {
             return Err(Error::BlockValidation(
                 format!("Invalid block height: expected {}, got {}", 
-                        current_height + 1, block.header.index)
+                        prev.header.index + 1, block.header.index)
             ));
         }
         
-        // Check previous hash
-        {
-            let latest_hash = self.latest_hash.lock().unwrap();
-            if block.header.previous_hash != *latest_hash {
-                return Err(Error::BlockValidation(
-                    format!("Invalid previous hash: expected {:?}, got {:?}", 
-                            latest_hash, block.header.previous_hash)
-                ));
-            }
+        // Never build on a branch that would revert a finalized block
+        let finalized_height = self.finalized.lock().unwrap().0;
+        if block.header.index <= finalized_height {
+            return Err(Error::BlockValidation(
+                format!("Block at height {} conflicts with finalized height {}", 
+                        block.header.index, finalized_height)
+            ));
         }
         
         // Validate timestamps
@@ -300,65 +1169,338 @@ impl Blockchain {
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_micros() as u64;
-            
+        
         if block.header.timestamp > now + 60_000_000 { // 1 minute in the future
             return Err(Error::BlockValidation(
                 format!("Block timestamp too far in the future: {}", block.header.timestamp)
             ));
         }
         
-        // Get the previous block
-        let prev_block = {
-            let blocks = self.blocks.lock().unwrap();
-            blocks.get(&current_height).cloned()
-        };
+        if block.header.timestamp <= prev.header.timestamp {
+            return Err(Error::BlockValidation(
+                format!("Block timestamp not greater than previous block: {} <= {}", 
+                        block.header.timestamp, prev.header.timestamp)
+            ));
+        }
         
-        if let Some(prev) = prev_block {
-            if block.header.timestamp <= prev.header.timestamp {
+        // Check the header commits to the block contents
+        block.verify_merkle_roots()?;
+        
+        // Check the block limits
+        let tx_ids = block.transaction_ids();
+        if tx_ids.len() > self.config.max_transactions_per_block {
+            return Err(Error::BlockValidation(
+                format!("Block contains {} transactions, exceeding the limit of {}", 
+                        tx_ids.len(), self.config.max_transactions_per_block)
+            ));
+        }
+        
+        let mut seen = HashSet::with_capacity(tx_ids.len());
+        for tx_id in &tx_ids {
+            if !seen.insert(*tx_id) {
                 return Err(Error::BlockValidation(
-                    format!("Block timestamp not greater than previous block: {} <= {}", 
-                            block.header.timestamp, prev.header.timestamp)
+                    format!("Duplicate transaction {} in block", hex::encode(tx_id))
                 ));
             }
         }
         
-        // In a real implementation, we would also validate:
-        // - Transaction validity
-        // - State transitions
-        // - Merkle roots
-        // - Signatures
+        // The first block of an epoch must commit to the parameters the
+        // previous epoch's load implies, and no other block may
+        let parameters = self.parameters_at(&prev, block.header.index)?;
+        let expected = Some(parameters).filter(|_| self.config.adaptive_parameters.is_epoch_start(block.header.index));
+        if block.header.parameters != expected {
+            return Err(Error::BlockValidation(
+                format!("Block parameters {:?} do not match the expected {:?}", 
+                        block.header.parameters, expected)
+            ));
+        }
         
-        Ok(())
-    }
-    
-    /// Get a block by height
-    pub fn get_block_by_height(&self, height: u64) -> Result<Block> {
-        // First try in-memory cache
-        {
-            let blocks = self.blocks.lock().unwrap();
-            if let Some(block) = blocks.get(&height) {
-                return Ok(block.clone());
-            }
+        // The first block of a resharding epoch must commit to a shard
+        // layout, which is checked against the epoch boundary when the
+        // block is applied, and no other block may
+        if block.header.shard_layout.is_some() != self.config.resharding.is_epoch_start(block.header.index) {
+            return Err(Error::BlockValidation(
+                format!("Block {} {} a shard layout", block.header.index,
+                        if block.header.shard_layout.is_some() { "must not commit to" } else { "must commit to" })
+            ));
         }
+        let layout = self.layout_for(block)?;
         
-        // If not in memory and we have a chain store, try there
-        if let Some(ref chain_store) = self.chain_store {
-            return chain_store.get_block_by_height(height);
+        // Each shard must charge the base fee its last use on the branch
+        // implies
+        let base_fees = self.config.fee_market.base_fees_after(&self.shard_usage_of(&prev)?, &block.header.shard_identifiers);
+        if block.header.base_fees != base_fees {
+            return Err(Error::BlockValidation(
+                format!("Base fees {:?} do not match the expected {:?}", 
+                        block.header.base_fees, base_fees)
+            ));
         }
         
-        Err(Error::State(format!("Block not found at height {}", height)))
-    }
-    
-    /// Get a block by hash
-    pub fn get_block_by_hash(&self, hash: &[u8]) -> Result<Block> {
-        // If we have a chain store, use it
+        let transactions = self.resolve_block_transactions(block)?;
+        let size = block.size_with_transactions(&transactions)?;
+        if size as u64 > parameters.max_block_size {
+            return Err(Error::BlockValidation(
+                format!("Block size {} exceeds maximum {}", size, parameters.max_block_size)
+            ));
+        }
+        
+        // Each shard data must be for a shard of the block and list only
+        // transactions sent from that shard, which pay its base fee
+        let mut listed = HashMap::new();
+        for shard_data in &block.shard_data {
+            if !block.header.shard_identifiers.contains(&shard_data.shard_id) {
+                return Err(Error::BlockValidation(
+                    format!("Shard {} is not in block {}", shard_data.shard_id, block.header.index)
+                ));
+            }
+            for tx_id in &shard_data.transactions {
+                listed.insert(tx_id, shard_data.shard_id);
+            }
+        }
+        
+        // Every transaction must be signed by its sender for this chain, name
+        // the shards of its accounts, fall within its time bounds at this
+        // block and cover its shard's base fee
+        for tx in &transactions {
+            if listed.get(&tx.id) != Some(&tx.sender_shard) {
+                return Err(Error::BlockValidation(format!(
+                    "Transaction {} is not listed under its sender shard {}", hex::encode(&tx.id), tx.sender_shard
+                )));
+            }
+            tx.is_valid(&self.config.chain_id)
+                .and_then(|_| layout.check_transaction(tx))
+                .and_then(|_| tx.check_time_bounds(block.header.index, block.header.timestamp))
+                .and_then(|_| FeeCharge::new(tx, block.base_fee(tx.sender_shard)).map(|_| ()))
+                .map_err(|e| Error::BlockValidation(format!("Invalid transaction in block: {}", e)))?;
+        }
+        
+        self.validate_settlements(block, &prev)?;
+        
+        Ok(transactions)
+    }
+    
+    /// Check the cross-shard settlements of a block whose parent is `prev`
+    ///
+    /// Each must prove a receipt of an earlier block on the same branch, and
+    /// be settled by a shard of this block: the destination up to the
+    /// transfer's timeout and the source after it.
+    fn validate_settlements(&self, block: &Block, prev: &Block) -> Result<()> {
+        let mut seen = HashSet::with_capacity(block.cross_shard_settlements.len());
+        for settlement in &block.cross_shard_settlements {
+            let proof = &settlement.proof;
+            let invalid = |reason: &str| Error::BlockValidation(format!(
+                "Invalid settlement of {}: {}", hex::encode(settlement.transaction_id()), reason
+            ));
+            
+            if !seen.insert(settlement.transaction_id()) {
+                return Err(invalid("settled twice in block"));
+            }
+            if proof.source_height >= block.header.index {
+                return Err(invalid("source block is not an ancestor"));
+            }
+            
+            let source = self.ancestor_at(prev, proof.source_height)?;
+            proof.verify(&source.header)?;
+            
+            let expired = block.header.index > proof.expires_at(self.config.cross_shard_timeout);
+            if settlement.refund != expired {
+                return Err(invalid(if expired { "transfer has timed out" } else { "refund before timeout" }));
+            }
+            if !block.header.shard_identifiers.contains(&settlement.settling_shard()) {
+                return Err(invalid("settling shard is not in block"));
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// Block parameters of the configuration, used until the first epoch
+    /// change
+    fn initial_parameters(&self) -> BlockParameters {
+        BlockParameters {
+            max_block_size: self.config.max_block_size as u64,
+            block_interval_ms: self.config.target_block_time * 1000,
+        }
+    }
+    
+    /// Block parameters committed by the first block of an epoch
+    fn committed_parameters(block: &Block) -> Result<BlockParameters> {
+        block.header.parameters.ok_or_else(|| Error::BlockValidation(
+            format!("Block {} does not commit to block parameters", block.header.index)
+        ))
+    }
+    
+    /// Block parameters in effect for a block at `height` whose parent is
+    /// `prev`
+    ///
+    /// At the start of an epoch they follow from the load of the previous
+    /// epoch on the same branch; later blocks use the ones committed at
+    /// the start.
+    fn parameters_at(&self, prev: &Block, height: u64) -> Result<BlockParameters> {
+        let adaptive = &self.config.adaptive_parameters;
+        let progress = self.epoch_progress_of(prev)?;
+        if adaptive.is_epoch_start(height) {
+            Ok(adaptive.next_parameters(&progress.parameters, &progress.load))
+        } else {
+            Ok(progress.parameters)
+        }
+    }
+    
+    /// Block parameters a block was built under and the load of its epoch
+    /// up to and including it
+    ///
+    /// Follows from the progress of the parent if it is known, and is
+    /// measured from the branch otherwise.
+    fn epoch_progress_of(&self, block: &Block) -> Result<EpochProgress> {
+        let known = |hash: &[u8]| self.epoch_progress.lock().unwrap().get(hash).copied();
+        if let Some(progress) = known(&block.hash()[..]) {
+            return Ok(progress);
+        }
+        
+        let start = self.config.adaptive_parameters.epoch_start(block.header.index);
+        if block.header.index > start {
+            if let Some(parent) = known(&block.header.previous_hash) {
+                return parent.extend(block);
+            }
+        }
+        
+        let blocks = self.branch_from(block, start)?;
+        let parameters = match blocks.first() {
+            Some(first) if first.header.index > 0 => Self::committed_parameters(first)?,
+            _ => self.initial_parameters(),
+        };
+        EpochProgress::measure(&blocks, parameters)
+    }
+    
+    /// Base fee and usage of each shard as of a block of the block tree,
+    /// following from those as of its parent
+    fn shard_usage_of(&self, block: &Block) -> Result<ShardUsage> {
+        let shard_usage = self.shard_usage.lock().unwrap();
+        if let Some(usage) = shard_usage.get(&block.hash()[..]) {
+            return Ok(usage.clone());
+        }
+        shard_usage.get(&block.header.previous_hash)
+            .map(|usage| usage.after(block))
+            .ok_or_else(|| Error::BlockValidation(
+                format!("No shard usage known for the parent of block {}", block.header.index)
+            ))
+    }
+    
+    /// Rank the mempool by the base fees of the block after `head`, on the
+    /// shards of `layout`
+    fn set_mempool_base_fees(&self, head: &Block, layout: &ShardLayout) -> Result<()> {
+        let shard_ids = layout.shard_ids();
+        let base_fees = self.config.fee_market.base_fees_after(&self.shard_usage_of(head)?, &shard_ids);
+        self.mempool.set_base_fees(shard_ids.into_iter().zip(base_fees).collect());
+        Ok(())
+    }
+    
+    /// Shard layout a block is built under
+    ///
+    /// The first block of a resharding epoch carries the layout; any other
+    /// block follows its parent, and the first epoch the genesis shards.
+    fn layout_for(&self, block: &Block) -> Result<ShardLayout> {
+        let missing = |height: u64| Error::BlockValidation(
+            format!("Block {} does not commit to a shard layout", height)
+        );
+        if let Some(ref layout) = block.header.shard_layout {
+            return Ok(layout.clone());
+        }
+        if self.config.resharding.is_epoch_start(block.header.index) {
+            return Err(missing(block.header.index));
+        }
+        if let Some(layout) = self.shard_layouts.lock().unwrap().get(&block.header.previous_hash) {
+            return Ok(layout.clone());
+        }
+        
+        let start = self.config.resharding.epoch_start(block.header.index);
+        if !self.config.resharding.is_epoch_start(start) {
+            let genesis = self.get_block_by_height(0)?;
+            return Ok(ShardLayout::new(&genesis.header.shard_identifiers));
+        }
+        self.ancestor_at(block, start)?.header.shard_layout.ok_or_else(|| missing(start))
+    }
+    
+    /// Get the blocks from `height` up to `tip` on the branch ending at
+    /// `tip`, oldest first
+    fn branch_from(&self, tip: &Block, height: u64) -> Result<Vec<Block>> {
+        let mut blocks = vec![tip.clone()];
+        while let Some(oldest) = blocks.last().filter(|block| block.header.index > height) {
+            let parent = self.ancestor_at(oldest, oldest.header.index - 1)?;
+            blocks.push(parent);
+        }
+        blocks.reverse();
+        Ok(blocks)
+    }
+    
+    /// Get a block of the block tree by hash
+    fn tree_block(&self, hash: &[u8]) -> Result<Block> {
+        self.block_tree.lock().unwrap().as_ref()
+            .ok_or_else(|| Error::BlockValidation("Blockchain not initialized".to_string()))?
+            .get(hash)
+            .map(|entry| entry.block.clone())
+            .ok_or_else(|| Error::BlockValidation(format!("Unknown block {}", hex::encode(hash))))
+    }
+    
+    /// Get the block at `height` on the branch ending at `tip`
+    fn ancestor_at(&self, tip: &Block, height: u64) -> Result<Block> {
+        let mut current = tip.clone();
+        while current.header.index > height {
+            let parent = self.block_tree.lock().unwrap().as_ref()
+                .and_then(|tree| tree.get(&current.header.previous_hash))
+                .map(|entry| entry.block.clone());
+            current = match parent {
+                Some(parent) => parent,
+                // Blocks below the tree are finalized and canonical
+                None => return self.get_block_by_height(height),
+            };
+        }
+        Ok(current)
+    }
+    
+    /// Get a block by height
+    pub fn get_block_by_height(&self, height: u64) -> Result<Block> {
+        // First try in-memory cache
+        {
+            let blocks = self.blocks.lock().unwrap();
+            if let Some(block) = blocks.get(&height) {
+                return Ok(block.clone());
+            }
+        }
+        
+        // If not in memory and we have a chain store, try there
+        if let Some(ref chain_store) = self.chain_store {
+            return chain_store.get_block_by_height(height);
+        }
+        
+        Err(Error::State(format!("Block not found at height {}", height)))
+    }
+    
+    /// Get a block by hash
+    pub fn get_block_by_hash(&self, hash: &[u8]) -> Result<Block> {
+        // If we have a chain store, use it
         if let Some(ref chain_store) = self.chain_store {
             return chain_store.get_block_by_hash(hash);
         }
         
-        // Otherwise, we'd need to search through in-memory blocks
-        // This is inefficient and would be improved in a real implementation
-        Err(Error::State(format!("Block not found with hash {:?}", hash)))
+        // Otherwise, search through the in-memory blocks
+        let blocks = self.blocks.lock().unwrap();
+        blocks.values()
+            .find(|block| block.hash()[..] == *hash)
+            .cloned()
+            .ok_or_else(|| Error::State(format!("Block not found with hash {:?}", hash)))
+    }
+    
+    /// Get the block parameters that apply to the next block on the head
+    pub fn block_parameters(&self) -> Result<BlockParameters> {
+        let head = self.tree_block(&self.get_latest_hash())?;
+        self.parameters_at(&head, head.header.index + 1)
+    }
+    
+    /// Get the base fee the next block on the head charges on a shard
+    pub fn base_fee(&self, shard_id: ShardId) -> Result<u64> {
+        let head = self.tree_block(&self.get_latest_hash())?;
+        Ok(self.config.fee_market.base_fee_after(&self.shard_usage_of(&head)?, shard_id))
     }
     
     /// Get the current height of the blockchain
@@ -381,6 +1523,26 @@ impl Blockchain {
         self.mempool.clone()
     }
     
+    /// Get the shard manager
+    pub fn shard_manager(&self) -> Arc<Mutex<ShardManager>> {
+        self.shard_manager.clone()
+    }
+    
+    /// Get the chain id this blockchain accepts blocks and transactions for
+    pub fn chain_id(&self) -> &str {
+        &self.config.chain_id
+    }
+    
+    /// Get the chain store, if one is attached
+    pub fn chain_store(&self) -> Option<Arc<ChainStore>> {
+        self.chain_store.clone()
+    }
+    
+    /// Get the state database, if one is attached
+    pub fn state_db(&self) -> Option<Arc<StateDB>> {
+        self.state_db.clone()
+    }
+    
     /// Check if the chain has a block at the given height
     pub fn has_block_at_height(&self, height: u64) -> bool {
         // First check in-memory cache
@@ -406,21 +1568,37 @@ impl Blockchain {
             return chain_store.has_block_hash(hash);
         }
         
-        // Otherwise, we'd need to search through in-memory blocks
-        // This is inefficient and would be improved in a real implementation
-        false
+        // Otherwise, search through the in-memory blocks
+        let blocks = self.blocks.lock().unwrap();
+        blocks.values().any(|block| block.hash()[..] == *hash)
     }
     
     /// Add a transaction to the mempool
     pub fn add_transaction(&self, transaction: Transaction) -> Result<()> {
-        self.mempool.add_transaction(transaction)
+        self.mempool.add_transaction(&transaction)
+    }
+    
+    /// Get the receipt of a confirmed transaction
+    pub fn get_receipt(&self, tx_id: &[u8]) -> Result<Receipt> {
+        match self.chain_store {
+            Some(ref chain_store) => chain_store.get_receipt(tx_id),
+            None => Err(Error::State("Receipts require a chain store".to_string())),
+        }
+    }
+    
+    /// Get the logs matching a filter from the canonical chain
+    pub fn get_logs(&self, filter: &LogFilter) -> Result<Vec<LogEntry>> {
+        match self.chain_store {
+            Some(ref chain_store) => chain_store.get_logs(filter),
+            None => Err(Error::State("Log queries require a chain store".to_string())),
+        }
     }
     
     /// Get a transaction by ID
     pub fn get_transaction(&self, tx_id: &[u8]) -> Result<Transaction> {
         // First check mempool
         if let Some(tx) = self.mempool.get_transaction(tx_id) {
-            return Ok(tx);
+            return Ok((*tx).clone());
         }
         
         // If not in mempool and we have a chain store, check there
@@ -428,14 +1606,22 @@ impl Blockchain {
             return chain_store.get_transaction(tx_id);
         }
         
-        Err(Error::State(format!("Transaction not found with ID {:?}", tx_id)))
+        // Otherwise, search through the in-memory blocks
+        let blocks = self.blocks.lock().unwrap();
+        blocks.values()
+            .flat_map(|block| block.transactions.iter())
+            .find(|tx| tx.id == tx_id)
+            .cloned()
+            .ok_or_else(|| Error::State(format!("Transaction not found with ID {:?}", tx_id)))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::consensus::{Consensus, DPoSConsensus};
     use crate::types::ShardId;
+    use crate::crypto::KeyPair;
     use std::time::{SystemTime, UNIX_EPOCH};
     
     fn current_time_micros() -> u64 {
@@ -483,6 +1669,11 @@ mod tests {
         // Retrieve genesis block
         let retrieved = blockchain.get_block_by_height(0).unwrap();
         assert_eq!(retrieved.header.index, 0);
+        
+        // Genesis and latest hash are the real block hash
+        assert_eq!(blockchain.get_genesis_hash(), genesis.hash().to_vec());
+        assert_eq!(blockchain.get_latest_hash(), genesis.hash().to_vec());
+        assert!(blockchain.has_block_with_hash(&genesis.hash()));
     }
     
     #[test]
@@ -508,6 +1699,12 @@ mod tests {
         // Retrieve the block
         let retrieved = blockchain.get_block_by_height(1).unwrap();
         assert_eq!(retrieved.header.index, 1);
+        
+        // The latest hash now identifies the new block
+        assert_eq!(blockchain.get_latest_hash(), block.hash().to_vec());
+        assert_ne!(blockchain.get_latest_hash(), blockchain.get_genesis_hash());
+        let by_hash = blockchain.get_block_by_hash(&block.hash()).unwrap();
+        assert_eq!(by_hash.header.index, 1);
     }
     
     #[test]
@@ -536,6 +1733,16 @@ mod tests {
         invalid_block.header.timestamp = current_time_micros() + 120_000_000; // 2 minutes in the future
         assert!(blockchain.validate_block(&invalid_block).is_err());
         
+        // Block with a tampered transaction root should fail
+        let mut invalid_block = valid_block.clone();
+        invalid_block.header.transaction_root = vec![9; 32];
+        assert!(blockchain.validate_block(&invalid_block).is_err());
+        
+        // Block whose validator set does not match its merkle root should fail
+        let mut invalid_block = valid_block.clone();
+        invalid_block.add_validator(vec![9; 32]);
+        assert!(blockchain.validate_block(&invalid_block).is_err());
+        
         // Valid block should pass
         assert!(blockchain.validate_block(&valid_block).is_ok());
     }
@@ -549,17 +1756,19 @@ mod tests {
         blockchain.initialize_with_genesis(genesis).unwrap();
         
         // Create a transaction
-        let sender_key = vec![1; 32];
+        let sender_keypair = KeyPair::from_seed(&[1; 32]).unwrap();
+        let sender_key = sender_keypair.public_key();
         let recipient = vec![2; 20];
-        let tx = Transaction::new_transfer(
+        let mut tx = Transaction::new_transfer(
             sender_key,
             0, // sender shard
             recipient,
             0, // recipient shard
             1000, // amount
-            10, // fee
+            1000, // fee
             0, // nonce
         );
+        tx.sign(&sender_keypair, DEFAULT_CHAIN_ID);
         
         // Add to mempool
         blockchain.add_transaction(tx.clone()).unwrap();
@@ -582,4 +1791,1084 @@ mod tests {
         // Transaction should no longer be in mempool
         assert!(blockchain.mempool.get_transaction(&tx.id).is_none());
     }
+    
+    #[test]
+    fn test_block_execution_updates_state() {
+        let mut blockchain = Blockchain::new().unwrap();
+        let state_db = Arc::new(StateDB::new("", &crate::storage::StorageConfig::default()).unwrap());
+        blockchain.set_state_db(state_db.clone());
+        
+        let genesis = blockchain.generate_genesis_block(None, None, None).unwrap();
+        blockchain.initialize_with_genesis(genesis).unwrap();
+        
+        // Fund the sender
+        let sender_keypair = KeyPair::from_seed(&[1; 32]).unwrap();
+        let sender_key = sender_keypair.public_key();
+        let sender = state_transition::address_from_public_key(&sender_key);
+        state_db.set_account_balance(&sender, 10_000).unwrap();
+        
+        // A payable transaction and one with a nonce gap
+        let recipient = vec![2; 20];
+        let mut tx = Transaction::new_transfer(sender_key.clone(), 0, recipient.clone(), 0, 1000, 200, 0);
+        tx.sign(&sender_keypair, DEFAULT_CHAIN_ID);
+        let mut gapped = Transaction::new_transfer(sender_key, 0, recipient.clone(), 0, 1000, 200, 5);
+        gapped.sign(&sender_keypair, DEFAULT_CHAIN_ID);
+        blockchain.add_transaction(tx.clone()).unwrap();
+        blockchain.add_transaction(gapped.clone()).unwrap();
+        
+        // Only the executable transaction is included
+        let block = blockchain.create_block(vec![0 as ShardId], None).unwrap();
+        assert_eq!(block.transaction_ids(), vec![&tx.id]);
+        assert_ne!(block.header.state_root, EMPTY_ROOT.to_vec());
+        
+        // A block with a wrong state root is rejected without changing state
+        let mut tampered = block.clone();
+        tampered.header.state_root = vec![9; 32];
+        assert!(blockchain.add_block(tampered).is_err());
+        assert_eq!(state_db.get_account_balance(&sender).unwrap(), 10_000);
+        
+        blockchain.add_block(block).unwrap();
+        assert_eq!(state_db.get_account_balance(&sender).unwrap(), 10_000 - 1200);
+        assert_eq!(state_db.get_account_nonce(&sender).unwrap(), 1);
+        assert_eq!(state_db.get_account_balance(&recipient).unwrap(), 1000);
+    }
+    
+    #[test]
+    fn test_block_leaves_out_transactions_that_fail() {
+        let mut blockchain = Blockchain::new().unwrap();
+        let state_db = Arc::new(StateDB::new("", &crate::storage::StorageConfig::default()).unwrap());
+        blockchain.set_state_db(state_db.clone());
+        
+        let genesis = blockchain.generate_genesis_block(None, None, None).unwrap();
+        blockchain.initialize_with_genesis(genesis).unwrap();
+        
+        // The sender can only pay for the first of its two transfers
+        let sender_keypair = KeyPair::from_seed(&[1; 32]).unwrap();
+        let sender = state_transition::address_from_public_key(&sender_keypair.public_key());
+        state_db.set_account_balance(&sender, 1500).unwrap();
+        
+        let recipient = vec![2; 20];
+        let mut paid = Transaction::new_transfer(sender_keypair.public_key(), 0, recipient.clone(), 0, 1000, 200, 0);
+        paid.sign(&sender_keypair, DEFAULT_CHAIN_ID);
+        let mut overdrawn = Transaction::new_transfer(sender_keypair.public_key(), 0, recipient.clone(), 0, 1000, 200, 1);
+        overdrawn.sign(&sender_keypair, DEFAULT_CHAIN_ID);
+        blockchain.add_transaction(paid.clone()).unwrap();
+        blockchain.add_transaction(overdrawn).unwrap();
+        
+        // The failing transfer is left out and the state root covers the rest
+        let block = blockchain.create_block(vec![0 as ShardId], None).unwrap();
+        assert_eq!(block.transaction_ids(), vec![&paid.id]);
+        blockchain.add_block(block).unwrap();
+        assert_eq!(state_db.get_account_balance(&sender).unwrap(), 300);
+        assert_eq!(state_db.get_account_balance(&recipient).unwrap(), 1000);
+    }
+    
+    #[test]
+    fn test_state_dependencies_follow_included_blocks() {
+        let mut blockchain = Blockchain::new().unwrap();
+        let state_db = Arc::new(StateDB::new("", &crate::storage::StorageConfig::default()).unwrap());
+        blockchain.set_state_db(state_db.clone());
+        
+        let genesis = blockchain.generate_genesis_block(None, None, None).unwrap();
+        blockchain.initialize_with_genesis(genesis).unwrap();
+        
+        let alice = KeyPair::from_seed(&[1; 32]).unwrap();
+        let bob = KeyPair::from_seed(&[2; 32]).unwrap();
+        let carol = KeyPair::from_seed(&[3; 32]).unwrap();
+        for key in [&alice, &bob, &carol] {
+            state_db.set_account_balance(&state_transition::address_from_public_key(&key.public_key()), 10_000).unwrap();
+        }
+        
+        let mut parent = Transaction::new_transfer(alice.public_key(), 0, vec![9; 20], 0, 1000, 1000, 0);
+        parent.sign(&alice, DEFAULT_CHAIN_ID);
+        blockchain.add_transaction(parent.clone()).unwrap();
+        let block = blockchain.create_block(vec![0 as ShardId], None).unwrap();
+        
+        // One dependent expects the state the parent's block produces, the other a different one
+        let state_dependent = |sender: &KeyPair, required_state: Vec<u8>| {
+            let mut tx = Transaction::new_transfer(sender.public_key(), 0, vec![9; 20], 0, 1000, 1000, 0);
+            tx.dependencies.push(Dependency {
+                transaction_id: parent.id.clone(),
+                dependency_type: DependencyType::State,
+                required_state: Some(required_state),
+            });
+            tx.sign(sender, DEFAULT_CHAIN_ID);
+            tx
+        };
+        let expecting = state_dependent(&bob, block.header.state_root.clone());
+        let mismatched = state_dependent(&carol, vec![9; 32]);
+        blockchain.add_transaction(expecting.clone()).unwrap();
+        blockchain.add_transaction(mismatched).unwrap();
+        
+        blockchain.add_block(block).unwrap();
+        let block = blockchain.create_block(vec![0 as ShardId], None).unwrap();
+        assert_eq!(block.transaction_ids(), vec![&expecting.id]);
+    }
+    
+    #[test]
+    fn test_optimistic_execution_reuses_speculation() {
+        let mut blockchain = Blockchain::new().unwrap();
+        let state_db = Arc::new(StateDB::new("", &crate::storage::StorageConfig::default()).unwrap());
+        blockchain.set_state_db(state_db.clone());
+        blockchain.set_optimistic_execution(true);
+        
+        let genesis = blockchain.generate_genesis_block(None, None, None).unwrap();
+        blockchain.initialize_with_genesis(genesis).unwrap();
+        
+        let alice = KeyPair::from_seed(&[1; 32]).unwrap();
+        let bob = KeyPair::from_seed(&[2; 32]).unwrap();
+        let alice_address = state_transition::address_from_public_key(&alice.public_key());
+        for key in [&alice, &bob] {
+            state_db.set_account_balance(&state_transition::address_from_public_key(&key.public_key()), 10_000).unwrap();
+        }
+        
+        let transfer = |sender: &KeyPair, nonce: u64| {
+            let mut tx = Transaction::new_transfer(sender.public_key(), 0, vec![9; 20], 0, 1000, 1000, nonce);
+            tx.sign(sender, DEFAULT_CHAIN_ID);
+            tx
+        };
+        
+        // Both speculations still hold when the block arrives
+        blockchain.add_transaction(transfer(&alice, 0)).unwrap();
+        blockchain.add_transaction(transfer(&bob, 0)).unwrap();
+        assert_eq!(blockchain.speculate_pending().unwrap(), 2);
+        let block = blockchain.create_block(vec![0 as ShardId], None).unwrap();
+        blockchain.add_block(block).unwrap();
+        
+        let stats = blockchain.optimistic_stats().unwrap();
+        assert_eq!((stats.hits, stats.conflicts, stats.unspeculated), (2, 0, 0));
+        
+        // Alice's balance changes after her next transfer was speculated
+        blockchain.add_transaction(transfer(&alice, 1)).unwrap();
+        assert_eq!(blockchain.speculate_pending().unwrap(), 1);
+        state_db.set_account_balance(&alice_address, 50_000).unwrap();
+        let block = blockchain.create_block(vec![0 as ShardId], None).unwrap();
+        blockchain.add_block(block).unwrap();
+        
+        let stats = blockchain.optimistic_stats().unwrap();
+        assert_eq!((stats.hits, stats.conflicts, stats.unspeculated), (2, 1, 0));
+        assert_eq!(state_db.get_account_balance(&alice_address).unwrap(), 50_000 - 2000);
+        assert!((stats.hit_rate() - 2.0 / 3.0).abs() < 1e-9);
+    }
+    
+    #[test]
+    fn test_initialize_with_spec() {
+        let mut blockchain = Blockchain::new().unwrap();
+        let state_db = Arc::new(StateDB::new("", &crate::storage::StorageConfig::default()).unwrap());
+        blockchain.set_state_db(state_db.clone());
+        
+        let sender_keypair = KeyPair::from_seed(&[1; 32]).unwrap();
+        let sender_key = sender_keypair.public_key();
+        let sender = state_transition::address_from_public_key(&sender_key);
+        let spec = GenesisSpec {
+            chain_id: "sebure-test".to_string(),
+            timestamp: current_time_micros() - 1_000_000,
+            shard_count: 1,
+            accounts: vec![GenesisAccount { address: hex::encode(&sender), balance: 10_000 }],
+            validators: vec![GenesisValidator { public_key: hex::encode(vec![7; 32]), stake: 2_000, commission_rate: 0.05 }],
+            consensus: crate::consensus::ConsensusConfig::default(),
+            genesis_hash: None,
+        };
+        
+        let validators = blockchain.initialize_with_spec(&spec).unwrap();
+        assert_eq!(validators.validator_count(), 1);
+        assert_eq!(blockchain.get_genesis_hash(), spec.build_block().unwrap().hash().to_vec());
+        assert_eq!(state_db.get_account_balance(&sender).unwrap(), 10_000);
+        assert_eq!(blockchain.chain_id(), "sebure-test");
+        
+        // Transactions signed for another chain are not admitted
+        let mut tx = Transaction::new_transfer(sender_key, 0, vec![2; 20], 0, 1000, 200, 0);
+        tx.sign(&sender_keypair, DEFAULT_CHAIN_ID);
+        assert!(blockchain.add_transaction(tx.clone()).is_err());
+        
+        // The seeded balance can be spent in the next block
+        tx.sign(&sender_keypair, "sebure-test");
+        blockchain.add_transaction(tx).unwrap();
+        let block = blockchain.create_block(vec![0 as ShardId], None).unwrap();
+        assert_eq!(block.header.chain_id, "sebure-test");
+        blockchain.add_block(block).unwrap();
+        assert_eq!(state_db.get_account_balance(&sender).unwrap(), 10_000 - 1200);
+    }
+    
+    #[test]
+    fn test_blocks_from_other_chains_are_rejected() {
+        let mut blockchain = Blockchain::new().unwrap();
+        let genesis = blockchain.generate_genesis_block(None, None, None).unwrap();
+        assert_eq!(genesis.header.chain_id, DEFAULT_CHAIN_ID);
+        
+        // A genesis block for another chain cannot initialize this one
+        let mut foreign_genesis = genesis.clone();
+        foreign_genesis.header.chain_id = "sebure-devnet".to_string();
+        assert!(blockchain.initialize_with_genesis(foreign_genesis).is_err());
+        
+        blockchain.initialize_with_genesis(genesis.clone()).unwrap();
+        let t = genesis.header.timestamp;
+        
+        let mut foreign = child_block(&genesis, t + 1, 0, &[], None);
+        foreign.header.chain_id = "sebure-devnet".to_string();
+        assert!(rejection(&blockchain, &foreign).contains("is for chain sebure-devnet"));
+        
+        let native = child_block(&genesis, t + 1, 0, &[], None);
+        assert!(blockchain.validate_block(&native).is_ok());
+    }
+    
+    #[test]
+    fn test_transaction_ids_must_match_contents() {
+        let mut blockchain = Blockchain::new().unwrap();
+        let genesis = blockchain.generate_genesis_block(None, None, None).unwrap();
+        blockchain.initialize_with_genesis(genesis.clone()).unwrap();
+        let t = genesis.header.timestamp;
+        
+        // A validly signed transaction under an ID of the sender's choosing
+        let mut renamed = test_transfer(1, 1000);
+        renamed.id = vec![1; 32];
+        assert!(blockchain.add_transaction(renamed.clone()).is_err());
+        
+        let mut block = child_block(&genesis, t + 1, 0, &[renamed.clone()], None);
+        block.transactions = vec![renamed];
+        assert!(rejection(&blockchain, &block).contains("does not match its contents"));
+    }
+    
+    fn test_transfer(id: u8, fee: u32) -> Transaction {
+        let sender = KeyPair::from_seed(&[id; 32]).unwrap();
+        let mut tx = Transaction::new_transfer(sender.public_key(), 0, vec![2; 20], 0, 1000, fee, 0);
+        tx.sign(&sender, DEFAULT_CHAIN_ID);
+        tx
+    }
+    
+    /// Genesis block dividing the keyspace between shards 0 and 1
+    fn two_shard_genesis(blockchain: &Blockchain) -> Block {
+        let mut genesis = blockchain.generate_genesis_block(None, None, None).unwrap();
+        genesis.header.shard_identifiers = vec![0, 1];
+        genesis
+    }
+    
+    /// Key pair whose account the layout puts on `shard_id`
+    fn keypair_on(layout: &ShardLayout, shard_id: ShardId) -> KeyPair {
+        (1..=u8::MAX)
+            .map(|seed| KeyPair::from_seed(&[seed; 32]).unwrap())
+            .find(|keypair| layout.shard_for_address(&state_transition::address_from_public_key(&keypair.public_key())) == shard_id)
+            .unwrap()
+    }
+    
+    /// Address that the layout puts on `shard_id`
+    fn address_on(layout: &ShardLayout, shard_id: ShardId) -> Vec<u8> {
+        (1..=u8::MAX)
+            .map(|byte| vec![byte; 32])
+            .find(|address| layout.shard_for_address(address) == shard_id)
+            .unwrap()
+    }
+    
+    fn rejection(blockchain: &Blockchain, block: &Block) -> String {
+        match blockchain.validate_block(block) {
+            Err(Error::BlockValidation(message)) => message,
+            other => panic!("expected a block validation error, got {:?}", other),
+        }
+    }
+    
+    #[test]
+    fn test_block_building_respects_limits() {
+        let txs = vec![test_transfer(1, 1000), test_transfer(2, 3000), test_transfer(3, 2000)];
+        
+        // The count limit applies to the whole block, best fees first
+        let mut config = BlockchainConfig::default();
+        config.max_transactions_per_block = 2;
+        let mut blockchain = Blockchain::with_config(config).unwrap();
+        let genesis = blockchain.generate_genesis_block(None, None, None).unwrap();
+        blockchain.initialize_with_genesis(genesis).unwrap();
+        for tx in &txs {
+            blockchain.add_transaction(tx.clone()).unwrap();
+        }
+        
+        let block = blockchain.create_block(vec![0 as ShardId], None).unwrap();
+        assert_eq!(block.transaction_ids(), vec![&txs[1].id, &txs[2].id]);
+        
+        // The byte limit is measured on the encoded block and transactions
+        let mut one_tx_block = block.clone();
+        one_tx_block.shard_data[0].transactions.truncate(1);
+        one_tx_block.transactions.truncate(1);
+        let limit = one_tx_block.encoded_size().unwrap();
+        
+        let mut config = BlockchainConfig::default();
+        config.max_block_size = limit;
+        let mut blockchain = Blockchain::with_config(config).unwrap();
+        let genesis = blockchain.generate_genesis_block(None, None, None).unwrap();
+        blockchain.initialize_with_genesis(genesis).unwrap();
+        for tx in &txs {
+            blockchain.add_transaction(tx.clone()).unwrap();
+        }
+        
+        let block = blockchain.create_block(vec![0 as ShardId], None).unwrap();
+        assert_eq!(block.transaction_ids(), vec![&txs[1].id]);
+        assert!(blockchain.validate_block(&block).is_ok());
+    }
+    
+    #[test]
+    fn test_block_keeps_dependents_behind_parents() {
+        let mut blockchain = Blockchain::new().unwrap();
+        let genesis = two_shard_genesis(&blockchain);
+        blockchain.initialize_with_genesis(genesis).unwrap();
+        let layout = ShardLayout::uniform(2);
+        
+        // The parent is on a shard laid out after its dependent's
+        let alice = keypair_on(&layout, 1);
+        let mut parent = Transaction::new_transfer(alice.public_key(), 1, address_on(&layout, 1), 1, 1000, 1000, 0);
+        parent.sign(&alice, DEFAULT_CHAIN_ID);
+        
+        let bob = keypair_on(&layout, 0);
+        let mut child = Transaction::new_transfer(bob.public_key(), 0, address_on(&layout, 0), 0, 1000, 1000, 0);
+        child.add_dependency(parent.id.clone(), DependencyType::Hard);
+        child.sign(&bob, DEFAULT_CHAIN_ID);
+        
+        blockchain.add_transaction(parent.clone()).unwrap();
+        blockchain.add_transaction(child.clone()).unwrap();
+        
+        let block = blockchain.create_block(vec![0 as ShardId, 1], None).unwrap();
+        assert_eq!(block.transaction_ids(), vec![&parent.id]);
+        blockchain.add_block(block).unwrap();
+        
+        // With the parent included, the dependent can go in the next block
+        let block = blockchain.create_block(vec![0 as ShardId, 1], None).unwrap();
+        assert_eq!(block.transaction_ids(), vec![&child.id]);
+    }
+    
+    #[test]
+    fn test_blocks_respect_transaction_time_bounds() {
+        let mut blockchain = Blockchain::new().unwrap();
+        let genesis = blockchain.generate_genesis_block(None, None, None).unwrap();
+        blockchain.initialize_with_genesis(genesis.clone()).unwrap();
+        let t = genesis.header.timestamp;
+        
+        let sender = KeyPair::from_seed(&[1; 32]).unwrap();
+        let mut scheduled = Transaction::new_transfer(sender.public_key(), 0, vec![2; 20], 0, 1000, 1000, 0);
+        scheduled.valid_after = Some(TimeBound::Height(2));
+        scheduled.sign(&sender, DEFAULT_CHAIN_ID);
+        blockchain.add_transaction(scheduled.clone()).unwrap();
+        
+        // Neither a producer nor a validator lets it in early
+        let block = blockchain.create_block(vec![0 as ShardId], Some(t + 1)).unwrap();
+        assert!(block.transaction_ids().is_empty());
+        let early = child_block(&genesis, t + 1, 0, &[scheduled.clone()], None);
+        assert!(rejection(&blockchain, &early).contains("is not valid before"));
+        
+        blockchain.add_block(block).unwrap();
+        let block = blockchain.create_block(vec![0 as ShardId], Some(t + 2)).unwrap();
+        assert_eq!(block.transaction_ids(), vec![&scheduled.id]);
+        
+        // An offer is rejected in blocks after it lapses
+        let other = KeyPair::from_seed(&[2; 32]).unwrap();
+        let mut offer = Transaction::new_transfer(other.public_key(), 0, vec![2; 20], 0, 1000, 1000, 0);
+        offer.valid_until = Some(TimeBound::Timestamp(t + 5));
+        offer.sign(&other, DEFAULT_CHAIN_ID);
+        blockchain.add_transaction(offer.clone()).unwrap();
+        
+        let parent = blockchain.get_block_by_height(1).unwrap();
+        let late = child_block(&parent, t + 6, 0, &[offer.clone()], None);
+        assert!(rejection(&blockchain, &late).contains("expired at"));
+        let in_time = child_block(&parent, t + 5, 0, &[offer], None);
+        assert!(blockchain.validate_block(&in_time).is_ok());
+    }
+    
+    #[test]
+    fn test_transactions_must_name_their_shards() {
+        let mut blockchain = Blockchain::new().unwrap();
+        let genesis = two_shard_genesis(&blockchain);
+        blockchain.initialize_with_genesis(genesis.clone()).unwrap();
+        let layout = ShardLayout::uniform(2);
+        
+        let alice = keypair_on(&layout, 0);
+        let mut misplaced = Transaction::new_transfer(alice.public_key(), 0, address_on(&layout, 0), 1, 1000, 1000, 0);
+        misplaced.sign(&alice, DEFAULT_CHAIN_ID);
+        assert!(blockchain.add_transaction(misplaced.clone()).is_err());
+        
+        // Nor can a producer put it in a block
+        let mut block = child_block(&genesis, genesis.header.timestamp + 1, 0, &[], None);
+        block.add_transactions(0, vec![misplaced]).unwrap();
+        block.update_merkle_roots();
+        assert!(rejection(&blockchain, &block).contains("but it is on shard 0"));
+    }
+    
+    /// Chain with state where alice, on shard 0, holds 10,000 and has sent
+    /// 1,000 to bob on shard 1 in block 1
+    fn chain_with_cross_shard_transfer(timeout: u64) -> (Blockchain, Arc<StateDB>, Transaction, u64) {
+        let mut config = BlockchainConfig::default();
+        config.cross_shard_timeout = timeout;
+        let mut blockchain = Blockchain::with_config(config).unwrap();
+        let state_db = Arc::new(StateDB::new("", &crate::storage::StorageConfig::default()).unwrap());
+        blockchain.set_state_db(state_db.clone());
+        let genesis = two_shard_genesis(&blockchain);
+        blockchain.initialize_with_genesis(genesis.clone()).unwrap();
+        let t = genesis.header.timestamp;
+        let layout = ShardLayout::uniform(2);
+        
+        let alice = keypair_on(&layout, 0);
+        let alice_address = state_transition::address_from_public_key(&alice.public_key());
+        state_db.set_account_balance(&alice_address, 10_000).unwrap();
+        
+        let mut tx = Transaction::new_transfer(alice.public_key(), 0, address_on(&layout, 1), 1, 1000, 1000, 0);
+        tx.sign(&alice, DEFAULT_CHAIN_ID);
+        blockchain.add_transaction(tx.clone()).unwrap();
+        
+        let block = blockchain.create_block(vec![0 as ShardId], Some(t + 1)).unwrap();
+        assert_eq!(block.transaction_ids(), vec![&tx.id]);
+        assert_eq!(block.cross_shard_receipts.len(), 1);
+        blockchain.add_block(block).unwrap();
+        
+        (blockchain, state_db, tx, t)
+    }
+    
+    #[test]
+    fn test_cross_shard_transfer_is_credited() {
+        let (mut blockchain, state_db, tx, t) = chain_with_cross_shard_transfer(10);
+        let alice = state_transition::address_from_public_key(&tx.sender_public_key);
+        let escrow = state_transition::escrow_address(&tx.id);
+        
+        // The amount is held in escrow until a block covers the destination
+        assert_eq!(state_db.get_account_balance(&alice).unwrap(), 8000);
+        assert_eq!(state_db.get_account_balance(&escrow).unwrap(), 1000);
+        assert!(blockchain.shard_manager().lock().unwrap().is_pending(&tx.id));
+        
+        let block = blockchain.create_block(vec![0 as ShardId], Some(t + 2)).unwrap();
+        assert!(block.cross_shard_settlements.is_empty());
+        blockchain.add_block(block).unwrap();
+        
+        let block = blockchain.create_block(vec![0 as ShardId, 1], Some(t + 3)).unwrap();
+        assert_eq!(block.cross_shard_settlements.len(), 1);
+        assert!(!block.cross_shard_settlements[0].refund);
+        
+        // The settlement cannot be dropped or turned into a refund
+        let mut dropped = block.clone();
+        dropped.cross_shard_settlements.clear();
+        assert!(blockchain.add_block(dropped).is_err());
+        let mut refunded = block.clone();
+        refunded.cross_shard_settlements[0].refund = true;
+        assert!(rejection(&blockchain, &refunded).contains("refund before timeout"));
+        
+        blockchain.add_block(block).unwrap();
+        assert_eq!(state_db.get_account_balance(&tx.recipient_address).unwrap(), 1000);
+        assert_eq!(state_db.get_account_balance(&escrow).unwrap(), 0);
+        assert!(!blockchain.shard_manager().lock().unwrap().is_pending(&tx.id));
+        
+        // Settled transfers are not settled again
+        let block = blockchain.create_block(vec![0 as ShardId, 1], Some(t + 4)).unwrap();
+        assert!(block.cross_shard_settlements.is_empty());
+    }
+    
+    #[test]
+    fn test_restart_resumes_pending_transfers() {
+        let dir = tempfile::tempdir().unwrap();
+        let chain_store = Arc::new(ChainStore::new(dir.path().to_str().unwrap(), &crate::storage::StorageConfig::default()).unwrap());
+        let state_db = Arc::new(StateDB::new("", &crate::storage::StorageConfig::default()).unwrap());
+        let start = |genesis: &Block| {
+            let mut blockchain = Blockchain::new().unwrap();
+            blockchain.set_chain_store(chain_store.clone());
+            blockchain.set_state_db(state_db.clone());
+            blockchain.initialize_with_genesis(genesis.clone()).map(|_| blockchain)
+        };
+        
+        let genesis = two_shard_genesis(&Blockchain::new().unwrap());
+        let t = genesis.header.timestamp;
+        let mut blockchain = start(&genesis).unwrap();
+        let layout = ShardLayout::uniform(2);
+        
+        let alice = keypair_on(&layout, 0);
+        state_db.set_account_balance(&state_transition::address_from_public_key(&alice.public_key()), 10_000).unwrap();
+        let mut tx = Transaction::new_transfer(alice.public_key(), 0, address_on(&layout, 1), 1, 1000, 1000, 0);
+        tx.sign(&alice, DEFAULT_CHAIN_ID);
+        blockchain.add_transaction(tx.clone()).unwrap();
+        let block = blockchain.create_block(vec![0 as ShardId], Some(t + 1)).unwrap();
+        blockchain.add_block(block).unwrap();
+        drop(blockchain);
+        
+        // Another chain cannot be started on the same store
+        let mut other = genesis.clone();
+        other.header.timestamp += 1;
+        assert!(start(&other).is_err());
+        
+        // After a restart the transfer is still pending and gets settled
+        let mut blockchain = start(&genesis).unwrap();
+        assert_eq!(blockchain.get_height(), 1);
+        assert!(blockchain.shard_manager().lock().unwrap().is_pending(&tx.id));
+        
+        let block = blockchain.create_block(vec![0 as ShardId, 1], Some(t + 2)).unwrap();
+        assert_eq!(block.cross_shard_settlements.len(), 1);
+        blockchain.add_block(block).unwrap();
+        assert_eq!(state_db.get_account_balance(&tx.recipient_address).unwrap(), 1000);
+    }
+    
+    #[test]
+    fn test_cross_shard_transfer_is_refunded_after_timeout() {
+        let (mut blockchain, state_db, tx, t) = chain_with_cross_shard_transfer(1);
+        let alice = state_transition::address_from_public_key(&tx.sender_public_key);
+        let escrow = state_transition::escrow_address(&tx.id);
+        let total = |state_db: &StateDB| {
+            [&alice, &tx.recipient_address, &escrow].iter()
+                .map(|address| state_db.get_account_balance(address).unwrap())
+                .sum::<u64>()
+        };
+        
+        // No destination block arrives in time
+        let block = blockchain.create_block(vec![0 as ShardId], Some(t + 2)).unwrap();
+        assert!(block.cross_shard_settlements.is_empty());
+        blockchain.add_block(block).unwrap();
+        assert_eq!(total(&state_db), 9000);
+        
+        // Too late for the destination to credit it
+        let late = blockchain.create_block(vec![1 as ShardId], Some(t + 3)).unwrap();
+        assert!(late.cross_shard_settlements.is_empty());
+        
+        let block = blockchain.create_block(vec![0 as ShardId], Some(t + 3)).unwrap();
+        assert_eq!(block.cross_shard_settlements.len(), 1);
+        assert!(block.cross_shard_settlements[0].refund);
+        
+        let mut credited = block.clone();
+        credited.cross_shard_settlements[0].refund = false;
+        assert!(rejection(&blockchain, &credited).contains("transfer has timed out"));
+        let mut credited = late.clone();
+        credited.cross_shard_settlements = block.cross_shard_settlements.clone();
+        credited.cross_shard_settlements[0].refund = false;
+        assert!(rejection(&blockchain, &credited).contains("transfer has timed out"));
+        
+        blockchain.add_block(block).unwrap();
+        assert_eq!(state_db.get_account_balance(&alice).unwrap(), 9000);
+        assert_eq!(state_db.get_account_balance(&tx.recipient_address).unwrap(), 0);
+        assert_eq!(total(&state_db), 9000);
+    }
+    
+    #[test]
+    fn test_busy_shard_is_split_at_epoch_boundary() {
+        let mut config = BlockchainConfig::default();
+        config.resharding = ReshardingConfig {
+            epoch_length: 2,
+            split_transactions: 1,
+            ..ReshardingConfig::default()
+        };
+        let mut blockchain = Blockchain::with_config(config).unwrap();
+        let state_db = Arc::new(StateDB::new("", &crate::storage::StorageConfig::default()).unwrap());
+        blockchain.set_state_db(state_db.clone());
+        let genesis = blockchain.generate_genesis_block(None, None, None).unwrap();
+        blockchain.initialize_with_genesis(genesis.clone()).unwrap();
+        let t = genesis.header.timestamp;
+        assert_eq!(blockchain.shard_manager().lock().unwrap().layout().shard_ids(), vec![0]);
+        
+        let alice = KeyPair::from_seed(&[1; 32]).unwrap();
+        state_db.set_account_balance(&state_transition::address_from_public_key(&alice.public_key()), 10_000).unwrap();
+        let mut tx = Transaction::new_transfer(alice.public_key(), 0, vec![2; 32], 0, 1000, 1000, 0);
+        tx.sign(&alice, DEFAULT_CHAIN_ID);
+        blockchain.add_transaction(tx).unwrap();
+        
+        for i in 1..=2 {
+            let block = blockchain.create_block(vec![0 as ShardId], Some(t + i)).unwrap();
+            blockchain.add_block(block).unwrap();
+        }
+        
+        // The epoch's transaction crossed the threshold, so shard 0 is split
+        // and both halves commit to the accounts they now hold
+        let layout = {
+            let shard_manager = blockchain.shard_manager();
+            let shard_manager = shard_manager.lock().unwrap();
+            assert_eq!(shard_manager.layout().shard_ids(), vec![0, 1]);
+            assert_eq!(shard_manager.layout().epoch, 1);
+            for id in [0, 1] {
+                assert_eq!(state_db.get_shard_state_root(id).unwrap(), Some(shard_manager.get_shard(id).unwrap().state_root.clone()));
+            }
+            let active: u32 = [0, 1].iter().map(|&id| shard_manager.get_shard(id).unwrap().active_accounts).sum();
+            assert_eq!(active, 2);
+            shard_manager.layout().clone()
+        };
+        
+        // The next block commits to the new layout, and every node must agree on it
+        let block = blockchain.create_block(vec![0 as ShardId, 1], Some(t + 3)).unwrap();
+        assert_eq!(block.header.shard_layout.as_ref(), Some(&layout));
+        let mut missing = block.clone();
+        missing.header.shard_layout = None;
+        assert!(rejection(&blockchain, &missing).contains("must commit to a shard layout"));
+        let mut tampered = block.clone();
+        tampered.header.shard_layout = Some(ShardLayout::uniform(1));
+        assert!(blockchain.add_block(tampered).is_err());
+        blockchain.add_block(block).unwrap();
+        
+        // Transactions must follow the new layout from then on
+        let alice_shard = layout.shard_for_address(&state_transition::address_from_public_key(&alice.public_key()));
+        let mut misplaced = Transaction::new_transfer(alice.public_key(), 1 - alice_shard, vec![2; 32], layout.shard_for_address(&[2; 32]), 1000, 1000, 1);
+        misplaced.sign(&alice, DEFAULT_CHAIN_ID);
+        assert!(blockchain.add_transaction(misplaced).is_err());
+        let block = blockchain.create_block(vec![0 as ShardId, 1], Some(t + 4)).unwrap();
+        assert_eq!(block.header.shard_layout, None);
+    }
+    
+    #[test]
+    fn test_block_parameters_adapt_at_epoch_starts() {
+        let mut config = BlockchainConfig::default();
+        config.max_block_size = 2000;
+        config.adaptive_parameters = AdaptiveConfig {
+            epoch_length: 2,
+            min_block_size: 1000,
+            grow_fullness: 0,
+            shrink_fullness: 0,
+            ..AdaptiveConfig::default()
+        };
+        let mut blockchain = Blockchain::with_config(config).unwrap();
+        let genesis = blockchain.generate_genesis_block(None, None, None).unwrap();
+        blockchain.initialize_with_genesis(genesis.clone()).unwrap();
+        let t = genesis.header.timestamp;
+        assert_eq!(blockchain.block_parameters().unwrap(), BlockParameters { max_block_size: 2000, block_interval_ms: 2000 });
+        
+        let block = blockchain.create_block(vec![0 as ShardId], Some(t + 2_000_000)).unwrap();
+        assert_eq!(block.header.parameters, None);
+        blockchain.add_block(block).unwrap();
+        
+        // Blocks were produced on time and used some space, so the next
+        // epoch has larger blocks and a shorter interval
+        let grown = BlockParameters { max_block_size: 2250, block_interval_ms: 1750 };
+        assert_eq!(blockchain.block_parameters().unwrap(), grown);
+        let block = blockchain.create_block(vec![0 as ShardId], Some(t + 4_000_000)).unwrap();
+        assert_eq!(block.header.parameters, Some(grown));
+        
+        // Every node must agree on the committed parameters
+        let mut tampered = block.clone();
+        tampered.header.parameters = Some(BlockParameters { max_block_size: 8000, ..grown });
+        assert!(rejection(&blockchain, &tampered).contains("do not match"));
+        let mut missing = block.clone();
+        missing.header.parameters = None;
+        assert!(rejection(&blockchain, &missing).contains("do not match"));
+        blockchain.add_block(block).unwrap();
+        
+        // Within the epoch blocks carry no parameters but follow the committed ones
+        let block = blockchain.create_block(vec![0 as ShardId], Some(t + 21_500_000)).unwrap();
+        assert_eq!(block.header.parameters, None);
+        assert_eq!(blockchain.block_parameters().unwrap(), grown);
+        blockchain.add_block(block).unwrap();
+        
+        // Nine slots were missed within the epoch, so the interval grows
+        let block = blockchain.create_block(vec![0 as ShardId], Some(t + 23_500_000)).unwrap();
+        assert_eq!(block.header.parameters, Some(BlockParameters { max_block_size: 2250, block_interval_ms: 1968 }));
+        blockchain.add_block(block).unwrap();
+        
+        // A side branch that missed no slots keeps its own epoch load
+        let side = child_block(&blockchain.get_block_by_height(2).unwrap(), t + 5_750_000, 0, &[], None);
+        blockchain.add_block(side.clone()).unwrap();
+        assert_eq!(blockchain.get_height(), 4);
+        assert_eq!(blockchain.parameters_at(&side, 4).unwrap(), BlockParameters { max_block_size: 2531, block_interval_ms: 1532 });
+    }
+    
+    #[test]
+    fn test_base_fee_follows_shard_usage() {
+        let mut config = BlockchainConfig::default();
+        config.fee_market = FeeMarketConfig {
+            initial_base_fee: 500,
+            target_transactions: 1,
+            ..FeeMarketConfig::default()
+        };
+        // Admit transactions that tip nothing, to see blocks leave them out
+        config.mempool_config.min_fee_per_byte = 0;
+        let mut blockchain = Blockchain::with_config(config).unwrap();
+        let genesis = blockchain.generate_genesis_block(None, None, None).unwrap();
+        blockchain.initialize_with_genesis(genesis.clone()).unwrap();
+        let t = genesis.header.timestamp;
+        assert_eq!(blockchain.base_fee(0).unwrap(), 500);
+        
+        let cheap = test_transfer(1, 300);
+        let txs = vec![cheap.clone(), test_transfer(2, 1000), test_transfer(3, 1000)];
+        for tx in &txs {
+            blockchain.add_transaction(tx.clone()).unwrap();
+        }
+        
+        // A maximum fee below the base fee keeps a transaction out
+        let block = blockchain.create_block(vec![0 as ShardId], Some(t + 1)).unwrap();
+        assert_eq!(block.header.base_fees, vec![500]);
+        assert_eq!(block.transaction_ids().len(), 2);
+        assert!(!block.transaction_ids().contains(&&cheap.id));
+        
+        let mut underpaid = child_block(&genesis, t + 1, 0, &[cheap.clone()], None);
+        underpaid.header.base_fees = vec![500];
+        assert!(rejection(&blockchain, &underpaid).contains("below the base fee"));
+        
+        // Every node must agree on the base fees
+        let mut tampered = block.clone();
+        tampered.header.base_fees = vec![400];
+        assert!(rejection(&blockchain, &tampered).contains("Base fees"));
+        blockchain.add_block(block).unwrap();
+        
+        // Twice the target raises the base fee by an eighth; a shard no
+        // block included starts at the initial base fee
+        assert_eq!(blockchain.base_fee(0).unwrap(), 562);
+        assert_eq!(blockchain.base_fee(1).unwrap(), 500);
+        
+        // A transaction cannot pay the base fee of a shard other than its own
+        let head = blockchain.get_block_by_height(1).unwrap();
+        let mut misplaced = child_block(&head, t + 2, 0, &[cheap], None);
+        misplaced.header.shard_identifiers = vec![1];
+        misplaced.header.base_fees = vec![500];
+        misplaced.shard_data[0].shard_id = 1;
+        misplaced.update_merkle_roots();
+        assert!(rejection(&blockchain, &misplaced).contains("not listed under its sender shard 0"));
+        
+        // A block that leaves shard 0 out keeps its base fee
+        let block = blockchain.create_block(vec![1 as ShardId], Some(t + 2)).unwrap();
+        blockchain.add_block(block).unwrap();
+        assert_eq!(blockchain.base_fee(0).unwrap(), 562);
+    }
+    
+    #[test]
+    fn test_block_validation_enforces_limits() {
+        let mut config = BlockchainConfig::default();
+        config.max_transactions_per_block = 2;
+        let mut blockchain = Blockchain::with_config(config).unwrap();
+        let genesis = blockchain.generate_genesis_block(None, None, None).unwrap();
+        blockchain.initialize_with_genesis(genesis.clone()).unwrap();
+        let t = genesis.header.timestamp;
+        
+        let txs = vec![test_transfer(1, 1000), test_transfer(2, 1000), test_transfer(3, 1000)];
+        for tx in &txs {
+            blockchain.add_transaction(tx.clone()).unwrap();
+        }
+        
+        let too_many = child_block(&genesis, t + 1, 0, &txs, None);
+        assert!(rejection(&blockchain, &too_many).contains("exceeding the limit of 2"));
+        
+        let duplicate = child_block(&genesis, t + 1, 0, &[txs[0].clone(), txs[0].clone()], None);
+        assert!(rejection(&blockchain, &duplicate).contains("Duplicate transaction"));
+        
+        let unknown = child_block(&genesis, t + 1, 0, &[test_transfer(9, 1000)], None);
+        assert!(rejection(&blockchain, &unknown).contains("Unknown transaction"));
+        
+        // A transaction altered after signing is rejected even when the block carries it
+        let mut forged = test_transfer(4, 1000);
+        forged.amount = 5_000;
+        forged.id = forged.compute_id();
+        let mut forged_block = child_block(&genesis, t + 1, 0, &[], None);
+        forged_block.add_transactions(0, vec![forged]).unwrap();
+        forged_block.update_merkle_roots();
+        assert!(rejection(&blockchain, &forged_block).contains("Invalid signature"));
+        
+        let mut config = BlockchainConfig::default();
+        config.max_block_size = 100;
+        let mut blockchain = Blockchain::with_config(config).unwrap();
+        let genesis = blockchain.generate_genesis_block(None, None, None).unwrap();
+        blockchain.initialize_with_genesis(genesis.clone()).unwrap();
+        blockchain.add_transaction(txs[0].clone()).unwrap();
+        let oversized = child_block(&genesis, genesis.header.timestamp + 1, 0, &txs[..1], None);
+        assert!(rejection(&blockchain, &oversized).contains("exceeds maximum 100"));
+    }
+    
+    #[test]
+    fn test_blocks_carry_transaction_bodies() {
+        let path = std::env::temp_dir().join(format!("sebure-test-bodies-{}", rand::random::<u64>()));
+        let chain_store = Arc::new(ChainStore::new(path.to_str().unwrap(), &crate::storage::StorageConfig::default()).unwrap());
+        
+        let mut producer = Blockchain::new().unwrap();
+        let mut importer = Blockchain::new().unwrap();
+        importer.set_chain_store(chain_store.clone());
+        
+        let genesis = producer.generate_genesis_block(None, None, None).unwrap();
+        producer.initialize_with_genesis(genesis.clone()).unwrap();
+        importer.initialize_with_genesis(genesis).unwrap();
+        
+        let tx = test_transfer(1, 1000);
+        producer.add_transaction(tx.clone()).unwrap();
+        let block = producer.create_block(vec![0 as ShardId], None).unwrap();
+        assert_eq!(block.transactions.len(), 1);
+        
+        // A compact block cannot be imported by a node that lacks the transaction
+        let mut compact = block.to_compact();
+        assert_eq!(importer.missing_transactions(&compact), vec![tx.id.clone()]);
+        assert!(importer.validate_block(&compact).is_err());
+        assert!(producer.missing_transactions(&compact).is_empty());
+        producer.complete_block(&mut compact).unwrap();
+        assert!(compact.verify_bodies().is_ok());
+        
+        // The full block is self-contained
+        importer.add_block(block).unwrap();
+        assert!(importer.mempool().get_transaction(&tx.id).is_none());
+        assert_eq!(importer.get_transaction(&tx.id).unwrap().id, tx.id);
+        assert_eq!(chain_store.get_transaction(&tx.id).unwrap().id, tx.id);
+        
+        // Confirmed transactions are also found without a chain store
+        producer.add_block(compact).unwrap();
+        assert_eq!(producer.get_transaction(&tx.id).unwrap().id, tx.id);
+        
+        std::fs::remove_dir_all(path).ok();
+    }
+    
+    #[test]
+    fn test_receipts_and_logs_are_queryable() {
+        let path = std::env::temp_dir().join(format!("sebure-test-receipts-{}", rand::random::<u64>()));
+        let chain_store = Arc::new(ChainStore::new(path.to_str().unwrap(), &crate::storage::StorageConfig::default()).unwrap());
+        let state_db = Arc::new(StateDB::new("", &crate::storage::StorageConfig::default()).unwrap());
+        
+        let mut blockchain = Blockchain::new().unwrap();
+        blockchain.set_chain_store(chain_store);
+        blockchain.set_state_db(state_db.clone());
+        let genesis = blockchain.generate_genesis_block(None, None, None).unwrap();
+        blockchain.initialize_with_genesis(genesis).unwrap();
+        
+        // Two funded senders each make a transfer
+        let txs = vec![test_transfer(1, 1000), test_transfer(2, 1000)];
+        let senders: Vec<Vec<u8>> = txs.iter()
+            .map(|tx| state_transition::address_from_public_key(&tx.sender_public_key))
+            .collect();
+        for (tx, sender) in txs.iter().zip(&senders) {
+            state_db.set_account_balance(sender, 10_000).unwrap();
+            blockchain.add_transaction(tx.clone()).unwrap();
+        }
+        
+        let block = blockchain.create_block(vec![0 as ShardId], None).unwrap();
+        blockchain.add_block(block.clone()).unwrap();
+        
+        let receipt = blockchain.get_receipt(&txs[0].id).unwrap();
+        assert_eq!(receipt.status, state_transition::RECEIPT_STATUS_SUCCESS);
+        assert_eq!(receipt.logs.len(), 1);
+        assert!(blockchain.get_receipt(&[9; 32]).is_err());
+        
+        // Filter by emitting address
+        let filter = LogFilter { addresses: vec![senders[1].clone()], ..LogFilter::default() };
+        let logs = blockchain.get_logs(&filter).unwrap();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].transaction_id, txs[1].id);
+        assert_eq!(logs[0].block_height, 1);
+        assert_eq!(logs[0].block_hash, block.hash().to_vec());
+        
+        // Filter by topic
+        let filter = LogFilter {
+            topics: vec![Some(state_transition::TRANSFER_TOPIC.to_vec()), Some(senders[0].clone())],
+            ..LogFilter::default()
+        };
+        let logs = blockchain.get_logs(&filter).unwrap();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].transaction_id, txs[0].id);
+        
+        let filter = LogFilter { topics: vec![Some(state_transition::STAKE_TOPIC.to_vec())], ..LogFilter::default() };
+        assert!(blockchain.get_logs(&filter).unwrap().is_empty());
+        
+        // Filter by block range
+        let filter = LogFilter { from_height: 0, to_height: Some(0), ..LogFilter::default() };
+        assert!(blockchain.get_logs(&filter).unwrap().is_empty());
+        let filter = LogFilter { from_height: 1, to_height: Some(1), ..LogFilter::default() };
+        assert_eq!(blockchain.get_logs(&filter).unwrap().len(), 2);
+        
+        std::fs::remove_dir_all(path).ok();
+    }
+    
+    /// Build a child block, committing to its execution result if state is given
+    fn child_block(parent: &Block,
+                   timestamp: u64,
+                   validators: usize,
+                   transactions: &[Transaction],
+                   state_db: Option<&StateDB>) -> Block {
+        let mut block = Block::new(
+            parent.header.index + 1,
+            timestamp,
+            parent.hash().to_vec(),
+            vec![0 as ShardId],
+        );
+        for i in 0..validators {
+            block.add_validator(test_validator(i).public_key());
+        }
+        if !transactions.is_empty() {
+            block.shard_data.push(ShardData {
+                shard_id: 0,
+                transactions: transactions.iter().map(|tx| tx.id.clone()).collect(),
+                execution_proof: Vec::new(),
+                validator_signatures: Vec::new(),
+            });
+        }
+        block.update_merkle_roots();
+        
+        if let Some(state_db) = state_db {
+            let (result, _) = StateTransition::new(state_db)
+                .execute_block(&block, transactions)
+                .unwrap();
+            block.header.state_root = result.state_root;
+            block.set_receipts(&result.receipts).unwrap();
+        }
+        
+        for i in 0..validators {
+            block.sign_as_validator(&test_validator(i)).unwrap();
+        }
+        block
+    }
+    
+    /// Key pair of the `i`th validator signing test blocks
+    fn test_validator(i: usize) -> KeyPair {
+        KeyPair::from_seed(&[i as u8 + 1; 32]).unwrap()
+    }
+    
+    #[test]
+    fn test_side_branch_is_kept() {
+        let mut blockchain = Blockchain::new().unwrap();
+        let genesis = blockchain.generate_genesis_block(None, None, None).unwrap();
+        blockchain.initialize_with_genesis(genesis.clone()).unwrap();
+        blockchain.set_active_validators((0..2).map(|i| test_validator(i).public_key())).unwrap();
+        let t = genesis.header.timestamp;
+        
+        // The heavier block at height 1 stays the head
+        let a1 = child_block(&genesis, t + 1, 2, &[], None);
+        let b1 = child_block(&genesis, t + 2, 1, &[], None);
+        blockchain.add_block(a1.clone()).unwrap();
+        blockchain.add_block(b1.clone()).unwrap();
+        assert_eq!(blockchain.get_latest_hash(), a1.hash().to_vec());
+        
+        // Building on the side branch is accepted and makes it the best chain
+        let b2 = child_block(&b1, t + 3, 1, &[], None);
+        blockchain.add_block(b2.clone()).unwrap();
+        assert_eq!(blockchain.get_height(), 2);
+        assert_eq!(blockchain.get_latest_hash(), b2.hash().to_vec());
+        assert_eq!(blockchain.get_block_by_height(1).unwrap().hash(), b1.hash());
+        
+        // Known blocks and unknown parents are rejected
+        assert!(blockchain.add_block(b2.clone()).is_err());
+        let mut orphan = child_block(&b2, t + 4, 1, &[], None);
+        orphan.header.previous_hash = vec![9; 32];
+        assert!(blockchain.add_block(orphan).is_err());
+    }
+    
+    #[test]
+    fn test_reorg_rolls_back_state() {
+        let mut blockchain = Blockchain::new().unwrap();
+        let state_db = Arc::new(StateDB::new("", &crate::storage::StorageConfig::default()).unwrap());
+        blockchain.set_state_db(state_db.clone());
+        let reorgs = blockchain.subscribe_reorgs();
+        
+        let genesis = blockchain.generate_genesis_block(None, None, None).unwrap();
+        blockchain.initialize_with_genesis(genesis.clone()).unwrap();
+        blockchain.set_active_validators((0..2).map(|i| test_validator(i).public_key())).unwrap();
+        let t = genesis.header.timestamp;
+        
+        let sender_keypair = KeyPair::from_seed(&[1; 32]).unwrap();
+        let sender_key = sender_keypair.public_key();
+        let sender = state_transition::address_from_public_key(&sender_key);
+        state_db.set_account_balance(&sender, 10_000).unwrap();
+        
+        let recipient = vec![2; 20];
+        let mut tx = Transaction::new_transfer(sender_key, 0, recipient.clone(), 0, 1000, 200, 0);
+        tx.sign(&sender_keypair, DEFAULT_CHAIN_ID);
+        blockchain.add_transaction(tx.clone()).unwrap();
+        
+        // Both branches start from the genesis state
+        let a1 = child_block(&genesis, t + 1, 1, &[tx.clone()], Some(&state_db));
+        let b1 = child_block(&genesis, t + 2, 0, &[], Some(&state_db));
+        let b2 = child_block(&b1, t + 3, 0, &[], Some(&state_db));
+        
+        blockchain.add_block(a1.clone()).unwrap();
+        assert_eq!(state_db.get_account_balance(&recipient).unwrap(), 1000);
+        assert!(blockchain.mempool().get_transaction(&tx.id).is_none());
+        
+        // A lighter side branch does not move the head
+        blockchain.add_block(b1.clone()).unwrap();
+        assert_eq!(blockchain.get_latest_hash(), a1.hash().to_vec());
+        assert!(reorgs.try_recv().is_err());
+        
+        // A longer branch triggers a reorg
+        blockchain.add_block(b2.clone()).unwrap();
+        assert_eq!(blockchain.get_latest_hash(), b2.hash().to_vec());
+        assert_eq!(state_db.get_account_balance(&recipient).unwrap(), 0);
+        assert_eq!(state_db.get_account_balance(&sender).unwrap(), 10_000);
+        assert_eq!(state_db.get_account_nonce(&sender).unwrap(), 0);
+        
+        // The orphaned transaction is back in the mempool
+        assert!(blockchain.mempool().get_transaction(&tx.id).is_some());
+        
+        let event = reorgs.try_recv().unwrap();
+        assert_eq!(event.old_head, a1.hash().to_vec());
+        assert_eq!(event.new_head, b2.hash().to_vec());
+        assert_eq!(event.common_ancestor, genesis.hash().to_vec());
+        assert_eq!(event.reverted, vec![a1.hash().to_vec()]);
+        assert_eq!(event.applied, vec![b1.hash().to_vec(), b2.hash().to_vec()]);
+        assert_eq!(event.orphaned_transactions, vec![tx.id.clone()]);
+    }
+    
+    #[test]
+    fn test_consensus_follows_reorg() {
+        let mut blockchain = Blockchain::new().unwrap();
+        let spec = GenesisSpec {
+            chain_id: DEFAULT_CHAIN_ID.to_string(),
+            timestamp: current_time_micros() - 5_000_000,
+            shard_count: 1,
+            accounts: Vec::new(),
+            validators: vec![GenesisValidator { public_key: hex::encode(test_validator(0).public_key()), stake: 2_000, commission_rate: 0.05 }],
+            consensus: crate::consensus::ConsensusConfig::default(),
+            genesis_hash: None,
+        };
+        let validators = blockchain.initialize_with_spec(&spec).unwrap();
+        let mut consensus = DPoSConsensus::with_validators(spec.consensus_config(), validators);
+        let reorgs = blockchain.subscribe_reorgs();
+        let genesis = blockchain.get_block_by_height(0).unwrap();
+        let t = genesis.header.timestamp;
+        
+        // Consensus follows the head, which a signed block keeps at first
+        let a1 = child_block(&genesis, t + 2_000_000, 1, &[], None);
+        let b1 = child_block(&genesis, t + 2_000_001, 0, &[], None);
+        let b2 = child_block(&b1, t + 4_000_001, 0, &[], None);
+        blockchain.add_block(a1.clone()).unwrap();
+        consensus.process_block(a1).unwrap();
+        blockchain.add_block(b1.clone()).unwrap();
+        assert!(reorgs.try_recv().is_err());
+        
+        // The longer branch replaces it in consensus too, which then
+        // expects the next block on top of it
+        blockchain.add_block(b2.clone()).unwrap();
+        consensus.apply_reorg(&reorgs.try_recv().unwrap(), &blockchain).unwrap();
+        let b3 = child_block(&b2, t + 6_000_001, 0, &[], None);
+        consensus.validate_block(&b3).unwrap();
+        assert!(consensus.validate_block(&child_block(&b1, t + 6_000_001, 0, &[], None)).is_err());
+    }
+    
+    #[test]
+    fn test_failed_reorg_restores_chain() {
+        let mut blockchain = Blockchain::new().unwrap();
+        let state_db = Arc::new(StateDB::new("", &crate::storage::StorageConfig::default()).unwrap());
+        blockchain.set_state_db(state_db.clone());
+        
+        let genesis = blockchain.generate_genesis_block(None, None, None).unwrap();
+        blockchain.initialize_with_genesis(genesis.clone()).unwrap();
+        blockchain.set_active_validators((0..2).map(|i| test_validator(i).public_key())).unwrap();
+        let t = genesis.header.timestamp;
+        
+        let sender_keypair = KeyPair::from_seed(&[1; 32]).unwrap();
+        let sender_key = sender_keypair.public_key();
+        let sender = state_transition::address_from_public_key(&sender_key);
+        state_db.set_account_balance(&sender, 10_000).unwrap();
+        
+        let recipient = vec![2; 20];
+        let mut tx = Transaction::new_transfer(sender_key, 0, recipient.clone(), 0, 1000, 200, 0);
+        tx.sign(&sender_keypair, DEFAULT_CHAIN_ID);
+        blockchain.add_transaction(tx.clone()).unwrap();
+        
+        let a1 = child_block(&genesis, t + 1, 1, &[tx], Some(&state_db));
+        let b1 = child_block(&genesis, t + 2, 0, &[], Some(&state_db));
+        let mut b2 = child_block(&b1, t + 3, 0, &[], Some(&state_db));
+        b2.header.state_root = vec![9; 32];
+        
+        blockchain.add_block(a1.clone()).unwrap();
+        blockchain.add_block(b1).unwrap();
+        
+        // The heavier branch fails to execute, so the old chain stays
+        assert!(blockchain.add_block(b2.clone()).is_err());
+        assert_eq!(blockchain.get_latest_hash(), a1.hash().to_vec());
+        assert_eq!(state_db.get_account_balance(&recipient).unwrap(), 1000);
+        assert_eq!(state_db.get_account_nonce(&sender).unwrap(), 1);
+        assert!(!blockchain.has_block_with_hash(&b2.hash()));
+    }
+    
+    #[test]
+    fn test_finalized_blocks_are_not_reverted() {
+        let mut config = BlockchainConfig::default();
+        config.finality_confirmations = 1;
+        let mut blockchain = Blockchain::with_config(config).unwrap();
+        
+        let genesis = blockchain.generate_genesis_block(None, None, None).unwrap();
+        blockchain.initialize_with_genesis(genesis.clone()).unwrap();
+        let t = genesis.header.timestamp;
+        
+        let a1 = child_block(&genesis, t + 1, 1, &[], None);
+        let a2 = child_block(&a1, t + 2, 1, &[], None);
+        blockchain.add_block(a1).unwrap();
+        blockchain.add_block(a2.clone()).unwrap();
+        
+        // Height 1 is final, so a competing block there is rejected
+        let b1 = child_block(&genesis, t + 3, 5, &[], None);
+        assert!(blockchain.add_block(b1).is_err());
+        assert_eq!(blockchain.get_latest_hash(), a2.hash().to_vec());
+    }
 }
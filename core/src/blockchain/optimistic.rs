@@ -0,0 +1,369 @@
+//! # Optimistic Execution
+//!
+//! This module pre-executes pending transactions before they are placed in a
+//! block. Each speculation remembers the accounts it read and the changes it
+//! made. When a block arrives, a speculation is reused if the accounts it read
+//! still hold the same values at its position in the block; otherwise only
+//! that transaction is executed again. Speculations are charged the base fee
+//! expected for the next block, and are only reused in a block that charges
+//! the same.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use rayon::prelude::*;
+
+use crate::blockchain::{Block, Transaction, Receipt, OptimisticStatus};
+use crate::blockchain::parallel::{AccessSet, ExecutionSchedule, StateKey};
+use crate::blockchain::state_transition::{StateTransition, StateOverlay, ExecutionResult};
+use crate::blockchain::fee_market::FeeCharge;
+use crate::storage::AccountInfo;
+use crate::types::{Result, Error, ShardId};
+
+/// Result of pre-executing a transaction
+#[derive(Debug, Clone)]
+struct Speculation {
+    /// The transaction, marked with its optimistic status
+    transaction: Transaction,
+    
+    /// Account values the transaction read, keyed by address
+    reads: BTreeMap<Vec<u8>, AccountInfo>,
+    
+    /// Account values the transaction wrote, keyed by address
+    writes: BTreeMap<Vec<u8>, AccountInfo>,
+    
+    /// Receipt produced by the transaction
+    receipt: Receipt,
+    
+    /// Base fee the transaction was charged
+    base_fee: u64,
+}
+
+/// Counters describing how well speculation predicted block execution
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OptimisticStats {
+    /// Transactions pre-executed
+    pub speculated: u64,
+    
+    /// Block transactions whose speculative result was reused
+    pub hits: u64,
+    
+    /// Block transactions re-executed because their speculation conflicted
+    pub conflicts: u64,
+    
+    /// Block transactions that had not been speculated
+    pub unspeculated: u64,
+}
+
+impl OptimisticStats {
+    /// Share of speculated block transactions whose result was reused
+    pub fn hit_rate(&self) -> f64 {
+        let checked = self.hits + self.conflicts;
+        if checked == 0 {
+            0.0
+        } else {
+            self.hits as f64 / checked as f64
+        }
+    }
+}
+
+/// Pre-executes pending transactions and reuses the results in blocks
+#[derive(Default)]
+pub struct OptimisticExecutor {
+    /// Speculations from the latest run, keyed by transaction ID
+    speculations: Arc<Mutex<HashMap<Vec<u8>, Speculation>>>,
+    
+    /// Accumulated statistics
+    stats: Arc<Mutex<OptimisticStats>>,
+}
+
+impl OptimisticExecutor {
+    /// Create an executor with no speculations
+    pub fn new() -> Self {
+        OptimisticExecutor::default()
+    }
+    
+    /// Pre-execute transactions in the order they are expected to be included
+    ///
+    /// Each transaction runs against the committed state plus the changes of
+    /// the transactions before it; non-conflicting transactions run in
+    /// parallel, each charged `base_fee` of its shard. Failing transactions
+    /// are not kept. Replaces the results of the previous run and returns
+    /// the number of transactions speculated.
+    pub fn speculate<F>(&self, engine: &StateTransition, transactions: &[Arc<Transaction>], base_fee: F) -> Result<usize>
+    where
+        F: Fn(ShardId) -> u64 + Sync,
+    {
+        let transactions: Vec<Transaction> = transactions.iter().map(|tx| (**tx).clone()).collect();
+        let access_sets: Vec<AccessSet> = transactions.par_iter().map(AccessSet::of).collect();
+        let schedule = ExecutionSchedule::build(&transactions, &access_sets);
+        
+        let mut overlay = engine.overlay();
+        let mut speculations = HashMap::new();
+        for group in &schedule.groups {
+            let outcomes: Vec<Result<Option<Speculation>>> = group.par_iter()
+                .map(|&index| {
+                    let mut reads = BTreeMap::new();
+                    for address in access_sets[index].accounts() {
+                        reads.insert(address.clone(), overlay.get_account(address)?);
+                    }
+                    
+                    let base_fee = base_fee(transactions[index].sender_shard);
+                    let mut local = StateOverlay::with_accounts(&overlay, reads.clone());
+                    let receipt = match engine.execute_transaction(&mut local, &transactions[index], base_fee, None) {
+                        Ok(receipt) => receipt,
+                        Err(_) => return Ok(None),
+                    };
+                    
+                    let mut transaction = transactions[index].clone();
+                    transaction.mark_executed();
+                    Ok(Some(Speculation {
+                        transaction,
+                        reads,
+                        writes: local.into_changes(),
+                        receipt,
+                        base_fee,
+                    }))
+                })
+                .collect();
+            
+            for outcome in outcomes {
+                if let Some(speculation) = outcome? {
+                    overlay.apply_changes(speculation.writes.clone());
+                    speculations.insert(speculation.transaction.id.clone(), speculation);
+                }
+            }
+        }
+        
+        let speculated = speculations.len();
+        *self.speculations.lock().unwrap() = speculations;
+        self.stats.lock().unwrap().speculated += speculated as u64;
+        
+        Ok(speculated)
+    }
+    
+    /// Execute a block's transactions, reusing speculative results
+    ///
+    /// Transactions run in block order. A speculation is reused when it was
+    /// charged the block's base fee, every account it read holds the same
+    /// value at its position in the block and the block producer's account
+    /// is not among them; any other transaction
+    /// is executed again. The result is identical to that of
+    /// [`StateTransition::execute_block`].
+    pub fn execute_block<'a>(
+        &self,
+        engine: &StateTransition<'a>,
+        block: &Block,
+        transactions: &[Transaction],
+    ) -> Result<(ExecutionResult, StateOverlay<'a>)> {
+        StateTransition::check_block_transactions(block, transactions)?;
+        let fee_recipient = StateTransition::fee_recipient(block);
+        
+        let mut speculations = self.speculations.lock().unwrap();
+        let mut stats = OptimisticStats::default();
+        let mut overlay = engine.overlay();
+        engine.apply_settlements(&mut overlay, block)?;
+        let mut receipts = Vec::with_capacity(transactions.len());
+        let mut fees_collected = 0u64;
+        let mut fees_burned = 0u64;
+        
+        for tx in transactions {
+            let failed = |e: Error| Error::BlockValidation(format!(
+                "Transaction {} failed: {}", hex::encode(&tx.id), e
+            ));
+            let base_fee = block.base_fee(tx.sender_shard);
+            
+            // A block transaction with a known ID but a different signature
+            // is a different transaction
+            let speculation = speculations.get_mut(&tx.id)
+                .filter(|speculation| speculation.transaction.signature == tx.signature);
+            let reused = match speculation {
+                Some(speculation) => {
+                    let touches_producer = fee_recipient.as_ref().map_or(false, |recipient| {
+                        AccessSet::of(tx).touches(&StateKey::Account(recipient.clone()))
+                    });
+                    let mut unchanged = !touches_producer && speculation.base_fee == base_fee;
+                    for (address, value) in &speculation.reads {
+                        if !unchanged {
+                            break;
+                        }
+                        unchanged = overlay.get_account(address)? == *value;
+                    }
+                    
+                    if unchanged {
+                        speculation.transaction.mark_confirmed();
+                        stats.hits += 1;
+                        overlay.apply_changes(speculation.writes.clone());
+                        if let Some(ref recipient) = fee_recipient {
+                            let charge = FeeCharge::new(tx, base_fee).map_err(failed)?;
+                            overlay.credit(recipient, charge.producer_share()).map_err(failed)?;
+                        }
+                        Some(speculation.receipt.clone())
+                    } else {
+                        speculation.transaction.mark_rolled_back();
+                        stats.conflicts += 1;
+                        None
+                    }
+                },
+                None => {
+                    stats.unspeculated += 1;
+                    None
+                },
+            };
+            
+            let receipt = match reused {
+                Some(receipt) => receipt,
+                None => engine.execute_transaction(&mut overlay, tx, base_fee, fee_recipient.as_deref()).map_err(failed)?,
+            };
+            
+            let charge = FeeCharge::new(tx, base_fee).map_err(failed)?;
+            fees_collected += charge.total();
+            fees_burned += charge.burned;
+            receipts.push(receipt);
+        }
+        
+        let state_root = overlay.state_root()?;
+        
+        let mut total = self.stats.lock().unwrap();
+        total.hits += stats.hits;
+        total.conflicts += stats.conflicts;
+        total.unspeculated += stats.unspeculated;
+        
+        Ok((ExecutionResult { receipts, state_root, fees_collected, fees_burned }, overlay))
+    }
+    
+    /// Optimistic status of a transaction from the latest speculation run
+    pub fn status(&self, tx_id: &[u8]) -> Option<OptimisticStatus> {
+        self.speculations.lock().unwrap()
+            .get(tx_id)
+            .map(|speculation| speculation.transaction.optimistic_status.clone())
+    }
+    
+    /// Accumulated statistics
+    pub fn stats(&self) -> OptimisticStats {
+        *self.stats.lock().unwrap()
+    }
+    
+    /// Drop all speculations
+    pub fn clear(&self) {
+        self.speculations.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::ShardData;
+    use crate::blockchain::state_transition::address_from_public_key;
+    use crate::crypto::KeyPair;
+    use crate::storage::{StateDB, StorageConfig};
+    
+    fn transfer(sender: &KeyPair, recipient: Vec<u8>, amount: u64, nonce: u64) -> Transaction {
+        let mut tx = Transaction::new_transfer(sender.public_key(), 0, recipient, 0, amount, 10, nonce);
+        tx.id = [sender.public_key(), nonce.to_be_bytes().to_vec()].concat();
+        tx
+    }
+    
+    fn block_for(transactions: &[Transaction]) -> Block {
+        let mut block = Block::new(1, 1_000_000, vec![0; 32], vec![0]);
+        block.add_shard_data(ShardData {
+            shard_id: 0,
+            transactions: transactions.iter().map(|tx| tx.id.clone()).collect(),
+            execution_proof: Vec::new(),
+            validator_signatures: Vec::new(),
+        }).unwrap();
+        block.add_validator(vec![7; 32]);
+        block.update_merkle_roots();
+        block
+    }
+    
+    #[test]
+    fn test_only_conflicting_transactions_are_re_executed() {
+        let state_db = StateDB::new("", &StorageConfig::default()).unwrap();
+        let alice = KeyPair::from_seed(&[1; 32]).unwrap();
+        let bob = KeyPair::from_seed(&[2; 32]).unwrap();
+        let carol = KeyPair::from_seed(&[3; 32]).unwrap();
+        let dave = KeyPair::from_seed(&[4; 32]).unwrap();
+        for key in [&alice, &bob, &carol, &dave] {
+            state_db.set_account_balance(&address_from_public_key(&key.public_key()), 1000).unwrap();
+        }
+        let engine = StateTransition::new(&state_db);
+        
+        // Speculated in the order alice, bob, carol
+        let alice_tx = transfer(&alice, vec![9; 20], 100, 0);
+        let bob_tx = transfer(&bob, vec![9; 20], 200, 0);
+        let carol_tx = transfer(&carol, vec![8; 20], 300, 0);
+        let pending: Vec<Arc<Transaction>> = [&alice_tx, &bob_tx, &carol_tx].iter()
+            .map(|tx| Arc::new((*tx).clone()))
+            .collect();
+        
+        let executor = OptimisticExecutor::new();
+        assert_eq!(executor.speculate(&engine, &pending, |_| 0).unwrap(), 3);
+        assert!(matches!(executor.status(&bob_tx.id), Some(OptimisticStatus::Executed)));
+        
+        // The block orders bob before alice; both credit the same recipient,
+        // so both see a different balance than speculated
+        let unspeculated = transfer(&dave, vec![6; 20], 0, 0);
+        let txs = vec![bob_tx.clone(), alice_tx.clone(), carol_tx.clone(), unspeculated];
+        let block = block_for(&txs);
+        
+        let (optimistic, optimistic_overlay) = executor.execute_block(&engine, &block, &txs).unwrap();
+        let (serial, serial_overlay) = engine.execute_block(&block, &txs).unwrap();
+        assert_eq!(format!("{:?}", optimistic), format!("{:?}", serial));
+        assert_eq!(optimistic_overlay.changes(), serial_overlay.changes());
+        
+        assert!(matches!(executor.status(&bob_tx.id), Some(OptimisticStatus::RolledBack)));
+        assert!(matches!(executor.status(&alice_tx.id), Some(OptimisticStatus::RolledBack)));
+        assert!(matches!(executor.status(&carol_tx.id), Some(OptimisticStatus::Confirmed)));
+        
+        let stats = executor.stats();
+        assert_eq!(stats, OptimisticStats { speculated: 3, hits: 1, conflicts: 2, unspeculated: 1 });
+        assert!((stats.hit_rate() - 1.0 / 3.0).abs() < 1e-9);
+    }
+    
+    #[test]
+    fn test_failed_speculation_is_not_kept() {
+        let state_db = StateDB::new("", &StorageConfig::default()).unwrap();
+        let alice = KeyPair::from_seed(&[1; 32]).unwrap();
+        state_db.set_account_balance(&address_from_public_key(&alice.public_key()), 100).unwrap();
+        let engine = StateTransition::new(&state_db);
+        
+        let affordable = transfer(&alice, vec![9; 20], 50, 0);
+        let overdraft = transfer(&alice, vec![9; 20], 50, 1);
+        let pending = vec![Arc::new(affordable.clone()), Arc::new(overdraft.clone())];
+        
+        let executor = OptimisticExecutor::new();
+        assert_eq!(executor.speculate(&engine, &pending, |_| 0).unwrap(), 1);
+        assert!(executor.status(&overdraft.id).is_none());
+        
+        // The block fails exactly as serial execution does
+        let txs = vec![affordable, overdraft];
+        let block = block_for(&txs);
+        let optimistic = executor.execute_block(&engine, &block, &txs).err().unwrap();
+        let serial = engine.execute_block(&block, &txs).err().unwrap();
+        assert_eq!(optimistic.to_string(), serial.to_string());
+    }
+    
+    #[test]
+    fn test_speculation_at_another_base_fee_is_re_executed() {
+        let state_db = StateDB::new("", &StorageConfig::default()).unwrap();
+        let alice = KeyPair::from_seed(&[1; 32]).unwrap();
+        state_db.set_account_balance(&address_from_public_key(&alice.public_key()), 1000).unwrap();
+        let engine = StateTransition::new(&state_db);
+        
+        let tx = transfer(&alice, vec![9; 20], 100, 0);
+        let executor = OptimisticExecutor::new();
+        assert_eq!(executor.speculate(&engine, &[Arc::new(tx.clone())], |_| 4).unwrap(), 1);
+        
+        // The block charges a different base fee than speculated
+        let txs = vec![tx.clone()];
+        let mut block = block_for(&txs);
+        block.header.base_fees = vec![6];
+        
+        let (optimistic, _) = executor.execute_block(&engine, &block, &txs).unwrap();
+        let (serial, _) = engine.execute_block(&block, &txs).unwrap();
+        assert_eq!(format!("{:?}", optimistic), format!("{:?}", serial));
+        assert_eq!(optimistic.fees_burned, 3);
+        assert!(matches!(executor.status(&tx.id), Some(OptimisticStatus::RolledBack)));
+    }
+}
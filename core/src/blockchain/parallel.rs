@@ -0,0 +1,298 @@
+//! # Parallel Execution Scheduling
+//!
+//! This module groups the transactions of a block for parallel execution.
+//! Each transaction's read and write sets are derived from the accounts and
+//! contract storage it touches. Conflicting transactions keep their block
+//! order, while the others are placed in groups that can run concurrently.
+
+use std::collections::{BTreeSet, HashMap};
+
+use crate::blockchain::Transaction;
+use crate::blockchain::state_transition::{address_from_public_key, escrow_address, stake_address};
+use crate::types::TransactionType;
+
+/// A piece of state that executing a transaction can read or write
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum StateKey {
+    /// Balance and nonce of an account
+    Account(Vec<u8>),
+    
+    /// Storage of a contract
+    ///
+    /// Calls do not name the storage keys they use, so a call claims all of
+    /// its contract's storage.
+    ContractStorage(Vec<u8>),
+}
+
+/// State read and written by a transaction
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccessSet {
+    /// Keys the transaction reads
+    pub reads: BTreeSet<StateKey>,
+    
+    /// Keys the transaction writes
+    pub writes: BTreeSet<StateKey>,
+}
+
+impl AccessSet {
+    /// Derive the access set of a transaction
+    ///
+    /// The block producer's fee credit is left out; it is applied after all
+    /// transactions have run.
+    pub fn of(tx: &Transaction) -> Self {
+        let sender = address_from_public_key(&tx.sender_public_key);
+        let mut access = AccessSet::default();
+        
+        // Fee, nonce and any value moved out of the sender's account
+        access.modify(StateKey::Account(sender.clone()));
+        
+        match tx.transaction_type {
+            TransactionType::Transfer if tx.is_cross_shard() => {
+                access.modify(StateKey::Account(escrow_address(&tx.id)));
+            },
+            TransactionType::Transfer => {
+                access.modify(StateKey::Account(tx.recipient_address.clone()));
+            },
+            TransactionType::ContractDeploy | TransactionType::ContractCall => {
+                access.modify(StateKey::Account(tx.recipient_address.clone()));
+                access.modify(StateKey::ContractStorage(tx.recipient_address.clone()));
+            },
+            TransactionType::Stake
+            | TransactionType::ValidatorRegister
+            | TransactionType::Unstake
+            | TransactionType::ValidatorUnregister => {
+                access.modify(StateKey::Account(stake_address(&sender)));
+            },
+            TransactionType::Batch => {
+                // A malformed batch fails on its own sender's account
+                for operation in tx.batch_operations().unwrap_or_default() {
+                    let key = match operation.transaction_type {
+                        TransactionType::Stake => stake_address(&sender),
+                        _ => operation.recipient_address,
+                    };
+                    access.modify(StateKey::Account(key));
+                }
+            },
+            TransactionType::System => {},
+        }
+        
+        access
+    }
+    
+    /// Record a key that is read and then written
+    fn modify(&mut self, key: StateKey) {
+        self.reads.insert(key.clone());
+        self.writes.insert(key);
+    }
+    
+    /// Check if the transaction reads or writes a key
+    pub fn touches(&self, key: &StateKey) -> bool {
+        self.reads.contains(key) || self.writes.contains(key)
+    }
+    
+    /// Account addresses the transaction reads
+    pub fn accounts(&self) -> impl Iterator<Item = &Vec<u8>> {
+        self.reads.iter().filter_map(|key| match key {
+            StateKey::Account(address) => Some(address),
+            StateKey::ContractStorage(_) => None,
+        })
+    }
+    
+    /// Check if running two transactions in a different order could change
+    /// the result of either
+    pub fn conflicts_with(&self, other: &AccessSet) -> bool {
+        !self.writes.is_disjoint(&other.writes)
+            || !self.writes.is_disjoint(&other.reads)
+            || !other.writes.is_disjoint(&self.reads)
+    }
+}
+
+/// Ordering constraints between the transactions of a block
+///
+/// Each transaction has an edge to the earlier transactions it must follow.
+/// Edges implied by others are omitted: a transaction writing a key follows
+/// the key's last writer and the readers since, and a transaction reading it
+/// follows the last writer.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConflictGraph {
+    /// Indices of the earlier transactions each transaction must follow
+    pub edges: Vec<Vec<usize>>,
+}
+
+impl ConflictGraph {
+    /// Build the conflict graph of a block's transactions
+    ///
+    /// Transactions that are not parallelizable follow every earlier
+    /// transaction and precede every later one.
+    pub fn build(transactions: &[Transaction], access_sets: &[AccessSet]) -> Self {
+        let mut edges: Vec<Vec<usize>> = Vec::with_capacity(transactions.len());
+        let mut last_writer: HashMap<&StateKey, usize> = HashMap::new();
+        let mut readers: HashMap<&StateKey, Vec<usize>> = HashMap::new();
+        let mut barrier: Option<usize> = None;
+        
+        for (index, (tx, access)) in transactions.iter().zip(access_sets).enumerate() {
+            let mut follows = BTreeSet::new();
+            
+            if !tx.can_execute_in_parallel() {
+                follows.extend(barrier.unwrap_or(0)..index);
+                barrier = Some(index);
+            } else if let Some(barrier) = barrier {
+                follows.insert(barrier);
+            }
+            
+            for key in &access.reads {
+                if let Some(&writer) = last_writer.get(key) {
+                    follows.insert(writer);
+                }
+            }
+            for key in &access.writes {
+                if let Some(&writer) = last_writer.get(key) {
+                    follows.insert(writer);
+                }
+                if let Some(key_readers) = readers.get(key) {
+                    follows.extend(key_readers.iter().copied());
+                }
+            }
+            
+            // Update the key history once the edges are known, so that a
+            // transaction never follows itself
+            for key in &access.reads {
+                readers.entry(key).or_insert_with(Vec::new).push(index);
+            }
+            for key in &access.writes {
+                last_writer.insert(key, index);
+                readers.insert(key, Vec::new());
+            }
+            
+            edges.push(follows.into_iter().collect());
+        }
+        
+        ConflictGraph { edges }
+    }
+}
+
+/// Transactions of a block grouped for parallel execution
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExecutionSchedule {
+    /// Groups in execution order, each holding the indices of transactions
+    /// that do not conflict with one another, in block order
+    pub groups: Vec<Vec<usize>>,
+}
+
+impl ExecutionSchedule {
+    /// Schedule a block's transactions
+    ///
+    /// Each transaction goes in the group after the latest one holding a
+    /// transaction it must follow.
+    pub fn build(transactions: &[Transaction], access_sets: &[AccessSet]) -> Self {
+        let graph = ConflictGraph::build(transactions, access_sets);
+        
+        let mut levels: Vec<usize> = Vec::with_capacity(graph.edges.len());
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        for (index, follows) in graph.edges.iter().enumerate() {
+            let level = follows.iter().map(|&earlier| levels[earlier] + 1).max().unwrap_or(0);
+            if level == groups.len() {
+                groups.push(Vec::new());
+            }
+            groups[level].push(index);
+            levels.push(level);
+        }
+        
+        ExecutionSchedule { groups }
+    }
+    
+    /// Record each transaction's group in its parallel markers
+    ///
+    /// `transactions` must be the transactions the schedule was built from.
+    pub fn assign_groups(&self, transactions: &mut [Transaction]) {
+        for (group_id, group) in self.groups.iter().enumerate() {
+            for &index in group {
+                transactions[index].set_parallel_group(group_id as u64, 0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KeyPair;
+    
+    fn transfer(sender: &KeyPair, recipient: Vec<u8>, nonce: u64) -> Transaction {
+        Transaction::new_transfer(sender.public_key(), 0, recipient, 0, 100, 10, nonce)
+    }
+    
+    fn schedule(transactions: &[Transaction]) -> Vec<Vec<usize>> {
+        let access_sets: Vec<AccessSet> = transactions.iter().map(AccessSet::of).collect();
+        ExecutionSchedule::build(transactions, &access_sets).groups
+    }
+    
+    #[test]
+    fn test_access_sets() {
+        let sender = KeyPair::from_seed(&[1; 32]).unwrap();
+        let address = address_from_public_key(&sender.public_key());
+        
+        let tx = transfer(&sender, vec![2; 20], 0);
+        let access = AccessSet::of(&tx);
+        assert!(access.touches(&StateKey::Account(address.clone())));
+        assert!(access.touches(&StateKey::Account(vec![2; 20])));
+        
+        let mut stake = transfer(&sender, Vec::new(), 1);
+        stake.transaction_type = TransactionType::Stake;
+        assert!(AccessSet::of(&stake).touches(&StateKey::Account(stake_address(&address))));
+        
+        let mut call = transfer(&sender, vec![3; 20], 2);
+        call.transaction_type = TransactionType::ContractCall;
+        assert!(AccessSet::of(&call).touches(&StateKey::ContractStorage(vec![3; 20])));
+        
+        // Sharing the sender is a conflict, distinct accounts are not
+        assert!(access.conflicts_with(&AccessSet::of(&stake)));
+        let other = KeyPair::from_seed(&[2; 32]).unwrap();
+        assert!(!access.conflicts_with(&AccessSet::of(&transfer(&other, vec![4; 20], 0))));
+    }
+    
+    #[test]
+    fn test_schedule_groups_independent_transactions() {
+        let senders: Vec<KeyPair> = (1..=3).map(|seed| KeyPair::from_seed(&[seed; 32]).unwrap()).collect();
+        
+        // Distinct senders paying distinct recipients all run together
+        let independent: Vec<Transaction> = senders.iter().enumerate()
+            .map(|(i, sender)| transfer(sender, vec![10 + i as u8; 20], 0))
+            .collect();
+        assert_eq!(schedule(&independent), vec![vec![0, 1, 2]]);
+        
+        // A shared recipient or sender orders the transactions
+        let txs = vec![
+            transfer(&senders[0], vec![9; 20], 0),
+            transfer(&senders[1], vec![9; 20], 0),
+            transfer(&senders[2], vec![8; 20], 0),
+            transfer(&senders[0], vec![7; 20], 1),
+        ];
+        assert_eq!(schedule(&txs), vec![vec![0, 2], vec![1, 3]]);
+        
+        // The recipient of one transfer sending in another
+        let recipient = address_from_public_key(&senders[1].public_key());
+        let txs = vec![
+            transfer(&senders[0], recipient, 0),
+            transfer(&senders[1], vec![9; 20], 0),
+        ];
+        assert_eq!(schedule(&txs), vec![vec![0], vec![1]]);
+    }
+    
+    #[test]
+    fn test_non_parallelizable_transactions_are_barriers() {
+        let senders: Vec<KeyPair> = (1..=4).map(|seed| KeyPair::from_seed(&[seed; 32]).unwrap()).collect();
+        let mut txs: Vec<Transaction> = senders.iter().enumerate()
+            .map(|(i, sender)| transfer(sender, vec![10 + i as u8; 20], 0))
+            .collect();
+        txs[2].parallel_markers.parallelizable = false;
+        
+        assert_eq!(schedule(&txs), vec![vec![0, 1], vec![2], vec![3]]);
+        
+        let access_sets: Vec<AccessSet> = txs.iter().map(AccessSet::of).collect();
+        let schedule = ExecutionSchedule::build(&txs, &access_sets);
+        schedule.assign_groups(&mut txs);
+        assert_eq!(txs[1].parallel_markers.group_id, Some(0));
+        assert_eq!(txs[3].parallel_markers.group_id, Some(2));
+    }
+}
@@ -0,0 +1,330 @@
+//! # Adaptive Block Parameters
+//!
+//! This module adjusts the block size limit and the block interval to the
+//! load the chain observes. At the start of each epoch, the previous
+//! epoch's blocks are measured for how full they were and how many slots
+//! passed without a block, and each parameter is moved by one step within
+//! the bounds set by governance. The result is committed in the header of
+//! the epoch's first block, so every node follows the same parameters.
+//!
+//! Only integer arithmetic is used, so that every node derives the same
+//! parameters from the same blocks.
+
+use serde::{Serialize, Deserialize};
+
+use crate::blockchain::Block;
+use crate::types::{Result, BlockHeight, Timestamp};
+
+/// Parts per thousand that a full block or an all-missed epoch counts as
+const PERMILLE: u64 = 1000;
+
+/// Block size limit and block interval in effect for an epoch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockParameters {
+    /// Maximum block size in bytes
+    pub max_block_size: u64,
+    
+    /// Time between blocks in milliseconds
+    pub block_interval_ms: u64,
+}
+
+impl BlockParameters {
+    /// Encode the parameters as committed to by the header hash
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(16);
+        data.extend_from_slice(&self.max_block_size.to_be_bytes());
+        data.extend_from_slice(&self.block_interval_ms.to_be_bytes());
+        data
+    }
+}
+
+/// Bounds and thresholds of the block parameter controller, set by
+/// governance
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdaptiveConfig {
+    /// Blocks per epoch; the parameters only change at the first block of
+    /// an epoch, and never if this is 0
+    pub epoch_length: u64,
+    
+    /// Lowest block size limit in bytes
+    pub min_block_size: u64,
+    
+    /// Highest block size limit in bytes
+    pub max_block_size: u64,
+    
+    /// Shortest block interval in milliseconds
+    pub min_block_interval_ms: u64,
+    
+    /// Longest block interval in milliseconds
+    pub max_block_interval_ms: u64,
+    
+    /// Average block fullness, in thousandths, above which the size limit
+    /// is raised
+    pub grow_fullness: u64,
+    
+    /// Average block fullness, in thousandths, below which the size limit
+    /// is lowered
+    pub shrink_fullness: u64,
+    
+    /// Share of missed slots, in thousandths, above which the interval is
+    /// lengthened
+    pub slow_missed_slots: u64,
+    
+    /// Share of missed slots, in thousandths, up to which the interval may
+    /// be shortened while blocks are full
+    pub fast_missed_slots: u64,
+    
+    /// Each step changes a parameter by its value divided by this
+    pub step_divisor: u64,
+}
+
+impl Default for AdaptiveConfig {
+    fn default() -> Self {
+        AdaptiveConfig {
+            epoch_length: 100,
+            min_block_size: 256 * 1024, // 256 KB
+            max_block_size: 8 * 1024 * 1024, // 8 MB
+            min_block_interval_ms: 1000,
+            max_block_interval_ms: 10_000,
+            grow_fullness: 800,
+            shrink_fullness: 250,
+            slow_missed_slots: 100,
+            fast_missed_slots: 10,
+            step_divisor: 8,
+        }
+    }
+}
+
+impl AdaptiveConfig {
+    /// Height of the first block of the epoch containing `height`
+    pub fn epoch_start(&self, height: BlockHeight) -> BlockHeight {
+        if self.epoch_length == 0 {
+            0
+        } else {
+            height - height % self.epoch_length
+        }
+    }
+    
+    /// Check if the block at `height` must commit to new parameters
+    ///
+    /// The genesis block uses the configured parameters instead.
+    pub fn is_epoch_start(&self, height: BlockHeight) -> bool {
+        height > 0 && self.epoch_start(height) == height
+    }
+    
+    /// Keep parameters within the governance bounds
+    pub fn clamp(&self, parameters: BlockParameters) -> BlockParameters {
+        BlockParameters {
+            max_block_size: parameters.max_block_size
+                .clamp(self.min_block_size, self.max_block_size.max(self.min_block_size)),
+            block_interval_ms: parameters.block_interval_ms
+                .clamp(self.min_block_interval_ms, self.max_block_interval_ms.max(self.min_block_interval_ms)),
+        }
+    }
+    
+    /// Parameters for the next epoch, given those of the last one and the
+    /// load it saw
+    ///
+    /// Missed slots mean producers cannot keep up, so the interval is
+    /// lengthened and blocks are not grown. Otherwise full blocks raise the
+    /// size limit, and shorten the interval if hardly any slot was missed.
+    /// Mostly empty blocks lower the size limit.
+    pub fn next_parameters(&self, current: &BlockParameters, load: &EpochLoad) -> BlockParameters {
+        let step = |value: u64| (value / self.step_divisor.max(1)).max(1);
+        let fullness = load.average_fullness();
+        let missed = load.missed_share();
+        let mut next = *current;
+        
+        if fullness < self.shrink_fullness {
+            next.max_block_size = next.max_block_size.saturating_sub(step(current.max_block_size));
+        } else if fullness > self.grow_fullness && missed <= self.slow_missed_slots {
+            next.max_block_size = next.max_block_size.saturating_add(step(current.max_block_size));
+        }
+        
+        if missed > self.slow_missed_slots {
+            next.block_interval_ms = next.block_interval_ms.saturating_add(step(current.block_interval_ms));
+        } else if missed <= self.fast_missed_slots && fullness > self.grow_fullness {
+            next.block_interval_ms = next.block_interval_ms.saturating_sub(step(current.block_interval_ms));
+        }
+        
+        self.clamp(next)
+    }
+}
+
+/// Load observed over the blocks of an epoch
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EpochLoad {
+    /// Number of blocks measured
+    pub blocks: u64,
+    
+    /// Sum of the blocks' fullness, in thousandths
+    pub fullness: u64,
+    
+    /// Slots between consecutive blocks that passed without a block
+    pub missed_slots: u64,
+}
+
+impl EpochLoad {
+    /// Measure consecutive blocks, oldest first, produced under `parameters`
+    ///
+    /// Blocks must carry their transaction bodies. The genesis block, and
+    /// the time between it and the first block, are not counted.
+    pub fn measure(blocks: &[Block], parameters: &BlockParameters) -> Result<Self> {
+        let mut load = EpochLoad::default();
+        let mut previous = None;
+        for block in blocks {
+            load.add(block, previous, parameters)?;
+            previous = Some(block.header.timestamp).filter(|_| block.header.index > 0);
+        }
+        Ok(load)
+    }
+    
+    /// Count a block produced under `parameters`, and the slots missed
+    /// since the counted block before it in the epoch, produced at
+    /// `previous`
+    pub fn add(&mut self, block: &Block, previous: Option<Timestamp>, parameters: &BlockParameters) -> Result<()> {
+        if block.header.index == 0 {
+            return Ok(());
+        }
+        let size = block.encoded_size()? as u64;
+        self.blocks += 1;
+        self.fullness += (size * PERMILLE / parameters.max_block_size.max(1)).min(PERMILLE);
+        
+        if let Some(previous) = previous {
+            let interval_micros = parameters.block_interval_ms.max(1) * 1000;
+            let gap = block.header.timestamp.saturating_sub(previous);
+            self.missed_slots += (gap / interval_micros).saturating_sub(1);
+        }
+        Ok(())
+    }
+    
+    /// Average block fullness in thousandths
+    pub fn average_fullness(&self) -> u64 {
+        if self.blocks == 0 {
+            0
+        } else {
+            self.fullness / self.blocks
+        }
+    }
+    
+    /// Share of the epoch's slots that were missed, in thousandths
+    pub fn missed_share(&self) -> u64 {
+        let slots = self.blocks + self.missed_slots;
+        if slots == 0 {
+            0
+        } else {
+            self.missed_slots * PERMILLE / slots
+        }
+    }
+}
+
+/// Block parameters a block was produced under and the load of its epoch
+/// up to and including it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EpochProgress {
+    /// Parameters in effect for the block
+    pub parameters: BlockParameters,
+    
+    /// Load of the epoch so far
+    pub load: EpochLoad,
+    
+    /// Timestamp of the block, if it counts towards the load
+    pub timestamp: Option<Timestamp>,
+}
+
+impl EpochProgress {
+    /// Progress of an epoch after `blocks`, its blocks so far oldest first,
+    /// produced under `parameters`
+    pub fn measure(blocks: &[Block], parameters: BlockParameters) -> Result<Self> {
+        Ok(EpochProgress {
+            parameters,
+            load: EpochLoad::measure(blocks, &parameters)?,
+            timestamp: blocks.last()
+                .filter(|block| block.header.index > 0)
+                .map(|block| block.header.timestamp),
+        })
+    }
+    
+    /// Progress after `block`, the next block of the same epoch
+    pub fn extend(&self, block: &Block) -> Result<Self> {
+        let mut load = self.load;
+        load.add(block, self.timestamp, &self.parameters)?;
+        Ok(EpochProgress {
+            parameters: self.parameters,
+            load,
+            timestamp: Some(block.header.timestamp).filter(|_| block.header.index > 0),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn parameters(max_block_size: u64, block_interval_ms: u64) -> BlockParameters {
+        BlockParameters { max_block_size, block_interval_ms }
+    }
+    
+    #[test]
+    fn test_epoch_starts() {
+        let config = AdaptiveConfig { epoch_length: 10, ..AdaptiveConfig::default() };
+        assert!(!config.is_epoch_start(0));
+        assert!(!config.is_epoch_start(9));
+        assert!(config.is_epoch_start(10));
+        assert_eq!(config.epoch_start(19), 10);
+        
+        let disabled = AdaptiveConfig { epoch_length: 0, ..AdaptiveConfig::default() };
+        assert!(!disabled.is_epoch_start(100));
+        assert_eq!(disabled.epoch_start(100), 0);
+    }
+    
+    #[test]
+    fn test_load_is_measured_from_blocks() {
+        let blocks: Vec<Block> = [(0, 0), (1, 5_000_000), (2, 7_000_000), (3, 15_000_000)].iter()
+            .map(|&(height, timestamp)| Block::new(height, timestamp, vec![0; 32], vec![0]))
+            .collect();
+        let size = blocks[1].encoded_size().unwrap() as u64;
+        
+        let load = EpochLoad::measure(&blocks, &parameters(size * 2, 2000)).unwrap();
+        assert_eq!(load.blocks, 3);
+        assert_eq!(load.average_fullness(), 500);
+        
+        // Three slots were skipped between the last two blocks
+        assert_eq!(load.missed_slots, 3);
+        assert_eq!(load.missed_share(), 500);
+        
+        // Extending the progress block by block measures the same load
+        let mut progress = EpochProgress::measure(&blocks[..1], parameters(size * 2, 2000)).unwrap();
+        for block in &blocks[1..] {
+            progress = progress.extend(block).unwrap();
+        }
+        assert_eq!(progress.load, load);
+    }
+    
+    #[test]
+    fn test_controller_follows_load() {
+        let config = AdaptiveConfig {
+            min_block_size: 1000,
+            max_block_size: 10_000,
+            min_block_interval_ms: 1000,
+            max_block_interval_ms: 4000,
+            ..AdaptiveConfig::default()
+        };
+        let current = parameters(8000, 2000);
+        let load = |fullness: u64, missed_slots: u64| EpochLoad { blocks: 10, fullness: fullness * 10, missed_slots };
+        
+        // Full blocks on time grow the blocks and speed the chain up
+        assert_eq!(config.next_parameters(&current, &load(900, 0)), parameters(9000, 1750));
+        
+        // Missed slots slow the chain down without growing blocks
+        assert_eq!(config.next_parameters(&current, &load(900, 5)), parameters(8000, 2250));
+        
+        // Empty blocks shrink, moderate load changes nothing
+        assert_eq!(config.next_parameters(&current, &load(100, 0)), parameters(7000, 2000));
+        assert_eq!(config.next_parameters(&current, &load(500, 0)), current);
+        
+        // The governance bounds always hold
+        assert_eq!(config.next_parameters(&parameters(10_000, 4000), &load(900, 20)), parameters(10_000, 4000));
+        assert_eq!(config.next_parameters(&parameters(1000, 1000), &load(0, 0)), parameters(1000, 1000));
+    }
+}
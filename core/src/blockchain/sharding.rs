@@ -1,227 +1,1791 @@
-use std::collections::{HashMap, VecDeque};
-use crate::types::{ShardId, ValidatorId, TransactionRef};
-use crate::crypto::{Bytes32, Signature};
-use crate::network::{Message, NetworkHandle};
+//! # Sharding
+//!
+//! This module tracks the shards of the network and moves value between
+//! them. A transfer to an account on another shard runs in two phases: the
+//! source shard moves the amount from the sender into an escrow account and
+//! its block emits a [`CrossShardReceipt`]. A later block on the destination
+//! shard proves the receipt against the source block's receipt root and
+//! credits the recipient from escrow. If no destination block settles the
+//! transfer before it times out, a block on the source shard refunds the
+//! sender instead, so escrowed value is always released exactly once.
+//!
+//! Accounts are mapped to shards by a [`ShardLayout`], which splits the
+//! space of account keys into one contiguous range per shard. At each epoch
+//! boundary, shards whose load crossed the [`ReshardingConfig`] thresholds
+//! are split or merged with a neighbour, and the state roots of the shards
+//! whose accounts moved are recomputed in the state database.
+//!
+//! A shard can also read accounts held by another shard: it sends a state
+//! request for a block height and accepts the response only if every
+//! account is on the responding shard and is proven against the state root
+//! of its own canonical header at that height. An account missing from the
+//! state is proven absent by the accounts on either side of its address.
+
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
 use serde::{Serialize, Deserialize};
-use std::sync::Arc;
-use tokio::sync::Mutex;
 
+use crate::blockchain::{Block, BlockHeader, CrossShardReceipt, Receipt, Transaction, EMPTY_ROOT};
+use crate::blockchain::state_transition::{account_leaf, address_from_public_key, compute_state_root, CROSS_SHARD_TOPIC};
+use crate::crypto::hash::{self, Hash, MerkleTree};
+use crate::storage::{AccountInfo, StateDB};
+use crate::types::{Result, Error, BlockHeight, ShardId, TransactionType};
+
+/// Number of blocks after its source block within which a cross-shard
+/// transfer can be credited, after which it is refunded
+pub const DEFAULT_CROSS_SHARD_TIMEOUT: BlockHeight = 100;
+
+/// Maximum number of recent cross-shard transaction IDs kept per shard
+const RECENT_CROSS_SHARD_LIMIT: usize = 100;
+
+/// Number of past epoch boundaries that can be reverted
+const EPOCH_CHECKPOINT_LIMIT: usize = 8;
+
+/// Maximum number of accounts in a state request
+pub const STATE_REQUEST_LIMIT: usize = 256;
+
+/// A shard of the network
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Shard {
+    /// Shard identifier
     pub id: ShardId,
-    pub validator_pool: Vec<ValidatorId>,
-    pub state_root: Bytes32,
-    pub last_block_height: u64,
+    
+    /// Validators assigned to the shard
+    pub validator_pool: Vec<Vec<u8>>,
+    
+    /// State root of the shard
+    pub state_root: Vec<u8>,
+    
+    /// Height of the last block that included the shard
+    pub last_block_height: BlockHeight,
+    
+    /// Number of transactions the shard has processed
     pub transaction_count: u64,
+    
+    /// Number of active accounts in the shard
     pub active_accounts: u32,
-    pub recent_cross_shard_transactions: Vec<TransactionRef>,
+    
+    /// Recent cross-shard transactions sent from or to the shard
+    pub recent_cross_shard_transactions: Vec<Vec<u8>>,
+    
+    /// Shards that frequently interact with this one
     pub neighbor_shards: Vec<ShardId>,
+    
+    /// Resource utilization between 0 and 1
     pub resource_utilization: f32,
+    
+    /// How accounts are partitioned into the shard
     pub partition_criteria: PartitioningRule,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl Shard {
+    /// Create an empty shard
+    pub fn new(id: ShardId) -> Self {
+        Shard {
+            id,
+            validator_pool: Vec::new(),
+            state_root: Vec::new(),
+            last_block_height: 0,
+            transaction_count: 0,
+            active_accounts: 0,
+            recent_cross_shard_transactions: Vec::new(),
+            neighbor_shards: Vec::new(),
+            resource_utilization: 0.0,
+            partition_criteria: PartitioningRule::AccountBased,
+        }
+    }
+}
+
+/// Rule deciding how a shard's accounts are divided when it is split
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PartitioningRule {
+    /// Split at the middle of the shard's key range
     AccountBased,
+    
+    /// Kept whole, since its load comes from a kind of transaction rather
+    /// than from a range of accounts
     TransactionTypeBased,
+    
+    /// Split so that both halves hold the same number of accounts
     ResourceBased,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum CrossShardMessage {
-    TransactionRequest(TransactionRef),
-    StateRequest(ShardId, Bytes32), // (requesting shard, state root)
-    StateResponse(ShardId, Bytes32, Vec<u8>), // (responding shard, state root, state data)
-    ValidationRequest(ShardId, Bytes32), // (requesting shard, block hash)
-    ValidationResponse(ShardId, Bytes32, Signature), // (responding shard, block hash, signature)
+/// Position of an account in the keyspace that shards partition
+///
+/// The first eight bytes of the hashed address, so that accounts spread
+/// evenly over the keyspace whatever their addresses look like.
+pub fn account_key(address: &[u8]) -> u64 {
+    let digest = hash::sha256(address);
+    let mut key = [0u8; 8];
+    key.copy_from_slice(&digest[..8]);
+    u64::from_be_bytes(key)
 }
 
-pub struct ShardManager {
-    shards: HashMap<ShardId, Shard>,
-    shard_assignment: HashMap<Bytes32, ShardId>, // Maps accounts to shards
-    message_queue: Arc<Mutex<VecDeque<(ShardId, CrossShardMessage)>>,
-    network: Arc<NetworkHandle>,
+/// Assignment of the account keyspace to shards
+///
+/// Each shard owns the keys from its range start up to the next range's
+/// start; the first range starts at 0 and the last runs to `u64::MAX`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShardLayout {
+    /// Epoch from which the layout applies
+    pub epoch: u64,
+    
+    /// Start key and shard of each range, in key order
+    pub ranges: Vec<(u64, ShardId)>,
+    
+    /// Identifier given to the next shard created by a split; retired
+    /// identifiers are never reused
+    pub next_shard_id: ShardId,
 }
 
-impl ShardManager {
-    pub fn new(network: Arc<NetworkHandle>) -> Self {
-        Self {
-            shards: HashMap::new(),
-            shard_assignment: HashMap::new(),
-            message_queue: Arc::new(Mutex::new(VecDeque::new())),
-            network,
+impl ShardLayout {
+    /// Divide the keyspace evenly between the given shards, in order
+    pub fn new(shard_ids: &[ShardId]) -> Self {
+        let shard_ids: &[ShardId] = if shard_ids.is_empty() { &[0] } else { shard_ids };
+        let count = shard_ids.len() as u128;
+        let ranges = shard_ids.iter().enumerate()
+            .map(|(i, &shard_id)| ((((i as u128) << 64) / count) as u64, shard_id))
+            .collect();
+        
+        ShardLayout {
+            epoch: 0,
+            ranges,
+            next_shard_id: shard_ids.iter().max().map_or(0, |&max| max.saturating_add(1)),
         }
     }
-
-    pub async fn send_cross_shard_message(&self, target_shard: ShardId, message: CrossShardMessage) {
-        let msg = Message::CrossShard(message.clone());
-        self.network.send_to_shard(target_shard, msg).await;
-        
-        // Also store locally for processing
-        let mut queue = self.message_queue.lock().await;
-        queue.push_back((target_shard, message));
+    
+    /// Divide the keyspace evenly between shards `0..count`
+    pub fn uniform(count: u16) -> Self {
+        Self::new(&(0..count.max(1)).collect::<Vec<ShardId>>())
     }
-
-    pub async fn process_messages(&self) {
-        let mut queue = self.message_queue.lock().await;
-        while let Some((shard_id, message)) = queue.pop_front() {
-            match message {
-                CrossShardMessage::TransactionRequest(tx) => {
-                    self.process_cross_shard_transaction(tx);
-                }
-                CrossShardMessage::StateRequest(requesting_shard, state_root) => {
-                    if let Some(shard) = self.shards.get(&shard_id) {
-                        // TODO: Send state response
+    
+    /// Shard owning an account key
+    pub fn shard_for_key(&self, key: u64) -> ShardId {
+        let index = self.ranges.partition_point(|&(start, _)| start <= key);
+        self.ranges[index.saturating_sub(1)].1
+    }
+    
+    /// Shard owning an account
+    pub fn shard_for_address(&self, address: &[u8]) -> ShardId {
+        self.shard_for_key(account_key(address))
+    }
+    
+    /// Shards in the layout, in key order
+    pub fn shard_ids(&self) -> Vec<ShardId> {
+        self.ranges.iter().map(|&(_, shard_id)| shard_id).collect()
+    }
+    
+    /// Check if a shard is in the layout
+    pub fn contains(&self, shard_id: ShardId) -> bool {
+        self.ranges.iter().any(|&(_, id)| id == shard_id)
+    }
+    
+    /// First and last key owned by a shard
+    pub fn range(&self, shard_id: ShardId) -> Option<(u64, u64)> {
+        let index = self.ranges.iter().position(|&(_, id)| id == shard_id)?;
+        let end = self.ranges.get(index + 1).map_or(u64::MAX, |&(next, _)| next - 1);
+        Some((self.ranges[index].0, end))
+    }
+    
+    /// Check that a transaction names the shards its accounts are on
+    ///
+    /// The sender must be on `sender_shard`, and the recipient of a
+    /// transfer, or of each transfer in a batch, on its recipient shard.
+    pub fn check_transaction(&self, tx: &Transaction) -> Result<()> {
+        let misplaced = |account: &str, address: &[u8], shard_id: ShardId| Error::TransactionValidation(format!(
+            "Transaction {} puts {} {} on shard {}, but it is on shard {}",
+            hex::encode(&tx.id), account, hex::encode(address), shard_id, self.shard_for_address(address)
+        ));
+        
+        let sender = address_from_public_key(&tx.sender_public_key);
+        if self.shard_for_address(&sender) != tx.sender_shard {
+            return Err(misplaced("sender", &sender, tx.sender_shard));
+        }
+        
+        match tx.transaction_type {
+            TransactionType::Transfer if self.shard_for_address(&tx.recipient_address) != tx.recipient_shard => {
+                Err(misplaced("recipient", &tx.recipient_address, tx.recipient_shard))
+            },
+            TransactionType::Batch => {
+                for operation in tx.batch_operations()? {
+                    if operation.transaction_type == TransactionType::Transfer
+                        && self.shard_for_address(&operation.recipient_address) != operation.recipient_shard {
+                        return Err(misplaced("recipient", &operation.recipient_address, operation.recipient_shard));
                     }
                 }
-                CrossShardMessage::StateResponse(responding_shard, state_root, state_data) => {
-                    // TODO: Handle state response
+                Ok(())
+            },
+            _ => Ok(()),
+        }
+    }
+    
+    /// Encode the layout as committed to by the header hash
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(14 + self.ranges.len() * 10);
+        data.extend_from_slice(&self.epoch.to_be_bytes());
+        data.extend_from_slice(&self.next_shard_id.to_be_bytes());
+        data.extend_from_slice(&(self.ranges.len() as u32).to_be_bytes());
+        for &(start, shard_id) in &self.ranges {
+            data.extend_from_slice(&start.to_be_bytes());
+            data.extend_from_slice(&shard_id.to_be_bytes());
+        }
+        data
+    }
+    
+    /// Apply a split or merge to the layout
+    pub fn apply(&mut self, action: &ReshardAction) -> Result<()> {
+        match *action {
+            ReshardAction::Split { shard, new_shard, at } => {
+                let (start, end) = self.range(shard)
+                    .ok_or_else(|| Error::State(format!("Cannot split unknown shard {}", shard)))?;
+                if at <= start || at > end {
+                    return Err(Error::State(format!("Cannot split shard {} at key {}", shard, at)));
                 }
-                CrossShardMessage::ValidationRequest(requesting_shard, block_hash) => {
-                    // TODO: Handle validation request
+                if new_shard < self.next_shard_id {
+                    return Err(Error::State(format!("Shard identifier {} was already used", new_shard)));
                 }
-                CrossShardMessage::ValidationResponse(responding_shard, block_hash, signature) => {
-                    // TODO: Handle validation response
+                
+                let index = self.ranges.partition_point(|&(range_start, _)| range_start <= start);
+                self.ranges.insert(index, (at, new_shard));
+                self.next_shard_id = new_shard.saturating_add(1);
+            },
+            ReshardAction::Merge { shard, absorbed } => {
+                let index = self.ranges.iter().position(|&(_, id)| id == shard)
+                    .ok_or_else(|| Error::State(format!("Cannot merge unknown shard {}", shard)))?;
+                if self.ranges.get(index + 1).map(|&(_, id)| id) != Some(absorbed) {
+                    return Err(Error::State(format!("Shard {} does not follow shard {}", absorbed, shard)));
                 }
-            }
+                
+                self.ranges.remove(index + 1);
+            },
         }
+        
+        Ok(())
     }
+}
 
-    pub fn assign_account(&mut self, account: Bytes32, shard_id: ShardId) {
-        self.shard_assignment.insert(account, shard_id);
+impl Default for ShardLayout {
+    fn default() -> Self {
+        Self::uniform(1)
     }
+}
 
-    pub fn get_shard_for_account(&self, account: &Bytes32) -> Option<ShardId> {
-        self.shard_assignment.get(account).copied()
-    }
+/// Change to the shard layout made at an epoch boundary
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReshardAction {
+    /// The keys of `shard` from `at` onwards move to the new shard
+    Split {
+        /// Shard being split
+        shard: ShardId,
+        
+        /// Shard created for the upper part of the range
+        new_shard: ShardId,
+        
+        /// First key of the new shard
+        at: u64,
+    },
+    
+    /// `absorbed`, the shard after `shard` in key order, joins it
+    Merge {
+        /// Shard that takes over the range
+        shard: ShardId,
+        
+        /// Shard that is retired
+        absorbed: ShardId,
+    },
+}
 
-    pub fn add_shard(&mut self, shard: Shard) {
-        self.shards.insert(shard.id, shard);
+/// Thresholds that trigger splitting and merging shards
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReshardingConfig {
+    /// Blocks per epoch; shards are resharded after the last block of each
+    pub epoch_length: BlockHeight,
+    
+    /// Transactions a shard can carry per block, against which its
+    /// resource utilization is measured
+    pub shard_capacity: u64,
+    
+    /// Utilization at or above which a shard is split
+    pub split_utilization: f32,
+    
+    /// Transactions in an epoch at or above which a shard is split
+    pub split_transactions: u64,
+    
+    /// Utilization at or below which a shard may be merged
+    pub merge_utilization: f32,
+    
+    /// Transactions in an epoch at or below which a shard may be merged
+    pub merge_transactions: u64,
+    
+    /// Fewest shards to keep
+    pub min_shards: usize,
+    
+    /// Most shards to create
+    pub max_shards: usize,
+}
+
+impl Default for ReshardingConfig {
+    fn default() -> Self {
+        ReshardingConfig {
+            epoch_length: 100,
+            shard_capacity: 1000,
+            split_utilization: 0.8,
+            split_transactions: 80_000,
+            merge_utilization: 0.2,
+            merge_transactions: 20_000,
+            min_shards: 1,
+            max_shards: 64,
+        }
     }
+}
 
-    pub fn get_shard(&self, shard_id: ShardId) -> Option<&Shard> {
-        self.shards.get(&shard_id)
+impl ReshardingConfig {
+    /// Check if `height` is the last block of an epoch
+    pub fn is_epoch_boundary(&self, height: BlockHeight) -> bool {
+        height > 0 && self.epoch_length > 0 && height % self.epoch_length == 0
     }
+    
+    /// Height of the first block of the epoch containing `height`
+    ///
+    /// The first epoch starts at block 1 and runs under the genesis layout.
+    pub fn epoch_start(&self, height: BlockHeight) -> BlockHeight {
+        if height == 0 || self.epoch_length == 0 {
+            height.min(1)
+        } else {
+            height - (height - 1) % self.epoch_length
+        }
+    }
+    
+    /// Check if the block at `height` must commit to the shard layout
+    /// resulting from the previous epoch boundary
+    pub fn is_epoch_start(&self, height: BlockHeight) -> bool {
+        height > 1 && self.epoch_start(height) == height
+    }
+}
+
+/// Load of a shard during the current epoch
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct EpochActivity {
+    /// Blocks that included the shard
+    blocks: u64,
+    
+    /// Transactions the shard carried
+    transactions: u64,
+}
+
+/// State needed to revert the epoch boundary at `height`
+#[derive(Debug, Clone)]
+struct EpochCheckpoint {
+    /// Height of the last block of the epoch
+    height: BlockHeight,
+    
+    /// Layout before the boundary
+    layout: ShardLayout,
+    
+    /// Shards before the boundary
+    shards: HashMap<ShardId, Shard>,
+    
+    /// Activity of the epoch that ended
+    activity: BTreeMap<ShardId, EpochActivity>,
+    
+    /// Recorded state roots the boundary replaced
+    roots: Vec<(ShardId, Option<Vec<u8>>)>,
+}
+
+/// Value moved by a cross-shard transfer, carried in its receipt
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CrossShardTransfer {
+    /// Address of the sender, refunded on timeout
+    pub sender: Vec<u8>,
+    
+    /// Address of the recipient
+    pub recipient: Vec<u8>,
+    
+    /// Shard the transfer was sent from
+    pub source_shard: ShardId,
+    
+    /// Shard the recipient is on
+    pub destination_shard: ShardId,
+    
+    /// Amount held in escrow
+    pub amount: u64,
+}
 
-    pub fn process_cross_shard_transaction(&mut self, tx: TransactionRef) {
-        // TODO: Implement cross-shard transaction processing
+impl CrossShardTransfer {
+    /// Encode the transfer for a log or receipt
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        bincode::serialize(self).map_err(|e| Error::Serialization(e.to_string()))
     }
+    
+    /// Decode a transfer from a log or receipt
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        bincode::deserialize(data).map_err(|e| Error::Serialization(e.to_string()))
+    }
+}
 
-    pub async fn synchronize_shards(&mut self) {
-        // Synchronize state with neighbor shards
-        for shard in self.shards.values() {
-            for neighbor in &shard.neighbor_shards {
-                if let Some(neighbor_shard) = self.shards.get(neighbor) {
-                    // Request state from neighbor
-                    let message = CrossShardMessage::StateRequest(
-                        shard.id,
-                        neighbor_shard.state_root
-                    );
-                    self.send_cross_shard_message(*neighbor, message).await;
-                }
-            }
-        }
+/// Cross-shard receipt for a transaction, if its execution sent value to
+/// another shard
+pub fn outbound_receipt(receipt: &Receipt) -> Result<Option<CrossShardReceipt>> {
+    let log = match receipt.logs.iter().find(|log| log.topics.first().map(Vec::as_slice) == Some(CROSS_SHARD_TOPIC)) {
+        Some(log) => log,
+        None => return Ok(None),
+    };
+    
+    let transfer = CrossShardTransfer::decode(&log.data)?;
+    Ok(Some(CrossShardReceipt {
+        transaction_id: receipt.transaction_id.clone(),
+        source_shard: transfer.source_shard,
+        destination_shard: transfer.destination_shard,
+        status: true,
+        data: log.data.clone(),
+    }))
+}
 
-        // Share block headers with neighbors
-        for shard in self.shards.values() {
-            if let Some(latest_block) = self.get_latest_block(shard.id) {
-                for neighbor in &shard.neighbor_shards {
-                    let message = CrossShardMessage::ValidationRequest(
-                        shard.id,
-                        latest_block.hash()
-                    );
-                    self.send_cross_shard_message(*neighbor, message).await;
-                }
-            }
+/// Cross-shard receipts a block emits, derived from the execution receipts
+/// of its transactions in block order
+pub fn outbound_receipts(receipts: &[Receipt]) -> Result<Vec<CrossShardReceipt>> {
+    let mut outbound = Vec::new();
+    for receipt in receipts {
+        if let Some(cross_shard) = outbound_receipt(receipt)? {
+            outbound.push(cross_shard);
         }
     }
+    Ok(outbound)
+}
 
-    fn get_latest_block(&self, shard_id: ShardId) -> Option<BlockHeader> {
-        // TODO: Implement block header retrieval
-        None
+/// Proof that a block emitted a cross-shard receipt
+///
+/// The execution receipt of the transfer is proven against the block's
+/// receipt root, and the cross-shard receipt must be the one it implies.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrossShardProof {
+    /// Receipt being proven
+    pub receipt: CrossShardReceipt,
+    
+    /// Height of the block that emitted the receipt
+    pub source_height: BlockHeight,
+    
+    /// Hash of the block that emitted the receipt
+    pub source_hash: Vec<u8>,
+    
+    /// Execution receipt of the transfer
+    pub execution_receipt: Receipt,
+    
+    /// Position of the execution receipt in the block
+    pub index: usize,
+    
+    /// Merkle path from the execution receipt to the receipt root
+    pub siblings: Vec<Hash>,
+}
+
+impl CrossShardProof {
+    /// Build the proof for a transfer emitted by `block`, whose execution
+    /// produced `receipts`
+    pub fn build(block: &Block, receipts: &[Receipt], transaction_id: &[u8]) -> Result<Self> {
+        let index = receipts.iter().position(|receipt| receipt.transaction_id == transaction_id)
+            .ok_or_else(|| Error::State(format!(
+                "Block {} has no receipt for {}", block.header.index, hex::encode(transaction_id)
+            )))?;
+        let receipt = outbound_receipt(&receipts[index])?
+            .ok_or_else(|| Error::State(format!(
+                "Transaction {} is not a cross-shard transfer", hex::encode(transaction_id)
+            )))?;
+        
+        Ok(CrossShardProof {
+            receipt,
+            source_height: block.header.index,
+            source_hash: block.hash().to_vec(),
+            execution_receipt: receipts[index].clone(),
+            index,
+            siblings: Block::receipt_proof(receipts, index)?,
+        })
+    }
+    
+    /// Check the proof against the header of its source block and return
+    /// the proven transfer
+    pub fn verify(&self, header: &BlockHeader) -> Result<CrossShardTransfer> {
+        let invalid = |reason: &str| Error::BlockValidation(format!(
+            "Invalid proof for cross-shard transfer {}: {}", hex::encode(&self.receipt.transaction_id), reason
+        ));
+        
+        if header.index != self.source_height || header.hash().to_vec() != self.source_hash {
+            return Err(invalid("source block does not match"));
+        }
+        
+        let root: Hash = header.receipt_root.as_slice().try_into()
+            .map_err(|_| invalid("malformed receipt root"))?;
+        let leaf = Block::receipt_leaf(&self.execution_receipt)?;
+        if !MerkleTree::verify_proof(&root, &leaf, &self.siblings, self.index) {
+            return Err(invalid("receipt is not in the source block"));
+        }
+        
+        if outbound_receipt(&self.execution_receipt)?.as_ref() != Some(&self.receipt) {
+            return Err(invalid("receipt does not match the executed transfer"));
+        }
+        
+        CrossShardTransfer::decode(&self.receipt.data)
     }
+    
+    /// Last height at which the transfer can be credited
+    pub fn expires_at(&self, timeout: BlockHeight) -> BlockHeight {
+        self.source_height.saturating_add(timeout)
+    }
+}
+
+/// Settlement of a cross-shard transfer in a block
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrossShardSettlement {
+    /// Proof of the receipt being settled
+    pub proof: CrossShardProof,
+    
+    /// Whether the sender is refunded rather than the recipient credited
+    pub refund: bool,
+}
 
-    async fn handle_state_response(&mut self, shard_id: ShardId, state_root: Bytes32, state_data: Vec<u8>) {
-        // Verify state root matches
-        if self.verify_state_root(shard_id, &state_root) {
-            // Apply state changes
-            self.apply_state_update(shard_id, state_data).await;
+impl CrossShardSettlement {
+    /// ID of the transaction that sent the transfer
+    pub fn transaction_id(&self) -> &Vec<u8> {
+        &self.proof.receipt.transaction_id
+    }
+    
+    /// Shard whose block must settle the transfer
+    pub fn settling_shard(&self) -> ShardId {
+        if self.refund {
+            self.proof.receipt.source_shard
+        } else {
+            self.proof.receipt.destination_shard
         }
     }
+}
+
+/// Proof of an account's state against the state root of a block
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateProof {
+    /// Address of the account
+    pub address: Vec<u8>,
+    
+    /// Balance of the account
+    pub balance: u64,
+    
+    /// Nonce of the account
+    pub nonce: u64,
+    
+    /// Position of the account in the state tree
+    pub index: usize,
+    
+    /// Merkle path from the account to the state root
+    pub siblings: Vec<Hash>,
+}
 
-    fn verify_state_root(&self, shard_id: ShardId, state_root: &Bytes32) -> bool {
-        // TODO: Implement state root verification
+impl StateProof {
+    /// Check the proof against a state root and return the proven account
+    ///
+    /// The state tree does not commit to whether an account is a contract,
+    /// so the returned account is never marked as one.
+    pub fn verify(&self, state_root: &[u8]) -> Result<AccountInfo> {
+        let invalid = |reason: &str| Error::Validation(format!(
+            "Invalid state proof for account {}: {}", hex::encode(&self.address), reason
+        ));
+        
+        let root: Hash = state_root.try_into()
+            .map_err(|_| invalid("malformed state root"))?;
+        let account = AccountInfo {
+            balance: self.balance,
+            nonce: self.nonce,
+            is_contract: false,
+        };
+        if self.index.checked_shr(self.siblings.len() as u32).map_or(false, |rest| rest > 0) {
+            return Err(invalid("index is outside the state tree"));
+        }
+        if !MerkleTree::verify_proof(&root, &account_leaf(&self.address, &account), &self.siblings, self.index) {
+            return Err(invalid("account is not in the state tree"));
+        }
+        
+        Ok(account)
+    }
+    
+    /// Check that no account follows this one in the state tree
+    ///
+    /// The tree pads its leaves with copies of the last one, so every
+    /// sibling to the right of the last account covers only copies of it.
+    fn is_last(&self) -> bool {
+        let mut padding = account_leaf(&self.address, &AccountInfo {
+            balance: self.balance,
+            nonce: self.nonce,
+            is_contract: false,
+        });
+        let mut index = self.index;
+        for sibling in &self.siblings {
+            if index % 2 == 0 && *sibling != padding {
+                return false;
+            }
+            padding = hash::sha256(&[padding, padding].concat());
+            index /= 2;
+        }
         true
     }
+}
 
-    async fn apply_state_update(&mut self, shard_id: ShardId, state_data: Vec<u8>) {
-        // TODO: Implement state application
-    }
+/// Proof of whether an account is in the state tree of a block
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AccountProof {
+    /// The account is in the state tree
+    Present(StateProof),
+    
+    /// The account is not in the state tree, shown by the accounts on
+    /// either side of its address being adjacent in the tree
+    Absent {
+        /// Address of the account
+        address: Vec<u8>,
+        
+        /// Last account before the address, if any
+        before: Option<StateProof>,
+        
+        /// First account after the address, if any
+        after: Option<StateProof>,
+    },
+}
 
-    pub async fn verify_shard_state(&self, shard_id: ShardId) -> bool {
-        // Verify local shard state
-        if let Some(shard) = self.shards.get(&shard_id) {
-            // Verify Merkle root consistency
-            if !self.verify_merkle_root(shard_id, &shard.state_root) {
-                return false;
+impl AccountProof {
+    /// Address of the proven account
+    pub fn address(&self) -> &[u8] {
+        match self {
+            AccountProof::Present(proof) => &proof.address,
+            AccountProof::Absent { address, .. } => address,
+        }
+    }
+    
+    /// Check the proof against a state root and return the proven account,
+    /// which is empty if it is absent
+    pub fn verify(&self, state_root: &[u8]) -> Result<AccountInfo> {
+        let (address, before, after) = match self {
+            AccountProof::Present(proof) => return proof.verify(state_root),
+            AccountProof::Absent { address, before, after } => (address, before, after),
+        };
+        let invalid = |reason: &str| Error::Validation(format!(
+            "Invalid absence proof for account {}: {}", hex::encode(address), reason
+        ));
+        
+        if let Some(before) = before {
+            before.verify(state_root)?;
+            if before.address >= *address {
+                return Err(invalid("preceding account does not precede it"));
             }
+        }
+        if let Some(after) = after {
+            after.verify(state_root)?;
+            if after.address <= *address {
+                return Err(invalid("following account does not follow it"));
+            }
+        }
+        let adjacent = match (before, after) {
+            (Some(before), Some(after)) => before.index.checked_add(1) == Some(after.index),
+            (None, Some(after)) => after.index == 0,
+            (Some(before), None) => before.is_last(),
+            (None, None) => state_root == &EMPTY_ROOT[..],
+        };
+        if !adjacent {
+            return Err(invalid("neighbouring accounts are not adjacent in the state tree"));
+        }
+        
+        Ok(AccountInfo {
+            balance: 0,
+            nonce: 0,
+            is_contract: false,
+        })
+    }
+}
 
-            // Verify cross-shard consistency with neighbors
-            for neighbor in &shard.neighbor_shards {
-                if let Some(neighbor_shard) = self.shards.get(neighbor) {
-                    // Verify shared accounts
-                    if !self.verify_shared_accounts(shard_id, *neighbor) {
-                        return false;
-                    }
+/// State tree of a block, kept to answer state requests without reading
+/// the whole state again
+pub struct StateTree {
+    /// Accounts in the tree, sorted by address
+    accounts: Vec<(Vec<u8>, AccountInfo)>,
+    
+    /// Merkle tree over the accounts
+    tree: MerkleTree,
+}
 
-                    // Verify cross-shard transactions
-                    if !self.verify_cross_shard_transactions(shard_id, *neighbor) {
-                        return false;
+impl StateTree {
+    /// Build the state tree over the whole state, sorted by address
+    ///
+    /// Accounts without balance or nonce are not in the state tree and are
+    /// left out.
+    pub fn new(accounts: Vec<(Vec<u8>, AccountInfo)>) -> Self {
+        let accounts: Vec<(Vec<u8>, AccountInfo)> = accounts.into_iter()
+            .filter(|(_, account)| account.balance > 0 || account.nonce > 0)
+            .collect();
+        let leaves: Vec<Hash> = accounts.iter()
+            .map(|(address, account)| account_leaf(address, account))
+            .collect();
+        StateTree {
+            tree: MerkleTree::new(&leaves),
+            accounts,
+        }
+    }
+    
+    /// Root of the state tree
+    pub fn root(&self) -> Vec<u8> {
+        self.tree.root().unwrap_or(EMPTY_ROOT).to_vec()
+    }
+    
+    /// Prove each account at `addresses` present or absent in the state
+    /// committed to by `header`
+    pub fn prove(&self, header: &BlockHeader, addresses: &[Vec<u8>]) -> Result<Vec<AccountProof>> {
+        if self.root() != header.state_root {
+            return Err(Error::State(format!(
+                "State does not match the state root of block {}", header.index
+            )));
+        }
+        
+        Ok(addresses.iter()
+            .map(|address| match self.accounts.binary_search_by(|(candidate, _)| candidate.cmp(address)) {
+                Ok(index) => AccountProof::Present(self.proof(index)),
+                Err(index) => AccountProof::Absent {
+                    address: address.clone(),
+                    before: index.checked_sub(1).map(|before| self.proof(before)),
+                    after: (index < self.accounts.len()).then(|| self.proof(index)),
+                },
+            })
+            .collect())
+    }
+    
+    /// Proof of the account at `index`
+    fn proof(&self, index: usize) -> StateProof {
+        let (address, account) = &self.accounts[index];
+        StateProof {
+            address: address.clone(),
+            balance: account.balance,
+            nonce: account.nonce,
+            index,
+            siblings: self.tree.generate_proof(index),
+        }
+    }
+}
+
+/// Messages exchanged between shards
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum CrossShardMessage {
+    /// Request for the proof of a cross-shard transfer
+    TransactionRequest(Vec<u8>),
+    
+    /// Proof of a receipt emitted for the receiving shard
+    ReceiptProof(CrossShardProof),
+    
+    /// Request for the state of accounts as of a block height
+    StateRequest(BlockHeight, Vec<Vec<u8>>),
+    
+    /// Accounts proven present or absent against the state root of the
+    /// block at a height
+    StateResponse(BlockHeight, Vec<AccountProof>),
+}
+
+/// Tracks shards, their layout and the cross-shard transfers awaiting
+/// settlement
+pub struct ShardManager {
+    /// Known shards
+    shards: HashMap<ShardId, Shard>,
+    
+    /// Current assignment of accounts to shards
+    layout: ShardLayout,
+    
+    /// Thresholds for splitting and merging shards
+    resharding: ReshardingConfig,
+    
+    /// Load of each shard in the layout during the current epoch
+    activity: BTreeMap<ShardId, EpochActivity>,
+    
+    /// Recent epoch boundaries, oldest first
+    checkpoints: VecDeque<EpochCheckpoint>,
+    
+    /// Canonical headers that incoming proofs are checked against
+    headers: BTreeMap<BlockHeight, BlockHeader>,
+    
+    /// Proofs of unsettled transfers by transaction ID
+    pending: BTreeMap<Vec<u8>, CrossShardProof>,
+    
+    /// State tree of the latest canonical block and its height, built on
+    /// the first state request for it
+    state_tree: Option<(BlockHeight, StateTree)>,
+    
+    /// Accounts of other shards proven against canonical headers, by height
+    remote_state: BTreeMap<BlockHeight, BTreeMap<Vec<u8>, AccountInfo>>,
+    
+    /// Messages waiting to be sent, with their target shard
+    outbox: VecDeque<(ShardId, CrossShardMessage)>,
+    
+    /// Blocks within which a transfer must be credited
+    timeout: BlockHeight,
+}
+
+impl ShardManager {
+    /// Create a shard manager with the default cross-shard timeout
+    pub fn new() -> Self {
+        Self::with_timeout(DEFAULT_CROSS_SHARD_TIMEOUT)
+    }
+    
+    /// Create a shard manager with the given cross-shard timeout
+    ///
+    /// It starts with a single shard and the default resharding thresholds.
+    pub fn with_timeout(timeout: BlockHeight) -> Self {
+        let layout = ShardLayout::default();
+        Self {
+            shards: layout.shard_ids().into_iter().map(|id| (id, Shard::new(id))).collect(),
+            layout,
+            resharding: ReshardingConfig::default(),
+            activity: BTreeMap::new(),
+            checkpoints: VecDeque::new(),
+            headers: BTreeMap::new(),
+            pending: BTreeMap::new(),
+            state_tree: None,
+            remote_state: BTreeMap::new(),
+            outbox: VecDeque::new(),
+            timeout,
+        }
+    }
+    
+    /// Blocks within which a transfer must be credited
+    pub fn timeout(&self) -> BlockHeight {
+        self.timeout
+    }
+    
+    /// Set the thresholds for splitting and merging shards
+    pub fn set_resharding_config(&mut self, config: ReshardingConfig) {
+        self.resharding = config;
+    }
+    
+    /// Current assignment of accounts to shards
+    pub fn layout(&self) -> &ShardLayout {
+        &self.layout
+    }
+    
+    /// Replace the shard layout, as at genesis
+    ///
+    /// Shards outside the layout are dropped, the epoch's activity is reset
+    /// and, given the state, the state root of every shard is recorded.
+    pub fn set_layout(&mut self, layout: ShardLayout, state_db: Option<&StateDB>) -> Result<()> {
+        self.checkpoints.clear();
+        self.restore_epoch(layout, &[], state_db)
+    }
+    
+    /// Resume the epoch running under `layout` after `blocks`, its blocks
+    /// so far oldest first, as after a restart
+    ///
+    /// Shards outside the layout are dropped, the epoch's activity is
+    /// counted again and, given the state, the state root of every shard is
+    /// recorded. If the last block ends the epoch, the shards are resharded
+    /// as they were then.
+    pub fn restore_epoch(&mut self, layout: ShardLayout, blocks: &[Block], state_db: Option<&StateDB>) -> Result<()> {
+        if let Some(state_db) = state_db {
+            for &shard_id in self.shards.keys().filter(|id| !layout.contains(**id)) {
+                state_db.set_shard_state_root(shard_id, None)?;
+            }
+        }
+        self.shards.retain(|id, _| layout.contains(*id));
+        for id in layout.shard_ids() {
+            self.shards.entry(id).or_insert_with(|| Shard::new(id));
+        }
+        self.layout = layout;
+        self.activity.clear();
+        for block in blocks {
+            self.count_activity(block);
+        }
+        self.update_utilization();
+        
+        if let Some(state_db) = state_db {
+            self.record_shard_roots(state_db, &self.layout.shard_ids())?;
+        }
+        if let Some(last) = blocks.last().filter(|block| self.is_epoch_boundary(block.header.index)) {
+            self.end_epoch(last.header.index, state_db)?;
+        }
+        Ok(())
+    }
+    
+    /// Get the shard an account belongs to
+    pub fn get_shard_for_account(&self, account: &[u8]) -> ShardId {
+        self.layout.shard_for_address(account)
+    }
+    
+    /// Add a shard, or replace the one with the same ID
+    ///
+    /// Only shards in the layout are assigned accounts.
+    pub fn add_shard(&mut self, shard: Shard) {
+        self.shards.insert(shard.id, shard);
+    }
+    
+    /// Get a shard by ID
+    pub fn get_shard(&self, shard_id: ShardId) -> Option<&Shard> {
+        self.shards.get(&shard_id)
+    }
+    
+    /// Record a block added to the canonical chain
+    ///
+    /// The transfers it emitted become pending and their proofs are queued
+    /// for the destination shards; the transfers it settled are done.
+    /// `receipts` are the execution receipts of the block.
+    pub fn record_block(&mut self, block: &Block, receipts: &[Receipt]) -> Result<()> {
+        let proofs = block.cross_shard_receipts.iter()
+            .map(|receipt| CrossShardProof::build(block, receipts, &receipt.transaction_id))
+            .collect::<Result<Vec<_>>>()?;
+        
+        let height = block.header.index;
+        self.headers.insert(height, block.header.clone());
+        
+        for settlement in &block.cross_shard_settlements {
+            self.pending.remove(settlement.transaction_id());
+        }
+        
+        for (receipt, proof) in block.cross_shard_receipts.iter().zip(proofs) {
+            self.outbox.push_back((receipt.destination_shard, CrossShardMessage::ReceiptProof(proof.clone())));
+            self.pending.insert(receipt.transaction_id.clone(), proof);
+            
+            for shard_id in [receipt.source_shard, receipt.destination_shard] {
+                if let Some(shard) = self.shards.get_mut(&shard_id) {
+                    shard.recent_cross_shard_transactions.push(receipt.transaction_id.clone());
+                    let excess = shard.recent_cross_shard_transactions.len().saturating_sub(RECENT_CROSS_SHARD_LIMIT);
+                    shard.recent_cross_shard_transactions.drain(..excess);
+                }
+            }
+        }
+        
+        for shard_data in &block.shard_data {
+            if let Some(shard) = self.shards.get_mut(&shard_data.shard_id) {
+                shard.transaction_count += shard_data.transactions.len() as u64;
+                shard.last_block_height = height;
+            }
+        }
+        self.count_activity(block);
+        self.update_utilization();
+        
+        // Keep the headers that pending or recent transfers may be proven against
+        let oldest_pending = self.pending.values().map(|proof| proof.source_height).min().unwrap_or(height);
+        let keep_from = height.saturating_sub(self.timeout).min(oldest_pending);
+        self.headers = self.headers.split_off(&keep_from);
+        self.remote_state = self.remote_state.split_off(&keep_from);
+        
+        Ok(())
+    }
+    
+    /// Rebuild the transfers awaiting settlement from the canonical chain,
+    /// oldest block first, as after a restart
+    ///
+    /// `receipts` loads the execution receipts of a block that sent
+    /// transfers. The proofs of the transfers still pending are queued for
+    /// their destination shards again.
+    pub fn restore_pending<F>(&mut self, blocks: &[Block], mut receipts: F) -> Result<()>
+    where
+        F: FnMut(&Block) -> Result<Vec<Receipt>>,
+    {
+        self.pending.clear();
+        for block in blocks {
+            for settlement in &block.cross_shard_settlements {
+                self.pending.remove(settlement.transaction_id());
+            }
+            if block.cross_shard_receipts.is_empty() {
+                continue;
+            }
+            
+            let executed = receipts(block)?;
+            for receipt in &block.cross_shard_receipts {
+                let proof = CrossShardProof::build(block, &executed, &receipt.transaction_id)?;
+                self.pending.insert(receipt.transaction_id.clone(), proof);
+            }
+        }
+        
+        // Keep the headers that pending or recent transfers may be proven against
+        let height = blocks.last().map_or(0, |block| block.header.index);
+        let oldest_pending = self.pending.values().map(|proof| proof.source_height).min().unwrap_or(height);
+        let keep_from = height.saturating_sub(self.timeout).min(oldest_pending);
+        for block in blocks.iter().filter(|block| block.header.index >= keep_from) {
+            self.headers.insert(block.header.index, block.header.clone());
+        }
+        
+        for proof in self.pending.values() {
+            self.outbox.push_back((proof.receipt.destination_shard, CrossShardMessage::ReceiptProof(proof.clone())));
+        }
+        Ok(())
+    }
+    
+    /// Undo `record_block` and any epoch boundary for a block removed from
+    /// the canonical chain
+    ///
+    /// Blocks must be reverted newest first. An epoch boundary older than
+    /// the kept checkpoints is rebuilt from the chain instead: `epoch` loads
+    /// the layout of the epoch the block ends and the epoch's earlier
+    /// blocks, oldest first.
+    pub fn revert_block<F>(&mut self, block: &Block, state_db: Option<&StateDB>, epoch: F) -> Result<()>
+    where
+        F: FnOnce() -> Result<(ShardLayout, Vec<Block>)>,
+    {
+        if self.headers.get(&block.header.index).map_or(false, |header| header.hash() == block.hash()) {
+            self.headers.remove(&block.header.index);
+            self.remote_state.remove(&block.header.index);
+        }
+        
+        let checkpoint = self.checkpoints.back().map_or(false, |checkpoint| checkpoint.height == block.header.index);
+        if checkpoint {
+            if let Some(checkpoint) = self.checkpoints.pop_back() {
+                self.layout = checkpoint.layout;
+                self.shards = checkpoint.shards;
+                self.activity = checkpoint.activity;
+                if let Some(state_db) = state_db {
+                    for (shard_id, root) in &checkpoint.roots {
+                        state_db.set_shard_state_root(*shard_id, root.as_deref())?;
                     }
                 }
             }
-
-            // Verify validator signatures
-            if !self.verify_validator_signatures(shard_id) {
-                return false;
+        }
+        
+        if !checkpoint && self.is_epoch_boundary(block.header.index) {
+            // The epoch's load is counted again without the block
+            let (layout, blocks) = epoch()?;
+            self.restore_epoch(layout, &blocks, state_db)?;
+        } else {
+            for &shard_id in &block.header.shard_identifiers {
+                if let Some(activity) = self.activity.get_mut(&shard_id) {
+                    activity.blocks = activity.blocks.saturating_sub(1);
+                }
+            }
+            for shard_data in &block.shard_data {
+                if let Some(activity) = self.activity.get_mut(&shard_data.shard_id) {
+                    activity.transactions = activity.transactions.saturating_sub(shard_data.transactions.len() as u64);
+                }
             }
+            self.update_utilization();
         }
-        true
+        
+        for receipt in &block.cross_shard_receipts {
+            self.pending.remove(&receipt.transaction_id);
+        }
+        
+        for settlement in &block.cross_shard_settlements {
+            self.pending.insert(settlement.transaction_id().clone(), settlement.proof.clone());
+        }
+        
+        Ok(())
     }
-
-    fn verify_merkle_root(&self, shard_id: ShardId, state_root: &Bytes32) -> bool {
-        // TODO: Implement Merkle root verification
-        true
+    
+    /// Check if `height` is the last block of an epoch
+    pub fn is_epoch_boundary(&self, height: BlockHeight) -> bool {
+        self.resharding.is_epoch_boundary(height)
     }
-
-    fn verify_shared_accounts(&self, shard_id: ShardId, neighbor_shard_id: ShardId) -> bool {
-        // TODO: Implement shared account verification
-        true
+    
+    /// Split and merge shards after the block at `height`, the last of an
+    /// epoch, and start a new epoch
+    ///
+    /// Given the state, the state roots of the shards whose accounts moved
+    /// are recomputed, and resource-based splits balance the accounts. The
+    /// accounts are only read if a shard is resource-based or the layout
+    /// changes.
+    pub fn end_epoch(&mut self, height: BlockHeight, state_db: Option<&StateDB>) -> Result<Vec<ReshardAction>> {
+        let resource_based = self.shards.values()
+            .any(|shard| shard.partition_criteria == PartitioningRule::ResourceBased);
+        let mut accounts = match state_db {
+            Some(state_db) if resource_based => Some(state_db.get_all_accounts()?),
+            _ => None,
+        };
+        let mut keys: Vec<u64> = accounts.iter().flatten().map(|(address, _)| account_key(address)).collect();
+        keys.sort_unstable();
+        keys.dedup();
+        
+        let actions = self.plan_resharding(&keys);
+        let mut layout = self.layout.clone();
+        for action in &actions {
+            layout.apply(action)?;
+        }
+        layout.epoch += 1;
+        
+        let mut affected: Vec<ShardId> = Vec::new();
+        for action in &actions {
+            match *action {
+                ReshardAction::Split { shard, new_shard, .. } => affected.extend([shard, new_shard]),
+                ReshardAction::Merge { shard, absorbed } => affected.extend([shard, absorbed]),
+            }
+        }
+        let roots = match state_db {
+            Some(state_db) => affected.iter()
+                .map(|&shard_id| Ok((shard_id, state_db.get_shard_state_root(shard_id)?)))
+                .collect::<Result<Vec<_>>>()?,
+            None => Vec::new(),
+        };
+        
+        self.checkpoints.push_back(EpochCheckpoint {
+            height,
+            layout: self.layout.clone(),
+            shards: self.shards.clone(),
+            activity: std::mem::take(&mut self.activity),
+            roots,
+        });
+        if self.checkpoints.len() > EPOCH_CHECKPOINT_LIMIT {
+            self.checkpoints.pop_front();
+        }
+        
+        for action in &actions {
+            match *action {
+                ReshardAction::Split { shard, new_shard, .. } => {
+                    let mut created = Shard::new(new_shard);
+                    if let Some(parent) = self.shards.get(&shard) {
+                        created.partition_criteria = parent.partition_criteria.clone();
+                        created.validator_pool = parent.validator_pool.clone();
+                    }
+                    self.shards.insert(new_shard, created);
+                    log::info!("Splitting shard {} into shard {} at epoch {}", shard, new_shard, layout.epoch);
+                },
+                ReshardAction::Merge { shard, absorbed } => {
+                    self.shards.remove(&absorbed);
+                    log::info!("Merging shard {} into shard {} at epoch {}", absorbed, shard, layout.epoch);
+                },
+            }
+        }
+        self.layout = layout;
+        self.update_utilization();
+        
+        if let Some(state_db) = state_db.filter(|_| !affected.is_empty()) {
+            let retired: Vec<ShardId> = affected.iter().copied().filter(|id| !self.layout.contains(*id)).collect();
+            for shard_id in retired {
+                state_db.set_shard_state_root(shard_id, None)?;
+            }
+            let accounts = match accounts.take() {
+                Some(accounts) => accounts,
+                None => state_db.get_all_accounts()?,
+            };
+            let live: Vec<ShardId> = affected.into_iter().filter(|id| self.layout.contains(*id)).collect();
+            self.record_shard_roots_from(&accounts, state_db, &live)?;
+        }
+        
+        Ok(actions)
     }
-
-    fn verify_cross_shard_transactions(&self, shard_id: ShardId, neighbor_shard_id: ShardId) -> bool {
-        // TODO: Implement cross-shard transaction verification
-        true
+    
+    /// Decide how to reshard at the end of the current epoch
+    ///
+    /// Overloaded shards are split, busiest first, while there are fewer
+    /// than `max_shards`. Then neighbouring pairs of underused shards are
+    /// merged in key order while there are more than `min_shards`. No shard
+    /// takes part in more than one change. `account_keys` are the sorted
+    /// keys of the existing accounts.
+    pub fn plan_resharding(&self, account_keys: &[u64]) -> Vec<ReshardAction> {
+        let config = &self.resharding;
+        let mut actions = Vec::new();
+        let mut involved: HashSet<ShardId> = HashSet::new();
+        let mut shard_count = self.layout.ranges.len();
+        let mut next_shard_id = self.layout.next_shard_id;
+        
+        let activity = |shard_id: ShardId| self.activity.get(&shard_id).copied().unwrap_or_default();
+        let utilization = |shard_id: ShardId| self.shards.get(&shard_id).map_or(0.0, |shard| shard.resource_utilization);
+        let criteria = |shard_id: ShardId| self.shards.get(&shard_id)
+            .map_or(PartitioningRule::AccountBased, |shard| shard.partition_criteria.clone());
+        
+        let mut overloaded: Vec<ShardId> = self.layout.shard_ids().into_iter()
+            .filter(|&id| utilization(id) >= config.split_utilization
+                || activity(id).transactions >= config.split_transactions)
+            .collect();
+        overloaded.sort_by_key(|&id| (std::cmp::Reverse(activity(id).transactions), id));
+        
+        for shard in overloaded {
+            if shard_count >= config.max_shards {
+                break;
+            }
+            if let Some(at) = self.split_point(shard, &criteria(shard), account_keys) {
+                actions.push(ReshardAction::Split { shard, new_shard: next_shard_id, at });
+                involved.insert(shard);
+                next_shard_id = next_shard_id.saturating_add(1);
+                shard_count += 1;
+            }
+        }
+        
+        let underused = |id: ShardId| !involved.contains(&id)
+            && criteria(id) != PartitioningRule::TransactionTypeBased
+            && utilization(id) <= config.merge_utilization
+            && activity(id).transactions <= config.merge_transactions;
+        let order = self.layout.shard_ids();
+        let mut merges = Vec::new();
+        let mut index = 0;
+        while index + 1 < order.len() && shard_count > config.min_shards {
+            let (shard, absorbed) = (order[index], order[index + 1]);
+            if underused(shard) && underused(absorbed) {
+                merges.push(ReshardAction::Merge { shard, absorbed });
+                shard_count -= 1;
+                index += 2;
+            } else {
+                index += 1;
+            }
+        }
+        actions.extend(merges);
+        
+        actions
+    }
+    
+    /// Key at which to split a shard under its partitioning rule, if it can
+    /// be split
+    fn split_point(&self, shard_id: ShardId, criteria: &PartitioningRule, account_keys: &[u64]) -> Option<u64> {
+        let (start, end) = self.layout.range(shard_id)?;
+        let midpoint = || if end > start { Some(start + (end - start) / 2 + 1) } else { None };
+        
+        match criteria {
+            PartitioningRule::AccountBased => midpoint(),
+            PartitioningRule::ResourceBased => {
+                let first = account_keys.partition_point(|&key| key < start);
+                let last = account_keys.partition_point(|&key| key <= end);
+                let keys = &account_keys[first..last];
+                if keys.len() >= 2 {
+                    Some(keys[keys.len() / 2])
+                } else {
+                    midpoint()
+                }
+            },
+            PartitioningRule::TransactionTypeBased => None,
+        }
+    }
+    
+    /// Count a block towards the activity of its shards in the current epoch
+    fn count_activity(&mut self, block: &Block) {
+        for &shard_id in &block.header.shard_identifiers {
+            if self.layout.contains(shard_id) {
+                self.activity.entry(shard_id).or_default().blocks += 1;
+            }
+        }
+        for shard_data in &block.shard_data {
+            if self.layout.contains(shard_data.shard_id) {
+                self.activity.entry(shard_data.shard_id).or_default().transactions += shard_data.transactions.len() as u64;
+            }
+        }
+    }
+    
+    /// Recompute each shard's resource utilization from the epoch's activity
+    fn update_utilization(&mut self) {
+        let capacity = self.resharding.shard_capacity.max(1) as f64;
+        for (id, shard) in self.shards.iter_mut() {
+            let activity = self.activity.get(id).copied().unwrap_or_default();
+            shard.resource_utilization = if activity.blocks == 0 {
+                0.0
+            } else {
+                (activity.transactions as f64 / (activity.blocks as f64 * capacity)).min(1.0) as f32
+            };
+        }
+    }
+    
+    /// Compute and record the state roots of the given shards
+    fn record_shard_roots(&mut self, state_db: &StateDB, shard_ids: &[ShardId]) -> Result<()> {
+        let accounts = state_db.get_all_accounts()?;
+        self.record_shard_roots_from(&accounts, state_db, shard_ids)
     }
+    
+    /// Compute the state roots of the given shards over `accounts`, sorted
+    /// by address, and record them
+    fn record_shard_roots_from(&mut self, accounts: &[(Vec<u8>, AccountInfo)], state_db: &StateDB, shard_ids: &[ShardId]) -> Result<()> {
+        for &shard_id in shard_ids {
+            let members: Vec<(&Vec<u8>, &AccountInfo)> = accounts.iter()
+                .filter(|(address, _)| self.layout.shard_for_address(address) == shard_id)
+                .map(|(address, account)| (address, account))
+                .collect();
+            let active = members.iter().filter(|(_, account)| account.balance > 0 || account.nonce > 0).count();
+            let root = compute_state_root(members);
+            
+            state_db.set_shard_state_root(shard_id, Some(&root))?;
+            if let Some(shard) = self.shards.get_mut(&shard_id) {
+                shard.state_root = root;
+                shard.active_accounts = active as u32;
+            }
+        }
+        Ok(())
+    }
+    
+    /// Settlements a block at `height` including `shard_ids` should carry
+    ///
+    /// Transfers are credited on their destination shard up to their expiry
+    /// height and refunded on their source shard after it.
+    pub fn settlements_for(&self, shard_ids: &[ShardId], height: BlockHeight) -> Vec<CrossShardSettlement> {
+        self.pending.values()
+            .filter(|proof| proof.source_height < height)
+            .map(|proof| CrossShardSettlement {
+                proof: proof.clone(),
+                refund: height > proof.expires_at(self.timeout),
+            })
+            .filter(|settlement| shard_ids.contains(&settlement.settling_shard()))
+            .collect()
+    }
+    
+    /// Check if a transfer is awaiting settlement
+    pub fn is_pending(&self, transaction_id: &[u8]) -> bool {
+        self.pending.contains_key(transaction_id)
+    }
+    
+    /// Number of transfers awaiting settlement
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+    
+    /// Handle a message received from another shard
+    ///
+    /// State requests are answered from `state_db`, which must hold the
+    /// state as of the latest canonical block.
+    pub fn handle_message(&mut self, from: ShardId, message: CrossShardMessage, state_db: Option<&StateDB>) -> Result<()> {
+        match message {
+            CrossShardMessage::TransactionRequest(transaction_id) => {
+                if let Some(proof) = self.pending.get(&transaction_id) {
+                    self.outbox.push_back((from, CrossShardMessage::ReceiptProof(proof.clone())));
+                }
+                Ok(())
+            },
+            CrossShardMessage::ReceiptProof(proof) => {
+                let header = self.headers.get(&proof.source_height)
+                    .ok_or_else(|| Error::State(format!(
+                        "No canonical header at height {} to check the proof against", proof.source_height
+                    )))?;
+                proof.verify(header)?;
+                
+                self.pending.entry(proof.receipt.transaction_id.clone()).or_insert(proof);
+                Ok(())
+            },
+            CrossShardMessage::StateRequest(height, addresses) => {
+                if addresses.len() > STATE_REQUEST_LIMIT {
+                    return Err(Error::Validation(format!(
+                        "State request for {} accounts exceeds the limit of {}", addresses.len(), STATE_REQUEST_LIMIT
+                    )));
+                }
+                let header = self.headers.iter().next_back()
+                    .filter(|&(&latest, _)| latest == height)
+                    .map(|(_, header)| header)
+                    .ok_or_else(|| Error::State(format!("State at height {} is not available", height)))?;
+                
+                // Reuse the state tree while the latest block keeps its height
+                // and state root
+                let cached = self.state_tree.as_ref()
+                    .map_or(false, |(tree_height, tree)| *tree_height == height && tree.root() == header.state_root);
+                if !cached {
+                    let state_db = state_db
+                        .ok_or_else(|| Error::State("No state to answer the request from".to_string()))?;
+                    self.state_tree = Some((height, StateTree::new(state_db.get_all_accounts()?)));
+                }
+                
+                let (_, tree) = self.state_tree.as_ref().unwrap();
+                let proofs = tree.prove(header, &addresses)?;
+                self.outbox.push_back((from, CrossShardMessage::StateResponse(height, proofs)));
+                Ok(())
+            },
+            CrossShardMessage::StateResponse(height, proofs) => {
+                let header = self.headers.get(&height)
+                    .ok_or_else(|| Error::State(format!(
+                        "No canonical header at height {} to check the state against", height
+                    )))?;
+                
+                // Reject the whole response if any account fails to verify
+                let mut accounts = BTreeMap::new();
+                for proof in &proofs {
+                    if self.layout.shard_for_address(proof.address()) != from {
+                        return Err(Error::Validation(format!(
+                            "Account {} is not on shard {}", hex::encode(proof.address()), from
+                        )));
+                    }
+                    accounts.insert(proof.address().to_vec(), proof.verify(&header.state_root)?);
+                }
+                
+                self.remote_state.entry(height).or_default().extend(accounts);
+                Ok(())
+            },
+        }
+    }
+    
+    /// Ask a shard for the state of accounts as of a canonical block height
+    pub fn request_state(&mut self, shard_id: ShardId, height: BlockHeight, addresses: Vec<Vec<u8>>) {
+        for chunk in addresses.chunks(STATE_REQUEST_LIMIT) {
+            self.outbox.push_back((shard_id, CrossShardMessage::StateRequest(height, chunk.to_vec())));
+        }
+    }
+    
+    /// Get an account of another shard as proven at a block height
+    pub fn remote_account(&self, height: BlockHeight, address: &[u8]) -> Option<&AccountInfo> {
+        self.remote_state.get(&height)?.get(address)
+    }
+    
+    /// Take the messages waiting to be sent, with their target shards
+    pub fn take_messages(&mut self) -> Vec<(ShardId, CrossShardMessage)> {
+        self.outbox.drain(..).collect()
+    }
+}
 
-    fn verify_validator_signatures(&self, shard_id: ShardId) -> bool {
-        // TODO: Implement validator signature verification
-        true
+impl Default for ShardManager {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::blockchain::{Log, ShardData};
     use crate::crypto::hash;
-
+    
+    fn cross_shard_receipt(id: u8, amount: u64) -> Receipt {
+        let transfer = CrossShardTransfer {
+            sender: vec![1; 32],
+            recipient: vec![2; 32],
+            source_shard: 0,
+            destination_shard: 1,
+            amount,
+        };
+        Receipt {
+            transaction_id: vec![id; 32],
+            status: 1,
+            gas_used: 21000,
+            state_root: Vec::new(),
+            logs: vec![Log {
+                address: vec![1; 32],
+                topics: vec![CROSS_SHARD_TOPIC.to_vec()],
+                data: transfer.encode().unwrap(),
+            }],
+        }
+    }
+    
+    fn source_block(height: BlockHeight, receipts: &[Receipt]) -> Block {
+        let mut block = Block::new(height, 1_000_000 + height, vec![0; 32], vec![0]);
+        block.cross_shard_receipts = outbound_receipts(receipts).unwrap();
+        block.set_receipts(receipts).unwrap();
+        block
+    }
+    
+    fn no_epoch() -> Result<(ShardLayout, Vec<Block>)> {
+        Err(Error::State("No epoch to rebuild".to_string()))
+    }
+    
+    fn block_with_transactions(height: BlockHeight, shard_ids: Vec<ShardId>, transactions: &[(ShardId, u8)]) -> Block {
+        let mut block = Block::new(height, 1_000_000 + height, vec![0; 32], shard_ids);
+        for &(shard_id, count) in transactions {
+            let mut shard_data = ShardData::new(shard_id);
+            shard_data.transactions = (0..count).map(|i| vec![i; 32]).collect();
+            block.shard_data.push(shard_data);
+        }
+        block
+    }
+    
     #[test]
     fn test_shard_assignment() {
         let mut manager = ShardManager::new();
-        let account = hash::sha256(b"test_account");
-        let shard_id = 1;
-
-        manager.assign_account(account, shard_id);
-        assert_eq!(manager.get_shard_for_account(&account), Some(shard_id));
+        let account = hash::sha256(b"test_account").to_vec();
+        assert_eq!(manager.get_shard_for_account(&account), 0);
+        
+        // The shard follows from the account key and the layout alone
+        manager.set_layout(ShardLayout::uniform(4), None).unwrap();
+        let shard_id = manager.get_shard_for_account(&account);
+        assert_eq!(shard_id, (account_key(&account) >> 62) as ShardId);
+        assert_eq!(ShardLayout::uniform(4).shard_for_address(&account), shard_id);
+    }
+    
+    #[test]
+    fn test_layout_split_and_merge() {
+        let mut layout = ShardLayout::uniform(2);
+        assert_eq!(layout.range(0), Some((0, (1 << 63) - 1)));
+        assert_eq!(layout.range(1), Some((1 << 63, u64::MAX)));
+        
+        layout.apply(&ReshardAction::Split { shard: 1, new_shard: 2, at: 3 << 62 }).unwrap();
+        assert_eq!(layout.shard_ids(), vec![0, 1, 2]);
+        assert_eq!(layout.shard_for_key((3 << 62) - 1), 1);
+        assert_eq!(layout.shard_for_key(3 << 62), 2);
+        
+        // Splits fall inside the range and use fresh identifiers
+        assert!(layout.apply(&ReshardAction::Split { shard: 0, new_shard: 3, at: 0 }).is_err());
+        assert!(layout.apply(&ReshardAction::Split { shard: 0, new_shard: 1, at: 1 }).is_err());
+        
+        // Only a shard's successor can be merged into it
+        assert!(layout.apply(&ReshardAction::Merge { shard: 0, absorbed: 2 }).is_err());
+        layout.apply(&ReshardAction::Merge { shard: 1, absorbed: 2 }).unwrap();
+        assert_eq!(layout.ranges, ShardLayout::uniform(2).ranges);
+        assert_eq!(layout.next_shard_id, 3);
+    }
+    
+    #[test]
+    fn test_split_points_follow_partitioning_rule() {
+        let mut manager = ShardManager::new();
+        manager.set_layout(ShardLayout::uniform(2), None).unwrap();
+        manager.activity.insert(0, EpochActivity { blocks: 1, transactions: 100_000 });
+        assert_eq!(
+            manager.plan_resharding(&[5, 10, 20, 1 << 63]),
+            vec![ReshardAction::Split { shard: 0, new_shard: 2, at: 1 << 62 }]
+        );
+        
+        let mut shard = Shard::new(0);
+        shard.partition_criteria = PartitioningRule::ResourceBased;
+        manager.add_shard(shard.clone());
+        assert_eq!(
+            manager.plan_resharding(&[5, 10, 20, 1 << 63]),
+            vec![ReshardAction::Split { shard: 0, new_shard: 2, at: 10 }]
+        );
+        
+        shard.partition_criteria = PartitioningRule::TransactionTypeBased;
+        manager.add_shard(shard);
+        assert!(manager.plan_resharding(&[5, 10, 20, 1 << 63]).is_empty());
+    }
+    
+    #[test]
+    fn test_epoch_boundaries_split_and_merge_shards() {
+        let state_db = StateDB::new("", &crate::storage::StorageConfig::default()).unwrap();
+        for i in 0..8u8 {
+            state_db.set_account_balance(&[i; 32], 100).unwrap();
+        }
+        
+        let mut manager = ShardManager::new();
+        manager.set_resharding_config(ReshardingConfig {
+            epoch_length: 2,
+            shard_capacity: 10,
+            ..ReshardingConfig::default()
+        });
+        manager.set_layout(ShardLayout::uniform(2), Some(&state_db)).unwrap();
+        
+        // Shard 0 runs at 90% of its capacity for an epoch
+        let first_epoch: Vec<Block> = (1..=2)
+            .map(|height| block_with_transactions(height, vec![0, 1], &[(0, 9)]))
+            .collect();
+        for block in &first_epoch {
+            manager.record_block(block, &[]).unwrap();
+        }
+        assert!((manager.get_shard(0).unwrap().resource_utilization - 0.9).abs() < 1e-6);
+        assert!(!manager.is_epoch_boundary(1));
+        assert!(manager.is_epoch_boundary(2));
+        
+        let actions = manager.end_epoch(2, Some(&state_db)).unwrap();
+        assert_eq!(actions, vec![ReshardAction::Split { shard: 0, new_shard: 2, at: 1 << 62 }]);
+        assert_eq!(manager.layout().epoch, 1);
+        
+        // The accounts that moved are committed to under their new shard
+        let active: u32 = manager.layout().shard_ids().iter()
+            .map(|&id| manager.get_shard(id).unwrap().active_accounts)
+            .sum();
+        assert_eq!(active, 8);
+        for id in [0, 2] {
+            let shard = manager.get_shard(id).unwrap();
+            assert_eq!(state_db.get_shard_state_root(id).unwrap(), Some(shard.state_root.clone()));
+        }
+        
+        // An idle epoch merges the split shards back together
+        let idle = block_with_transactions(3, vec![0], &[]);
+        manager.record_block(&idle, &[]).unwrap();
+        let boundary = block_with_transactions(4, vec![0], &[]);
+        manager.record_block(&boundary, &[]).unwrap();
+        let actions = manager.end_epoch(4, Some(&state_db)).unwrap();
+        assert_eq!(actions, vec![ReshardAction::Merge { shard: 0, absorbed: 2 }]);
+        assert!(manager.get_shard(2).is_none());
+        assert_eq!(state_db.get_shard_state_root(2).unwrap(), None);
+        
+        // Reverting the boundary block restores the previous layout
+        let split_root = manager.checkpoints.back().unwrap().roots.clone();
+        manager.revert_block(&boundary, Some(&state_db), no_epoch).unwrap();
+        assert_eq!(manager.layout().shard_ids(), vec![0, 2, 1]);
+        for (id, root) in split_root {
+            assert_eq!(state_db.get_shard_state_root(id).unwrap(), root);
+        }
+        
+        // Without a checkpoint the boundary is rebuilt from the epoch's blocks
+        manager.checkpoints.clear();
+        manager.revert_block(&idle, Some(&state_db), no_epoch).unwrap();
+        let layout = ShardLayout::uniform(2);
+        manager.revert_block(&first_epoch[1], Some(&state_db), || Ok((layout, first_epoch[..1].to_vec()))).unwrap();
+        assert_eq!(manager.layout(), &ShardLayout::uniform(2));
+        assert!(manager.get_shard(2).is_none());
+        assert_eq!(state_db.get_shard_state_root(2).unwrap(), None);
+        assert!((manager.get_shard(0).unwrap().resource_utilization - 0.9).abs() < 1e-6);
+        for id in [0, 1] {
+            assert_eq!(state_db.get_shard_state_root(id).unwrap(), Some(manager.get_shard(id).unwrap().state_root.clone()));
+        }
+    }
+    
+    #[test]
+    fn test_receipt_proofs() {
+        let receipts: Vec<Receipt> = (1..=3).map(|id| cross_shard_receipt(id, 100 * id as u64)).collect();
+        let block = source_block(5, &receipts);
+        
+        let proof = CrossShardProof::build(&block, &receipts, &[2; 32]).unwrap();
+        assert_eq!(proof.verify(&block.header).unwrap().amount, 200);
+        
+        // The receipt must be the one the execution receipt implies
+        let mut inflated = proof.clone();
+        let mut transfer = CrossShardTransfer::decode(&inflated.receipt.data).unwrap();
+        transfer.amount = 1_000;
+        inflated.receipt.data = transfer.encode().unwrap();
+        assert!(inflated.verify(&block.header).is_err());
+        
+        // And the execution receipt must be in the source block
+        let mut forged = proof.clone();
+        forged.execution_receipt.gas_used += 1;
+        assert!(forged.verify(&block.header).is_err());
+        
+        let other = source_block(6, &receipts);
+        assert!(proof.verify(&other.header).is_err());
+    }
+    
+    #[test]
+    fn test_settlements_credit_then_refund() {
+        let mut manager = ShardManager::with_timeout(3);
+        let receipts = vec![cross_shard_receipt(1, 500)];
+        let block = source_block(10, &receipts);
+        manager.record_block(&block, &receipts).unwrap();
+        assert!(manager.is_pending(&[1; 32]));
+        
+        // The proof is sent to the destination shard
+        let messages = manager.take_messages();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].0, 1);
+        
+        // Credited by destination blocks until the timeout
+        assert!(manager.settlements_for(&[0, 1], 10).is_empty());
+        assert!(manager.settlements_for(&[0], 11).is_empty());
+        let settlements = manager.settlements_for(&[1], 13);
+        assert_eq!(settlements.len(), 1);
+        assert!(!settlements[0].refund);
+        
+        // Refunded by source blocks after it
+        assert!(manager.settlements_for(&[1], 14).is_empty());
+        let settlements = manager.settlements_for(&[0], 14);
+        assert!(settlements[0].refund);
+        
+        // Settling completes the transfer, reverting the settlement reopens it
+        let mut settling = Block::new(14, 2_000_000, block.hash().to_vec(), vec![0]);
+        settling.cross_shard_settlements = settlements;
+        manager.record_block(&settling, &[]).unwrap();
+        assert_eq!(manager.pending_count(), 0);
+        manager.revert_block(&settling, None, no_epoch).unwrap();
+        assert!(manager.is_pending(&[1; 32]));
+    }
+    
+    #[test]
+    fn test_incoming_proofs_are_verified() {
+        let receipts = vec![cross_shard_receipt(1, 500)];
+        let block = source_block(10, &receipts);
+        let proof = CrossShardProof::build(&block, &receipts, &[1; 32]).unwrap();
+        
+        // A destination node that knows the source header accepts the proof
+        let mut destination = ShardManager::new();
+        assert!(destination.handle_message(0, CrossShardMessage::ReceiptProof(proof.clone()), None).is_err());
+        destination.record_block(&Block::new(10, block.header.timestamp, vec![0; 32], vec![1]), &[]).unwrap();
+        assert!(destination.handle_message(0, CrossShardMessage::ReceiptProof(proof.clone()), None).is_err());
+        
+        let mut destination = ShardManager::new();
+        destination.headers.insert(10, block.header.clone());
+        destination.handle_message(0, CrossShardMessage::ReceiptProof(proof), None).unwrap();
+        assert!(destination.is_pending(&[1; 32]));
+        
+        // And answers requests for it
+        destination.handle_message(2, CrossShardMessage::TransactionRequest(vec![1; 32]), None).unwrap();
+        let messages = destination.take_messages();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].0, 2);
+    }
+    
+    #[test]
+    fn test_state_requests_are_proven() {
+        let state_db = StateDB::new("", &crate::storage::StorageConfig::default()).unwrap();
+        for i in 1..=8u8 {
+            state_db.set_account_balance(&[i; 32], 100 * i as u64).unwrap();
+        }
+        let mut block = Block::new(7, 1_000_007, vec![0; 32], vec![0, 1]);
+        block.header.state_root = compute_state_root(state_db.get_all_accounts().unwrap().iter().map(|(address, account)| (address, account)));
+        
+        let layout = ShardLayout::uniform(2);
+        let remote: Vec<Vec<u8>> = (1..=8u8).map(|i| vec![i; 32]).filter(|a| layout.shard_for_address(a) == 1).collect();
+        let local = (1..=8u8).map(|i| vec![i; 32]).find(|a| layout.shard_for_address(a) == 0).unwrap();
+        
+        let mut responder = ShardManager::new();
+        responder.set_layout(layout.clone(), None).unwrap();
+        responder.headers.insert(7, block.header.clone());
+        let mut requester = ShardManager::new();
+        requester.set_layout(layout, None).unwrap();
+        requester.headers.insert(7, block.header.clone());
+        
+        // Only the latest state can be served
+        assert!(responder.handle_message(0, CrossShardMessage::StateRequest(6, remote.clone()), Some(&state_db)).is_err());
+        
+        requester.request_state(1, 7, remote.clone());
+        let (shard_id, request) = requester.take_messages().remove(0);
+        assert_eq!(shard_id, 1);
+        responder.handle_message(0, request, Some(&state_db)).unwrap();
+        let (shard_id, response) = responder.take_messages().remove(0);
+        assert_eq!(shard_id, 0);
+        
+        // A tampered balance or an account of another shard rejects the response
+        let proofs = match response.clone() {
+            CrossShardMessage::StateResponse(_, proofs) => proofs,
+            _ => panic!("expected a state response"),
+        };
+        let mut tampered = proofs.clone();
+        match &mut tampered[0] {
+            AccountProof::Present(proof) => proof.balance += 1,
+            AccountProof::Absent { .. } => panic!("expected the account to be present"),
+        }
+        assert!(requester.handle_message(1, CrossShardMessage::StateResponse(7, tampered), None).is_err());
+        let tree = StateTree::new(state_db.get_all_accounts().unwrap());
+        let foreign = tree.prove(&block.header, &[local]).unwrap();
+        assert!(foreign[0].verify(&block.header.state_root).is_ok());
+        assert!(requester.handle_message(1, CrossShardMessage::StateResponse(7, foreign), None).is_err());
+        assert!(requester.remote_account(7, &remote[0]).is_none());
+        
+        // Verified accounts are cached at the requested height
+        requester.handle_message(1, response, None).unwrap();
+        for address in &remote {
+            let expected = state_db.get_account_info(address).unwrap();
+            assert_eq!(requester.remote_account(7, address).map(|account| account.balance), Some(expected.balance));
+        }
+        
+        // And dropped once the block is reverted
+        requester.revert_block(&block, None, no_epoch).unwrap();
+        assert!(requester.remote_account(7, &remote[0]).is_none());
+    }
+    
+    #[test]
+    fn test_absent_accounts_are_proven() {
+        let accounts: Vec<(Vec<u8>, AccountInfo)> = [2u8, 4, 6].iter()
+            .map(|&i| (vec![i; 32], AccountInfo { balance: 100, nonce: 0, is_contract: false }))
+            .collect();
+        let tree = StateTree::new(accounts);
+        let mut header = Block::new(7, 1_000_007, vec![0; 32], vec![0]).header;
+        header.state_root = tree.root();
+        
+        // Missing accounts before, between and after the others are proven
+        // empty, and present ones keep their balance
+        let addresses: Vec<Vec<u8>> = (1..=7u8).map(|i| vec![i; 32]).collect();
+        let proofs = tree.prove(&header, &addresses).unwrap();
+        for (i, proof) in (1..=7u64).zip(&proofs) {
+            let expected = if i % 2 == 0 { 100 } else { 0 };
+            assert_eq!(proof.verify(&header.state_root).unwrap().balance, expected);
+        }
+        
+        // Neighbours that skip an account, or a last account that is not
+        // the last, do not prove absence
+        let (before, after) = match (&proofs[1], &proofs[5]) {
+            (AccountProof::Present(before), AccountProof::Present(after)) => (before.clone(), after.clone()),
+            _ => panic!("expected the accounts to be present"),
+        };
+        let skipping = AccountProof::Absent { address: vec![5; 32], before: Some(before.clone()), after: Some(after) };
+        assert!(skipping.verify(&header.state_root).is_err());
+        let not_last = AccountProof::Absent { address: vec![3; 32], before: Some(before), after: None };
+        assert!(not_last.verify(&header.state_root).is_err());
+        let unproven = AccountProof::Absent { address: vec![3; 32], before: None, after: None };
+        assert!(unproven.verify(&header.state_root).is_err());
+        
+        // An empty state proves every account absent
+        let empty = StateTree::new(Vec::new());
+        header.state_root = empty.root();
+        let proofs = empty.prove(&header, &addresses[..1]).unwrap();
+        assert!(proofs[0].verify(&header.state_root).is_ok());
+    }
+    
+    #[test]
+    fn test_state_tree_is_reused_for_the_latest_block() {
+        let state_db = StateDB::new("", &crate::storage::StorageConfig::default()).unwrap();
+        state_db.set_account_balance(&[1; 32], 100).unwrap();
+        let mut block = Block::new(7, 1_000_007, vec![0; 32], vec![0]);
+        block.header.state_root = compute_state_root(state_db.get_all_accounts().unwrap().iter().map(|(address, account)| (address, account)));
+        
+        let mut responder = ShardManager::new();
+        responder.headers.insert(7, block.header.clone());
+        responder.handle_message(1, CrossShardMessage::StateRequest(7, vec![vec![1; 32]]), Some(&state_db)).unwrap();
+        
+        // Later requests at the same height are answered without the state
+        responder.handle_message(1, CrossShardMessage::StateRequest(7, vec![vec![2; 32]]), None).unwrap();
+        assert_eq!(responder.take_messages().len(), 2);
+        
+        // A new block at the height with another state root needs the state again
+        let mut replaced = block.clone();
+        replaced.header.state_root = vec![1; 32];
+        responder.headers.insert(7, replaced.header);
+        assert!(responder.handle_message(1, CrossShardMessage::StateRequest(7, vec![vec![1; 32]]), None).is_err());
     }
 }
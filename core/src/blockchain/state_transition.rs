@@ -0,0 +1,966 @@
+//! # State Transition Engine
+//!
+//! This module applies the transactions of a block to the state database.
+//! Transactions are executed against an in-memory overlay so that a block is
+//! either applied as a whole or rejected without touching the stored state.
+
+use std::collections::BTreeMap;
+
+use rayon::prelude::*;
+
+use crate::blockchain::{Block, Transaction, Receipt, Log};
+use crate::blockchain::parallel::{AccessSet, ExecutionSchedule, StateKey};
+use crate::blockchain::fee_market::FeeCharge;
+use crate::blockchain::sharding::{self, CrossShardSettlement, CrossShardTransfer};
+use crate::crypto::hash::{self, Hash};
+use crate::storage::{StateDB, AccountInfo};
+use crate::types::{Result, Error, TransactionType};
+use super::block::merkle_root;
+
+/// Receipt status for a successfully executed transaction
+pub const RECEIPT_STATUS_SUCCESS: u32 = 1;
+
+/// Log topic emitted for value transfers
+pub const TRANSFER_TOPIC: &[u8] = b"Transfer";
+
+/// Log topic emitted when stake is locked
+pub const STAKE_TOPIC: &[u8] = b"Stake";
+
+/// Log topic emitted when stake is released
+pub const UNSTAKE_TOPIC: &[u8] = b"Unstake";
+
+/// Log topic emitted when value leaves for another shard
+pub const CROSS_SHARD_TOPIC: &[u8] = b"CrossShard";
+
+/// Derive the account address controlled by a public key
+pub fn address_from_public_key(public_key: &[u8]) -> Vec<u8> {
+    hash::sha256(public_key).to_vec()
+}
+
+/// Derive the address of the account holding the stake locked by `address`
+pub fn stake_address(address: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(5 + address.len());
+    data.extend_from_slice(b"stake");
+    data.extend_from_slice(address);
+    hash::sha256(&data).to_vec()
+}
+
+/// Derive the address of the account escrowing a cross-shard transfer
+///
+/// `transaction_id` is the hash of the transfer's signed payload, which
+/// blocks are checked against, so no sender can pick an escrow account.
+/// Its nonce is set once the transfer is settled, so that it cannot be
+/// settled twice.
+pub fn escrow_address(transaction_id: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(6 + transaction_id.len());
+    data.extend_from_slice(b"escrow");
+    data.extend_from_slice(transaction_id);
+    hash::sha256(&data).to_vec()
+}
+
+/// Hash an account into a state tree leaf
+pub fn account_leaf(address: &[u8], account: &AccountInfo) -> Hash {
+    let mut data = Vec::with_capacity(4 + address.len() + 16);
+    data.extend_from_slice(&(address.len() as u32).to_be_bytes());
+    data.extend_from_slice(address);
+    data.extend_from_slice(&account.balance.to_be_bytes());
+    data.extend_from_slice(&account.nonce.to_be_bytes());
+    hash::sha256(&data)
+}
+
+/// Compute the state root over a sorted set of accounts
+///
+/// Accounts without balance or nonce are treated as absent, so that reading
+/// an account never changes the root.
+pub fn compute_state_root<'a, I>(accounts: I) -> Vec<u8>
+where
+    I: IntoIterator<Item = (&'a Vec<u8>, &'a AccountInfo)>,
+{
+    let leaves: Vec<Hash> = accounts.into_iter()
+        .filter(|(_, account)| account.balance > 0 || account.nonce > 0)
+        .map(|(address, account)| account_leaf(address, account))
+        .collect();
+    merkle_root(&leaves)
+}
+
+/// Pending account changes layered over the state database
+pub struct StateOverlay<'a> {
+    /// Underlying state database
+    state_db: &'a StateDB,
+    
+    /// Accounts read ahead of time from another overlay, which are not
+    /// changes of this one
+    prefetched: BTreeMap<Vec<u8>, AccountInfo>,
+    
+    /// Modified accounts, keyed by address
+    accounts: BTreeMap<Vec<u8>, AccountInfo>,
+    
+    /// Changes of the update being applied by `atomically`, which are
+    /// merged into `accounts` once it succeeds
+    staged: Option<BTreeMap<Vec<u8>, AccountInfo>>,
+}
+
+impl<'a> StateOverlay<'a> {
+    /// Create an empty overlay over the given state database
+    pub fn new(state_db: &'a StateDB) -> Self {
+        StateOverlay {
+            state_db,
+            prefetched: BTreeMap::new(),
+            accounts: BTreeMap::new(),
+            staged: None,
+        }
+    }
+    
+    /// Get an account, preferring pending changes over stored state
+    pub fn get_account(&self, address: &[u8]) -> Result<AccountInfo> {
+        let pending = self.staged.as_ref()
+            .and_then(|staged| staged.get(address))
+            .or_else(|| self.accounts.get(address))
+            .or_else(|| self.prefetched.get(address));
+        if let Some(account) = pending {
+            return Ok(account.clone());
+        }
+        
+        Ok(AccountInfo {
+            balance: self.state_db.get_account_balance(address)?,
+            nonce: self.state_db.get_account_nonce(address)?,
+            is_contract: self.state_db.has_contract_code(address),
+        })
+    }
+    
+    /// Get an account balance
+    pub fn get_balance(&self, address: &[u8]) -> Result<u64> {
+        Ok(self.get_account(address)?.balance)
+    }
+    
+    /// Get an account nonce
+    pub fn get_nonce(&self, address: &[u8]) -> Result<u64> {
+        Ok(self.get_account(address)?.nonce)
+    }
+    
+    /// Credit an account
+    pub fn credit(&mut self, address: &[u8], amount: u64) -> Result<()> {
+        let mut account = self.get_account(address)?;
+        account.balance = account.balance.checked_add(amount)
+            .ok_or_else(|| Error::State(format!("Balance overflow for address {}", hex::encode(address))))?;
+        self.write(address.to_vec(), account);
+        Ok(())
+    }
+    
+    /// Debit an account, failing if the balance is insufficient
+    pub fn debit(&mut self, address: &[u8], amount: u64) -> Result<()> {
+        let mut account = self.get_account(address)?;
+        if account.balance < amount {
+            return Err(Error::TransactionValidation(format!(
+                "Insufficient balance for {}: {} < {}",
+                hex::encode(address), account.balance, amount
+            )));
+        }
+        account.balance -= amount;
+        self.write(address.to_vec(), account);
+        Ok(())
+    }
+    
+    /// Increment an account nonce
+    pub fn increment_nonce(&mut self, address: &[u8]) -> Result<()> {
+        let mut account = self.get_account(address)?;
+        account.nonce += 1;
+        self.write(address.to_vec(), account);
+        Ok(())
+    }
+    
+    /// Record a changed account in the current update
+    fn write(&mut self, address: Vec<u8>, account: AccountInfo) {
+        match self.staged {
+            Some(ref mut staged) => staged.insert(address, account),
+            None => self.accounts.insert(address, account),
+        };
+    }
+    
+    /// Apply an update all or nothing
+    ///
+    /// The changes `apply` makes are kept apart and merged into the pending
+    /// changes only if it succeeds, so a failed update costs no more than
+    /// the accounts it touched.
+    pub fn atomically<T, F>(&mut self, apply: F) -> Result<T>
+    where
+        F: FnOnce(&mut Self) -> Result<T>,
+    {
+        let outer = self.staged.replace(BTreeMap::new());
+        let result = apply(self);
+        let staged = std::mem::replace(&mut self.staged, outer).unwrap_or_default();
+        if result.is_ok() {
+            for (address, account) in staged {
+                self.write(address, account);
+            }
+        }
+        result
+    }
+    
+    /// Pending changes, keyed by address
+    pub fn changes(&self) -> &BTreeMap<Vec<u8>, AccountInfo> {
+        &self.accounts
+    }
+    
+    /// Create an empty overlay over the same state database as `base`,
+    /// reading the given accounts instead of stored state
+    pub(crate) fn with_accounts(base: &StateOverlay<'a>, prefetched: BTreeMap<Vec<u8>, AccountInfo>) -> Self {
+        StateOverlay {
+            state_db: base.state_db,
+            prefetched,
+            accounts: BTreeMap::new(),
+            staged: None,
+        }
+    }
+    
+    /// Take the pending changes
+    pub(crate) fn into_changes(self) -> BTreeMap<Vec<u8>, AccountInfo> {
+        self.accounts
+    }
+    
+    /// Add changes made elsewhere on top of the pending ones
+    pub(crate) fn apply_changes(&mut self, changes: BTreeMap<Vec<u8>, AccountInfo>) {
+        self.accounts.extend(changes);
+    }
+    
+    /// Compute the state root that would result from committing this overlay
+    pub fn state_root(&self) -> Result<Vec<u8>> {
+        let mut accounts: BTreeMap<Vec<u8>, AccountInfo> = self.state_db.get_all_accounts()?
+            .into_iter()
+            .collect();
+        for (address, account) in &self.accounts {
+            accounts.insert(address.clone(), account.clone());
+        }
+        Ok(compute_state_root(&accounts))
+    }
+    
+    /// Write all pending changes to the state database
+    ///
+    /// Returns the prior values of every touched account so the commit can
+    /// be reverted later.
+    pub fn commit(self) -> Result<StateUndo> {
+        let mut previous = BTreeMap::new();
+        for address in self.accounts.keys() {
+            previous.insert(address.clone(), AccountInfo {
+                balance: self.state_db.get_account_balance(address)?,
+                nonce: self.state_db.get_account_nonce(address)?,
+                is_contract: self.state_db.has_contract_code(address),
+            });
+        }
+        
+        for (address, account) in &self.accounts {
+            self.state_db.set_account_balance(address, account.balance)?;
+            self.state_db.set_account_nonce(address, account.nonce)?;
+        }
+        
+        Ok(StateUndo { previous })
+    }
+}
+
+/// Prior account values needed to revert a committed overlay
+#[derive(Debug, Clone, Default)]
+pub struct StateUndo {
+    /// Account values before the commit, keyed by address
+    previous: BTreeMap<Vec<u8>, AccountInfo>,
+}
+
+impl StateUndo {
+    /// Restore the accounts touched by the commit to their prior values
+    pub fn revert(&self, state_db: &StateDB) -> Result<()> {
+        for (address, account) in &self.previous {
+            state_db.set_account_balance(address, account.balance)?;
+            state_db.set_account_nonce(address, account.nonce)?;
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of executing the transactions of a block
+#[derive(Debug, Clone)]
+pub struct ExecutionResult {
+    /// One receipt per transaction, in block order
+    pub receipts: Vec<Receipt>,
+    
+    /// State root after applying the block
+    pub state_root: Vec<u8>,
+    
+    /// Total fees paid by the block's transactions
+    pub fees_collected: u64,
+    
+    /// Part of the collected fees that was burned
+    pub fees_burned: u64,
+}
+
+/// Executes transactions and blocks against the state database
+pub struct StateTransition<'a> {
+    /// State database the transitions are applied to
+    state_db: &'a StateDB,
+}
+
+impl<'a> StateTransition<'a> {
+    /// Create a state transition engine over the given state database
+    pub fn new(state_db: &'a StateDB) -> Self {
+        StateTransition { state_db }
+    }
+    
+    /// Create an empty overlay over this engine's state database
+    pub fn overlay(&self) -> StateOverlay<'a> {
+        StateOverlay::new(self.state_db)
+    }
+    
+    /// Execute a single transaction against an overlay
+    ///
+    /// The sender pays `base_fee` and its tip; the fee recipient is credited
+    /// all but the burned part. On error the overlay is left unchanged.
+    pub fn execute_transaction(
+        &self,
+        overlay: &mut StateOverlay<'a>,
+        tx: &Transaction,
+        base_fee: u64,
+        fee_recipient: Option<&[u8]>,
+    ) -> Result<Receipt> {
+        let sender = address_from_public_key(&tx.sender_public_key);
+        
+        // Check the nonce
+        let expected_nonce = overlay.get_nonce(&sender)?;
+        if tx.nonce != expected_nonce {
+            return Err(Error::TransactionValidation(format!(
+                "Invalid nonce for {}: expected {}, got {}",
+                hex::encode(&sender), expected_nonce, tx.nonce
+            )));
+        }
+        
+        // Check the balance covers amount and fee before changing anything
+        let charge = FeeCharge::new(tx, base_fee)?;
+        let total_cost = tx.amount.checked_add(charge.total())
+            .ok_or_else(|| Error::TransactionValidation("Transaction cost overflows".to_string()))?;
+        let balance = overlay.get_balance(&sender)?;
+        let needs_funds = !matches!(tx.transaction_type, TransactionType::Unstake | TransactionType::ValidatorUnregister);
+        let required = if needs_funds { total_cost } else { charge.total() };
+        if balance < required {
+            return Err(Error::TransactionValidation(format!(
+                "Insufficient balance for {}: {} < {}",
+                hex::encode(&sender), balance, required
+            )));
+        }
+        
+        let mut logs = Vec::new();
+        overlay.atomically(|staged| {
+            // Pay the fee; the burned part is credited to no one
+            staged.debit(&sender, charge.total())?;
+            if let Some(recipient) = fee_recipient {
+                staged.credit(recipient, charge.producer_share())?;
+            }
+            
+            // Apply the type-specific effect
+            if tx.transaction_type == TransactionType::Batch {
+                // Operations share the update, so a failing operation
+                // discards the effects of the ones before it
+                for operation in tx.batch_operations()? {
+                    Self::apply_effect(
+                        staged,
+                        &sender,
+                        operation.transaction_type,
+                        &operation.recipient_address,
+                        operation.amount,
+                        &mut logs,
+                    )?;
+                }
+            } else if tx.is_cross_shard() {
+                // Hold the amount in escrow until the destination shard credits it
+                let transfer = CrossShardTransfer {
+                    sender: sender.clone(),
+                    recipient: tx.recipient_address.clone(),
+                    source_shard: tx.sender_shard,
+                    destination_shard: tx.recipient_shard,
+                    amount: tx.amount,
+                };
+                staged.debit(&sender, tx.amount)?;
+                staged.credit(&escrow_address(&tx.id), tx.amount)?;
+                logs.push(Log {
+                    address: sender.clone(),
+                    topics: vec![CROSS_SHARD_TOPIC.to_vec(), sender.clone(), tx.recipient_address.clone()],
+                    data: transfer.encode()?,
+                });
+            } else {
+                Self::apply_effect(staged, &sender, tx.transaction_type, &tx.recipient_address, tx.amount, &mut logs)?;
+            }
+            
+            staged.increment_nonce(&sender)
+        })?;
+        
+        Ok(Receipt {
+            transaction_id: tx.id.clone(),
+            status: RECEIPT_STATUS_SUCCESS,
+            gas_used: tx.estimate_gas().min(tx.gas_limit),
+            state_root: Vec::new(),
+            logs,
+        })
+    }
+    
+    /// Move `amount` as a transaction of the given type would
+    fn apply_effect(
+        staged: &mut StateOverlay<'_>,
+        sender: &[u8],
+        transaction_type: TransactionType,
+        recipient: &[u8],
+        amount: u64,
+        logs: &mut Vec<Log>,
+    ) -> Result<()> {
+        match transaction_type {
+            TransactionType::Transfer
+            | TransactionType::ContractDeploy
+            | TransactionType::ContractCall => {
+                staged.debit(sender, amount)?;
+                staged.credit(recipient, amount)?;
+                logs.push(Log {
+                    address: sender.to_vec(),
+                    topics: vec![TRANSFER_TOPIC.to_vec(), sender.to_vec(), recipient.to_vec()],
+                    data: amount.to_be_bytes().to_vec(),
+                });
+            },
+            TransactionType::Stake | TransactionType::ValidatorRegister => {
+                staged.debit(sender, amount)?;
+                staged.credit(&stake_address(sender), amount)?;
+                logs.push(Log {
+                    address: sender.to_vec(),
+                    topics: vec![STAKE_TOPIC.to_vec(), sender.to_vec()],
+                    data: amount.to_be_bytes().to_vec(),
+                });
+            },
+            TransactionType::Unstake | TransactionType::ValidatorUnregister => {
+                staged.debit(&stake_address(sender), amount)?;
+                staged.credit(sender, amount)?;
+                logs.push(Log {
+                    address: sender.to_vec(),
+                    topics: vec![UNSTAKE_TOPIC.to_vec(), sender.to_vec()],
+                    data: amount.to_be_bytes().to_vec(),
+                });
+            },
+            TransactionType::System => {
+                return Err(Error::TransactionValidation(
+                    "System transactions cannot be included in blocks".to_string()
+                ));
+            },
+            TransactionType::Batch => {
+                return Err(Error::TransactionValidation(
+                    "Batches cannot be nested".to_string()
+                ));
+            },
+        }
+        
+        Ok(())
+    }
+    
+    /// Release the escrow of a cross-shard transfer to its recipient, or to
+    /// its sender for a refund
+    ///
+    /// The proof is not checked here. On error the overlay is left unchanged.
+    pub fn apply_settlement(&self, overlay: &mut StateOverlay<'a>, settlement: &CrossShardSettlement) -> Result<()> {
+        let receipt = &settlement.proof.receipt;
+        let transfer = CrossShardTransfer::decode(&receipt.data)?;
+        let escrow = escrow_address(&receipt.transaction_id);
+        
+        if overlay.get_nonce(&escrow)? != 0 {
+            return Err(Error::State(format!(
+                "Cross-shard transfer {} is already settled", hex::encode(&receipt.transaction_id)
+            )));
+        }
+        
+        let payee = if settlement.refund { &transfer.sender } else { &transfer.recipient };
+        overlay.atomically(|staged| {
+            staged.debit(&escrow, transfer.amount)?;
+            staged.increment_nonce(&escrow)?;
+            staged.credit(payee, transfer.amount)
+        })
+    }
+    
+    /// Apply the cross-shard settlements of a block, which come before its
+    /// transactions
+    pub(crate) fn apply_settlements(&self, overlay: &mut StateOverlay<'a>, block: &Block) -> Result<()> {
+        for settlement in &block.cross_shard_settlements {
+            self.apply_settlement(overlay, settlement)
+                .map_err(|e| Error::BlockValidation(format!(
+                    "Settlement of {} failed: {}", hex::encode(&settlement.proof.receipt.transaction_id), e
+                )))?;
+        }
+        Ok(())
+    }
+    
+    /// Execute a block's transactions without committing them
+    ///
+    /// `transactions` must be the block's transactions in block order. Any
+    /// failing transaction rejects the whole block.
+    pub fn execute_block(&self, block: &Block, transactions: &[Transaction]) -> Result<(ExecutionResult, StateOverlay<'a>)> {
+        Self::check_block_transactions(block, transactions)?;
+        let fee_recipient = Self::fee_recipient(block);
+        
+        let mut overlay = self.overlay();
+        self.apply_settlements(&mut overlay, block)?;
+        let mut receipts = Vec::with_capacity(transactions.len());
+        let mut fees_collected = 0u64;
+        let mut fees_burned = 0u64;
+        
+        for tx in transactions {
+            let base_fee = block.base_fee(tx.sender_shard);
+            let receipt = self.execute_transaction(&mut overlay, tx, base_fee, fee_recipient.as_deref())
+                .map_err(|e| Error::BlockValidation(format!(
+                    "Transaction {} failed: {}", hex::encode(&tx.id), e
+                )))?;
+            
+            let charge = FeeCharge::new(tx, base_fee)?;
+            fees_collected += charge.total();
+            fees_burned += charge.burned;
+            receipts.push(receipt);
+        }
+        
+        let state_root = overlay.state_root()?;
+        
+        Ok((ExecutionResult { receipts, state_root, fees_collected, fees_burned }, overlay))
+    }
+    
+    /// Execute a block's transactions in parallel without committing them
+    ///
+    /// Transactions are grouped by [`ExecutionSchedule`]; groups run one
+    /// after another and the transactions of a group run concurrently, each
+    /// against the accounts it reads. The block producer is credited the fees
+    /// once every transaction has run. The result is identical to that of
+    /// [`StateTransition::execute_block`], which is used instead when a
+    /// transaction fails or touches the producer's account.
+    pub fn execute_block_parallel(&self, block: &Block, transactions: &[Transaction]) -> Result<(ExecutionResult, StateOverlay<'a>)> {
+        Self::check_block_transactions(block, transactions)?;
+        let fee_recipient = Self::fee_recipient(block);
+        
+        let access_sets: Vec<AccessSet> = transactions.par_iter().map(AccessSet::of).collect();
+        if let Some(ref recipient) = fee_recipient {
+            let key = StateKey::Account(recipient.clone());
+            if access_sets.iter().any(|access| access.touches(&key)) {
+                return self.execute_block(block, transactions);
+            }
+        }
+        
+        let schedule = ExecutionSchedule::build(transactions, &access_sets);
+        let mut overlay = self.overlay();
+        self.apply_settlements(&mut overlay, block)?;
+        let mut receipts: Vec<Option<Receipt>> = transactions.iter().map(|_| None).collect();
+        
+        for group in &schedule.groups {
+            let outcomes: Vec<Result<(Receipt, BTreeMap<Vec<u8>, AccountInfo>)>> = group.par_iter()
+                .map(|&index| {
+                    let mut prefetched = BTreeMap::new();
+                    for address in access_sets[index].accounts() {
+                        prefetched.insert(address.clone(), overlay.get_account(address)?);
+                    }
+                    
+                    let mut local = StateOverlay::with_accounts(&overlay, prefetched);
+                    let tx = &transactions[index];
+                    let receipt = self.execute_transaction(&mut local, tx, block.base_fee(tx.sender_shard), None)?;
+                    Ok((receipt, local.into_changes()))
+                })
+                .collect();
+            
+            // Transactions in a group write disjoint accounts
+            for (&index, outcome) in group.iter().zip(outcomes) {
+                match outcome {
+                    Ok((receipt, changes)) => {
+                        overlay.apply_changes(changes);
+                        receipts[index] = Some(receipt);
+                    },
+                    // Report the failure exactly as serial execution would
+                    Err(_) => return self.execute_block(block, transactions),
+                }
+            }
+        }
+        
+        let mut fees_collected = 0u64;
+        let mut fees_burned = 0u64;
+        for tx in transactions {
+            let charge = FeeCharge::new(tx, block.base_fee(tx.sender_shard))?;
+            if let Some(ref recipient) = fee_recipient {
+                overlay.credit(recipient, charge.producer_share())
+                    .map_err(|e| Error::BlockValidation(format!(
+                        "Transaction {} failed: {}", hex::encode(&tx.id), e
+                    )))?;
+            }
+            fees_collected += charge.total();
+            fees_burned += charge.burned;
+        }
+        
+        let receipts = receipts.into_iter().flatten().collect();
+        let state_root = overlay.state_root()?;
+        
+        Ok((ExecutionResult { receipts, state_root, fees_collected, fees_burned }, overlay))
+    }
+    
+    /// Check that `transactions` are the block's transactions in block order
+    pub(crate) fn check_block_transactions(block: &Block, transactions: &[Transaction]) -> Result<()> {
+        let tx_ids = block.transaction_ids();
+        if tx_ids.len() != transactions.len() {
+            return Err(Error::BlockValidation(format!(
+                "Block references {} transactions but {} were provided",
+                tx_ids.len(), transactions.len()
+            )));
+        }
+        
+        for (tx_id, tx) in tx_ids.into_iter().zip(transactions) {
+            if *tx_id != tx.id {
+                return Err(Error::BlockValidation(format!(
+                    "Transaction {} does not match block entry {}",
+                    hex::encode(&tx.id), hex::encode(tx_id)
+                )));
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// Address credited with the fees of a block
+    pub(crate) fn fee_recipient(block: &Block) -> Option<Vec<u8>> {
+        block.validator_set.first()
+            .map(|producer| address_from_public_key(producer))
+    }
+    
+    /// Execute a block, check it against its header and commit the result
+    ///
+    /// Returns the execution result and the data needed to revert the block.
+    pub fn apply_block(&self, block: &Block, transactions: &[Transaction]) -> Result<(ExecutionResult, StateUndo)> {
+        let (result, overlay) = self.execute_block_parallel(block, transactions)?;
+        self.commit_execution(block, result, overlay)
+    }
+    
+    /// Check the result of executing a block against its header and commit it
+    pub fn commit_execution(&self, block: &Block, result: ExecutionResult, overlay: StateOverlay<'a>) -> Result<(ExecutionResult, StateUndo)> {
+        if block.header.state_root != result.state_root {
+            return Err(Error::BlockValidation(
+                "State root does not match executed state".to_string()
+            ));
+        }
+        block.verify_receipt_root(&result.receipts)?;
+        if block.cross_shard_receipts != sharding::outbound_receipts(&result.receipts)? {
+            return Err(Error::BlockValidation(
+                "Cross-shard receipts do not match executed transfers".to_string()
+            ));
+        }
+        
+        let undo = overlay.commit()?;
+        Ok((result, undo))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::ShardData;
+    use crate::crypto::KeyPair;
+    use crate::storage::StorageConfig;
+    use crate::types::DEFAULT_CHAIN_ID;
+    
+    fn test_state_db() -> StateDB {
+        StateDB::new("", &StorageConfig::default()).unwrap()
+    }
+    
+    fn transfer(sender: &KeyPair, recipient: Vec<u8>, amount: u64, fee: u32, nonce: u64) -> Transaction {
+        let mut tx = Transaction::new_transfer(sender.public_key(), 0, recipient, 0, amount, fee, nonce);
+        tx.sign(sender, DEFAULT_CHAIN_ID);
+        tx
+    }
+    
+    fn block_for(transactions: &[Transaction]) -> Block {
+        let mut block = Block::new(1, 1_000_000, vec![0; 32], vec![0]);
+        block.add_shard_data(ShardData {
+            shard_id: 0,
+            transactions: transactions.iter().map(|tx| tx.id.clone()).collect(),
+            execution_proof: Vec::new(),
+            validator_signatures: Vec::new(),
+        }).unwrap();
+        block.update_merkle_roots();
+        block
+    }
+    
+    #[test]
+    fn test_transfer_updates_balances_and_nonce() {
+        let state_db = test_state_db();
+        let sender = KeyPair::generate();
+        let sender_address = address_from_public_key(&sender.public_key());
+        state_db.set_account_balance(&sender_address, 1000).unwrap();
+        
+        let engine = StateTransition::new(&state_db);
+        let txs = vec![transfer(&sender, vec![2; 20], 300, 10, 0)];
+        let block = block_for(&txs);
+        
+        let (result, overlay) = engine.execute_block(&block, &txs).unwrap();
+        assert_eq!(result.receipts.len(), 1);
+        assert_eq!(result.receipts[0].status, RECEIPT_STATUS_SUCCESS);
+        assert_eq!(result.fees_collected, 10);
+        
+        // Nothing is written until the overlay is committed
+        assert_eq!(state_db.get_account_balance(&sender_address).unwrap(), 1000);
+        overlay.commit().unwrap();
+        
+        assert_eq!(state_db.get_account_balance(&sender_address).unwrap(), 690);
+        assert_eq!(state_db.get_account_nonce(&sender_address).unwrap(), 1);
+        assert_eq!(state_db.get_account_balance(&vec![2; 20]).unwrap(), 300);
+    }
+    
+    #[test]
+    fn test_block_is_atomic() {
+        let state_db = test_state_db();
+        let sender = KeyPair::generate();
+        let sender_address = address_from_public_key(&sender.public_key());
+        state_db.set_account_balance(&sender_address, 500).unwrap();
+        
+        let engine = StateTransition::new(&state_db);
+        
+        // The second transfer overdraws the account
+        let txs = vec![
+            transfer(&sender, vec![2; 20], 300, 10, 0),
+            transfer(&sender, vec![3; 20], 300, 10, 1),
+        ];
+        let block = block_for(&txs);
+        
+        assert!(engine.apply_block(&block, &txs).is_err());
+        assert_eq!(state_db.get_account_balance(&sender_address).unwrap(), 500);
+        assert_eq!(state_db.get_account_nonce(&sender_address).unwrap(), 0);
+        assert_eq!(state_db.get_account_balance(&vec![2; 20]).unwrap(), 0);
+    }
+    
+    #[test]
+    fn test_nonce_must_be_sequential() {
+        let state_db = test_state_db();
+        let sender = KeyPair::generate();
+        let sender_address = address_from_public_key(&sender.public_key());
+        state_db.set_account_balance(&sender_address, 1000).unwrap();
+        
+        let engine = StateTransition::new(&state_db);
+        let txs = vec![transfer(&sender, vec![2; 20], 100, 10, 1)];
+        
+        assert!(engine.execute_block(&block_for(&txs), &txs).is_err());
+    }
+    
+    #[test]
+    fn test_apply_block_checks_header_roots() {
+        let state_db = test_state_db();
+        let sender = KeyPair::generate();
+        let sender_address = address_from_public_key(&sender.public_key());
+        state_db.set_account_balance(&sender_address, 1000).unwrap();
+        
+        let engine = StateTransition::new(&state_db);
+        let txs = vec![transfer(&sender, vec![2; 20], 100, 10, 0)];
+        let mut block = block_for(&txs);
+        
+        // Header without execution results is rejected
+        assert!(engine.apply_block(&block, &txs).is_err());
+        
+        let (result, _) = engine.execute_block(&block, &txs).unwrap();
+        block.header.state_root = result.state_root.clone();
+        block.set_receipts(&result.receipts).unwrap();
+        
+        let (applied, undo) = engine.apply_block(&block, &txs).unwrap();
+        assert_eq!(applied.state_root, result.state_root);
+        
+        // The committed state matches the header
+        let accounts = state_db.get_all_accounts().unwrap();
+        let stored: BTreeMap<Vec<u8>, AccountInfo> = accounts.into_iter().collect();
+        assert_eq!(compute_state_root(&stored), block.header.state_root);
+        
+        // Reverting restores the prior balances
+        undo.revert(&state_db).unwrap();
+        assert_eq!(state_db.get_account_balance(&sender_address).unwrap(), 1000);
+        assert_eq!(state_db.get_account_nonce(&sender_address).unwrap(), 0);
+        assert_eq!(state_db.get_account_balance(&vec![2; 20]).unwrap(), 0);
+    }
+    
+    #[test]
+    fn test_stake_and_unstake() {
+        let state_db = test_state_db();
+        let sender = KeyPair::generate();
+        let sender_address = address_from_public_key(&sender.public_key());
+        state_db.set_account_balance(&sender_address, 1000).unwrap();
+        
+        let engine = StateTransition::new(&state_db);
+        let mut overlay = engine.overlay();
+        
+        let mut stake = transfer(&sender, Vec::new(), 400, 10, 0);
+        stake.transaction_type = TransactionType::Stake;
+        stake.sign(&sender, DEFAULT_CHAIN_ID);
+        engine.execute_transaction(&mut overlay, &stake, 0, None).unwrap();
+        assert_eq!(overlay.get_balance(&sender_address).unwrap(), 590);
+        assert_eq!(overlay.get_balance(&stake_address(&sender_address)).unwrap(), 400);
+        
+        // Cannot unstake more than was staked
+        let mut unstake = transfer(&sender, Vec::new(), 500, 10, 1);
+        unstake.transaction_type = TransactionType::Unstake;
+        unstake.sign(&sender, DEFAULT_CHAIN_ID);
+        assert!(engine.execute_transaction(&mut overlay, &unstake, 0, None).is_err());
+        assert_eq!(overlay.get_nonce(&sender_address).unwrap(), 1);
+        
+        unstake.amount = 400;
+        unstake.sign(&sender, DEFAULT_CHAIN_ID);
+        engine.execute_transaction(&mut overlay, &unstake, 0, None).unwrap();
+        assert_eq!(overlay.get_balance(&sender_address).unwrap(), 980);
+        assert_eq!(overlay.get_balance(&stake_address(&sender_address)).unwrap(), 0);
+    }
+    
+    #[test]
+    fn test_fees_credited_to_producer() {
+        let state_db = test_state_db();
+        let sender = KeyPair::generate();
+        let sender_address = address_from_public_key(&sender.public_key());
+        state_db.set_account_balance(&sender_address, 1000).unwrap();
+        
+        let engine = StateTransition::new(&state_db);
+        let txs = vec![transfer(&sender, vec![2; 20], 100, 25, 0)];
+        let mut block = block_for(&txs);
+        block.add_validator(vec![7; 32]);
+        block.update_merkle_roots();
+        
+        let (_, overlay) = engine.execute_block(&block, &txs).unwrap();
+        let producer = address_from_public_key(&[7; 32]);
+        assert_eq!(overlay.get_balance(&producer).unwrap(), 25);
+    }
+    
+    #[test]
+    fn test_base_fee_is_partly_burned() {
+        let state_db = test_state_db();
+        let sender = KeyPair::generate();
+        let sender_address = address_from_public_key(&sender.public_key());
+        state_db.set_account_balance(&sender_address, 1000).unwrap();
+        
+        let engine = StateTransition::new(&state_db);
+        let mut tx = transfer(&sender, vec![2; 20], 100, 25, 0);
+        tx.priority_fee = 5;
+        tx.sign(&sender, DEFAULT_CHAIN_ID);
+        let txs = vec![tx];
+        let mut block = block_for(&txs);
+        block.header.base_fees = vec![10];
+        block.add_validator(vec![7; 32]);
+        block.update_merkle_roots();
+        
+        // The sender pays the base fee and the tip, not its maximum fee
+        let producer = address_from_public_key(&[7; 32]);
+        let (serial, overlay) = engine.execute_block(&block, &txs).unwrap();
+        assert_eq!((serial.fees_collected, serial.fees_burned), (15, 5));
+        assert_eq!(overlay.get_balance(&sender_address).unwrap(), 885);
+        assert_eq!(overlay.get_balance(&producer).unwrap(), 10);
+        
+        let (parallel, overlay) = engine.execute_block_parallel(&block, &txs).unwrap();
+        assert_eq!(parallel.state_root, serial.state_root);
+        assert_eq!(overlay.get_balance(&producer).unwrap(), 10);
+        
+        // A maximum fee below the base fee cannot be included
+        block.header.base_fees = vec![30];
+        assert!(engine.execute_block(&block, &txs).is_err());
+    }
+    
+    #[test]
+    fn test_batch_applies_all_operations_or_none() {
+        use crate::blockchain::BatchOperation;
+        
+        let state_db = test_state_db();
+        let sender = KeyPair::generate();
+        let sender_address = address_from_public_key(&sender.public_key());
+        state_db.set_account_balance(&sender_address, 10_000).unwrap();
+        state_db.set_account_balance(&vec![4; 20], u64::MAX - 100).unwrap();
+        
+        let engine = StateTransition::new(&state_db);
+        let mut overlay = engine.overlay();
+        
+        // Payroll to three employees in one transaction
+        let payroll = vec![
+            BatchOperation::transfer(vec![2; 20], 0, 1000),
+            BatchOperation::transfer(vec![3; 20], 0, 2000),
+            BatchOperation::stake(500),
+        ];
+        let batch = Transaction::new_batch(sender.public_key(), 0, &payroll, 10, 0).unwrap();
+        let receipt = engine.execute_transaction(&mut overlay, &batch, 0, None).unwrap();
+        assert_eq!(receipt.logs.len(), 3);
+        assert_eq!(overlay.get_balance(&sender_address).unwrap(), 6490);
+        assert_eq!(overlay.get_balance(&vec![2; 20]).unwrap(), 1000);
+        assert_eq!(overlay.get_balance(&vec![3; 20]).unwrap(), 2000);
+        assert_eq!(overlay.get_balance(&stake_address(&sender_address)).unwrap(), 500);
+        assert_eq!(overlay.get_nonce(&sender_address).unwrap(), 1);
+        
+        // The last payment overflows its recipient, so none are made
+        let failing = vec![
+            BatchOperation::transfer(vec![2; 20], 0, 1000),
+            BatchOperation::transfer(vec![4; 20], 0, 1000),
+        ];
+        let batch = Transaction::new_batch(sender.public_key(), 0, &failing, 10, 1).unwrap();
+        assert!(engine.execute_transaction(&mut overlay, &batch, 0, None).is_err());
+        assert_eq!(overlay.get_balance(&sender_address).unwrap(), 6490);
+        assert_eq!(overlay.get_balance(&vec![2; 20]).unwrap(), 1000);
+        assert_eq!(overlay.get_nonce(&sender_address).unwrap(), 1);
+    }
+    
+    #[test]
+    fn test_parallel_execution_matches_serial() {
+        use rand::{Rng, SeedableRng};
+        use rand::rngs::StdRng;
+        
+        let keys: Vec<KeyPair> = (1..=6).map(|seed| KeyPair::from_seed(&[seed; 32]).unwrap()).collect();
+        let addresses: Vec<Vec<u8>> = keys.iter().map(|key| address_from_public_key(&key.public_key())).collect();
+        
+        let mut executed = 0;
+        for seed in 0..100u64 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let state_db = test_state_db();
+            for address in &addresses {
+                state_db.set_account_balance(address, rng.gen_range(0..5_000)).unwrap();
+            }
+            
+            // Random transactions among a few accounts, so that many conflict
+            let mut nonces = vec![0u64; keys.len()];
+            let mut staked = vec![0u64; keys.len()];
+            let mut txs = Vec::new();
+            for _ in 0..rng.gen_range(1..40u8) {
+                let sender = rng.gen_range(0..keys.len());
+                let recipient = if rng.gen_bool(0.5) {
+                    addresses[rng.gen_range(0..keys.len())].clone()
+                } else {
+                    vec![rng.gen_range(0..4u8); 20]
+                };
+                let mut tx = transfer(&keys[sender], recipient, rng.gen_range(0..400), rng.gen_range(0..20), nonces[sender]);
+                match rng.gen_range(0..10) {
+                    0 => {
+                        tx.transaction_type = TransactionType::Stake;
+                        staked[sender] += tx.amount;
+                    },
+                    1 => {
+                        tx.transaction_type = TransactionType::Unstake;
+                        tx.amount = rng.gen_range(0..=staked[sender]);
+                        staked[sender] -= tx.amount;
+                    },
+                    2 => tx.transaction_type = TransactionType::ContractCall,
+                    _ => {},
+                }
+                tx.parallel_markers.parallelizable = rng.gen_range(0..20) != 0;
+                tx.sign(&keys[sender], DEFAULT_CHAIN_ID);
+                nonces[sender] += 1;
+                txs.push(tx);
+            }
+            
+            let mut block = block_for(&txs);
+            match rng.gen_range(0..3) {
+                0 => {},
+                1 => block.add_validator(vec![7; 32]),
+                // The producer also sends and receives in the block
+                _ => block.add_validator(keys[0].public_key()),
+            }
+            block.update_merkle_roots();
+            
+            let engine = StateTransition::new(&state_db);
+            match (engine.execute_block(&block, &txs), engine.execute_block_parallel(&block, &txs)) {
+                (Ok((serial, serial_overlay)), Ok((parallel, parallel_overlay))) => {
+                    assert_eq!(format!("{:?}", serial), format!("{:?}", parallel), "seed {}", seed);
+                    assert_eq!(serial_overlay.changes(), parallel_overlay.changes(), "seed {}", seed);
+                    executed += 1;
+                },
+                (Err(serial), Err(parallel)) => assert_eq!(serial.to_string(), parallel.to_string(), "seed {}", seed),
+                (serial, parallel) => panic!("seed {}: serial ok {}, parallel ok {}", seed, serial.is_ok(), parallel.is_ok()),
+            }
+        }
+        
+        // Enough blocks executed cleanly for the comparison to mean something
+        assert!(executed >= 20, "only {} blocks executed", executed);
+    }
+}
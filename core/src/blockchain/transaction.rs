@@ -2,11 +2,17 @@
 //! 
 //! This module defines the transaction data structure and related functionality.
 
-use crate::crypto::signature::Signature;
-use crate::types::{Result, ShardId, Timestamp, TransactionType, Priority, DataType};
+use crate::crypto::signature::{self, KeyPair, Signature};
+use crate::types::{Result, Error, BlockHeight, ShardId, Timestamp, TransactionType, Priority, DataType, DEFAULT_CHAIN_ID};
 use serde::{Serialize, Deserialize};
 use crate::crypto::hash;
 
+/// Domain tag prefixed to every transaction signing payload
+pub const SIGNING_DOMAIN: &[u8] = b"SEBURE_TX_V1";
+
+/// Maximum number of operations in a batch transaction
+pub const MAX_BATCH_OPERATIONS: usize = 1000;
+
 /// Transaction represents a transfer of value or execution of logic
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transaction {
@@ -16,6 +22,9 @@ pub struct Transaction {
     /// Transaction format version
     pub version: u8,
     
+    /// Identifier of the chain the transaction is valid on
+    pub chain_id: String,
+    
     /// Type of transaction
     pub transaction_type: TransactionType,
     
@@ -34,9 +43,13 @@ pub struct Transaction {
     /// Amount to transfer
     pub amount: u64,
     
-    /// Transaction fee
+    /// Maximum fee the sender pays, covering the base fee of the block
+    /// and the priority fee
     pub fee: u32,
     
+    /// Maximum tip for the block producer on top of the base fee
+    pub priority_fee: u32,
+    
     /// Gas limit for smart contracts
     pub gas_limit: u32,
     
@@ -52,6 +65,12 @@ pub struct Transaction {
     /// Transaction dependencies with type information
     pub dependencies: Vec<Dependency>,
     
+    /// Earliest block the transaction may be included in
+    pub valid_after: Option<TimeBound>,
+    
+    /// Latest block the transaction may be included in
+    pub valid_until: Option<TimeBound>,
+    
     /// Signature of the transaction
     pub signature: Signature,
     
@@ -81,6 +100,75 @@ pub struct Dependency {
     pub required_state: Option<Vec<u8>>,
 }
 
+impl Dependency {
+    /// Canonical encoding of the dependency for the signing payload
+    fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.transaction_id.len() + 6);
+        data.push(match self.dependency_type {
+            DependencyType::Hard => 0,
+            DependencyType::Soft => 1,
+            DependencyType::State => 2,
+        });
+        data.extend_from_slice(&(self.transaction_id.len() as u32).to_be_bytes());
+        data.extend_from_slice(&self.transaction_id);
+        if let Some(ref state) = self.required_state {
+            data.push(1);
+            data.extend_from_slice(state);
+        } else {
+            data.push(0);
+        }
+        data
+    }
+}
+
+/// Block height or time bounding when a transaction may be included
+///
+/// Bounds are inclusive: a block at exactly the bound satisfies both
+/// `valid_after` and `valid_until`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimeBound {
+    /// Block height
+    Height(BlockHeight),
+    
+    /// Block timestamp in microseconds
+    Timestamp(Timestamp),
+}
+
+impl TimeBound {
+    /// Check if a block at `height` with `timestamp` is at or past the bound
+    pub fn reached(&self, height: BlockHeight, timestamp: Timestamp) -> bool {
+        match *self {
+            TimeBound::Height(bound) => height >= bound,
+            TimeBound::Timestamp(bound) => timestamp >= bound,
+        }
+    }
+    
+    /// Check if a block at `height` with `timestamp` is past the bound
+    pub fn passed(&self, height: BlockHeight, timestamp: Timestamp) -> bool {
+        match *self {
+            TimeBound::Height(bound) => height > bound,
+            TimeBound::Timestamp(bound) => timestamp > bound,
+        }
+    }
+    
+    /// Canonical encoding of an optional bound for the signing payload
+    fn encode(bound: &Option<TimeBound>) -> Vec<u8> {
+        let mut data = Vec::with_capacity(9);
+        match *bound {
+            None => data.push(0),
+            Some(TimeBound::Height(height)) => {
+                data.push(1);
+                data.extend_from_slice(&height.to_be_bytes());
+            },
+            Some(TimeBound::Timestamp(timestamp)) => {
+                data.push(2);
+                data.extend_from_slice(&timestamp.to_be_bytes());
+            },
+        }
+        data
+    }
+}
+
 /// Dependency type classification
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum DependencyType {
@@ -131,6 +219,47 @@ pub struct BatchInfo {
     pub batch_size: u32,
 }
 
+/// Operation carried by a batch transaction
+///
+/// Operations are applied in order with the batch sender as their sender,
+/// and either all of them take effect or none do.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchOperation {
+    /// Type of the operation, either a transfer or a stake
+    pub transaction_type: TransactionType,
+    
+    /// Recipient address, empty for stakes
+    pub recipient_address: Vec<u8>,
+    
+    /// Recipient shard ID
+    pub recipient_shard: ShardId,
+    
+    /// Amount moved by the operation
+    pub amount: u64,
+}
+
+impl BatchOperation {
+    /// Create a transfer operation
+    pub fn transfer(recipient_address: Vec<u8>, recipient_shard: ShardId, amount: u64) -> Self {
+        BatchOperation {
+            transaction_type: TransactionType::Transfer,
+            recipient_address,
+            recipient_shard,
+            amount,
+        }
+    }
+    
+    /// Create a stake operation
+    pub fn stake(amount: u64) -> Self {
+        BatchOperation {
+            transaction_type: TransactionType::Stake,
+            recipient_address: Vec::new(),
+            recipient_shard: 0,
+            amount,
+        }
+    }
+}
+
 /// Transaction data
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TransactionData {
@@ -151,6 +280,17 @@ impl TransactionData {
     pub fn content(&self) -> &Vec<u8> {
         &self.content
     }
+    
+    /// Encode the operations of a batch transaction
+    pub fn batch(operations: &[BatchOperation]) -> Result<Self> {
+        let content = bincode::serialize(operations)
+            .map_err(|e| Error::Serialization(e.to_string()))?;
+        
+        Ok(TransactionData {
+            data_type: DataType::Batch,
+            content,
+        })
+    }
 }
 
 impl Default for TransactionData {
@@ -196,6 +336,9 @@ pub struct Log {
 
 impl Transaction {
     /// Create a new basic transaction
+    ///
+    /// The priority fee starts out equal to the fee, so that whatever the
+    /// base fee leaves over goes to the block producer.
     pub fn new(
         sender_public_key: Vec<u8>,
         sender_shard: ShardId,
@@ -215,9 +358,6 @@ impl Transaction {
             .unwrap()
             .as_micros() as u64;
         
-        // Calculate transaction ID based on the transaction data
-        let id = hash::sha256(&[0; 32]); // We'll use a placeholder hash for now
-        
         // Convert simple dependencies to Dependency structs
         let dependencies = dependencies.into_iter().map(|dep_id| Dependency {
             transaction_id: dep_id,
@@ -225,9 +365,10 @@ impl Transaction {
             required_state: None,
         }).collect();
         
-        Transaction {
-            id: id.to_vec(),
+        let mut tx = Transaction {
+            id: Vec::new(),
             version: 1, // Set default version
+            chain_id: DEFAULT_CHAIN_ID.to_string(),
             transaction_type,
             sender_public_key,
             sender_shard,
@@ -235,11 +376,14 @@ impl Transaction {
             recipient_shard,
             amount,
             fee,
+            priority_fee: fee,
             gas_limit,
             nonce,
             timestamp,
             data,
             dependencies,
+            valid_after: None,
+            valid_until: None,
             signature,
             execution_priority: Priority::Normal,
             optimistic_status: OptimisticStatus::Pending,
@@ -249,7 +393,9 @@ impl Transaction {
                 phase: 0,
             },
             batch_info: None,
-        }
+        };
+        tx.id = tx.compute_id();
+        tx
     }
     
     /// Create a new transfer transaction
@@ -281,29 +427,215 @@ impl Transaction {
         )
     }
     
-    /// Verify transaction signature
-    pub fn verify_signature(&self) -> Result<()> {
-        // In a real implementation, we would:
-        // 1. Serialize the transaction data (except signature)
-        // 2. Verify the signature against the sender's public key
+    /// Create a new batch transaction
+    ///
+    /// The batch amount is the total of the operations, and the gas limit
+    /// covers a transfer per operation.
+    pub fn new_batch(
+        sender_public_key: Vec<u8>,
+        sender_shard: ShardId,
+        operations: &[BatchOperation],
+        fee: u32,
+        nonce: u64,
+    ) -> Result<Self> {
+        let amount = operations.iter()
+            .try_fold(0u64, |total, operation| total.checked_add(operation.amount))
+            .ok_or_else(|| Error::TransactionValidation("Batch amount overflows".to_string()))?;
+        
+        // Left empty; the caller must call `sign` before submitting the batch
+        let signature = Signature::new(vec![0; 64]);
+        
+        Ok(Self::new(
+            sender_public_key,
+            sender_shard,
+            Vec::new(), // Recipients are named by the operations
+            sender_shard,
+            amount,
+            fee,
+            21000u32.saturating_mul(operations.len() as u32),
+            nonce,
+            TransactionType::Batch,
+            TransactionData::batch(operations)?,
+            Vec::new(), // No dependencies
+            signature,
+        ))
+    }
+    
+    /// Canonical payload signed by the sender
+    ///
+    /// Only fields fixed at creation are covered, hashed with
+    /// `crypto::hash_transaction` and followed by the priority fee and the
+    /// time bounds. Scheduling state such as the execution priority and
+    /// optimistic status is excluded, and the chain id is prefixed so a
+    /// signature is only valid on one network.
+    pub fn signing_payload(&self) -> Vec<u8> {
+        let dependencies: Vec<Vec<u8>> = self.dependencies.iter()
+            .map(Dependency::encode)
+            .collect();
+        let body = hash::hash_transaction(
+            self.version,
+            self.transaction_type as u8,
+            &self.sender_public_key,
+            self.sender_shard,
+            &self.recipient_address,
+            self.recipient_shard,
+            self.amount,
+            self.fee,
+            self.gas_limit,
+            self.nonce,
+            self.timestamp,
+            self.data.data_type as u8,
+            &self.data.content,
+            &dependencies,
+        );
         
-        // For now, just return success
-        Ok(())
+        let mut payload = Vec::with_capacity(SIGNING_DOMAIN.len() + 8 + self.chain_id.len() + body.len());
+        payload.extend_from_slice(SIGNING_DOMAIN);
+        payload.extend_from_slice(&(self.chain_id.len() as u32).to_be_bytes());
+        payload.extend_from_slice(self.chain_id.as_bytes());
+        payload.extend_from_slice(&body);
+        payload.extend_from_slice(&self.priority_fee.to_be_bytes());
+        payload.extend_from_slice(&TimeBound::encode(&self.valid_after));
+        payload.extend_from_slice(&TimeBound::encode(&self.valid_until));
+        payload
+    }
+    
+    /// ID of the transaction: the hash of its signing payload
+    ///
+    /// Two transactions share an ID only if they sign the same content.
+    pub fn compute_id(&self) -> Vec<u8> {
+        hash::sha256(&self.signing_payload()).to_vec()
     }
     
-    /// Check if the transaction is valid
-    pub fn is_valid(&self) -> Result<()> {
-        // In a real implementation, we would:
+    /// Bind the transaction to the given chain, derive its ID and sign it
+    pub fn sign(&mut self, keypair: &KeyPair, chain_id: &str) {
+        self.chain_id = chain_id.to_string();
+        self.id = self.compute_id();
+        self.signature = keypair.sign(&self.signing_payload());
+    }
+    
+    /// Verify that the transaction is for the given chain and signed by its sender
+    pub fn verify_signature(&self, chain_id: &str) -> Result<()> {
+        if self.chain_id != chain_id {
+            return Err(Error::TransactionValidation(format!(
+                "Transaction {} is for chain {}, expected {}", hex::encode(&self.id), self.chain_id, chain_id
+            )));
+        }
+        
+        signature::verify(&self.sender_public_key, &self.signing_payload(), &self.signature)
+            .map_err(|e| Error::TransactionValidation(format!(
+                "Invalid signature on transaction {}: {}", hex::encode(&self.id), e
+            )))
+    }
+    
+    /// Check if the transaction is valid on the given chain
+    pub fn is_valid(&self, chain_id: &str) -> Result<()> {
+        // In a real implementation, we would also:
         // 1. Check transaction format and version
-        // 2. Verify signature
-        // 3. Check nonce, fee, gas limit, etc.
+        // 2. Check nonce, fee, gas limit, etc.
+        
+        if self.id != self.compute_id() {
+            return Err(Error::TransactionValidation(format!(
+                "Transaction ID {} does not match its contents", hex::encode(&self.id)
+            )));
+        }
+        self.verify_signature(chain_id)?;
+        
+        // A batch is checked as a unit, so one malformed operation rejects it
+        if self.transaction_type == TransactionType::Batch {
+            self.batch_operations()?;
+        }
+        
+        Ok(())
+    }
+    
+    /// Decode and check the operations of a batch transaction
+    ///
+    /// The batch must hold between one and `MAX_BATCH_OPERATIONS` transfers
+    /// within the sender's shard and stakes, and its amount must be their
+    /// total.
+    pub fn batch_operations(&self) -> Result<Vec<BatchOperation>> {
+        let invalid = |reason: String| Error::TransactionValidation(format!(
+            "Invalid batch {}: {}", hex::encode(&self.id), reason
+        ));
+        
+        if self.transaction_type != TransactionType::Batch || self.data.data_type != DataType::Batch {
+            return Err(invalid("not a batch transaction".to_string()));
+        }
+        
+        let operations: Vec<BatchOperation> = bincode::deserialize(&self.data.content)
+            .map_err(|e| invalid(format!("unreadable operations: {}", e)))?;
+        if operations.is_empty() || operations.len() > MAX_BATCH_OPERATIONS {
+            return Err(invalid(format!(
+                "{} operations, expected 1 to {}", operations.len(), MAX_BATCH_OPERATIONS
+            )));
+        }
+        
+        let mut total: u64 = 0;
+        for (position, operation) in operations.iter().enumerate() {
+            match operation.transaction_type {
+                TransactionType::Transfer if operation.recipient_address.is_empty() => {
+                    return Err(invalid(format!("transfer {} has no recipient", position)));
+                },
+                TransactionType::Transfer if operation.recipient_shard != self.sender_shard => {
+                    // Cross-shard transfers settle later and could not be undone with the batch
+                    return Err(invalid(format!("transfer {} leaves the sender's shard", position)));
+                },
+                TransactionType::Transfer | TransactionType::Stake => {},
+                other => {
+                    return Err(invalid(format!("operation {} has unsupported type {:?}", position, other)));
+                },
+            }
+            total = total.checked_add(operation.amount)
+                .ok_or_else(|| invalid("total amount overflows".to_string()))?;
+        }
+        
+        if total != self.amount {
+            return Err(invalid(format!("amount {} does not match operation total {}", self.amount, total)));
+        }
+        
+        Ok(operations)
+    }
+    
+    /// Check if the transaction moves value to an account on another shard
+    pub fn is_cross_shard(&self) -> bool {
+        self.transaction_type == TransactionType::Transfer && self.sender_shard != self.recipient_shard
+    }
+    
+    /// Check if a block at `height` with `timestamp` may include the transaction
+    pub fn check_time_bounds(&self, height: BlockHeight, timestamp: Timestamp) -> Result<()> {
+        if let Some(bound) = self.valid_after {
+            if !bound.reached(height, timestamp) {
+                return Err(Error::TransactionValidation(format!(
+                    "Transaction {} is not valid before {:?}", hex::encode(&self.id), bound
+                )));
+            }
+        }
         
-        self.verify_signature()?;
+        if let Some(bound) = self.valid_until {
+            if bound.passed(height, timestamp) {
+                return Err(Error::TransactionValidation(format!(
+                    "Transaction {} expired at {:?}", hex::encode(&self.id), bound
+                )));
+            }
+        }
         
-        // For now, just return success
         Ok(())
     }
     
+    /// Check if no block after one at `height` with `timestamp` may include
+    /// the transaction
+    ///
+    /// Later blocks have greater heights and timestamps, so an expired
+    /// transaction stays expired on the same chain.
+    pub fn is_expired_after(&self, height: BlockHeight, timestamp: Timestamp) -> bool {
+        match self.valid_until {
+            Some(TimeBound::Height(bound)) => height >= bound,
+            Some(TimeBound::Timestamp(bound)) => timestamp >= bound,
+            None => false,
+        }
+    }
+    
     /// Get the transaction ID
     pub fn id(&self) -> &Vec<u8> {
         &self.id
@@ -344,6 +676,11 @@ impl Transaction {
         self.fee
     }
     
+    /// Get the maximum priority fee
+    pub fn priority_fee(&self) -> u32 {
+        self.priority_fee
+    }
+    
     /// Get the transaction nonce
     pub fn nonce(&self) -> u64 {
         self.nonce
@@ -359,6 +696,13 @@ impl Transaction {
         &self.data
     }
     
+    /// Size of the transaction in bytes when encoded
+    pub fn encoded_size(&self) -> Result<usize> {
+        bincode::serialized_size(self)
+            .map(|size| size as usize)
+            .map_err(|e| Error::Serialization(e.to_string()))
+    }
+    
     /// Get the estimated gas cost
     pub fn estimate_gas(&self) -> u32 {
         // In a real implementation, this would depend on the transaction type and data
@@ -366,6 +710,10 @@ impl Transaction {
             TransactionType::Transfer => 21000,
             TransactionType::ContractDeploy => 100000,
             TransactionType::ContractCall => 50000,
+            TransactionType::Batch => {
+                let operations = self.batch_operations().map(|ops| ops.len()).unwrap_or(1);
+                21000u32.saturating_mul(operations as u32)
+            },
             _ => 21000,
         }
     }
@@ -507,11 +855,11 @@ mod tests {
     
     #[test]
     fn test_transaction_verification() {
-        let sender_public_key = vec![1; 32];
+        let keypair = KeyPair::generate();
         let recipient_address = vec![2; 20];
         
-        let tx = Transaction::new_transfer(
-            sender_public_key,
+        let mut tx = Transaction::new_transfer(
+            keypair.public_key(),
             0,
             recipient_address,
             1,
@@ -520,8 +868,57 @@ mod tests {
             0,
         );
         
-        assert!(tx.verify_signature().is_ok());
-        assert!(tx.is_valid().is_ok());
+        // Unsigned transactions are rejected
+        assert!(tx.verify_signature(DEFAULT_CHAIN_ID).is_err());
+        
+        tx.sign(&keypair, DEFAULT_CHAIN_ID);
+        assert!(tx.verify_signature(DEFAULT_CHAIN_ID).is_ok());
+        assert!(tx.is_valid(DEFAULT_CHAIN_ID).is_ok());
+        
+        // The signature does not carry over to another chain
+        assert!(tx.verify_signature("sebure-devnet").is_err());
+        
+        let mut replayed = tx.clone();
+        replayed.chain_id = "sebure-devnet".to_string();
+        assert!(replayed.verify_signature("sebure-devnet").is_err());
+        
+        // The ID is derived from the signed content and cannot be chosen
+        assert_eq!(tx.id, tx.compute_id());
+        let mut renamed = tx.clone();
+        renamed.id = vec![1; 32];
+        assert!(renamed.verify_signature(DEFAULT_CHAIN_ID).is_ok());
+        assert!(renamed.is_valid(DEFAULT_CHAIN_ID).is_err());
+    }
+    
+    #[test]
+    fn test_signing_payload_covers_immutable_fields() {
+        let keypair = KeyPair::generate();
+        let mut tx = Transaction::new_transfer(keypair.public_key(), 0, vec![2; 20], 1, 1000, 10, 0);
+        tx.sign(&keypair, DEFAULT_CHAIN_ID);
+        
+        // Scheduling state can change without invalidating the signature
+        let mut scheduled = tx.clone();
+        scheduled.execution_priority = Priority::High;
+        scheduled.optimistic_status = OptimisticStatus::Executed;
+        scheduled.parallel_markers.group_id = Some(7);
+        assert!(scheduled.verify_signature(DEFAULT_CHAIN_ID).is_ok());
+        
+        // Tampering with signed fields breaks it
+        let mut tampered = tx.clone();
+        tampered.amount += 1;
+        assert!(tampered.verify_signature(DEFAULT_CHAIN_ID).is_err());
+        
+        let mut tampered = tx.clone();
+        tampered.priority_fee -= 1;
+        assert!(tampered.verify_signature(DEFAULT_CHAIN_ID).is_err());
+        
+        let mut tampered = tx.clone();
+        tampered.dependencies.push(Dependency {
+            transaction_id: vec![9; 32],
+            dependency_type: DependencyType::Soft,
+            required_state: None,
+        });
+        assert!(tampered.verify_signature(DEFAULT_CHAIN_ID).is_err());
     }
     
     #[test]
@@ -670,4 +1067,73 @@ mod tests {
         assert!(tx.should_expedite());
         assert!(matches!(tx.get_priority(), Priority::High));
     }
+    
+    #[test]
+    fn test_batch_validation() {
+        let keypair = KeyPair::generate();
+        let operations = vec![
+            BatchOperation::transfer(vec![2; 20], 0, 300),
+            BatchOperation::transfer(vec![3; 20], 0, 200),
+            BatchOperation::stake(500),
+        ];
+        
+        let mut batch = Transaction::new_batch(keypair.public_key(), 0, &operations, 10, 0).unwrap();
+        assert_eq!(batch.amount, 1000);
+        assert_eq!(batch.estimate_gas(), 63000);
+        batch.sign(&keypair, DEFAULT_CHAIN_ID);
+        assert!(batch.is_valid(DEFAULT_CHAIN_ID).is_ok());
+        assert_eq!(batch.batch_operations().unwrap(), operations);
+        
+        // Every operation is checked before any of them is accepted
+        let mut malformed = operations.clone();
+        malformed.push(BatchOperation::transfer(Vec::new(), 0, 1));
+        let mut batch = Transaction::new_batch(keypair.public_key(), 0, &malformed, 10, 0).unwrap();
+        batch.sign(&keypair, DEFAULT_CHAIN_ID);
+        assert!(batch.is_valid(DEFAULT_CHAIN_ID).is_err());
+        
+        let mut cross_shard = operations.clone();
+        cross_shard[1].recipient_shard = 1;
+        let batch = Transaction::new_batch(keypair.public_key(), 0, &cross_shard, 10, 0).unwrap();
+        assert!(batch.batch_operations().is_err());
+        
+        let mut nested = operations.clone();
+        nested[0].transaction_type = TransactionType::Batch;
+        let batch = Transaction::new_batch(keypair.public_key(), 0, &nested, 10, 0).unwrap();
+        assert!(batch.batch_operations().is_err());
+        
+        // The amount must match the operations
+        let mut batch = Transaction::new_batch(keypair.public_key(), 0, &operations, 10, 0).unwrap();
+        batch.amount = 999;
+        batch.sign(&keypair, DEFAULT_CHAIN_ID);
+        assert!(batch.is_valid(DEFAULT_CHAIN_ID).is_err());
+        
+        assert!(Transaction::new_batch(keypair.public_key(), 0, &[], 10, 0).unwrap().batch_operations().is_err());
+    }
+    
+    #[test]
+    fn test_time_bounds() {
+        let keypair = KeyPair::generate();
+        let mut tx = Transaction::new_transfer(keypair.public_key(), 0, vec![2; 20], 0, 1000, 10, 0);
+        tx.valid_after = Some(TimeBound::Height(10));
+        tx.valid_until = Some(TimeBound::Timestamp(5_000));
+        tx.sign(&keypair, DEFAULT_CHAIN_ID);
+        
+        // Both bounds are inclusive
+        assert!(tx.check_time_bounds(9, 1_000).is_err());
+        assert!(tx.check_time_bounds(10, 1_000).is_ok());
+        assert!(tx.check_time_bounds(20, 5_000).is_ok());
+        assert!(tx.check_time_bounds(20, 5_001).is_err());
+        
+        assert!(!tx.is_expired_after(20, 4_999));
+        assert!(tx.is_expired_after(20, 5_000));
+        
+        // The bounds are covered by the signature
+        let mut extended = tx.clone();
+        extended.valid_until = Some(TimeBound::Timestamp(6_000));
+        assert!(extended.verify_signature(DEFAULT_CHAIN_ID).is_err());
+        
+        let mut unlocked = tx.clone();
+        unlocked.valid_after = None;
+        assert!(unlocked.verify_signature(DEFAULT_CHAIN_ID).is_err());
+    }
 }
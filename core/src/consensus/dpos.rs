@@ -2,7 +2,7 @@
 //! 
 //! This module implements the DPoS consensus mechanism for the SEBURE blockchain.
 
-use crate::blockchain::{Block, ShardData};
+use crate::blockchain::{Block, Blockchain, ReorgEvent, ShardData, ShardLayout};
 use crate::types::{Result, Error, BlockHeight, ShardId, Timestamp};
 use super::{Consensus, ConsensusConfig, ConsensusState, Validator, ValidatorPool, Shard, ValidatorId};
 use std::sync::{Arc, Mutex};
@@ -80,6 +80,47 @@ impl DPoSConsensus {
         }
     }
     
+    /// Create a DPoS consensus instance starting from a known validator pool
+    pub fn with_validators(config: ConsensusConfig, validators: ValidatorPool) -> Self {
+        let consensus = Self::new(config);
+        consensus.state.lock().unwrap().validators = validators;
+        consensus
+    }
+    
+    /// Set the shard layout the chain is currently under, as kept by the
+    /// blockchain's shard manager
+    pub fn set_shard_layout(&self, layout: ShardLayout) {
+        self.state.lock().unwrap().shard_layout = layout;
+    }
+    
+    /// Rewind to `head` after a reorganization replaced the blocks above
+    /// it, so that the new branch can be processed on top of it
+    ///
+    /// Block parameters committed above `head` no longer apply. The shard
+    /// layout is set separately, from the blockchain's shard manager.
+    pub fn revert_to(&self, head: &Block) {
+        let height = head.header.index;
+        {
+            let mut state = self.state.lock().unwrap();
+            state.height = height;
+            state.epoch = state.get_epoch_for_height(height, self.config.blocks_per_epoch);
+            state.last_block_time = head.header.timestamp;
+            state.block_parameters.split_off(&(height + 1));
+        }
+        self.block_history.lock().unwrap().retain(|&block_height, _| block_height <= height);
+    }
+    
+    /// Follow a reorganization of `blockchain`, rewinding to the common
+    /// ancestor and processing the blocks of the new branch
+    pub fn apply_reorg(&mut self, event: &ReorgEvent, blockchain: &Blockchain) -> Result<()> {
+        self.revert_to(&blockchain.get_block_by_hash(&event.common_ancestor)?);
+        for hash in &event.applied {
+            self.process_block(blockchain.get_block_by_hash(hash)?)?;
+        }
+        self.set_shard_layout(blockchain.shard_manager().lock().unwrap().layout().clone());
+        Ok(())
+    }
+    
     /// Set the local node's public key
     pub fn set_local_public_key(&mut self, public_key: Vec<u8>) {
         self.local_public_key = Some(public_key);
@@ -99,7 +140,8 @@ impl DPoSConsensus {
         let elapsed = now - last_block_time;
         
         // Convert block interval from milliseconds to microseconds
-        let interval_micros = self.config.block_interval_ms as u64 * 1000;
+        let interval_ms = self.state.lock().unwrap().block_interval_ms(self.config.block_interval_ms);
+        let interval_micros = interval_ms * 1000;
         
         elapsed >= interval_micros
     }
@@ -192,12 +234,28 @@ impl DPoSConsensus {
         state.height = block.header.index;
         state.last_block_time = block.header.timestamp;
         
+        // Follow the block parameters committed for the new epoch
+        if let Some(parameters) = block.header.parameters {
+            state.block_parameters.insert(block.header.index, parameters);
+        }
+        if let Some(finalized) = block.header.index.checked_sub(self.config.finality_confirmations) {
+            state.prune_block_parameters(finalized);
+        }
+        
+        // Follow the shard layout committed for the new resharding epoch
+        if let Some(layout) = &block.header.shard_layout {
+            state.shard_layout = layout.clone();
+        }
+        
         // Check if this is the beginning of a new epoch
-        if state.is_epoch_start(block.header.index, self.config.blocks_per_epoch) {
+        let is_epoch_start = state.is_epoch_start(block.header.index, self.config.blocks_per_epoch);
+        if is_epoch_start {
             state.epoch = state.get_epoch_for_height(block.header.index, self.config.blocks_per_epoch);
-            
+        }
+        drop(state); // Release lock before updating validators, history and rewards
+        
+        if is_epoch_start {
             // Update validators for the new epoch if needed
-            drop(state); // Release lock before calling update_validators
             self.update_validators()?;
             
             // Generate block schedule for the next epoch
@@ -253,6 +311,13 @@ impl DPoSConsensus {
 
     /// Get scheduled validator for a specific height and shard
     pub fn get_scheduled_validator(&self, height: BlockHeight, shard: ShardId) -> Option<Vec<u8>> {
+        let state = self.state.lock().unwrap();
+        self.scheduled_validator(&state, height, shard)
+    }
+    
+    /// Get scheduled validator for a specific height and shard, with the
+    /// consensus state already locked
+    fn scheduled_validator(&self, state: &ConsensusState, height: BlockHeight, shard: ShardId) -> Option<Vec<u8>> {
         // First check block schedule
         let block_schedule = self.block_schedule.lock().unwrap();
         if let Some(height_schedule) = block_schedule.get(&height) {
@@ -262,7 +327,6 @@ impl DPoSConsensus {
         }
         
         // If not found in schedule, use the validator selection algorithm
-        let state = self.state.lock().unwrap();
         state.validators.select_validator_for_block(height, shard)
             .map(|v| v.public_key.clone())
     }
@@ -401,6 +465,9 @@ impl Consensus for DPoSConsensus {
         // Add shard data to the block
         block.add_shard_data(shard_data)?;
         
+        // Commit to the transactions and validator set in the header
+        block.update_merkle_roots();
+        
         // In a real implementation, we would sign the block here
         
         Ok(block)
@@ -428,7 +495,7 @@ impl Consensus for DPoSConsensus {
         
         // Check that the block interval is valid
         let min_timestamp = state.last_block_time + 
-                           (self.config.block_interval_ms as u64 * 1000) - 
+                           (state.block_interval_ms(self.config.block_interval_ms) * 1000) - 
                            1_000_000; // Allow 1 second tolerance
         if block.header.timestamp < min_timestamp {
             return Err(Error::BlockValidation(format!(
@@ -440,7 +507,7 @@ impl Consensus for DPoSConsensus {
         // Verify the producer is scheduled for this block
         for shard_id in &block.header.shard_identifiers {
             // Check if the block producer is scheduled
-            let scheduled_producer = match self.get_scheduled_validator(block.header.index, *shard_id) {
+            let scheduled_producer = match self.scheduled_validator(&state, block.header.index, *shard_id) {
                 Some(pubkey) => pubkey,
                 None => {
                     return Err(Error::BlockValidation(format!(
@@ -482,11 +549,13 @@ impl Consensus for DPoSConsensus {
             // In a real implementation, we would validate each transaction
         }
         
+        // Verify the transaction and validator roots
+        block.verify_merkle_roots()?;
+        
         // Verify block state roots
         // In a real implementation, we would:
         // 1. Verify state root matches computed state after applying transactions
-        // 2. Verify transaction root matches the merkle root of all transactions
-        // 3. Verify receipt root matches the merkle root of all receipts
+        // 2. Verify receipt root matches the merkle root of all receipts
         
         Ok(())
     }
@@ -565,6 +634,7 @@ impl Consensus for DPoSConsensus {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::blockchain::BlockParameters;
     use crate::consensus::validator::Validator;
     
     fn create_test_validator(id: u8, stake: u64) -> Validator {
@@ -710,6 +780,11 @@ mod tests {
         );
         
         assert!(consensus.validate_block(&invalid_timestamp_block).is_err());
+        
+        // Test mismatched transaction root
+        let mut invalid_root_block = block.clone();
+        invalid_root_block.header.transaction_root = vec![1; 32];
+        assert!(consensus.validate_block(&invalid_root_block).is_err());
     }
     
     #[test]
@@ -817,10 +892,11 @@ mod tests {
     fn test_process_block() {
         let mut consensus = setup_consensus_with_validators();
         
-        // Create a block to process
+        // Create a block to process, one block interval after initialization
+        let timestamp = consensus.state.lock().unwrap().last_block_time + consensus.config.block_interval_ms * 1000;
         let block = Block::new(
             1, // Height 1
-            DPoSConsensus::current_time_micros(),
+            timestamp,
             vec![0; 32],
             vec![0],
         );
@@ -838,4 +914,38 @@ mod tests {
         let history = consensus.block_history.lock().unwrap();
         assert!(history.contains_key(&1));
     }
+    
+    #[test]
+    fn test_process_block_follows_committed_shard_layout() {
+        let mut consensus = setup_consensus_with_validators();
+        consensus.set_shard_layout(ShardLayout::uniform(1));
+        
+        let timestamp = consensus.state.lock().unwrap().last_block_time + consensus.config.block_interval_ms * 1000;
+        let mut block = Block::new(1, timestamp, vec![0; 32], vec![0]);
+        block.header.shard_layout = Some(ShardLayout::uniform(2));
+        consensus.process_block(block).unwrap();
+        
+        assert_eq!(consensus.state.lock().unwrap().shard_layout, ShardLayout::uniform(2));
+    }
+    
+    #[test]
+    fn test_revert_drops_block_parameters_of_the_replaced_branch() {
+        let mut consensus = setup_consensus_with_validators();
+        let configured = consensus.config.block_interval_ms;
+        
+        let timestamp = consensus.state.lock().unwrap().last_block_time + configured * 1000;
+        let head = Block::new(1, timestamp, vec![0; 32], vec![0]);
+        consensus.process_block(head.clone()).unwrap();
+        
+        let mut block = Block::new(2, head.header.timestamp + configured * 1000, vec![0; 32], vec![0]);
+        block.header.parameters = Some(BlockParameters { max_block_size: 1024, block_interval_ms: configured * 2 });
+        consensus.process_block(block).unwrap();
+        assert_eq!(consensus.state.lock().unwrap().block_interval_ms(configured), configured * 2);
+        
+        consensus.revert_to(&head);
+        let state = consensus.state.lock().unwrap();
+        assert_eq!((state.height, state.last_block_time), (1, head.header.timestamp));
+        assert_eq!(state.block_interval_ms(configured), configured);
+        assert!(!consensus.block_history.lock().unwrap().contains_key(&2));
+    }
 }
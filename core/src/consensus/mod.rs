@@ -11,12 +11,15 @@ pub use validator::Validator;
 pub use validator::ValidatorPool;
 pub use dpos::DPoSConsensus;
 
-use crate::blockchain::Block;
+use crate::blockchain::{Block, BlockParameters, ShardLayout};
 use crate::types::{Result, BlockHeight, ShardId};
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
 use std::sync::{Arc, Mutex};
 
 /// Consensus configuration
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ConsensusConfig {
     /// Number of validators per pool
     pub validators_per_pool: usize,
@@ -107,6 +110,13 @@ pub struct ConsensusState {
     
     /// Current validator set
     pub validators: ValidatorPool,
+    
+    /// Current assignment of accounts to shards
+    pub shard_layout: ShardLayout,
+    
+    /// Block parameters committed at the start of each epoch that a revert
+    /// can still go back to, by height of the committing block
+    pub block_parameters: BTreeMap<BlockHeight, BlockParameters>,
 }
 
 impl ConsensusState {
@@ -118,6 +128,8 @@ impl ConsensusState {
             last_block_time: 0,
             is_active: false,
             validators: ValidatorPool::new(),
+            shard_layout: ShardLayout::default(),
+            block_parameters: BTreeMap::new(),
         }
     }
     
@@ -131,9 +143,26 @@ impl ConsensusState {
         height % blocks_per_epoch == 0
     }
     
-    /// Get the shard for a given height
-    pub fn get_shard_for_height(&self, height: BlockHeight, shard_count: u16) -> ShardId {
-        (height % shard_count as u64) as ShardId
+    /// Get the block interval in milliseconds, given the configured one
+    pub fn block_interval_ms(&self, configured: u64) -> u64 {
+        self.block_parameters.values().next_back()
+            .map_or(configured, |parameters| parameters.block_interval_ms)
+    }
+    
+    /// Drop the block parameters that no revert above the `finalized`
+    /// height can need, keeping the ones in effect at that height
+    pub fn prune_block_parameters(&mut self, finalized: BlockHeight) {
+        let reversible = self.block_parameters.split_off(&(finalized + 1));
+        let in_effect = self.block_parameters.pop_last();
+        self.block_parameters = reversible;
+        if let Some((height, parameters)) = in_effect {
+            self.block_parameters.insert(height, parameters);
+        }
+    }
+    
+    /// Get the shard an account belongs to under the current layout
+    pub fn get_shard_for_account(&self, address: &[u8]) -> ShardId {
+        self.shard_layout.shard_for_address(address)
     }
 }
 
@@ -252,7 +281,7 @@ mod tests {
     
     #[test]
     fn test_consensus_state() {
-        let state = ConsensusState::new();
+        let mut state = ConsensusState::new();
         
         assert_eq!(state.height, 0);
         assert_eq!(state.epoch, 0);
@@ -272,11 +301,28 @@ mod tests {
         assert!(!state.is_epoch_start(101, 100));
         
         // Test shard assignment
-        assert_eq!(state.get_shard_for_height(0, 4), 0);
-        assert_eq!(state.get_shard_for_height(1, 4), 1);
-        assert_eq!(state.get_shard_for_height(2, 4), 2);
-        assert_eq!(state.get_shard_for_height(3, 4), 3);
-        assert_eq!(state.get_shard_for_height(4, 4), 0);
+        assert_eq!(state.get_shard_for_account(&[1; 32]), 0);
+        state.shard_layout = ShardLayout::uniform(4);
+        let shards: Vec<ShardId> = (0..64u8).map(|i| state.get_shard_for_account(&[i; 32])).collect();
+        assert!((0..4).all(|shard| shards.contains(&shard)));
+        assert_eq!(state.get_shard_for_account(&[7; 32]), shards[7]);
+        
+        // Committed block parameters override the configured interval
+        assert_eq!(state.block_interval_ms(2000), 2000);
+        state.block_parameters.insert(100, BlockParameters { max_block_size: 1024, block_interval_ms: 1500 });
+        assert_eq!(state.block_interval_ms(2000), 1500);
+        
+        // Pruning keeps the parameters in effect at the finalized height
+        // and those committed above it
+        state.block_parameters.insert(200, BlockParameters { max_block_size: 1024, block_interval_ms: 1750 });
+        state.block_parameters.insert(300, BlockParameters { max_block_size: 1024, block_interval_ms: 2250 });
+        state.prune_block_parameters(150);
+        assert_eq!(state.block_parameters.keys().copied().collect::<Vec<_>>(), vec![100, 200, 300]);
+        state.prune_block_parameters(250);
+        assert_eq!(state.block_parameters.keys().copied().collect::<Vec<_>>(), vec![200, 300]);
+        state.prune_block_parameters(300);
+        assert_eq!(state.block_parameters.keys().copied().collect::<Vec<_>>(), vec![300]);
+        assert_eq!(state.block_interval_ms(2000), 2250);
     }
     
     #[test]
@@ -360,7 +360,7 @@ impl ValidatorPool {
             self.validators.iter_mut().collect();
             
         validators_by_stake.sort_by(|a, b| 
-            b.1.total_stake().cmp(&a.1.total_stake()));
+            b.1.total_stake().cmp(&a.1.total_stake()).then_with(|| a.0.cmp(b.0)));
         
         // Assign validators to shards, distributing them evenly
         for (idx, (id, validator)) in validators_by_stake.iter_mut().enumerate() {
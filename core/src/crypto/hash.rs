@@ -129,13 +129,13 @@ impl MerkleTree {
             let mut combined = Vec::with_capacity(64);
             
             if idx % 2 == 0 {
-                // Current is right sibling
-                combined.extend_from_slice(sibling);
-                combined.extend_from_slice(&current);
-            } else {
                 // Current is left sibling
                 combined.extend_from_slice(&current);
                 combined.extend_from_slice(sibling);
+            } else {
+                // Current is right sibling
+                combined.extend_from_slice(sibling);
+                combined.extend_from_slice(&current);
             }
             
             current = sha256(&combined);
@@ -146,45 +146,82 @@ impl MerkleTree {
     }
 }
 
+/// Append a variable-length field with a 4-byte big-endian length prefix
+fn append_length_prefixed(data: &mut Vec<u8>, field: &[u8]) {
+    data.extend_from_slice(&(field.len() as u32).to_be_bytes());
+    data.extend_from_slice(field);
+}
+
 /// Calculate the hash of a block header
+///
+/// Every header field except the aggregated signature is committed to.
+/// Variable-length fields are length-prefixed so that the encoding is
+/// unambiguous.
 pub fn hash_block_header(
     index: u64,
+    chain_id: &str,
     timestamp: u64,
-    previous_hash: &Hash,
-    merkle_root: &Hash,
-    validator_merkle: &Hash,
-    nonce: u64,
+    previous_hash: &[u8],
+    state_root: &[u8],
+    transaction_root: &[u8],
+    receipt_root: &[u8],
+    validator_merkle: &[u8],
     shard_identifiers: &[u16],
+    base_fees: &[u64],
+    parameters: &[u8],
+    shard_layout: &[u8],
 ) -> Hash {
     let mut data = Vec::new();
     
     // Convert index to bytes and append
     data.extend_from_slice(&index.to_be_bytes());
     
+    // Append chain ID
+    append_length_prefixed(&mut data, chain_id.as_bytes());
+    
     // Append timestamp
     data.extend_from_slice(&timestamp.to_be_bytes());
     
     // Append previous hash
-    data.extend_from_slice(previous_hash);
+    append_length_prefixed(&mut data, previous_hash);
     
-    // Append merkle root
-    data.extend_from_slice(merkle_root);
+    // Append state root
+    append_length_prefixed(&mut data, state_root);
     
-    // Append validator merkle root
-    data.extend_from_slice(validator_merkle);
+    // Append transaction merkle root
+    append_length_prefixed(&mut data, transaction_root);
     
-    // Append nonce
-    data.extend_from_slice(&nonce.to_be_bytes());
+    // Append receipt merkle root
+    append_length_prefixed(&mut data, receipt_root);
+    
+    // Append validator merkle root
+    append_length_prefixed(&mut data, validator_merkle);
     
     // Append shard identifiers
+    data.extend_from_slice(&(shard_identifiers.len() as u32).to_be_bytes());
     for &shard_id in shard_identifiers {
         data.extend_from_slice(&shard_id.to_be_bytes());
     }
     
+    // Append the base fee of each shard
+    data.extend_from_slice(&(base_fees.len() as u32).to_be_bytes());
+    for &base_fee in base_fees {
+        data.extend_from_slice(&base_fee.to_be_bytes());
+    }
+    
+    // Append block parameters, empty if the header carries none
+    append_length_prefixed(&mut data, parameters);
+    
+    // Append the shard layout, empty if the header carries none
+    append_length_prefixed(&mut data, shard_layout);
+    
     sha256(&data)
 }
 
 /// Calculate the hash of a transaction
+///
+/// Variable-length fields are length-prefixed so that the encoding is
+/// unambiguous.
 pub fn hash_transaction(
     version: u8,
     transaction_type: u8,
@@ -210,13 +247,13 @@ pub fn hash_transaction(
     data.push(transaction_type);
     
     // Append sender public key
-    data.extend_from_slice(sender_public_key);
+    append_length_prefixed(&mut data, sender_public_key);
     
     // Append sender shard
     data.extend_from_slice(&sender_shard.to_be_bytes());
     
     // Append recipient address
-    data.extend_from_slice(recipient_address);
+    append_length_prefixed(&mut data, recipient_address);
     
     // Append recipient shard
     data.extend_from_slice(&recipient_shard.to_be_bytes());
@@ -240,11 +277,12 @@ pub fn hash_transaction(
     data.push(data_type);
     
     // Append data content
-    data.extend_from_slice(data_content);
+    append_length_prefixed(&mut data, data_content);
     
     // Append dependencies
+    data.extend_from_slice(&(dependencies.len() as u32).to_be_bytes());
     for dependency in dependencies {
-        data.extend_from_slice(dependency);
+        append_length_prefixed(&mut data, dependency);
     }
     
     sha256(&data)
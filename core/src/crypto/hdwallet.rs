@@ -360,13 +360,10 @@ impl Mnemonic {
         // Calculate SHA-256 hash of entropy
         let hash = sha256(entropy);
         
-        // Take the first checksum_bits of the hash
-        let checksum = hash[0] >> (8 - checksum_bits);
-        
-        // Combine entropy and checksum
+        // Combine entropy and checksum, which is the first checksum_bits of the hash
         let mut combined = Vec::with_capacity(entropy.len() + 1);
         combined.extend_from_slice(entropy);
-        combined.push(checksum);
+        combined.push(hash[0]);
         
         // Convert to indices (11 bits per word)
         let total_bits = entropy_bits + checksum_bits;
@@ -375,30 +372,10 @@ impl Mnemonic {
         let mut words = Vec::with_capacity(word_count);
         
         for i in 0..word_count {
-            let start_bit = i * 11;
-            let start_byte = start_bit / 8;
-            let end_byte = (start_bit + 11 - 1) / 8;
-            let start_bit_in_byte = start_bit % 8;
-            
-            let mut index = 0;
-            
-            if end_byte >= combined.len() {
-                // Handle the case where we need bits from a byte that doesn't exist
-                // This can happen for the last word when checksum bits are involved
-                let bits_from_last_byte = 8 - start_bit_in_byte;
-                index = (combined[start_byte] & ((1 << bits_from_last_byte) - 1)) as u16;
-                index <<= (11 - bits_from_last_byte);
-            } else if start_byte == end_byte {
-                // All 11 bits are in the same byte
-                index = ((combined[start_byte] >> (8 - start_bit_in_byte - 11)) & 0x7FF) as u16;
-            } else {
-                // Bits are split across two bytes
-                let bits_from_first_byte = 8 - start_bit_in_byte;
-                let bits_from_second_byte = 11 - bits_from_first_byte;
-                
-                index = (combined[start_byte] & ((1 << bits_from_first_byte) - 1)) as u16;
-                index <<= bits_from_second_byte;
-                index |= (combined[end_byte] >> (8 - bits_from_second_byte)) as u16;
+            let mut index: u16 = 0;
+            for bit in i * 11..(i + 1) * 11 {
+                let value = (combined[bit / 8] >> (7 - bit % 8)) & 1;
+                index = (index << 1) | value as u16;
             }
             
             if index as usize >= BIP39_WORDLIST.len() {
@@ -447,23 +424,11 @@ impl Mnemonic {
         let mut entropy = vec![0u8; entropy_bytes];
         
         for (i, &index) in indices.iter().enumerate() {
-            let start_bit = i * 11;
-            let start_byte = start_bit / 8;
-            let start_bit_in_byte = start_bit % 8;
-            
-            if start_byte < entropy.len() {
-                // Add the first part of the index to the current byte
-                let bits_in_first_byte = std::cmp::min(8 - start_bit_in_byte, 11);
-                let mask = (1 << bits_in_first_byte) - 1;
-                let value = (index >> (11 - bits_in_first_byte)) & mask;
-                entropy[start_byte] |= (value as u8) << (8 - start_bit_in_byte - bits_in_first_byte);
-                
-                // If there are remaining bits, add them to the next byte
-                if bits_in_first_byte < 11 && start_byte + 1 < entropy.len() {
-                    let bits_in_second_byte = 11 - bits_in_first_byte;
-                    let mask = (1 << bits_in_second_byte) - 1;
-                    let value = index & mask;
-                    entropy[start_byte + 1] |= (value as u8) << (8 - bits_in_second_byte);
+            for bit in 0..11 {
+                let position = i * 11 + bit;
+                if position < entropy_bits {
+                    let value = ((index >> (10 - bit)) & 1) as u8;
+                    entropy[position / 8] |= value << (7 - position % 8);
                 }
             }
         }
@@ -754,7 +719,7 @@ mod tests {
     
     #[test]
     fn test_mnemonic_from_phrase() -> Result<()> {
-        let phrase = "abandon ability able about above absent absorb abstract absurd abuse access accident";
+        let phrase = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
         let mnemonic = Mnemonic::from_phrase(phrase)?;
         
         assert_eq!(mnemonic.word_count(), 12);
@@ -765,7 +730,7 @@ mod tests {
     
     #[test]
     fn test_mnemonic_to_seed() -> Result<()> {
-        let phrase = "abandon ability able about above absent absorb abstract absurd abuse access accident";
+        let phrase = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
         let mnemonic = Mnemonic::from_phrase(phrase)?;
         
         // Without passphrase
@@ -8,7 +8,6 @@ pub mod signature;
 pub mod address;
 pub mod keystore;
 pub mod hdwallet;
-pub mod bip39_wordlist;
 
 // Re-export main types
 pub use hash::Hash;
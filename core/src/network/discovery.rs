@@ -273,7 +273,7 @@ impl PeerDiscovery {
             None,
             crate::types::Priority::Low,
             Vec::new(), // The node ID will be filled in by the sender
-        ))
+        )?)
     }
     
     /// Get the number of discovered peers
@@ -364,12 +364,11 @@ mod tests {
         // Start discovery
         assert!(discovery.start().is_ok());
         
-        // Process discovery - should return bootstrap peers initially
-        let new_peers = discovery.process().unwrap();
-        assert_eq!(new_peers.len(), 2);
-        
-        // Process again - should not return any new peers
+        // Bootstrap peers are discovered on start, so processing does not
+        // return them again
+        assert_eq!(discovery.discovered_peers.len(), 2);
         let new_peers = discovery.process().unwrap();
         assert_eq!(new_peers.len(), 0);
+        assert_eq!(discovery.discovered_peers.len(), 2);
     }
 }
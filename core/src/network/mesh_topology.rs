@@ -395,7 +395,7 @@ impl MeshTopology {
         }
         
         // Prioritize regions with fewer connections
-        let mut regions: Vec<_> = Region::all().collect();
+        let mut regions: Vec<_> = Region::all();
         regions.sort_by_key(|r| connections_per_region.get(r).cloned().unwrap_or(0));
         
         // Select candidates from each region
@@ -511,8 +511,8 @@ impl MeshTopology {
     pub fn get_peers_in_region(&self, region: Region) -> impl Iterator<Item = &SocketAddr> {
         self.peers_by_region
             .get(&region)
-            .map(|peers| peers.iter())
-            .unwrap_or_else(|| [].iter())
+            .into_iter()
+            .flatten()
     }
     
     /// Get the best peers for message propagation
@@ -2,7 +2,8 @@
 //! 
 //! This module defines network message structures and serialization/deserialization.
 
-use crate::types::{ShardId, Priority, Transaction};
+use crate::types::{ShardId, Priority};
+use crate::blockchain::Transaction;
 use serde::{Serialize, Deserialize};
 use std::collections::VecDeque;
 use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
@@ -32,6 +33,12 @@ pub enum ProtocolError {
     InvalidMessageFormat,
 }
 
+impl From<ProtocolError> for crate::types::Error {
+    fn from(error: ProtocolError) -> Self {
+        crate::types::Error::Network(error.to_string())
+    }
+}
+
 /// Network message types
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Hash)]
 pub enum MessageType {
@@ -122,9 +129,11 @@ impl Message {
         let (compressed_data, is_compressed) = if data.len() > COMPRESSION_THRESHOLD {
             let mut compressor = Compress::new(COMPRESSION_LEVEL, false);
             let mut output = vec![0; data.len()];
-            let status = compressor.compress(&data, &mut output, FlushCompress::Finish)?;
+            let status = compressor.compress(&data, &mut output, FlushCompress::Finish)
+                .map_err(|e| ProtocolError::CompressionError(e.to_string()))?;
             
-            if status == flate2::Status::Ok {
+            if status == flate2::Status::StreamEnd {
+                output.truncate(compressor.total_out() as usize);
                 (output, true)
             } else {
                 return Err(ProtocolError::CompressionError(
@@ -163,7 +172,8 @@ impl Message {
         // Encode transactions using binary format
         let mut batch_data = Vec::new();
         for tx in transactions {
-            let tx_bytes = tx.to_binary()?;
+            let tx_bytes = bincode::serialize(&tx)
+                .map_err(|e| ProtocolError::SerializationError(e.to_string()))?;
             batch_data.extend_from_slice(&tx_bytes);
         }
         
@@ -236,8 +246,8 @@ mod tests {
 
     #[test]
     fn test_transaction_batch() -> Result<(), ProtocolError> {
-        let tx1 = Transaction::new_dummy(1);
-        let tx2 = Transaction::new_dummy(2);
+        let tx1 = Transaction::new_transfer(vec![1; 32], 0, vec![2; 20], 0, 100, 10, 1);
+        let tx2 = Transaction::new_transfer(vec![1; 32], 0, vec![2; 20], 0, 200, 10, 2);
         
         let batch = Message::new_transaction_batch(
             vec![tx1, tx2],
@@ -277,7 +287,7 @@ mod tests {
             Some(0),
             Priority::High,
             vec![10, 11, 12],
-        );
+        ).unwrap();
         
         // Serialize to bytes
         let bytes = msg.serialize().unwrap();
@@ -304,7 +314,7 @@ mod tests {
             Some(0),
             Priority::High,
             vec![10, 11, 12],
-        );
+        ).unwrap();
         
         assert!(msg.verify_signature());
         assert!(msg.verify_checksum());
@@ -42,7 +42,7 @@ pub use fast_path::FastPathConfig;
 pub use bandwidth_manager::BandwidthManager;
 pub use bandwidth_manager::BandwidthConfig;
 
-use crate::types::{Result, Error};
+use crate::types::{Result, Error, DEFAULT_CHAIN_ID};
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
@@ -64,6 +64,9 @@ pub struct NetworkConfig {
     
     /// Connection timeout in seconds
     pub connection_timeout: u64,
+    
+    /// Chain id peers must share, taken from the genesis specification
+    pub chain_id: String,
 }
 
 impl Default for NetworkConfig {
@@ -74,6 +77,7 @@ impl Default for NetworkConfig {
             max_peers: 25,
             announce_interval: 300, // 5 minutes
             connection_timeout: 10,
+            chain_id: DEFAULT_CHAIN_ID.to_string(),
         }
     }
 }
@@ -124,7 +128,7 @@ impl Network {
         // Create protocol instance
         let protocol = Protocol::new(
             ProtocolConfig::default(),
-            "sebure-mainnet".to_string(), // Network ID
+            self.config.chain_id.clone(), // Network ID
             vec![1, 2, 3, 4], // Genesis hash (placeholder)
             vec![5, 6, 7, 8], // Node ID (placeholder)
             format!("sebure/{}", env!("CARGO_PKG_VERSION")), // User agent
@@ -408,7 +412,7 @@ mod tests {
     
     #[test]
     fn test_network_start_stop() {
-        let network = Network::new(NetworkConfig::default());
+        let mut network = Network::new(NetworkConfig::default());
         
         // Start the network
         assert!(network.start().is_ok());
@@ -192,9 +192,7 @@ impl NodeCommunication {
         last_broadcast.insert(MessageType::BlockAnnouncement, now);
         
         // Create the block announcement message
-        // Note: We'll rely on Block::hash being defined in the blockchain module
-        // or call a helper function from there to get the hash
-        let block_hash = Vec::new(); // To be implemented
+        let block_hash = block.hash().to_vec();
         let height = block.header.index;
         
         // Create announcement data - using simple serialization approach for now
@@ -211,7 +209,7 @@ impl NodeCommunication {
             None, // Shard ID
             Priority::High,
             Vec::new(), // Sender ID will be filled by the network layer
-        );
+        )?;
         
         // Track which peers we've sent announcements to
         let mut known_blocks = self.known_blocks.lock().unwrap();
@@ -263,7 +261,7 @@ impl NodeCommunication {
             None, // Shard ID
             Priority::High,
             Vec::new(), // Sender ID will be filled by the network layer
-        );
+        )?;
         
         // Send the full block to selected peers
         for &peer_addr in &selected_peers {
@@ -312,7 +310,7 @@ impl NodeCommunication {
             None, // Shard ID
             Priority::High,
             Vec::new(), // Sender ID will be filled by the network layer
-        );
+        )?;
         
         // Send the request
         if let Err(e) = self.transport.send(peer_addr, &block_request_msg) {
@@ -375,7 +373,7 @@ impl NodeCommunication {
             None, // Shard ID
             Priority::High,
             Vec::new(), // Sender ID will be filled by the network layer
-        );
+        )?;
         
         // Track which peers we've sent announcements to
         let mut known_txs = self.known_txs.lock().unwrap();
@@ -441,7 +439,7 @@ impl NodeCommunication {
                 None, // Shard ID
                 Priority::Low,
                 Vec::new(), // Sender ID will be filled by the network layer
-            );
+            )?;
             
             // Send the batch to all peers
             for &peer_addr in peers {
@@ -524,7 +522,7 @@ impl NodeCommunication {
             None, // Shard ID
             Priority::Low,
             Vec::new(), // Sender ID will be filled by the network layer
-        );
+        )?;
         
         // Send the request
         if let Err(e) = self.transport.send(peer_addr, &tx_request_msg) {
@@ -10,7 +10,7 @@ use std::sync::{Arc, Mutex, RwLock};
 use std::time::{Duration, Instant};
 use std::thread::{self, JoinHandle};
 use log::{debug, error, info, warn};
-use sysinfo::{System, SystemExt, ProcessExt, CpuExt, DiskExt};
+use sysinfo::{System, SystemExt, ProcessExt, CpuExt, DiskExt, NetworkExt};
 
 /// Configuration for the resource management system
 #[derive(Clone, Debug)]
@@ -258,6 +258,7 @@ impl ResourceManager {
         }
         
         *running = false;
+        drop(running); // Release lock so the monitoring thread can observe the stop
         
         // Wait for monitoring thread to finish
         if let Some(thread) = self.monitor_thread.take() {
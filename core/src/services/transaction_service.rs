@@ -3,15 +3,20 @@
 //! This module implements the transaction service, which provides functionality
 //! for creating, signing, validating, and submitting transactions.
 
-use crate::blockchain::{Transaction, TransactionData, Mempool};
-use crate::crypto::signature::{self, KeyPair, Signature};
+use crate::blockchain::{Transaction, TransactionData, BatchOperation, Mempool, AdmissionOutcome, BASE_FEE_BURN_PERCENT};
+use crate::blockchain::fee_market::suggested_max_fee;
+use crate::crypto::signature::{KeyPair, Signature};
 use crate::crypto::hash;
-use crate::types::{Result, Error, ShardId, TransactionType, DataType, Priority};
+use crate::types::{Result, Error, ShardId, TransactionType, Priority, DEFAULT_CHAIN_ID};
 use crate::storage::state_db::StateDB;
+use crate::storage::ChainStore;
 use std::sync::{Arc, Mutex, RwLock};
 use std::collections::HashMap;
 use log::{info, debug, error, warn};
 
+/// Reads the base fee the next block charges on a shard
+pub type BaseFeeSource = Arc<dyn Fn(ShardId) -> Result<u64> + Send + Sync>;
+
 /// Fee estimation model
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FeeEstimationModel {
@@ -24,10 +29,30 @@ pub enum FeeEstimationModel {
     /// Fee based on transaction type
     TypeBased,
     
-    /// Dynamic fee based on network congestion
+    /// Dynamic fee that follows the mempool's minimum admission fee
     Dynamic,
 }
 
+/// Fee quote for a transaction
+///
+/// A transaction signed with the quoted maximum and priority fees pays the
+/// base fee of the block that includes it plus the priority fee, as long as
+/// the two stay within the maximum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeeQuote {
+    /// Base fee the next block charges on the shard
+    pub base_fee: u64,
+    
+    /// Tip for the block producer, priced by the fee model
+    pub priority_fee: u32,
+    
+    /// Maximum fee, leaving room for the base fee to double
+    pub max_fee: u32,
+    
+    /// Part of the current base fee that is burned
+    pub burned: u64,
+}
+
 /// Configuration for the transaction service
 #[derive(Debug, Clone)]
 pub struct TransactionServiceConfig {
@@ -37,14 +62,14 @@ pub struct TransactionServiceConfig {
     /// Fee estimation model
     pub fee_model: FeeEstimationModel,
     
-    /// Network congestion multiplier (for dynamic fee model)
-    pub congestion_multiplier: f32,
-    
     /// Maximum transaction history items to keep per address
     pub max_history_items: usize,
     
     /// Whether to cache transaction history
     pub cache_history: bool,
+    
+    /// Chain id that transactions are signed for
+    pub chain_id: String,
 }
 
 impl Default for TransactionServiceConfig {
@@ -52,9 +77,9 @@ impl Default for TransactionServiceConfig {
         TransactionServiceConfig {
             default_fee: 10,
             fee_model: FeeEstimationModel::SizeBased,
-            congestion_multiplier: 1.0,
             max_history_items: 100,
             cache_history: true,
+            chain_id: DEFAULT_CHAIN_ID.to_string(),
         }
     }
 }
@@ -63,29 +88,66 @@ impl Default for TransactionServiceConfig {
 /// fee estimation, and submission to the mempool.
 pub struct TransactionService {
     /// Mempool for transaction submission
-    mempool: Arc<Mutex<Mempool>>,
+    mempool: Arc<Mempool>,
     
     /// State database for account information
     state_db: Arc<StateDB>,
     
+    /// Chain store for confirmed transaction history
+    chain_store: Option<Arc<ChainStore>>,
+    
     /// Cache for transaction history by address
     tx_history: Mutex<HashMap<Vec<u8>, Vec<Transaction>>>,
     
+    /// Source of the base fee of the next block on each shard
+    base_fee_source: Option<BaseFeeSource>,
+    
     /// Service configuration
     config: TransactionServiceConfig,
 }
 
 impl TransactionService {
     /// Creates a new TransactionService with the given mempool and state database.
-    pub fn new(mempool: Arc<Mutex<Mempool>>, state_db: Arc<StateDB>, config: TransactionServiceConfig) -> Self {
+    pub fn new(mempool: Arc<Mempool>, state_db: Arc<StateDB>, config: TransactionServiceConfig) -> Self {
         TransactionService {
             mempool,
             state_db,
+            chain_store: None,
             tx_history: Mutex::new(HashMap::new()),
+            base_fee_source: None,
             config,
         }
     }
     
+    /// Returns the mempool transactions are submitted to.
+    pub fn mempool(&self) -> Arc<Mempool> {
+        self.mempool.clone()
+    }
+    
+    /// Sets the chain store used for confirmed transaction history.
+    pub fn set_chain_store(&mut self, chain_store: Arc<ChainStore>) {
+        self.chain_store = Some(chain_store);
+    }
+    
+    /// Sets where the base fee the next block charges on a shard is read
+    /// from, normally `Blockchain::base_fee`.
+    pub fn set_base_fee_source(&mut self, source: BaseFeeSource) {
+        self.base_fee_source = Some(source);
+    }
+    
+    /// Gets the base fee the next block charges on a shard, or 0 if it
+    /// cannot be read.
+    pub fn base_fee(&self, shard_id: ShardId) -> u64 {
+        let source = match &self.base_fee_source {
+            Some(source) => source,
+            None => return 0,
+        };
+        source(shard_id).unwrap_or_else(|e| {
+            warn!("Failed to read the base fee of shard {}: {}", shard_id, e);
+            0
+        })
+    }
+    
     /// Creates a new transaction with the given parameters.
     pub fn create_transaction(
         &self,
@@ -108,15 +170,26 @@ impl TransactionService {
             None => {
                 // Calculate address from public key
                 let address = hash::sha256(sender_public_key);
-                self.state_db.get_account_nonce(&address).unwrap_or(0)
+                let account_nonce = self.state_db.get_account_nonce(&address).unwrap_or(0);
+                
+                // Follow on from the sender's ready transactions in the mempool
+                let pending_nonce = self.mempool.next_nonce(&address);
+                pending_nonce.map_or(account_nonce, |nonce| nonce.max(account_nonce))
             }
         };
         
-        // Use provided fee or estimate
-        let fee = fee.unwrap_or_else(|| self.estimate_fee(
-            transaction_type,
-            data.as_ref().map(|d| d.content.len()).unwrap_or(0),
-        ));
+        // Use provided fee, which may all go to the producer, or quote one
+        let (fee, priority_fee) = match fee {
+            Some(fee) => (fee, fee),
+            None => {
+                let quote = self.estimate_fee(
+                    transaction_type,
+                    sender_shard,
+                    data.as_ref().map(|d| d.content.len()).unwrap_or(0),
+                );
+                (quote.max_fee, quote.priority_fee)
+            },
+        };
         
         // Use provided gas limit or estimate
         let gas_limit = gas_limit.unwrap_or_else(|| match transaction_type {
@@ -150,36 +223,34 @@ impl TransactionService {
             dependencies,
             signature,
         );
+        tx.priority_fee = priority_fee;
         
         // Set priority if provided
         if let Some(p) = priority {
             tx.execution_priority = p;
         }
         
+        // Bind the transaction to the configured chain
+        tx.chain_id = self.config.chain_id.clone();
+        
         // Calculate transaction ID
-        let tx_bytes = bincode::serialize(&tx)
-            .map_err(|e| Error::Serialization(e.to_string()))?;
-        let tx_hash = hash::sha256(&tx_bytes);
-        tx.id = tx_hash.to_vec();
+        tx.id = Self::compute_id(&tx)?;
         
         Ok(tx)
     }
     
+    /// Computes the ID of a transaction from its signing payload.
+    pub fn compute_id(tx: &Transaction) -> Result<Vec<u8>> {
+        Ok(tx.compute_id())
+    }
+    
     /// Signs a transaction with the given private key.
     pub fn sign_transaction(&self, tx: &mut Transaction, private_key: &[u8]) -> Result<()> {
         // Create a keypair from the private key
         let keypair = KeyPair::from_seed(private_key)?;
         
-        // Serialize the transaction (excluding the signature)
-        let mut tx_copy = tx.clone();
-        tx_copy.signature = Signature::new(vec![0; 64]); // Clear signature for signing
-        
-        let tx_bytes = bincode::serialize(&tx_copy)
-            .map_err(|e| Error::Serialization(e.to_string()))?;
-        
-        // Sign the transaction
-        let signature = keypair.sign(&tx_bytes);
-        tx.signature = signature;
+        // Sign the canonical payload for the configured chain
+        tx.sign(&keypair, &self.config.chain_id);
         
         Ok(())
     }
@@ -195,18 +266,13 @@ impl TransactionService {
             return Err(Error::TransactionValidation("Sender public key is empty".to_string()));
         }
         
-        if tx.recipient_address.is_empty() {
+        // Batches name their recipients in their operations
+        if tx.recipient_address.is_empty() && tx.transaction_type != TransactionType::Batch {
             return Err(Error::TransactionValidation("Recipient address is empty".to_string()));
         }
         
-        // Verify signature
-        let mut tx_copy = tx.clone();
-        tx_copy.signature = Signature::new(vec![0; 64]); // Clear signature for verification
-        
-        let tx_bytes = bincode::serialize(&tx_copy)
-            .map_err(|e| Error::Serialization(e.to_string()))?;
-        
-        signature::verify(&tx.sender_public_key, &tx_bytes, &tx.signature)?;
+        // Verify signature, and the operations of a batch
+        tx.is_valid(&self.config.chain_id)?;
         
         // Calculate address from public key
         let sender_address = hash::sha256(&tx.sender_public_key);
@@ -219,8 +285,8 @@ impl TransactionService {
             ));
         }
         
-        // Check balance for transfers
-        if tx.transaction_type == TransactionType::Transfer {
+        // Check balance for transfers and batches
+        if matches!(tx.transaction_type, TransactionType::Transfer | TransactionType::Batch) {
             let balance = self.state_db.get_account_balance(&sender_address).unwrap_or(0);
             let total_cost = tx.amount + tx.fee as u64;
             
@@ -231,42 +297,95 @@ impl TransactionService {
             }
         }
         
-        // Verify transaction ID
-        let tx_bytes = bincode::serialize(&tx_copy)
-            .map_err(|e| Error::Serialization(e.to_string()))?;
-        let tx_hash = hash::sha256(&tx_bytes);
-        
-        if tx.id != tx_hash.to_vec() {
-            return Err(Error::TransactionValidation(
-                "Transaction ID does not match hash of transaction data".to_string()
-            ));
-        }
-        
         Ok(())
     }
     
-    /// Submits a transaction to the mempool.
-    pub fn submit_transaction(&self, tx: Transaction) -> Result<()> {
+    /// Submits a transaction to the mempool and reports whether it was added,
+    /// replaced a pending transaction, or was turned away.
+    pub fn submit_transaction(&self, tx: Transaction) -> Result<AdmissionOutcome> {
         // Validate the transaction
         self.validate_transaction(&tx)?;
         
         // Add to mempool
-        self.mempool.lock().unwrap().add_transaction(tx.clone())?;
+        let outcome = self.mempool.admit(&tx)?;
+        match outcome {
+            AdmissionOutcome::Added => {},
+            AdmissionOutcome::Replaced { ref replaced, ref evicted } => {
+                self.forget_history(replaced);
+                for tx_id in evicted {
+                    self.forget_history(tx_id);
+                }
+            },
+            AdmissionOutcome::Underpriced { .. } | AdmissionOutcome::Duplicate => return Ok(outcome),
+        }
         
         // Add to transaction history if caching is enabled
         if self.config.cache_history {
             let sender_address = hash::sha256(&tx.sender_public_key);
             self.add_to_history(sender_address.to_vec(), tx.clone());
             
-            // Also add to recipient's history
-            self.add_to_history(tx.recipient_address.clone(), tx);
+            // Also add to the recipients' history
+            if tx.transaction_type == TransactionType::Batch {
+                for operation in tx.batch_operations()? {
+                    if !operation.recipient_address.is_empty() {
+                        self.add_to_history(operation.recipient_address, tx.clone());
+                    }
+                }
+            } else {
+                self.add_to_history(tx.recipient_address.clone(), tx);
+            }
         }
         
-        Ok(())
+        Ok(outcome)
+    }
+    
+    /// Re-signs a pending transaction with a higher fee so that it replaces
+    /// the original in the mempool.
+    ///
+    /// Without an explicit fee, the lowest fee the mempool accepts as a
+    /// replacement is used. The priority fee rises by as much as the fee.
+    pub fn speed_up_transaction(
+        &self,
+        tx_id: &[u8],
+        private_key: &[u8],
+        fee: Option<u32>,
+    ) -> Result<(Transaction, AdmissionOutcome)> {
+        let (pending, minimum_fee) = {
+            let mempool = &self.mempool;
+            let pending = mempool.get_transaction(tx_id).ok_or_else(|| Error::TransactionValidation(
+                format!("Transaction {} is not pending", hex::encode(tx_id))
+            ))?;
+            let minimum_fee = mempool.replacement_fee(tx_id).unwrap_or(pending.fee);
+            (pending, minimum_fee)
+        };
+        
+        let mut tx = (*pending).clone();
+        tx.fee = fee.unwrap_or(minimum_fee);
+        tx.priority_fee = pending.priority_fee.saturating_add(tx.fee.saturating_sub(pending.fee));
+        self.sign_transaction(&mut tx, private_key)?;
+        
+        let outcome = self.submit_transaction(tx.clone())?;
+        Ok((tx, outcome))
+    }
+    
+    /// Quotes the fees for a transaction on a shard.
+    ///
+    /// The priority fee follows the fee model, and the maximum fee covers
+    /// twice the shard's current base fee plus the priority fee.
+    pub fn estimate_fee(&self, transaction_type: TransactionType, shard_id: ShardId, data_size: usize) -> FeeQuote {
+        let base_fee = self.base_fee(shard_id);
+        let priority_fee = self.estimate_priority_fee(transaction_type, data_size);
+        
+        FeeQuote {
+            base_fee,
+            priority_fee,
+            max_fee: suggested_max_fee(base_fee, priority_fee),
+            burned: base_fee * BASE_FEE_BURN_PERCENT / 100,
+        }
     }
     
-    /// Estimates the fee for a transaction.
-    pub fn estimate_fee(&self, transaction_type: TransactionType, data_size: usize) -> u32 {
+    /// Estimates the priority fee for a transaction with the fee model.
+    fn estimate_priority_fee(&self, transaction_type: TransactionType, data_size: usize) -> u32 {
         match self.config.fee_model {
             FeeEstimationModel::Fixed => {
                 self.config.default_fee
@@ -286,7 +405,7 @@ impl TransactionService {
                 }
             },
             FeeEstimationModel::Dynamic => {
-                // Base fee adjusted by congestion multiplier
+                // Type-based base fee plus size fee
                 let base_fee = match transaction_type {
                     TransactionType::Transfer => self.config.default_fee,
                     TransactionType::ContractDeploy => self.config.default_fee * 10,
@@ -296,25 +415,50 @@ impl TransactionService {
                 
                 let size_fee = (data_size / 100) as u32; // 1 fee unit per 100 bytes
                 
-                // Apply congestion multiplier
-                ((base_fee + size_fee) as f32 * self.config.congestion_multiplier) as u32
+                // Pay at least what the mempool currently admits
+                let minimum_fee = self.mempool.minimum_fee(data_size);
+                (base_fee + size_fee).max(minimum_fee.min(u32::MAX as u64) as u32)
             },
         }
     }
     
-    /// Gets the transaction history for an address.
+    /// Gets the transaction history for an address, newest first.
+    ///
+    /// Pending transactions submitted through this service come before
+    /// confirmed ones, up to `max_history_items` in total.
     pub fn get_transaction_history(&self, address: &[u8]) -> Vec<Transaction> {
+        let mut history = Vec::new();
+        
         if self.config.cache_history {
-            // Try to get from cache first
-            let history = self.tx_history.lock().unwrap();
-            if let Some(txs) = history.get(address) {
-                return txs.clone();
+            let cache = self.tx_history.lock().unwrap();
+            if let Some(txs) = cache.get(address) {
+                history.extend(txs.iter()
+                    .filter(|tx| self.chain_store.as_ref().map_or(true, |store| !store.has_transaction(&tx.id)))
+                    .cloned());
+                history.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+            }
+        }
+        
+        if self.chain_store.is_some() {
+            match self.get_confirmed_history(address, 0, self.config.max_history_items) {
+                Ok(confirmed) => history.extend(confirmed),
+                Err(e) => warn!("Failed to read confirmed history: {}", e),
             }
         }
         
-        // If not in cache or caching disabled, return empty
-        // In a real implementation, we would query the blockchain
-        Vec::new()
+        history.truncate(self.config.max_history_items);
+        history
+    }
+    
+    /// Gets a page of the confirmed transactions for an address, newest first.
+    pub fn get_confirmed_history(&self, address: &[u8], offset: usize, limit: usize) -> Result<Vec<Transaction>> {
+        let chain_store = self.chain_store.as_ref()
+            .ok_or_else(|| Error::State("Transaction history requires a chain store".to_string()))?;
+        
+        chain_store.get_address_history(address, offset, limit)
+            .into_iter()
+            .map(|entry| chain_store.get_transaction(&entry.transaction_id))
+            .collect()
     }
     
     /// Adds a transaction to the history cache.
@@ -337,6 +481,14 @@ impl TransactionService {
         }
     }
     
+    /// Removes a transaction from the history cache.
+    fn forget_history(&self, tx_id: &[u8]) {
+        let mut history = self.tx_history.lock().unwrap();
+        for txs in history.values_mut() {
+            txs.retain(|tx| tx.id != tx_id);
+        }
+    }
+    
     /// Gets the balance for an address.
     pub fn get_balance(&self, address: &[u8]) -> Result<u64> {
         self.state_db.get_account_balance(address)
@@ -375,6 +527,41 @@ impl TransactionService {
         Ok(tx)
     }
     
+    /// Creates a batch transaction that applies all of its operations or none.
+    pub fn create_batch(
+        &self,
+        sender_private_key: &[u8],
+        sender_public_key: &[u8],
+        sender_shard: ShardId,
+        operations: &[BatchOperation],
+        fee: Option<u32>,
+    ) -> Result<Transaction> {
+        let amount = operations.iter()
+            .try_fold(0u64, |total, operation| total.checked_add(operation.amount))
+            .ok_or_else(|| Error::TransactionValidation("Batch amount overflows".to_string()))?;
+        
+        // Create the transaction
+        let mut tx = self.create_transaction(
+            sender_public_key,
+            sender_shard,
+            &[], // Recipients are named by the operations
+            sender_shard,
+            amount,
+            fee,
+            Some(21000u32.saturating_mul(operations.len() as u32)),
+            None, // Use account nonce
+            TransactionType::Batch,
+            Some(TransactionData::batch(operations)?),
+            None, // No dependencies
+            None, // Use default priority
+        )?;
+        
+        // Sign the transaction
+        self.sign_transaction(&mut tx, sender_private_key)?;
+        
+        Ok(tx)
+    }
+    
     /// Updates the configuration.
     pub fn update_config(&mut self, config: TransactionServiceConfig) {
         self.config = config;
@@ -386,11 +573,12 @@ mod tests {
     use super::*;
     use crate::blockchain::MempoolConfig;
     use crate::storage::StorageConfig;
+    use crate::crypto::signature;
     use std::sync::Arc;
     
     // Helper function to create a test transaction service
     fn create_test_service() -> TransactionService {
-        let mempool = Arc::new(Mutex::new(Mempool::new(MempoolConfig::default())));
+        let mempool = Arc::new(Mempool::new(MempoolConfig::default()));
         
         // Create an in-memory state DB for testing
         let storage_config = StorageConfig {
@@ -468,14 +656,81 @@ mod tests {
         // Sign the transaction
         service.sign_transaction(&mut tx, &private_key).unwrap();
         
-        // Verify the signature
-        let mut tx_copy = tx.clone();
-        tx_copy.signature = Signature::new(vec![0; 64]); // Clear signature for verification
+        // Verify the signature over the canonical payload
+        let payload = tx.signing_payload();
+        assert!(signature::verify(&public_key, &payload, &tx.signature).is_ok());
+        assert!(tx.verify_signature(DEFAULT_CHAIN_ID).is_ok());
+        
+        // Mutable scheduling fields are not covered
+        tx.execution_priority = Priority::High;
+        assert!(tx.verify_signature(DEFAULT_CHAIN_ID).is_ok());
+        
+        // Other chains do not accept the signature
+        assert!(tx.verify_signature("sebure-devnet").is_err());
+    }
+    
+    #[test]
+    fn test_speed_up_transaction() {
+        let mempool = Arc::new(Mempool::new(MempoolConfig::default()));
+        let state_db = Arc::new(StateDB::new("", &StorageConfig::default()).unwrap());
+        let service = TransactionService::new(mempool.clone(), state_db.clone(), TransactionServiceConfig::default());
         
-        let tx_bytes = bincode::serialize(&tx_copy).unwrap();
-        let result = signature::verify(&public_key, &tx_bytes, &tx.signature);
+        let keypair = KeyPair::from_seed(&[7; 32]).unwrap();
+        let private_key = keypair.private_key();
+        let public_key = keypair.public_key();
+        state_db.set_account_balance(&hash::sha256(&public_key), 1_000_000).unwrap();
+        
+        let tx = service.create_transfer(&private_key, &public_key, 0, &[2; 20], 0, 1000, Some(1000)).unwrap();
+        assert_eq!(service.submit_transaction(tx.clone()).unwrap(), AdmissionOutcome::Added);
+        assert_eq!(service.submit_transaction(tx.clone()).unwrap(), AdmissionOutcome::Duplicate);
+        
+        // Speeding up uses the minimum replacement fee by default
+        let (faster, outcome) = service.speed_up_transaction(&tx.id, &private_key, None).unwrap();
+        assert_eq!(faster.fee, 1100);
+        assert_eq!(faster.priority_fee, 1100);
+        assert_eq!(faster.nonce, tx.nonce);
+        assert_eq!(outcome, AdmissionOutcome::Replaced { replaced: tx.id.clone(), evicted: Vec::new() });
+        assert!(mempool.get_transaction(&tx.id).is_none());
+        assert!(service.get_transaction_history(&hash::sha256(&public_key)).iter().all(|h| h.id != tx.id));
+        
+        // An insufficient bump is reported and leaves the pending transaction alone
+        let (_, outcome) = service.speed_up_transaction(&faster.id, &private_key, Some(1150)).unwrap();
+        assert_eq!(outcome, AdmissionOutcome::Underpriced { minimum_fee: 1210, minimum_priority_fee: 1210 });
+        assert!(mempool.get_transaction(&faster.id).is_some());
+        
+        // Only pending transactions can be sped up
+        assert!(service.speed_up_transaction(&tx.id, &private_key, None).is_err());
+    }
+    
+    #[test]
+    fn test_batch_submitted_as_unit() {
+        let mempool = Arc::new(Mempool::new(MempoolConfig::default()));
+        let state_db = Arc::new(StateDB::new("", &StorageConfig::default()).unwrap());
+        let service = TransactionService::new(mempool.clone(), state_db.clone(), TransactionServiceConfig::default());
         
-        assert!(result.is_ok());
+        let keypair = KeyPair::from_seed(&[9; 32]).unwrap();
+        let private_key = keypair.private_key();
+        let public_key = keypair.public_key();
+        state_db.set_account_balance(&hash::sha256(&public_key), 1_000_000).unwrap();
+        
+        let payroll = vec![
+            BatchOperation::transfer(vec![2; 20], 0, 1000),
+            BatchOperation::transfer(vec![3; 20], 0, 2000),
+        ];
+        let batch = service.create_batch(&private_key, &public_key, 0, &payroll, Some(5000)).unwrap();
+        assert_eq!(batch.amount, 3000);
+        assert_eq!(service.submit_transaction(batch.clone()).unwrap(), AdmissionOutcome::Added);
+        assert!(service.get_transaction_history(&[3; 20]).iter().any(|tx| tx.id == batch.id));
+        
+        // One bad operation turns the whole batch away
+        let malformed = vec![
+            BatchOperation::transfer(vec![2; 20], 0, 1000),
+            BatchOperation::transfer(Vec::new(), 0, 2000),
+        ];
+        let batch = service.create_batch(&private_key, &public_key, 0, &malformed, Some(5000)).unwrap();
+        assert!(service.submit_transaction(batch.clone()).is_err());
+        assert!(mempool.admit(&batch).is_err());
+        assert!(mempool.get_transaction(&batch.id).is_none());
     }
     
     #[test]
@@ -493,8 +748,8 @@ mod tests {
             config,
         );
         
-        let fee = service.estimate_fee(TransactionType::Transfer, 1000);
-        assert_eq!(fee, 10);
+        let quote = service.estimate_fee(TransactionType::Transfer, 0, 1000);
+        assert_eq!(quote.priority_fee, 10);
         
         // Test size-based fee model
         let mut config = TransactionServiceConfig::default();
@@ -503,8 +758,8 @@ mod tests {
         
         service.update_config(config);
         
-        let fee = service.estimate_fee(TransactionType::Transfer, 1000);
-        assert_eq!(fee, 10 + 10); // base fee + size fee (1000 bytes = 10 fee units)
+        let quote = service.estimate_fee(TransactionType::Transfer, 0, 1000);
+        assert_eq!(quote.priority_fee, 10 + 10); // default fee + size fee (1000 bytes = 10 fee units)
         
         // Test type-based fee model
         let mut config = TransactionServiceConfig::default();
@@ -513,13 +768,47 @@ mod tests {
         
         service.update_config(config);
         
-        let transfer_fee = service.estimate_fee(TransactionType::Transfer, 0);
-        let deploy_fee = service.estimate_fee(TransactionType::ContractDeploy, 0);
-        let call_fee = service.estimate_fee(TransactionType::ContractCall, 0);
+        let transfer_fee = service.estimate_fee(TransactionType::Transfer, 0, 0).priority_fee;
+        let deploy_fee = service.estimate_fee(TransactionType::ContractDeploy, 0, 0).priority_fee;
+        let call_fee = service.estimate_fee(TransactionType::ContractCall, 0, 0).priority_fee;
         
         assert_eq!(transfer_fee, 10);
         assert_eq!(deploy_fee, 100);
         assert_eq!(call_fee, 50);
+        
+        // Test dynamic fee model, which follows the mempool's minimum fee
+        let mut config = TransactionServiceConfig::default();
+        config.fee_model = FeeEstimationModel::Dynamic;
+        config.default_fee = 10;
+        
+        service.update_config(config);
+        
+        let minimum_fee = service.mempool.minimum_fee(0);
+        assert_eq!(service.estimate_fee(TransactionType::Transfer, 0, 0).priority_fee, minimum_fee as u32);
+        assert_eq!(service.estimate_fee(TransactionType::ContractDeploy, 0, 0).priority_fee, 100u32.max(minimum_fee as u32));
+    }
+    
+    #[test]
+    fn test_fee_quotes_follow_base_fee() {
+        let mut service = create_test_service();
+        
+        // Without a base fee the whole fee is the tip
+        let quote = service.estimate_fee(TransactionType::Transfer, 1, 0);
+        assert_eq!(quote, FeeQuote { base_fee: 0, priority_fee: 10, max_fee: 10, burned: 0 });
+        
+        // The maximum fee leaves room for the base fee to double, on the
+        // quoted shard only
+        service.set_base_fee_source(Arc::new(|shard_id| Ok(if shard_id == 1 { 40 } else { 0 })));
+        let quote = service.estimate_fee(TransactionType::Transfer, 1, 0);
+        assert_eq!(quote, FeeQuote { base_fee: 40, priority_fee: 10, max_fee: 90, burned: 20 });
+        assert_eq!(service.estimate_fee(TransactionType::Transfer, 0, 0).max_fee, 10);
+        
+        // Transactions without an explicit fee are signed with the quote
+        let tx = service.create_transaction(
+            &[1; 32], 1, &[2; 20], 1, 1000, None, None, Some(0),
+            TransactionType::Transfer, None, None, None,
+        ).unwrap();
+        assert_eq!((tx.fee, tx.priority_fee), (90, 10));
     }
     
     #[test]
@@ -555,4 +844,41 @@ mod tests {
         assert_eq!(history.len(), 1);
         assert_eq!(history[0].id, tx.id);
     }
+    
+    #[test]
+    fn test_transaction_history_from_chain_store() {
+        let mut service = create_test_service();
+        assert!(service.get_confirmed_history(&[2; 20], 0, 10).is_err());
+        
+        let path = std::env::temp_dir().join(format!("sebure-test-history-{}", rand::random::<u64>()));
+        let chain_store = Arc::new(ChainStore::new(path.to_str().unwrap(), &StorageConfig::default()).unwrap());
+        service.set_chain_store(chain_store.clone());
+        
+        let sender_public_key = vec![1; 32];
+        let sender_address = hash::sha256(&sender_public_key).to_vec();
+        let recipient_address = vec![2; 20];
+        
+        // Confirm two transfers in consecutive blocks
+        for height in 1..=2u8 {
+            let mut tx = Transaction::new_transfer(sender_public_key.clone(), 0, recipient_address.clone(), 0, 100, 10, height as u64);
+            tx.id = vec![height; 32];
+            let mut block = crate::blockchain::Block::new(height as u64, height as u64 * 1_000, vec![0; 32], vec![0]);
+            block.add_transactions(0, vec![tx]).unwrap();
+            chain_store.put_block(block).unwrap();
+        }
+        
+        // A pending transaction is listed ahead of confirmed ones
+        let mut pending = Transaction::new_transfer(sender_public_key.clone(), 0, recipient_address.clone(), 0, 100, 10, 3);
+        pending.id = vec![3; 32];
+        service.add_to_history(sender_address.clone(), pending);
+        
+        let ids: Vec<Vec<u8>> = service.get_transaction_history(&sender_address).iter().map(|tx| tx.id.clone()).collect();
+        assert_eq!(ids, vec![vec![3; 32], vec![2; 32], vec![1; 32]]);
+        
+        let page = service.get_confirmed_history(&recipient_address, 1, 5).unwrap();
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].id, vec![1; 32]);
+        
+        std::fs::remove_dir_all(path).ok();
+    }
 }
@@ -855,7 +855,7 @@ mod tests {
         let keypair = KeyPair::generate();
         let public_key = keypair.public_key();
         
-        Transaction::new_transfer(
+        let mut tx = Transaction::new_transfer(
             public_key.clone(),
             0,
             public_key,
@@ -869,7 +869,9 @@ mod tests {
             //     .unwrap()
             //     .as_micros() as u64,
             // vec![0, 1, 2, 3],
-        )
+        );
+        tx.sign(&keypair, crate::types::DEFAULT_CHAIN_ID);
+        tx
     }
 
     #[test]
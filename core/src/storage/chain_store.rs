@@ -2,11 +2,12 @@
 //! 
 //! This module provides storage for blockchain data, including blocks and transactions.
 
-use crate::blockchain::{Block, Transaction};
-use crate::types::{Result, Error, BlockHeight};
+use crate::blockchain::{Block, Transaction, Receipt, Log};
+use crate::blockchain::state_transition::address_from_public_key;
+use crate::types::{Result, Error, BlockHeight, Timestamp};
 use std::path::Path;
 use std::sync::{Arc, Mutex};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 /// Keys used in the chain store database
 pub enum ChainStoreKey {
@@ -27,6 +28,9 @@ pub enum ChainStoreKey {
     
     /// Key for the genesis block hash
     GenesisBlockHash,
+    
+    /// Key for storing a receipt by transaction ID
+    Receipt(Vec<u8>),
 }
 
 impl ChainStoreKey {
@@ -54,7 +58,91 @@ impl ChainStoreKey {
             ChainStoreKey::LatestBlockHeight => vec![0x04],
             ChainStoreKey::LatestBlockHash => vec![0x05],
             ChainStoreKey::GenesisBlockHash => vec![0x06],
+            ChainStoreKey::Receipt(tx_id) => {
+                let mut key = Vec::with_capacity(1 + tx_id.len());
+                key.push(0x07); // prefix for receipt
+                key.extend_from_slice(tx_id);
+                key
+            },
+        }
+    }
+}
+
+/// Receipt stored together with the block that produced it
+#[derive(Debug, Clone)]
+struct StoredReceipt {
+    /// Height of the block containing the transaction
+    block_height: BlockHeight,
+    
+    /// The receipt itself
+    receipt: Receipt,
+}
+
+/// A log together with the position where it was emitted
+#[derive(Debug, Clone)]
+pub struct LogEntry {
+    /// Height of the block containing the transaction
+    pub block_height: BlockHeight,
+    
+    /// Hash of the block containing the transaction
+    pub block_hash: Vec<u8>,
+    
+    /// Transaction that emitted the log
+    pub transaction_id: Vec<u8>,
+    
+    /// Position of the log within the block
+    pub log_index: usize,
+    
+    /// The log itself
+    pub log: Log,
+}
+
+/// A confirmed transaction involving an address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    /// Height of the block containing the transaction
+    pub block_height: BlockHeight,
+    
+    /// Position of the transaction within the block
+    pub index: usize,
+    
+    /// Timestamp of the block containing the transaction
+    pub timestamp: Timestamp,
+    
+    /// ID of the transaction
+    pub transaction_id: Vec<u8>,
+    
+    /// Whether the address sent the transaction
+    pub is_outgoing: bool,
+}
+
+/// Criteria for log queries
+#[derive(Debug, Clone, Default)]
+pub struct LogFilter {
+    /// First block height to search, inclusive
+    pub from_height: BlockHeight,
+    
+    /// Last block height to search, inclusive; the latest block if `None`
+    pub to_height: Option<BlockHeight>,
+    
+    /// Emitting addresses to match; any address if empty
+    pub addresses: Vec<Vec<u8>>,
+    
+    /// Topics to match by position; `None` matches any topic
+    pub topics: Vec<Option<Vec<u8>>>,
+}
+
+impl LogFilter {
+    /// Check if a log matches the address and topic criteria
+    pub fn matches(&self, log: &Log) -> bool {
+        if !self.addresses.is_empty() && !self.addresses.contains(&log.address) {
+            return false;
         }
+        
+        self.topics.iter().enumerate().all(|(i, topic)| match topic {
+            Some(topic) => log.topics.get(i) == Some(topic),
+            None => true,
+        })
     }
 }
 
@@ -73,6 +161,18 @@ pub struct ChainStore {
     /// In-memory cache of transactions by ID (for testing/prototype)
     transactions: Arc<Mutex<HashMap<Vec<u8>, Transaction>>>,
     
+    /// Receipts by transaction ID
+    receipts: Arc<Mutex<HashMap<Vec<u8>, StoredReceipt>>>,
+    
+    /// Transaction IDs with receipts, in block order, by block height
+    receipts_by_height: Arc<Mutex<BTreeMap<BlockHeight, (Vec<u8>, Vec<Vec<u8>>)>>>,
+    
+    /// History entries by address, keyed by block height and position
+    address_history: Arc<Mutex<HashMap<Vec<u8>, BTreeMap<(BlockHeight, usize), HistoryEntry>>>>,
+    
+    /// Addresses with history entries, by block height
+    history_by_height: Arc<Mutex<BTreeMap<BlockHeight, Vec<Vec<u8>>>>>,
+    
     /// Latest block height
     latest_height: Arc<Mutex<Option<BlockHeight>>>,
     
@@ -101,6 +201,10 @@ impl ChainStore {
             blocks_by_height: Arc::new(Mutex::new(HashMap::new())),
             blocks_by_hash: Arc::new(Mutex::new(HashMap::new())),
             transactions: Arc::new(Mutex::new(HashMap::new())),
+            receipts: Arc::new(Mutex::new(HashMap::new())),
+            receipts_by_height: Arc::new(Mutex::new(BTreeMap::new())),
+            address_history: Arc::new(Mutex::new(HashMap::new())),
+            history_by_height: Arc::new(Mutex::new(BTreeMap::new())),
             latest_height: Arc::new(Mutex::new(None)),
             latest_hash: Arc::new(Mutex::new(None)),
             genesis_hash: Arc::new(Mutex::new(None)),
@@ -116,7 +220,17 @@ impl ChainStore {
     /// Store a block in the chain store
     pub fn put_block(&self, block: Block) -> Result<()> {
         let height = block.header.index;
-        let hash = block.header.previous_hash.clone();
+        let hash = block.hash().to_vec();
+        
+        // Store the transactions carried by the block
+        {
+            let mut transactions = self.transactions.lock().unwrap();
+            for tx in &block.transactions {
+                transactions.insert(tx.id.clone(), tx.clone());
+            }
+        }
+        
+        self.index_address_history(&block);
         
         // Store block by height
         let mut blocks_by_height = self.blocks_by_height.lock().unwrap();
@@ -128,7 +242,7 @@ impl ChainStore {
         
         // Update latest height and hash if this is a new latest block
         let mut latest_height = self.latest_height.lock().unwrap();
-        if latest_height.is_none() || latest_height.unwrap() < height {
+        if latest_height.is_none() || latest_height.unwrap() <= height {
             *latest_height = Some(height);
             
             let mut latest_hash = self.latest_hash.lock().unwrap();
@@ -144,6 +258,68 @@ impl ChainStore {
         Ok(())
     }
     
+    /// Index the senders and recipients of a block's transactions
+    ///
+    /// Replaces the entries of any block previously stored at the same height.
+    fn index_address_history(&self, block: &Block) {
+        let height = block.header.index;
+        let mut address_history = self.address_history.lock().unwrap();
+        let mut by_height = self.history_by_height.lock().unwrap();
+        
+        // Drop entries of a block replaced by a reorganization
+        if let Some(addresses) = by_height.remove(&height) {
+            for address in addresses {
+                if let Some(entries) = address_history.get_mut(&address) {
+                    entries.retain(|(entry_height, _), _| *entry_height != height);
+                    if entries.is_empty() {
+                        address_history.remove(&address);
+                    }
+                }
+            }
+        }
+        
+        let mut addresses = Vec::new();
+        for (index, tx) in block.transactions.iter().enumerate() {
+            let sender = address_from_public_key(&tx.sender_public_key);
+            let mut record = |address: &Vec<u8>, is_outgoing: bool| {
+                address_history.entry(address.clone())
+                    .or_insert_with(BTreeMap::new)
+                    .entry((height, index))
+                    .or_insert_with(|| HistoryEntry {
+                        block_height: height,
+                        index,
+                        timestamp: block.header.timestamp,
+                        transaction_id: tx.id.clone(),
+                        is_outgoing,
+                    });
+                if !addresses.contains(address) {
+                    addresses.push(address.clone());
+                }
+            };
+            // The sender is recorded first so a self-transfer counts as outgoing
+            record(&sender, true);
+            record(&tx.recipient_address, false);
+        }
+        
+        if !addresses.is_empty() {
+            by_height.insert(height, addresses);
+        }
+    }
+    
+    /// Get a page of the confirmed transactions involving an address, newest first
+    pub fn get_address_history(&self, address: &[u8], offset: usize, limit: usize) -> Vec<HistoryEntry> {
+        let address_history = self.address_history.lock().unwrap();
+        address_history.get(address)
+            .map(|entries| entries.values().rev().skip(offset).take(limit).cloned().collect())
+            .unwrap_or_default()
+    }
+    
+    /// Count the confirmed transactions involving an address
+    pub fn count_address_history(&self, address: &[u8]) -> usize {
+        let address_history = self.address_history.lock().unwrap();
+        address_history.get(address).map_or(0, |entries| entries.len())
+    }
+    
     /// Store a transaction in the chain store
     pub fn put_transaction(&self, transaction: Transaction) -> Result<()> {
         let mut transactions = self.transactions.lock().unwrap();
@@ -151,6 +327,99 @@ impl ChainStore {
         Ok(())
     }
     
+    /// Store the receipts produced by executing a canonical block
+    ///
+    /// Replaces the receipts of any block previously stored at the same height.
+    pub fn put_receipts(&self, block: &Block, receipts: &[Receipt]) -> Result<()> {
+        let height = block.header.index;
+        let mut stored = self.receipts.lock().unwrap();
+        let mut by_height = self.receipts_by_height.lock().unwrap();
+        
+        // Drop receipts of a block replaced by a reorganization
+        if let Some((_, old_ids)) = by_height.remove(&height) {
+            for tx_id in old_ids {
+                if stored.get(&tx_id).map_or(false, |r| r.block_height == height) {
+                    stored.remove(&tx_id);
+                }
+            }
+        }
+        
+        let mut tx_ids = Vec::with_capacity(receipts.len());
+        for receipt in receipts {
+            tx_ids.push(receipt.transaction_id.clone());
+            stored.insert(receipt.transaction_id.clone(), StoredReceipt {
+                block_height: height,
+                receipt: receipt.clone(),
+            });
+        }
+        by_height.insert(height, (block.hash().to_vec(), tx_ids));
+        
+        Ok(())
+    }
+    
+    /// Get the receipt of a confirmed transaction
+    pub fn get_receipt(&self, tx_id: &[u8]) -> Result<Receipt> {
+        let receipts = self.receipts.lock().unwrap();
+        receipts.get(tx_id)
+            .map(|stored| stored.receipt.clone())
+            .ok_or_else(|| Error::State(format!("Receipt not found for transaction {:?}", tx_id)))
+    }
+    
+    /// Get the receipts of the canonical block, in block order
+    pub fn get_block_receipts(&self, block: &Block) -> Result<Vec<Receipt>> {
+        let receipts = self.receipts.lock().unwrap();
+        let by_height = self.receipts_by_height.lock().unwrap();
+        match by_height.get(&block.header.index) {
+            Some((block_hash, tx_ids)) if block_hash[..] == block.hash()[..] => tx_ids.iter()
+                .map(|tx_id| receipts.get(tx_id).map(|stored| stored.receipt.clone()).ok_or_else(|| Error::State(
+                    format!("Receipt not found for transaction {}", hex::encode(tx_id))
+                )))
+                .collect(),
+            _ => Err(Error::State(format!("Receipts not found for block {}", block.header.index))),
+        }
+    }
+    
+    /// Get the logs matching a filter, in chain order
+    pub fn get_logs(&self, filter: &LogFilter) -> Result<Vec<LogEntry>> {
+        let to_height = match filter.to_height.or(self.get_latest_height()) {
+            Some(to_height) => to_height,
+            None => return Ok(Vec::new()),
+        };
+        if filter.from_height > to_height {
+            return Err(Error::Validation(format!(
+                "Invalid log range: {} is after {}", filter.from_height, to_height
+            )));
+        }
+        
+        let receipts = self.receipts.lock().unwrap();
+        let by_height = self.receipts_by_height.lock().unwrap();
+        let mut entries = Vec::new();
+        
+        for (height, (block_hash, tx_ids)) in by_height.range(filter.from_height..=to_height) {
+            let mut log_index = 0;
+            for tx_id in tx_ids {
+                let stored = match receipts.get(tx_id) {
+                    Some(stored) => stored,
+                    None => continue,
+                };
+                for log in &stored.receipt.logs {
+                    if filter.matches(log) {
+                        entries.push(LogEntry {
+                            block_height: *height,
+                            block_hash: block_hash.clone(),
+                            transaction_id: tx_id.clone(),
+                            log_index,
+                            log: log.clone(),
+                        });
+                    }
+                    log_index += 1;
+                }
+            }
+        }
+        
+        Ok(entries)
+    }
+    
     /// Get a block by its height
     pub fn get_block_by_height(&self, height: BlockHeight) -> Result<Block> {
         let blocks_by_height = self.blocks_by_height.lock().unwrap();
@@ -262,7 +531,7 @@ mod tests {
         
         // Create and store a block
         let block = create_test_block(1);
-        let hash = block.header.previous_hash.clone();
+        let hash = block.hash().to_vec();
         chain_store.put_block(block.clone()).unwrap();
         
         // Retrieve by height
@@ -294,7 +563,7 @@ mod tests {
         
         // Create and store genesis block
         let genesis = create_test_block(0);
-        let hash = genesis.header.previous_hash.clone();
+        let hash = genesis.hash().to_vec();
         chain_store.put_block(genesis).unwrap();
         
         // Check genesis hash
@@ -304,6 +573,28 @@ mod tests {
         std::fs::remove_dir_all(path).ok();
     }
     
+    #[test]
+    fn test_blocks_indexed_by_own_hash() {
+        let path = temp_dir();
+        let config = super::super::StorageConfig::default();
+        
+        let chain_store = ChainStore::new(&path, &config).unwrap();
+        
+        // Two blocks sharing a parent must not collide in the hash index
+        let block1 = create_test_block(1);
+        let mut block2 = create_test_block(2);
+        block2.header.previous_hash = block1.header.previous_hash.clone();
+        chain_store.put_block(block1.clone()).unwrap();
+        chain_store.put_block(block2.clone()).unwrap();
+        
+        assert_eq!(chain_store.get_block_by_hash(&block1.hash()).unwrap().header.index, 1);
+        assert_eq!(chain_store.get_block_by_hash(&block2.hash()).unwrap().header.index, 2);
+        assert!(!chain_store.has_block_hash(&block1.header.previous_hash));
+        
+        // Clean up
+        std::fs::remove_dir_all(path).ok();
+    }
+    
     #[test]
     fn test_missing_block() {
         let path = temp_dir();
@@ -318,4 +609,121 @@ mod tests {
         // Clean up
         std::fs::remove_dir_all(path).ok();
     }
+    
+    #[test]
+    fn test_block_transactions_are_stored() {
+        let path = temp_dir();
+        let config = super::super::StorageConfig::default();
+        
+        let chain_store = ChainStore::new(&path, &config).unwrap();
+        
+        let mut tx = Transaction::new_transfer(vec![1; 32], 0, vec![2; 20], 0, 100, 10, 0);
+        tx.id = vec![5; 32];
+        let mut block = create_test_block(1);
+        block.add_transactions(0, vec![tx.clone()]).unwrap();
+        
+        assert!(chain_store.get_transaction(&tx.id).is_err());
+        chain_store.put_block(block).unwrap();
+        assert_eq!(chain_store.get_transaction(&tx.id).unwrap().id, tx.id);
+        
+        // Clean up
+        std::fs::remove_dir_all(path).ok();
+    }
+    
+    #[test]
+    fn test_receipts_replaced_on_reorg() {
+        let path = temp_dir();
+        let config = super::super::StorageConfig::default();
+        
+        let chain_store = ChainStore::new(&path, &config).unwrap();
+        
+        let receipt = |id: u8, address: u8| Receipt {
+            transaction_id: vec![id; 32],
+            status: 1,
+            gas_used: 21000,
+            state_root: Vec::new(),
+            logs: vec![Log { address: vec![address; 32], topics: vec![b"Transfer".to_vec()], data: Vec::new() }],
+        };
+        
+        let block1 = create_test_block(1);
+        chain_store.put_block(block1.clone()).unwrap();
+        chain_store.put_receipts(&block1, &[receipt(1, 7), receipt(2, 8)]).unwrap();
+        assert_eq!(chain_store.get_receipt(&vec![1; 32]).unwrap().gas_used, 21000);
+        assert_eq!(chain_store.get_logs(&LogFilter::default()).unwrap().len(), 2);
+        
+        // A competing block at the same height replaces the receipts
+        let mut block1b = create_test_block(1);
+        block1b.header.timestamp += 1;
+        chain_store.put_block(block1b.clone()).unwrap();
+        chain_store.put_receipts(&block1b, &[receipt(2, 8)]).unwrap();
+        assert!(chain_store.get_receipt(&vec![1; 32]).is_err());
+        
+        let logs = chain_store.get_logs(&LogFilter::default()).unwrap();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].block_hash, block1b.hash().to_vec());
+        assert_eq!(logs[0].log_index, 0);
+        
+        let filter = LogFilter { addresses: vec![vec![7; 32]], ..LogFilter::default() };
+        assert!(chain_store.get_logs(&filter).unwrap().is_empty());
+        
+        // Clean up
+        std::fs::remove_dir_all(path).ok();
+    }
+    
+    #[test]
+    fn test_address_history() {
+        let path = temp_dir();
+        let config = super::super::StorageConfig::default();
+        
+        let chain_store = ChainStore::new(&path, &config).unwrap();
+        
+        let alice_key = vec![1; 32];
+        let alice = address_from_public_key(&alice_key);
+        let bob = vec![2; 32];
+        let transfer = |id: u8, sender: &Vec<u8>, recipient: &Vec<u8>| {
+            let mut tx = Transaction::new_transfer(sender.clone(), 0, recipient.clone(), 0, 100, 10, 0);
+            tx.id = vec![id; 32];
+            tx
+        };
+        
+        // Alice sends twice in block 1 and receives once in block 2
+        let mut block1 = create_test_block(1);
+        block1.add_transactions(0, vec![transfer(1, &alice_key, &bob), transfer(2, &alice_key, &bob)]).unwrap();
+        let mut block2 = create_test_block(2);
+        block2.header.timestamp = block1.header.timestamp + 1;
+        block2.add_transactions(0, vec![transfer(3, &vec![3; 32], &alice)]).unwrap();
+        chain_store.put_block(block1.clone()).unwrap();
+        chain_store.put_block(block2).unwrap();
+        
+        assert_eq!(chain_store.count_address_history(&alice), 3);
+        let history = chain_store.get_address_history(&alice, 0, 10);
+        let ids: Vec<Vec<u8>> = history.iter().map(|e| e.transaction_id.clone()).collect();
+        assert_eq!(ids, vec![vec![3; 32], vec![2; 32], vec![1; 32]]);
+        assert!(!history[0].is_outgoing);
+        assert!(history[1].is_outgoing);
+        assert!(history[0].timestamp > history[2].timestamp);
+        
+        // Pagination
+        let page = chain_store.get_address_history(&alice, 1, 1);
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].transaction_id, vec![2; 32]);
+        assert!(chain_store.get_address_history(&alice, 3, 10).is_empty());
+        assert_eq!(chain_store.count_address_history(&bob), 2);
+        
+        // A competing block at height 2 replaces its entries
+        let mut block2b = create_test_block(2);
+        block2b.header.timestamp = block1.header.timestamp + 2;
+        block2b.add_transactions(0, vec![transfer(4, &alice_key, &vec![5; 32])]).unwrap();
+        chain_store.put_block(block2b).unwrap();
+        
+        let ids: Vec<Vec<u8>> = chain_store.get_address_history(&alice, 0, 10).iter()
+            .map(|e| e.transaction_id.clone())
+            .collect();
+        assert_eq!(ids, vec![vec![4; 32], vec![2; 32], vec![1; 32]]);
+        assert_eq!(chain_store.count_address_history(&address_from_public_key(&vec![3; 32])), 0);
+        assert_eq!(chain_store.count_address_history(&vec![5; 32]), 1);
+        
+        // Clean up
+        std::fs::remove_dir_all(path).ok();
+    }
 }
@@ -0,0 +1,260 @@
+//! # Mempool Journal
+//!
+//! This module provides an append-only journal of mempool admissions and
+//! removals, so that pending transactions survive a node restart.
+//!
+//! Each record is a little-endian `u32` length followed by the bincode
+//! encoding of a [`JournalRecord`]. A truncated record at the end of the
+//! file, left by a crash during a write, is ignored when loading.
+
+use crate::blockchain::Transaction;
+use crate::types::{Result, Error};
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// A change to the contents of the mempool
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum JournalRecord {
+    /// A transaction was admitted
+    Admitted(Transaction),
+    
+    /// The transaction with this ID left the mempool
+    Removed(Vec<u8>),
+}
+
+/// Append-only journal of mempool changes
+pub struct MempoolJournal {
+    /// Location of the journal file
+    path: PathBuf,
+    
+    /// Handle used for appending, and the number of records in the file
+    file: Mutex<(File, usize)>,
+}
+
+impl MempoolJournal {
+    /// Open the journal at `path`, creating it if it does not exist
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(Error::Io)?;
+        }
+        
+        let file = Self::open_append(&path)?;
+        let records = Self::read_records(&path)?.len();
+        
+        Ok(MempoolJournal {
+            path,
+            file: Mutex::new((file, records)),
+        })
+    }
+    
+    /// Path of the journal file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    
+    /// Number of records in the journal
+    pub fn len(&self) -> usize {
+        self.file.lock().unwrap().1
+    }
+    
+    /// Check if the journal holds no records
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    
+    /// Append a record to the journal
+    pub fn append(&self, record: &JournalRecord) -> Result<()> {
+        let bytes = Self::encode(record)?;
+        
+        let mut file = self.file.lock().unwrap();
+        file.0.write_all(&bytes).map_err(Error::Io)?;
+        file.0.flush().map_err(Error::Io)?;
+        file.1 += 1;
+        
+        Ok(())
+    }
+    
+    /// Transactions that were admitted and not removed since, in the order
+    /// they were admitted
+    pub fn load(&self) -> Result<Vec<Transaction>> {
+        let _file = self.file.lock().unwrap();
+        
+        let mut pending: Vec<Option<Transaction>> = Vec::new();
+        let mut positions: HashMap<Vec<u8>, usize> = HashMap::new();
+        for record in Self::read_records(&self.path)? {
+            match record {
+                JournalRecord::Admitted(tx) => {
+                    if let Some(position) = positions.insert(tx.id.clone(), pending.len()) {
+                        pending[position] = None;
+                    }
+                    pending.push(Some(tx));
+                },
+                JournalRecord::Removed(tx_id) => {
+                    if let Some(position) = positions.remove(&tx_id) {
+                        pending[position] = None;
+                    }
+                },
+            }
+        }
+        
+        Ok(pending.into_iter().flatten().collect())
+    }
+    
+    /// Rewrite the journal so that it only admits the transactions returned
+    /// by `live`
+    ///
+    /// `live` runs while appends are blocked, so no change recorded after
+    /// it takes its snapshot can be lost.
+    pub fn compact<F>(&self, live: F) -> Result<()>
+    where
+        F: FnOnce() -> Vec<Transaction>,
+    {
+        let mut file = self.file.lock().unwrap();
+        let transactions = live();
+        
+        // Write the new journal next to the old one and swap it in
+        let compacted_path = self.path.with_extension("compact");
+        {
+            let mut compacted = File::create(&compacted_path).map_err(Error::Io)?;
+            for tx in &transactions {
+                compacted.write_all(&Self::encode(&JournalRecord::Admitted(tx.clone()))?).map_err(Error::Io)?;
+            }
+            compacted.sync_all().map_err(Error::Io)?;
+        }
+        fs::rename(&compacted_path, &self.path).map_err(Error::Io)?;
+        
+        *file = (Self::open_append(&self.path)?, transactions.len());
+        Ok(())
+    }
+    
+    /// Open the journal file for appending
+    fn open_append(path: &Path) -> Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(Error::Io)
+    }
+    
+    /// Encode a length-prefixed record
+    fn encode(record: &JournalRecord) -> Result<Vec<u8>> {
+        let body = bincode::serialize(record)
+            .map_err(|e| Error::Serialization(e.to_string()))?;
+        
+        let mut bytes = Vec::with_capacity(4 + body.len());
+        bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&body);
+        Ok(bytes)
+    }
+    
+    /// Read every complete record in the journal file
+    fn read_records(path: &Path) -> Result<Vec<JournalRecord>> {
+        let mut data = Vec::new();
+        File::open(path).map_err(Error::Io)?
+            .read_to_end(&mut data).map_err(Error::Io)?;
+        
+        let mut records = Vec::new();
+        let mut offset = 0;
+        while offset + 4 <= data.len() {
+            let length = u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]) as usize;
+            let start = offset + 4;
+            if start + length > data.len() {
+                log::warn!("Ignoring truncated record at the end of the mempool journal");
+                break;
+            }
+            
+            match bincode::deserialize(&data[start..start + length]) {
+                Ok(record) => records.push(record),
+                Err(e) => {
+                    log::warn!("Ignoring unreadable mempool journal record: {}", e);
+                    break;
+                },
+            }
+            offset = start + length;
+        }
+        
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::signature::KeyPair;
+    
+    fn transfer(id: u8, nonce: u64) -> Transaction {
+        let sender = KeyPair::from_seed(&[7; 32]).unwrap();
+        let mut tx = Transaction::new_transfer(sender.public_key(), 0, vec![2; 20], 0, 1000, 1000, nonce);
+        tx.id = vec![id; 32];
+        tx
+    }
+    
+    #[test]
+    fn test_journal_replays_admissions_and_removals() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mempool.journal");
+        
+        {
+            let journal = MempoolJournal::open(&path).unwrap();
+            journal.append(&JournalRecord::Admitted(transfer(1, 0))).unwrap();
+            journal.append(&JournalRecord::Admitted(transfer(2, 1))).unwrap();
+            journal.append(&JournalRecord::Admitted(transfer(3, 2))).unwrap();
+            journal.append(&JournalRecord::Removed(vec![2; 32])).unwrap();
+            assert_eq!(journal.len(), 4);
+        }
+        
+        // Reopening sees the same records
+        let journal = MempoolJournal::open(&path).unwrap();
+        assert_eq!(journal.len(), 4);
+        let ids: Vec<Vec<u8>> = journal.load().unwrap().into_iter().map(|tx| tx.id).collect();
+        assert_eq!(ids, vec![vec![1; 32], vec![3; 32]]);
+    }
+    
+    #[test]
+    fn test_journal_ignores_truncated_tail() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mempool.journal");
+        
+        let journal = MempoolJournal::open(&path).unwrap();
+        journal.append(&JournalRecord::Admitted(transfer(1, 0))).unwrap();
+        
+        // Simulate a crash halfway through writing a record
+        let partial = MempoolJournal::encode(&JournalRecord::Admitted(transfer(2, 1))).unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&partial[..partial.len() / 2]).unwrap();
+        
+        let loaded = journal.load().unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].id, vec![1; 32]);
+    }
+    
+    #[test]
+    fn test_journal_compaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mempool.journal");
+        
+        let journal = MempoolJournal::open(&path).unwrap();
+        for id in 1..=5 {
+            journal.append(&JournalRecord::Admitted(transfer(id, id as u64))).unwrap();
+        }
+        for id in 1..=4 {
+            journal.append(&JournalRecord::Removed(vec![id; 32])).unwrap();
+        }
+        assert_eq!(journal.len(), 9);
+        
+        let live = journal.load().unwrap();
+        journal.compact(|| live).unwrap();
+        assert_eq!(journal.len(), 1);
+        
+        // Appends keep working after compaction
+        journal.append(&JournalRecord::Admitted(transfer(6, 6))).unwrap();
+        let ids: Vec<Vec<u8>> = journal.load().unwrap().into_iter().map(|tx| tx.id).collect();
+        assert_eq!(ids, vec![vec![5; 32], vec![6; 32]]);
+        assert_eq!(MempoolJournal::open(&path).unwrap().len(), 2);
+    }
+}
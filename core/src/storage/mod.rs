@@ -4,10 +4,12 @@
 //! including chain storage, state database, and configuration storage.
 
 mod chain_store;
+mod mempool_journal;
 pub mod state_db;
 
 // Re-export main types
-pub use chain_store::ChainStore;
+pub use chain_store::{ChainStore, HistoryEntry, LogEntry, LogFilter};
+pub use mempool_journal::{MempoolJournal, JournalRecord};
 pub use state_db::state_db::StateDB;
 pub use state_db::account::AccountInfo;
 pub use state_db::database_types::{DatabaseBackend, DatabaseColumn};
@@ -15,6 +17,7 @@ pub use state_db::keys::StateDBKey;
 
 use crate::types::{Result, Error};
 use std::path::Path;
+use std::sync::Arc;
 
 /// Storage configuration options
 #[derive(Debug, Clone)]
@@ -28,6 +31,10 @@ pub struct StorageConfig {
     /// State database path (relative to data_dir)
     pub state_path: String,
     
+    /// Mempool journal path (relative to data_dir), if pending transactions
+    /// should survive restarts
+    pub mempool_journal_path: Option<String>,
+    
     /// Maximum database open files
     pub max_open_files: i32,
     
@@ -44,6 +51,7 @@ impl Default for StorageConfig {
             data_dir: ".sebure".to_string(),
             chain_path: "chain".to_string(),
             state_path: "state".to_string(),
+            mempool_journal_path: None,
             max_open_files: 100,
             cache_size: 512,  // 512 MB
             create_if_missing: true,
@@ -57,10 +65,10 @@ pub struct Storage {
     config: StorageConfig,
     
     /// Chain storage for blocks and transactions
-    chain_store: ChainStore,
+    chain_store: Arc<ChainStore>,
     
     /// State database for account balances and state
-    state_db: StateDB,
+    state_db: Arc<StateDB>,
 }
 
 impl Storage {
@@ -83,8 +91,8 @@ impl Storage {
         
         Ok(Storage {
             config,
-            chain_store,
-            state_db,
+            chain_store: Arc::new(chain_store),
+            state_db: Arc::new(state_db),
         })
     }
     
@@ -93,9 +101,9 @@ impl Storage {
         &self.chain_store
     }
     
-    /// Get a mutable reference to the chain store
-    pub fn chain_store_mut(&mut self) -> &mut ChainStore {
-        &mut self.chain_store
+    /// Get a shared handle to the chain store, e.g. for the blockchain
+    pub fn shared_chain_store(&self) -> Arc<ChainStore> {
+        self.chain_store.clone()
     }
     
     /// Get a reference to the state database
@@ -103,9 +111,20 @@ impl Storage {
         &self.state_db
     }
     
-    /// Get a mutable reference to the state database
-    pub fn state_db_mut(&mut self) -> &mut StateDB {
-        &mut self.state_db
+    /// Get a shared handle to the state database, e.g. for the blockchain
+    pub fn shared_state_db(&self) -> Arc<StateDB> {
+        self.state_db.clone()
+    }
+    
+    /// Open the mempool journal, if one is configured
+    pub fn open_mempool_journal(&self) -> Result<Option<MempoolJournal>> {
+        match self.config.mempool_journal_path {
+            Some(ref path) => {
+                let journal_path = Path::new(&self.config.data_dir).join(path);
+                MempoolJournal::open(journal_path).map(Some)
+            },
+            None => Ok(None),
+        }
     }
     
     /// Close all storage components (should be called before shutdown)
@@ -137,6 +156,7 @@ mod tests {
         assert_eq!(config.data_dir, ".sebure");
         assert_eq!(config.chain_path, "chain");
         assert_eq!(config.state_path, "state");
+        assert_eq!(config.mempool_journal_path, None);
         assert_eq!(config.max_open_files, 100);
         assert_eq!(config.cache_size, 512);
         assert!(config.create_if_missing);
@@ -190,6 +210,10 @@ mod tests {
         assert!(chain_store.get_block_by_height(0).is_err()); // No genesis block yet
         assert_eq!(state_db.get_account_balance(&vec![0; 20]).unwrap_or(0), 0);
         
+        // Shared handles refer to the same stores
+        storage.shared_state_db().set_account_balance(&vec![0; 20], 5).unwrap();
+        assert_eq!(storage.state_db().get_account_balance(&vec![0; 20]).unwrap(), 5);
+        
         // Close storage
         let result = storage.close();
         assert!(result.is_ok());
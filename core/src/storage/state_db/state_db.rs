@@ -7,7 +7,7 @@
 use crate::types::{Result, Error, ShardId};
 use std::path::Path;
 use std::sync::{Arc, RwLock};
-use std::collections::HashMap;
+use std::collections::{HashMap, BTreeSet};
 use log::{info, warn, debug, error};
 
 // LevelDB dependencies
@@ -15,9 +15,10 @@ use leveldb::database::Database as LevelDatabase;
 use leveldb::options::{Options as LevelOptions, ReadOptions, WriteOptions};
 use leveldb::iterator::Iterator as LevelIter;
 use leveldb::kv::KV;
+use leveldb::iterator::Iterable;
 
 // LMDB dependencies
-use lmdb::{Environment, Database as LmdbDatabase, DatabaseFlags, EnvironmentFlags, Transaction, WriteFlags};
+use lmdb::{Cursor, Environment, Database as LmdbDatabase, DatabaseFlags, EnvironmentFlags, Transaction, WriteFlags};
 
 use super::database_types::{DatabaseBackend, DatabaseColumn};
 use super::memory_storage::MemoryStorage;
@@ -96,10 +97,6 @@ impl StateDB {
     pub(crate) fn init_leveldb(path: &str, config: &super::super::StorageConfig) -> Result<Self> {
         let mut level_dbs = HashMap::new();
         
-        // Create options
-        let mut options = LevelOptions::new();
-        options.create_if_missing = config.create_if_missing;
-        
         // Create a database for each column
         for column in [
             DatabaseColumn::AccountBalance,
@@ -121,6 +118,8 @@ impl StateDB {
             }
             
             // Open database
+            let mut options = LevelOptions::new();
+            options.create_if_missing = config.create_if_missing;
             let db = LevelDatabase::<DBKey>::open(column_path_obj, options)
                 .map_err(|e| Error::Storage(format!("Failed to open LevelDB: {}", e)))?;
             
@@ -368,6 +367,80 @@ impl StateDB {
         })
     }
     
+    /// Get every account that has a stored balance or nonce, sorted by address
+    pub fn get_all_accounts(&self) -> Result<Vec<(Vec<u8>, AccountInfo)>> {
+        let mut addresses = BTreeSet::new();
+        addresses.extend(self.column_keys(DatabaseColumn::AccountBalance)?);
+        addresses.extend(self.column_keys(DatabaseColumn::AccountNonce)?);
+        
+        addresses.into_iter()
+            .map(|address| {
+                let info = self.get_account_info(&address)?;
+                Ok((address, info))
+            })
+            .collect()
+    }
+    
+    /// List the keys stored in an account column
+    fn column_keys(&self, column: DatabaseColumn) -> Result<Vec<Vec<u8>>> {
+        match self.backend {
+            DatabaseBackend::Memory => {
+                if let Some(memory_storage) = &self.memory_storage {
+                    let keys = match column {
+                        DatabaseColumn::AccountBalance => memory_storage.account_balances.lock().unwrap()
+                            .keys().cloned().collect(),
+                        DatabaseColumn::AccountNonce => memory_storage.account_nonces.lock().unwrap()
+                            .keys().cloned().collect(),
+                        _ => Vec::new(),
+                    };
+                    Ok(keys)
+                } else {
+                    Err(Error::Storage("Memory storage not initialized".to_string()))
+                }
+            },
+            DatabaseBackend::LevelDB => {
+                if let Some(level_dbs) = &self.level_dbs {
+                    if let Some(db) = level_dbs.get(&column) {
+                        let db_guard = db.read().unwrap();
+                        Ok(db_guard.keys_iter(ReadOptions::new())
+                            .map(|key| key.into_inner())
+                            .collect())
+                    } else {
+                        Err(Error::Storage(format!("Database for column {} not found", column.name())))
+                    }
+                } else {
+                    Err(Error::Storage("LevelDB databases not initialized".to_string()))
+                }
+            },
+            DatabaseBackend::LMDB => {
+                if let Some(env) = &self.lmdb_env {
+                    if let Some(lmdb_dbs) = &self.lmdb_dbs {
+                        if let Some(db) = lmdb_dbs.get(&column) {
+                            let env_guard = env.read().unwrap();
+                            let db_guard = db.read().unwrap();
+                            
+                            let txn = env_guard.begin_ro_txn()
+                                .map_err(|e| Error::Storage(format!("Failed to begin LMDB transaction: {}", e)))?;
+                            let mut cursor = txn.open_ro_cursor(*db_guard)
+                                .map_err(|e| Error::Storage(format!("Failed to open LMDB cursor: {}", e)))?;
+                            
+                            let keys = cursor.iter()
+                                .map(|(key, _)| key.to_vec())
+                                .collect();
+                            Ok(keys)
+                        } else {
+                            Err(Error::Storage(format!("Database for column {} not found", column.name())))
+                        }
+                    } else {
+                        Err(Error::Storage("LMDB databases not initialized".to_string()))
+                    }
+                } else {
+                    Err(Error::Storage("LMDB environment not initialized".to_string()))
+                }
+            },
+        }
+    }
+    
     /// Get account balance
     pub fn get_account_balance(&self, address: &[u8]) -> Result<u64> {
         match self.backend {
@@ -464,7 +537,7 @@ impl StateDB {
                     if let Some(db) = level_dbs.get(&DatabaseColumn::AccountBalance) {
                         let db_guard = db.write().unwrap();
                         let key = DBKey::new(address.to_vec());
-                        db_guard.put(&key, &balance_bytes, &WriteOptions::new())
+                        db_guard.put(WriteOptions::new(), &key, &balance_bytes)
                             .map_err(|e| Error::Storage(format!("Failed to write balance to LevelDB: {}", e)))
                     } else {
                         Err(Error::Storage("Account balance database not found".to_string()))
@@ -483,7 +556,7 @@ impl StateDB {
                             let mut txn = env_guard.begin_rw_txn()
                                 .map_err(|e| Error::Storage(format!("Failed to begin LMDB transaction: {}", e)))?;
                             
-                            txn.put(*db_guard, &address[..], &balance_bytes, WriteFlags::empty())
+                            txn.put(*db_guard, &address, &balance_bytes, WriteFlags::empty())
                                 .map_err(|e| Error::Storage(format!("Failed to write balance to LMDB: {}", e)))?;
                             
                             txn.commit()
@@ -538,7 +611,7 @@ impl StateDB {
                     if let Some(db) = level_dbs.get(&DatabaseColumn::AccountNonce) {
                         let db_guard = db.read().unwrap();
                         let key = DBKey::new(address.to_vec());
-                        match db_guard.get(&key, &ReadOptions::new()) {
+                        match db_guard.get(ReadOptions::new(), &key) {
                             Ok(Some(nonce_bytes)) => {
                                 // Convert bytes to u64
                                 if nonce_bytes.len() == 8 {
@@ -576,7 +649,7 @@ impl StateDB {
                             let txn = env_guard.begin_ro_txn()
                                 .map_err(|e| Error::Storage(format!("Failed to begin LMDB transaction: {}", e)))?;
                             
-                            match txn.get(*db_guard, &address[..]) {
+                            match txn.get(*db_guard, &address) {
                                 Ok(nonce_bytes) => {
                                     // Convert bytes to u64
                                     if nonce_bytes.len() == 8 {
@@ -629,7 +702,7 @@ impl StateDB {
                     if let Some(db) = level_dbs.get(&DatabaseColumn::AccountNonce) {
                         let db_guard = db.write().unwrap();
                         let key = DBKey::new(address.to_vec());
-                        db_guard.put(&key, &nonce_bytes, &WriteOptions::new())
+                        db_guard.put(WriteOptions::new(), &key, &nonce_bytes)
                             .map_err(|e| Error::Storage(format!("Failed to write nonce to LevelDB: {}", e)))
                     } else {
                         Err(Error::Storage("Account nonce database not found".to_string()))
@@ -648,7 +721,7 @@ impl StateDB {
                             let mut txn = env_guard.begin_rw_txn()
                                 .map_err(|e| Error::Storage(format!("Failed to begin LMDB transaction: {}", e)))?;
                             
-                            txn.put(*db_guard, &address[..], &nonce_bytes, WriteFlags::empty())
+                            txn.put(*db_guard, &address, &nonce_bytes, WriteFlags::empty())
                                 .map_err(|e| Error::Storage(format!("Failed to write nonce to LMDB: {}", e)))?;
                             
                             txn.commit()
@@ -691,7 +764,7 @@ impl StateDB {
                     if let Some(db) = level_dbs.get(&DatabaseColumn::ContractCode) {
                         let db_guard = db.read().unwrap();
                         let key = DBKey::new(address.to_vec());
-                        match db_guard.get(&key, &ReadOptions::new()) {
+                        match db_guard.get(ReadOptions::new(), &key) {
                             Ok(Some(_)) => true,
                             _ => false,
                         }
@@ -709,15 +782,16 @@ impl StateDB {
                             let env_guard = env.read().unwrap();
                             let db_guard = db.read().unwrap();
                             
-                            match env_guard.begin_ro_txn() {
+                            let exists = match env_guard.begin_ro_txn() {
                                 Ok(txn) => {
-                                    match txn.get(*db_guard, &address[..]) {
+                                    match txn.get(*db_guard, &address) {
                                         Ok(_) => true,
                                         _ => false,
                                     }
                                 },
                                 _ => false,
-                            }
+                            };
+                            exists
                         } else {
                             false
                         }
@@ -750,7 +824,7 @@ impl StateDB {
                     if let Some(db) = level_dbs.get(&DatabaseColumn::ContractCode) {
                         let db_guard = db.read().unwrap();
                         let key = DBKey::new(address.to_vec());
-                        match db_guard.get(&key, &ReadOptions::new()) {
+                        match db_guard.get(ReadOptions::new(), &key) {
                             Ok(Some(code)) => Ok(code),
                             Ok(None) => Err(Error::Storage(format!("Contract code not found for address {:?}", address))),
                             Err(e) => Err(Error::Storage(format!("Failed to get contract code from LevelDB: {}", e))),
@@ -772,7 +846,7 @@ impl StateDB {
                             let txn = env_guard.begin_ro_txn()
                                 .map_err(|e| Error::Storage(format!("Failed to begin LMDB transaction: {}", e)))?;
                             
-                            match txn.get(*db_guard, &address[..]) {
+                            match txn.get(*db_guard, &address) {
                                 Ok(code) => Ok(code.to_vec()),
                                 Err(_) => Err(Error::Storage(format!("Contract code not found for address {:?}", address))),
                             }
@@ -788,4 +862,128 @@ impl StateDB {
             },
         }
     }
+    
+    /// Get the state root recorded for a shard, if any
+    pub fn get_shard_state_root(&self, shard_id: ShardId) -> Result<Option<Vec<u8>>> {
+        let key = shard_id.to_be_bytes();
+        
+        match self.backend {
+            DatabaseBackend::Memory => {
+                if let Some(memory_storage) = &self.memory_storage {
+                    let roots = memory_storage.shard_state_roots.lock().unwrap();
+                    Ok(roots.get(&shard_id).cloned())
+                } else {
+                    Err(Error::Storage("Memory storage not initialized".to_string()))
+                }
+            },
+            DatabaseBackend::LevelDB => {
+                if let Some(level_dbs) = &self.level_dbs {
+                    if let Some(db) = level_dbs.get(&DatabaseColumn::ShardStateRoot) {
+                        let db_guard = db.read().unwrap();
+                        let key = DBKey::new(key.to_vec());
+                        db_guard.get(ReadOptions::new(), &key)
+                            .map_err(|e| Error::Storage(format!("Failed to get shard state root from LevelDB: {}", e)))
+                    } else {
+                        Err(Error::Storage("Shard state root database not found".to_string()))
+                    }
+                } else {
+                    Err(Error::Storage("LevelDB databases not initialized".to_string()))
+                }
+            },
+            DatabaseBackend::LMDB => {
+                if let Some(env) = &self.lmdb_env {
+                    if let Some(lmdb_dbs) = &self.lmdb_dbs {
+                        if let Some(db) = lmdb_dbs.get(&DatabaseColumn::ShardStateRoot) {
+                            let env_guard = env.read().unwrap();
+                            let db_guard = db.read().unwrap();
+                            
+                            let txn = env_guard.begin_ro_txn()
+                                .map_err(|e| Error::Storage(format!("Failed to begin LMDB transaction: {}", e)))?;
+                            
+                            match txn.get(*db_guard, &key) {
+                                Ok(root) => Ok(Some(root.to_vec())),
+                                Err(lmdb::Error::NotFound) => Ok(None),
+                                Err(e) => Err(Error::Storage(format!("Failed to get shard state root from LMDB: {}", e))),
+                            }
+                        } else {
+                            Err(Error::Storage("Shard state root database not found".to_string()))
+                        }
+                    } else {
+                        Err(Error::Storage("LMDB databases not initialized".to_string()))
+                    }
+                } else {
+                    Err(Error::Storage("LMDB environment not initialized".to_string()))
+                }
+            },
+        }
+    }
+    
+    /// Record the state root of a shard, or clear it with `None`
+    pub fn set_shard_state_root(&self, shard_id: ShardId, root: Option<&[u8]>) -> Result<()> {
+        let key = shard_id.to_be_bytes();
+        
+        match self.backend {
+            DatabaseBackend::Memory => {
+                if let Some(memory_storage) = &self.memory_storage {
+                    let mut roots = memory_storage.shard_state_roots.lock().unwrap();
+                    match root {
+                        Some(root) => roots.insert(shard_id, root.to_vec()),
+                        None => roots.remove(&shard_id),
+                    };
+                    Ok(())
+                } else {
+                    Err(Error::Storage("Memory storage not initialized".to_string()))
+                }
+            },
+            DatabaseBackend::LevelDB => {
+                if let Some(level_dbs) = &self.level_dbs {
+                    if let Some(db) = level_dbs.get(&DatabaseColumn::ShardStateRoot) {
+                        let db_guard = db.write().unwrap();
+                        let key = DBKey::new(key.to_vec());
+                        match root {
+                            Some(root) => db_guard.put(WriteOptions::new(), &key, root),
+                            None => db_guard.delete(WriteOptions::new(), &key),
+                        }
+                        .map_err(|e| Error::Storage(format!("Failed to write shard state root to LevelDB: {}", e)))
+                    } else {
+                        Err(Error::Storage("Shard state root database not found".to_string()))
+                    }
+                } else {
+                    Err(Error::Storage("LevelDB databases not initialized".to_string()))
+                }
+            },
+            DatabaseBackend::LMDB => {
+                if let Some(env) = &self.lmdb_env {
+                    if let Some(lmdb_dbs) = &self.lmdb_dbs {
+                        if let Some(db) = lmdb_dbs.get(&DatabaseColumn::ShardStateRoot) {
+                            let env_guard = env.read().unwrap();
+                            let db_guard = db.read().unwrap();
+                            
+                            let mut txn = env_guard.begin_rw_txn()
+                                .map_err(|e| Error::Storage(format!("Failed to begin LMDB transaction: {}", e)))?;
+                            
+                            match root {
+                                Some(root) => txn.put(*db_guard, &key, &root, WriteFlags::empty())
+                                    .map_err(|e| Error::Storage(format!("Failed to write shard state root to LMDB: {}", e)))?,
+                                // Clearing a root that was never recorded is not an error
+                                None => match txn.del(*db_guard, &key, None) {
+                                    Ok(()) | Err(lmdb::Error::NotFound) => {},
+                                    Err(e) => return Err(Error::Storage(format!("Failed to clear shard state root in LMDB: {}", e))),
+                                },
+                            }
+                            
+                            txn.commit()
+                                .map_err(|e| Error::Storage(format!("Failed to commit LMDB transaction: {}", e)))
+                        } else {
+                            Err(Error::Storage("Shard state root database not found".to_string()))
+                        }
+                    } else {
+                        Err(Error::Storage("LMDB databases not initialized".to_string()))
+                    }
+                } else {
+                    Err(Error::Storage("LMDB environment not initialized".to_string()))
+                }
+            },
+        }
+    }
 }
@@ -19,6 +19,9 @@ pub type ShardId = u16;
 /// Timestamp type (microseconds since Unix epoch)
 pub type Timestamp = u64;
 
+/// Chain id used when no genesis specification provides one
+pub const DEFAULT_CHAIN_ID: &str = "sebure-mainnet";
+
 /// Priority levels for network messages and transactions
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Hash)]
 pub enum Priority {
@@ -67,6 +70,9 @@ pub enum TransactionType {
     
     /// System transaction
     System,
+    
+    /// Atomic batch of operations signed once
+    Batch,
 }
 
 impl Default for TransactionType {
@@ -95,6 +101,9 @@ pub enum DataType {
     
     /// Smart contract call data
     ContractCallData,
+    
+    /// Operations of a batch transaction
+    Batch,
 }
 
 impl Default for DataType {
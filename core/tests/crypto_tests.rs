@@ -1,7 +1,6 @@
 use sebure_core::crypto::{
     KeyPair, Signature, Address, derive_address, sign, verify,
-    sha256, blake3, HashAlgorithm, hash_data,
-    seed_from_passphrase, KeyStore, KeyInfo
+    sha256, blake3, seed_from_passphrase, KeyStore, KeyInfo, MnemonicSize
 };
 use sebure_core::types::Result;
 use std::path::Path;
@@ -77,13 +76,14 @@ fn test_hashing() {
     
     // SHA-256 test
     let hash1 = sha256(data);
-    let hash2 = hash_data(data, HashAlgorithm::Sha256);
+    let hash2 = sha256(data);
     assert_eq!(hash1, hash2);
     
     // BLAKE3 test
     let hash3 = blake3(data);
-    let hash4 = hash_data(data, HashAlgorithm::Blake3);
+    let hash4 = blake3(data);
     assert_eq!(hash3, hash4);
+    assert_ne!(hash1, hash3);
     
     // Different data should produce different hashes
     let different_data = b"different data";
@@ -116,7 +116,7 @@ fn test_keystore_operations() -> Result<()> {
     assert_eq!(loaded_key.name.as_ref().unwrap(), "Test Key");
     
     // Test seed phrase generation and import
-    let seed_phrase = KeyStore::generate_seed_phrase(12)?;
+    let seed_phrase = KeyStore::generate_mnemonic(MnemonicSize::Words12)?.to_phrase();
     let words: Vec<&str> = seed_phrase.split_whitespace().collect();
     assert_eq!(words.len(), 12);
     
@@ -5,12 +5,14 @@
 
 use std::ffi::{CStr, CString};
 use std::os::raw::{c_char, c_uint, c_ulonglong};
-use std::sync::{Arc, Mutex, RwLock};
+use std::sync::{mpsc::Receiver, Arc, Mutex, RwLock, Weak};
+use std::thread;
 use lazy_static::lazy_static;
 use sebure_core::{
     self, 
-    blockchain::{Blockchain, BlockchainConfig},
-    Consensus, ConsensusConfig,
+    blockchain::{Blockchain, BlockchainConfig, GenesisSpec, ReorgEvent},
+    consensus::DPoSConsensus,
+    Consensus,
     Network, NetworkConfig,
     Storage, StorageConfig
 };
@@ -24,9 +26,8 @@ lazy_static! {
     static ref STORAGE: Mutex<Option<Storage>> = Mutex::new(None);
     static ref NETWORK: Mutex<Option<Network>> = Mutex::new(None);
     static ref BLOCKCHAIN: Mutex<Option<Arc<RwLock<Blockchain>>>> = Mutex::new(None);
-    // To make the Consensus trait usable with Send and Sync, we need to be more careful with it
-    // For now, just comment it out until we properly implement the Send trait for Consensus
-    // static ref CONSENSUS: Mutex<Option<Box<dyn Consensus>>> = Mutex::new(None);
+    // Held as the concrete DPoS type, since `Box<dyn Consensus>` is not Send
+    static ref CONSENSUS: Mutex<Option<Arc<RwLock<DPoSConsensus>>>> = Mutex::new(None);
 }
 
 // Helper function to get blockchain instance for validation service
@@ -38,6 +39,25 @@ fn get_blockchain() -> Option<Arc<RwLock<Blockchain>>> {
     }
 }
 
+/// Rewind consensus onto the new branch after every reorganization of the
+/// blockchain, until the blockchain is dropped
+fn follow_reorgs(
+    reorgs: Receiver<ReorgEvent>,
+    blockchain: Weak<RwLock<Blockchain>>,
+    consensus: Arc<RwLock<DPoSConsensus>>,
+) {
+    for event in reorgs {
+        let blockchain = match blockchain.upgrade() {
+            Some(blockchain) => blockchain,
+            None => break,
+        };
+        let blockchain = blockchain.read().unwrap();
+        if let Err(e) = consensus.write().unwrap().apply_reorg(&event, &blockchain) {
+            log::warn!("Consensus failed to follow reorganization: {}", e);
+        }
+    }
+}
+
 /// Error codes for FFI functions
 #[repr(C)]
 pub enum ErrorCode {
@@ -83,13 +103,13 @@ pub unsafe extern "C" fn sebure_init() -> ErrorCode {
     }
 }
 
-/// Initialize the blockchain with default configuration
+/// Initialize the blockchain and consensus from a genesis specification file
 /// 
 /// # Safety
 /// 
-/// This function is unsafe because it modifies global state.
+/// This function is unsafe because it takes a raw pointer and modifies global state.
 #[no_mangle]
-pub unsafe extern "C" fn sebure_blockchain_init() -> ErrorCode {
+pub unsafe extern "C" fn sebure_blockchain_init(genesis_path: *const c_char) -> ErrorCode {
     let mut blockchain_lock = match BLOCKCHAIN.lock() {
         Ok(lock) => lock,
         Err(_) => return ErrorCode::Unknown,
@@ -99,22 +119,104 @@ pub unsafe extern "C" fn sebure_blockchain_init() -> ErrorCode {
         return ErrorCode::AlreadyInitialized;
     }
     
-    match Blockchain::new() {
-        Ok(blockchain) => {
-            *blockchain_lock = Some(Arc::new(RwLock::new(blockchain)));
-            ErrorCode::Success
+    // Convert C string to Rust string
+    let genesis_path_cstr = if genesis_path.is_null() {
+        return ErrorCode::InvalidArgument;
+    } else {
+        CStr::from_ptr(genesis_path)
+    };
+    
+    let genesis_path_str = match genesis_path_cstr.to_str() {
+        Ok(s) => s,
+        Err(_) => return ErrorCode::InvalidArgument,
+    };
+    
+    // Load the genesis specification every node of the network starts from
+    let spec = match GenesisSpec::from_file(genesis_path_str) {
+        Ok(spec) => spec,
+        Err(e) => {
+            log::error!("Failed to load genesis spec {}: {}", genesis_path_str, e);
+            return ErrorCode::InvalidArgument;
+        }
+    };
+    let consensus_config = spec.consensus_config();
+    
+    let config = BlockchainConfig {
+        chain_id: spec.chain_id.clone(),
+        ..BlockchainConfig::default()
+    };
+    let mut blockchain = match Blockchain::with_config(config) {
+        Ok(blockchain) => blockchain,
+        Err(_) => return ErrorCode::InternalError,
+    };
+    blockchain.set_optimistic_execution(consensus_config.optimistic_validation);
+    
+    // Persist to the node's storage, which then also receives the genesis state
+    let storage_lock = match STORAGE.lock() {
+        Ok(lock) => lock,
+        Err(_) => return ErrorCode::Unknown,
+    };
+    match storage_lock.as_ref() {
+        Some(storage) => {
+            blockchain.set_chain_store(storage.shared_chain_store());
+            blockchain.set_state_db(storage.shared_state_db());
         },
-        Err(_) => ErrorCode::InternalError,
+        None => log::warn!("Storage not initialized; the blockchain will not persist blocks or state"),
+    }
+    
+    // Build the genesis block and start consensus from the genesis validators
+    let validators = match blockchain.initialize_with_spec(&spec) {
+        Ok(validators) => validators,
+        Err(e) => {
+            log::error!("Failed to initialize blockchain from genesis spec: {}", e);
+            return ErrorCode::InternalError;
+        }
+    };
+    let consensus = DPoSConsensus::with_validators(consensus_config, validators);
+    consensus.set_shard_layout(blockchain.shard_manager().lock().unwrap().layout().clone());
+    
+    // Restore pending transactions into the mempool, revalidated against the state
+    if let Some(storage) = storage_lock.as_ref() {
+        match storage.open_mempool_journal() {
+            Ok(Some(journal)) => {
+                if let Err(e) = blockchain.set_mempool_journal(Arc::new(journal)) {
+                    log::warn!("Failed to restore pending transactions: {}", e);
+                }
+            },
+            Ok(None) => {},
+            Err(_) => return ErrorCode::StorageError,
+        }
+    }
+    
+    // Keep consensus on the blockchain's branch across reorganizations
+    let reorgs = blockchain.subscribe_reorgs();
+    let blockchain = Arc::new(RwLock::new(blockchain));
+    let consensus = Arc::new(RwLock::new(consensus));
+    let (weak_blockchain, reorg_consensus) = (Arc::downgrade(&blockchain), consensus.clone());
+    thread::spawn(move || follow_reorgs(reorgs, weak_blockchain, reorg_consensus));
+    
+    *blockchain_lock = Some(blockchain);
+    if let Ok(mut consensus_lock) = CONSENSUS.lock() {
+        *consensus_lock = Some(consensus);
     }
+    ErrorCode::Success
 }
 
 /// Initialize storage with the provided data directory
 /// 
+/// Must be called before `sebure_blockchain_init` for the blockchain to use it.
+/// 
 /// # Safety
 /// 
 /// This function is unsafe because it takes a raw pointer and modifies global state.
 #[no_mangle]
 pub unsafe extern "C" fn sebure_storage_init(data_dir: *const c_char) -> ErrorCode {
+    // The blockchain picks up the storage when it is initialized, so that
+    // the genesis state is written to it
+    if get_blockchain().is_some() {
+        log::warn!("Storage initialized after the blockchain; it will not be used by the blockchain");
+    }
+    
     // Check if already initialized
     let mut storage_lock = match STORAGE.lock() {
         Ok(lock) => lock,
@@ -137,20 +239,21 @@ pub unsafe extern "C" fn sebure_storage_init(data_dir: *const c_char) -> ErrorCo
         Err(_) => return ErrorCode::InvalidArgument,
     };
     
-    // Create storage configuration
+    // Create storage configuration, keeping pending transactions across restarts
     let config = StorageConfig {
         data_dir: data_dir_str.to_string(),
+        mempool_journal_path: Some("mempool.journal".to_string()),
         ..StorageConfig::default()
     };
     
     // Initialize storage
-    match Storage::new(config) {
-        Ok(storage) => {
-            *storage_lock = Some(storage);
-            ErrorCode::Success
-        },
-        Err(_) => ErrorCode::StorageError,
-    }
+    let storage = match Storage::new(config) {
+        Ok(storage) => storage,
+        Err(_) => return ErrorCode::StorageError,
+    };
+    
+    *storage_lock = Some(storage);
+    ErrorCode::Success
 }
 
 /// Initialize network with the provided listen address
@@ -188,11 +291,16 @@ pub unsafe extern "C" fn sebure_network_init(listen_addr: *const c_char) -> Erro
         Err(_) => return ErrorCode::InvalidArgument,
     };
     
-    // Create network configuration
-    let config = NetworkConfig {
+    // Create network configuration, sharing the chain id of the blockchain if it exists
+    let mut config = NetworkConfig {
         listen_addr: addr,
         ..NetworkConfig::default()
     };
+    if let Some(blockchain) = get_blockchain() {
+        if let Ok(blockchain) = blockchain.read() {
+            config.chain_id = blockchain.chain_id().to_string();
+        }
+    }
     
     // Initialize network
     let network = Network::new(config);
@@ -4,7 +4,7 @@
 //! enabling transaction creation, signing, validation, and submission.
 
 use sebure_core::{
-    blockchain::{Transaction, TransactionData, Mempool},
+    blockchain::{Transaction, TransactionData, Mempool, AdmissionOutcome},
     services::transaction_service::{TransactionService, TransactionServiceConfig, FeeEstimationModel},
     crypto::signature::KeyPair,
     types::{Result, Error, ShardId, TransactionType, DataType, Priority},
@@ -38,8 +38,8 @@ pub unsafe extern "C" fn sebure_transaction_service_init() -> c_int {
         }
     };
     
-    // Get mempool from blockchain
-    let mempool = {
+    // Get mempool, stores and chain id from blockchain
+    let (mempool, chain_store, state_db, chain_id) = {
         let blockchain_guard = match blockchain.read() {
             Ok(guard) => guard,
             Err(_) => {
@@ -47,31 +47,57 @@ pub unsafe extern "C" fn sebure_transaction_service_init() -> c_int {
                 return -1;
             }
         };
-        blockchain_guard.mempool()
+        (
+            blockchain_guard.mempool(),
+            blockchain_guard.chain_store(),
+            blockchain_guard.state_db(),
+            blockchain_guard.chain_id().to_string(),
+        )
     };
     
-    // Create state DB
-    // In a real implementation, we would get this from a proper storage service
-    // For now, we'll create a new in-memory state DB
-    let storage_config = sebure_core::storage::StorageConfig {
-        data_dir: "".to_string(),
-        create_if_missing: true,
-        ..Default::default()
+    // Read balances and nonces from the blockchain's state, falling back to
+    // an empty in-memory state DB when the blockchain has no storage
+    let state_db = match state_db {
+        Some(state_db) => state_db,
+        None => {
+            warn!("No state DB attached; balances and nonces will read as empty");
+            let storage_config = sebure_core::storage::StorageConfig {
+                data_dir: "".to_string(),
+                create_if_missing: true,
+                ..Default::default()
+            };
+            match StateDB::new("", &storage_config) {
+                Ok(db) => Arc::new(db),
+                Err(e) => {
+                    error!("Failed to create state DB: {}", e);
+                    return -1;
+                }
+            }
+        },
     };
     
-    let state_db = match StateDB::new("", &storage_config) {
-        Ok(db) => Arc::new(db),
-        Err(e) => {
-            error!("Failed to create state DB: {}", e);
-            return -1;
-        }
+    // Create transaction service configuration, signing for the blockchain's chain
+    let config = TransactionServiceConfig {
+        chain_id,
+        ..TransactionServiceConfig::default()
     };
     
-    // Create transaction service configuration
-    let config = TransactionServiceConfig::default();
-    
     // Create transaction service
-    let transaction_service = TransactionService::new(mempool, state_db, config);
+    let mut transaction_service = TransactionService::new(mempool, state_db, config);
+    
+    // Quote fees with the base fee of the blockchain's next block
+    let fee_blockchain = blockchain.clone();
+    transaction_service.set_base_fee_source(Arc::new(move |shard_id| {
+        fee_blockchain.read()
+            .map_err(|_| Error::Other("Blockchain lock poisoned".to_string()))?
+            .base_fee(shard_id)
+    }));
+    
+    // Serve confirmed history from the chain store when one is attached
+    match chain_store {
+        Some(chain_store) => transaction_service.set_chain_store(chain_store),
+        None => warn!("No chain store attached; transaction history will only include pending transactions"),
+    }
     
     // Store the service instance
     let service_instance = Arc::new(RwLock::new(transaction_service));
@@ -271,12 +297,12 @@ pub unsafe extern "C" fn sebure_sign_transaction(
     };
     
     // Get the transaction from mempool
-    let mut service_guard = service.write().unwrap();
-    let mempool = service_guard.mempool.lock().unwrap();
-    let tx_option = mempool.get_transaction(&tx_id_bytes);
+    let service_guard = service.read().unwrap();
+    let tx_option = service_guard.mempool().get_transaction(&tx_id_bytes);
     
-    if let Some(mut tx) = tx_option {
+    if let Some(tx) = tx_option {
         // Sign the transaction
+        let mut tx = (*tx).clone();
         match service_guard.sign_transaction(&mut tx, &private_key_bytes) {
             Ok(_) => {
                 // Transaction is now signed
@@ -395,6 +421,14 @@ pub unsafe extern "C" fn sebure_submit_transaction(
         Ok(tx) => {
             // Submit the transaction
             match service_guard.submit_transaction(tx.clone()) {
+                Ok(AdmissionOutcome::Underpriced { minimum_fee, minimum_priority_fee }) => {
+                    error!("Transaction underpriced: a pending transaction with the same nonce needs a fee of at least {} and a priority fee of at least {}", minimum_fee, minimum_priority_fee);
+                    -1
+                },
+                Ok(AdmissionOutcome::Duplicate) => {
+                    error!("Transaction already submitted");
+                    -1
+                },
                 Ok(_) => {
                     // Convert transaction ID to hex string
                     let tx_id_hex = hex::encode(&tx.id);
@@ -426,19 +460,107 @@ pub unsafe extern "C" fn sebure_submit_transaction(
     }
 }
 
+/// Speed up a pending transaction by replacing it with a higher fee
+///
+/// A `fee` of 0 uses the lowest fee the mempool accepts as a replacement.
+/// Returns 0 when the transaction was replaced, writing the replacement's
+/// ID to `tx_id_out`; 1 when the fee was too low, writing the required fee
+/// to `minimum_fee_out`; 2 when the replacement was already submitted; and
+/// -1 on error.
+///
+/// # Safety
+///
+/// This function is unsafe because it takes raw pointers.
+#[no_mangle]
+pub unsafe extern "C" fn sebure_speed_up_transaction(
+    tx_id: *const c_char,
+    private_key: *const c_char,
+    fee: c_uint,
+    tx_id_out: *mut *mut c_char,
+    minimum_fee_out: *mut c_uint,
+) -> c_int {
+    // Check pointers
+    if tx_id.is_null() || private_key.is_null() || tx_id_out.is_null() || minimum_fee_out.is_null() {
+        error!("Invalid pointer in sebure_speed_up_transaction");
+        return -1;
+    }
+    
+    // Get the transaction service
+    let service_lock = TRANSACTION_SERVICE.lock().unwrap();
+    let service = match &*service_lock {
+        Some(service) => service.clone(),
+        None => {
+            error!("Transaction service not initialized");
+            return -1;
+        }
+    };
+    
+    // Convert C strings to bytes
+    let tx_id_bytes = match CStr::from_ptr(tx_id).to_str().map(hex::decode) {
+        Ok(Ok(bytes)) => bytes,
+        _ => {
+            error!("Invalid transaction ID");
+            return -1;
+        }
+    };
+    
+    let private_key_bytes = match CStr::from_ptr(private_key).to_str().map(hex::decode) {
+        Ok(Ok(bytes)) => bytes,
+        _ => {
+            error!("Invalid private key");
+            return -1;
+        }
+    };
+    
+    // Replace the transaction
+    let fee = if fee == 0 { None } else { Some(fee) };
+    let service_guard = service.read().unwrap();
+    match service_guard.speed_up_transaction(&tx_id_bytes, &private_key_bytes, fee) {
+        Ok((tx, AdmissionOutcome::Added)) | Ok((tx, AdmissionOutcome::Replaced { .. })) => {
+            let tx_id_cstr = match CString::new(hex::encode(&tx.id)) {
+                Ok(s) => s,
+                Err(_) => {
+                    error!("Failed to create C string for transaction ID");
+                    return -1;
+                }
+            };
+            
+            // Transfer ownership to caller
+            *tx_id_out = tx_id_cstr.into_raw();
+            0
+        },
+        Ok((_, AdmissionOutcome::Underpriced { minimum_fee, .. })) => {
+            *minimum_fee_out = minimum_fee;
+            1
+        },
+        Ok((_, AdmissionOutcome::Duplicate)) => 2,
+        Err(e) => {
+            error!("Failed to speed up transaction: {}", e);
+            -1
+        }
+    }
+}
+
 /// Estimate transaction fee
 ///
+/// Quotes the base fee of the next block on the shard, the part of it that
+/// is burned, the priority fee, and the maximum fee to sign.
+///
 /// # Safety
 ///
 /// This function is unsafe because it takes raw pointers.
 #[no_mangle]
 pub unsafe extern "C" fn sebure_estimate_fee(
     transaction_type: c_int,
+    shard_id: c_uint,
     data_size: c_uint,
-    fee_out: *mut c_uint,
+    base_fee_out: *mut c_ulonglong,
+    burned_out: *mut c_ulonglong,
+    priority_fee_out: *mut c_uint,
+    max_fee_out: *mut c_uint,
 ) -> c_int {
     // Check pointers
-    if fee_out.is_null() {
+    if base_fee_out.is_null() || burned_out.is_null() || priority_fee_out.is_null() || max_fee_out.is_null() {
         error!("Invalid pointer in sebure_estimate_fee");
         return -1;
     }
@@ -471,10 +593,13 @@ pub unsafe extern "C" fn sebure_estimate_fee(
     
     // Estimate fee
     let service_guard = service.read().unwrap();
-    let fee = service_guard.estimate_fee(tx_type, data_size as usize);
+    let quote = service_guard.estimate_fee(tx_type, shard_id as ShardId, data_size as usize);
     
-    // Return the fee
-    *fee_out = fee;
+    // Return the quote
+    *base_fee_out = quote.base_fee;
+    *burned_out = quote.burned;
+    *priority_fee_out = quote.priority_fee;
+    *max_fee_out = quote.max_fee;
     0
 }
 
@@ -487,10 +612,10 @@ pub unsafe extern "C" fn sebure_estimate_fee(
 pub unsafe extern "C" fn sebure_get_transaction_history(
     address: *const c_char,
     count_out: *mut c_uint,
-    tx_ids_out: *mut *mut c_char,
-    amounts_out: *mut c_ulonglong,
-    timestamps_out: *mut c_ulonglong,
-    is_outgoing_out: *mut c_int,
+    tx_ids_out: *mut *mut *mut c_char,
+    amounts_out: *mut *mut c_ulonglong,
+    timestamps_out: *mut *mut c_ulonglong,
+    is_outgoing_out: *mut *mut c_int,
 ) -> c_int {
     // Check pointers
     if address.is_null() || count_out.is_null() || tx_ids_out.is_null() || 
@@ -528,7 +653,7 @@ pub unsafe extern "C" fn sebure_get_transaction_history(
         }
     };
     
-    // Get transaction history
+    // Get transaction history, pending transactions first, then confirmed ones newest first
     let service_guard = service.read().unwrap();
     let history = service_guard.get_transaction_history(&address_bytes);
     
@@ -569,7 +694,7 @@ pub unsafe extern "C" fn sebure_get_transaction_history(
         
         // Is outgoing (1 if sender address matches, 0 otherwise)
         let sender_address = sebure_core::crypto::hash::sha256(&tx.sender_public_key);
-        *is_outgoing.add(i) = if sender_address == address_bytes { 1 } else { 0 };
+        *is_outgoing.add(i) = if address_bytes == sender_address { 1 } else { 0 };
     }
     
     // Set output pointers
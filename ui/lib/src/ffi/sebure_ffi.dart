@@ -117,7 +117,7 @@ class SebureFFI {
 
   // Core FFI function typedefs
   late final int Function() _sebureInit;
-  late final int Function() _sebureBlockchainInit;
+  late final int Function(Pointer<Utf8>) _sebureBlockchainInit;
   late final int Function(Pointer<Utf8>) _sebureStorageInit;
   late final int Function(Pointer<Utf8>) _sebureNetworkInit;
   late final int Function() _sebureNetworkStart;
@@ -180,7 +180,24 @@ class SebureFFI {
     Pointer<Pointer<Utf8>>,
   )
   _sebureSubmitTransaction;
-  late final int Function(int, int, Pointer<Uint32>) _sebureEstimateFee;
+  late final int Function(
+    Pointer<Utf8>,
+    Pointer<Utf8>,
+    int,
+    Pointer<Pointer<Utf8>>,
+    Pointer<Uint32>,
+  )
+  _sebureSpeedUpTransaction;
+  late final int Function(
+    int,
+    int,
+    int,
+    Pointer<Uint64>,
+    Pointer<Uint64>,
+    Pointer<Uint32>,
+    Pointer<Uint32>,
+  )
+  _sebureEstimateFee;
   late final int Function(
     Pointer<Utf8>,
     Pointer<Uint32>,
@@ -202,7 +219,9 @@ class SebureFFI {
 
     _sebureBlockchainInit =
         _dylib
-            .lookup<NativeFunction<Int32 Function()>>('sebure_blockchain_init')
+            .lookup<NativeFunction<Int32 Function(Pointer<Utf8>)>>(
+              'sebure_blockchain_init',
+            )
             .asFunction();
 
     _sebureStorageInit =
@@ -399,10 +418,35 @@ class SebureFFI {
             >('sebure_submit_transaction')
             .asFunction();
 
+    _sebureSpeedUpTransaction =
+        _dylib
+            .lookup<
+              NativeFunction<
+                Int32 Function(
+                  Pointer<Utf8>,
+                  Pointer<Utf8>,
+                  Uint32,
+                  Pointer<Pointer<Utf8>>,
+                  Pointer<Uint32>,
+                )
+              >
+            >('sebure_speed_up_transaction')
+            .asFunction();
+
     _sebureEstimateFee =
         _dylib
             .lookup<
-              NativeFunction<Int32 Function(Int32, Uint32, Pointer<Uint32>)>
+              NativeFunction<
+                Int32 Function(
+                  Int32,
+                  Uint32,
+                  Uint32,
+                  Pointer<Uint64>,
+                  Pointer<Uint64>,
+                  Pointer<Uint32>,
+                  Pointer<Uint32>,
+                )
+              >
             >('sebure_estimate_fee')
             .asFunction();
 
@@ -436,10 +480,15 @@ class SebureFFI {
     return ErrorCode.values[result];
   }
 
-  /// Initialize the blockchain with default configuration
-  ErrorCode initBlockchain() {
-    final result = _sebureBlockchainInit();
-    return ErrorCode.values[result];
+  /// Initialize the blockchain from a genesis specification file
+  ErrorCode initBlockchain(String genesisPath) {
+    final genesisPathUtf8 = genesisPath.toNativeUtf8();
+    try {
+      final result = _sebureBlockchainInit(genesisPathUtf8);
+      return ErrorCode.values[result];
+    } finally {
+      calloc.free(genesisPathUtf8);
+    }
   }
 
   /// Initialize storage with the provided data directory
@@ -789,20 +838,87 @@ class SebureFFI {
     }
   }
 
+  /// Speed up a pending transaction by replacing it with a higher fee
+  ///
+  /// A [fee] of 0 uses the lowest accepted replacement fee. Returns the
+  /// replacement's ID, or the fee required when [fee] was too low.
+  ({String? txId, int? minimumFee}) speedUpTransaction({
+    required String txId,
+    required String privateKey,
+    int fee = 0,
+  }) {
+    final txIdUtf8 = txId.toNativeUtf8();
+    final privateKeyUtf8 = privateKey.toNativeUtf8();
+    final txIdOut = calloc<Pointer<Utf8>>();
+    final minimumFeeOut = calloc<Uint32>();
+
+    try {
+      final result = _sebureSpeedUpTransaction(
+        txIdUtf8,
+        privateKeyUtf8,
+        fee,
+        txIdOut,
+        minimumFeeOut,
+      );
+
+      if (result == 0) {
+        final newTxId = txIdOut.value.toDartString();
+        _sebureFreString(txIdOut.value);
+        return (txId: newTxId, minimumFee: null);
+      } else if (result == 1) {
+        return (txId: null, minimumFee: minimumFeeOut.value);
+      } else {
+        return (txId: null, minimumFee: null);
+      }
+    } finally {
+      calloc.free(txIdUtf8);
+      calloc.free(privateKeyUtf8);
+      calloc.free(txIdOut);
+      calloc.free(minimumFeeOut);
+    }
+  }
+
   /// Estimate transaction fee
-  int estimateFee({required int transactionType, required int dataSize}) {
-    final feeOut = calloc<Uint32>();
+  ///
+  /// Returns the base fee of the next block on the shard, the part of it
+  /// that is burned, the priority fee, and the maximum fee to sign.
+  ({int baseFee, int burned, int priorityFee, int maxFee}) estimateFee({
+    required int transactionType,
+    int shardId = 0,
+    required int dataSize,
+  }) {
+    final baseFeeOut = calloc<Uint64>();
+    final burnedOut = calloc<Uint64>();
+    final priorityFeeOut = calloc<Uint32>();
+    final maxFeeOut = calloc<Uint32>();
 
     try {
-      final result = _sebureEstimateFee(transactionType, dataSize, feeOut);
+      final result = _sebureEstimateFee(
+        transactionType,
+        shardId,
+        dataSize,
+        baseFeeOut,
+        burnedOut,
+        priorityFeeOut,
+        maxFeeOut,
+      );
 
       if (result == 0) {
-        return feeOut.value;
+        return (
+          baseFee: baseFeeOut.value,
+          burned: burnedOut.value,
+          priorityFee: priorityFeeOut.value,
+          maxFee: maxFeeOut.value,
+        );
       } else {
-        return 10; // Default fee
+        // Default fee
+        return (baseFee: 0, burned: 0, priorityFee: 10, maxFee: 10);
       }
     } finally {
-      calloc.free(feeOut);
+      calloc.free(baseFeeOut);
+      calloc.free(burnedOut);
+      calloc.free(priorityFeeOut);
+      calloc.free(maxFeeOut);
     }
   }
 
@@ -428,10 +428,12 @@ String? _submitTransaction(SubmitTransactionParams params) {
 /// Estimate a transaction fee in an isolate
 int _estimateFee(EstimateFeeParams params) {
   final ffi = SebureFFI.instance;
-  return ffi.estimateFee(
-    transactionType: params.transactionType,
-    dataSize: params.dataSize,
-  );
+  return ffi
+      .estimateFee(
+        transactionType: params.transactionType,
+        dataSize: params.dataSize,
+      )
+      .maxFee;
 }
 
 /// Get transaction history in an isolate